@@ -0,0 +1,36 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use image::{Rgba, RgbaImage};
+use std::hint::black_box;
+use tiler::{analyse, AnalysisOptions, ChannelWeights, ImageInfo};
+
+fn gradient(offset: u32, options: &AnalysisOptions) -> ImageInfo {
+    let img = RgbaImage::from_fn(200, 200, |x, y| {
+        Rgba([x as u8, (y + offset) as u8, (x + y) as u8, 255])
+    });
+    analyse(&img, options)
+}
+
+fn compare(c: &mut Criterion) {
+    let options = AnalysisOptions::new(Some(20));
+    let (a, b) = (gradient(0, &options), gradient(50, &options));
+    let weights = ChannelWeights::default();
+
+    c.bench_function("diff", |bench| {
+        bench.iter(|| {
+            black_box(&a)
+                .diff(black_box(&b), &weights)
+                .iter()
+                .sum::<i64>()
+        })
+    });
+    c.bench_function("diff_sum", |bench| {
+        bench.iter(|| black_box(&a).diff_sum(black_box(&b), &weights))
+    });
+    let threshold = a.diff_sum(&b, &weights) / 4;
+    c.bench_function("diff_less_than", |bench| {
+        bench.iter(|| black_box(&a).diff_less_than(black_box(&b), &weights, threshold))
+    });
+}
+
+criterion_group!(benches, compare);
+criterion_main!(benches);
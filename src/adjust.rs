@@ -0,0 +1,94 @@
+use image::{GenericImageView, Rgba, RgbaImage};
+
+/// Mean and standard deviation of the luminance of some pixels.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LuminanceStats {
+    pub mean: f32,
+    pub stddev: f32,
+}
+
+impl LuminanceStats {
+    /// Measure the luminance of the pixels of the given image.
+    pub fn of<I>(img: &I) -> Self
+    where
+        I: GenericImageView<Pixel = Rgba<u8>>,
+    {
+        let values: Vec<f32> = img.pixels().map(|(_, _, p)| luminance(&p)).collect();
+        let count = values.len().max(1) as f32;
+
+        let mean = values.iter().sum::<f32>() / count;
+        let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f32>() / count;
+
+        Self {
+            mean,
+            stddev: variance.sqrt(),
+        }
+    }
+}
+
+/// Rec. 601 luma of a pixel.
+pub(crate) fn luminance(p: &Rgba<u8>) -> f32 {
+    let [r, g, b, _] = p.0;
+    0.299 * r as f32 + 0.587 * g as f32 + 0.114 * b as f32
+}
+
+/// Adjust the brightness and contrast of the image so the luminance of its
+/// pixels has the target mean and standard deviation.
+///
+/// The same linear adjustment is applied to every channel, so hues are kept.
+pub fn match_luminance(img: &mut RgbaImage, target: &LuminanceStats) {
+    let current = LuminanceStats::of(img);
+
+    // A flat image has no contrast to scale, so only shift its brightness.
+    let contrast = if current.stddev > f32::EPSILON {
+        target.stddev / current.stddev
+    } else {
+        1.0
+    };
+    let adjust = |v: u8| ((v as f32 - current.mean) * contrast + target.mean).round() as u8;
+
+    for p in img.pixels_mut() {
+        let [r, g, b, a] = p.0;
+        *p = Rgba([adjust(r), adjust(g), adjust(b), a]);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn stripes(dark: u8, light: u8) -> RgbaImage {
+        RgbaImage::from_fn(4, 4, |x, _| {
+            let v = if x % 2 == 0 { dark } else { light };
+            Rgba([v, v, v, 255])
+        })
+    }
+
+    #[test]
+    fn test_measures_luminance_stats() {
+        let stats = LuminanceStats::of(&stripes(100, 200));
+
+        assert!((stats.mean - 150.0).abs() < 0.01);
+        assert!((stats.stddev - 50.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_matches_target_luminance() {
+        let mut img = stripes(100, 200);
+        let target = LuminanceStats::of(&stripes(20, 60));
+
+        match_luminance(&mut img, &target);
+
+        assert_eq!(img, stripes(20, 60));
+    }
+
+    #[test]
+    fn test_shifts_brightness_of_flat_images() {
+        let mut img = stripes(100, 100);
+        let target = LuminanceStats::of(&stripes(20, 60));
+
+        match_luminance(&mut img, &target);
+
+        assert_eq!(img, stripes(40, 40));
+    }
+}
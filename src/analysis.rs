@@ -1,65 +1,452 @@
 use core::fmt::Debug;
+use std::borrow::Cow;
+use std::str::FromStr;
 
-use image::{imageops, Pixel, RgbaImage};
+use image::{imageops, GenericImageView, Pixel, Rgba, RgbaImage};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::core::Rectangle;
+use crate::resize::{Resize, ResizeFilter};
+use crate::summary::Summary;
+use crate::tiling::choose_tile_area;
 
 const SAMPLE_SIZE: u8 = 8;
 
+/// Version of the way images are analysed, to be bumped whenever the same
+/// image and options would be analysed differently, so that stored analyses
+/// are redone rather than compared with fresh ones.
+#[cfg(feature = "index")]
+pub(crate) const ANALYSIS_VERSION: u32 = 1;
+
 pub fn analyse(img: &RgbaImage, options: &AnalysisOptions) -> ImageInfo {
     let size = options.sample_size as u32;
     let (width, height) = img.dimensions();
 
     // Resize image as a simple way to get pixel data
-    let tiny_version = imageops::thumbnail(img, size, size);
-
-    let colors = tiny_version
-        .pixels()
-        .map(|p| {
-            let vals = p.channels();
-            ColorInfo::new(vals[0].to_owned(), vals[1].to_owned(), vals[2].to_owned())
-        })
-        .collect();
+    let tiny_version = options.resize().apply(img, size, size);
 
-    ImageInfo {
-        width,
-        height,
-        colors,
-    }
+    ImageInfo::sampled(width, height, &tiny_version, options)
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(deny_unknown_fields))]
 pub struct AnalysisOptions {
     pub sample_size: u8,
+    pub channel_weights: ChannelWeights,
+    /// Whether to store a summary of the colors along with the samples.
+    pub summarise: bool,
+    /// Whether to average colors in linear light rather than as encoded.
+    pub linear_light: bool,
+    /// Filter images are resized with to sample them.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub filter: ResizeFilter,
+    /// Whether to sample tiles over only the square drawn from them, and
+    /// cells over their whole area, so each sample of a cell lines up with
+    /// the part of a tile drawn over it.
+    pub matched_layout: bool,
 }
 
 impl AnalysisOptions {
     pub fn new(sample_size: Option<u8>) -> AnalysisOptions {
         Self {
             sample_size: sample_size.unwrap_or(SAMPLE_SIZE),
+            channel_weights: ChannelWeights::default(),
+            summarise: false,
+            linear_light: true,
+            filter: ResizeFilter::default(),
+            matched_layout: false,
+        }
+    }
+}
+
+impl AnalysisOptions {
+    /// How images are resized to be analysed.
+    pub(crate) fn resize(&self) -> Resize {
+        Resize::new(self.filter, self.linear_light)
+    }
+}
+
+/// Analyse a library image as a tile, over only the square drawn from it if
+/// sampling layouts are matched.
+pub(crate) fn analyse_tile(img: &RgbaImage, options: &AnalysisOptions) -> ImageInfo {
+    if !options.matched_layout {
+        return analyse(img, options);
+    }
+    let area = choose_tile_area(img.width(), img.height());
+    let tile = imageops::crop_imm(img, area.x, area.y, area.width, area.height);
+    analyse(&tile.to_image(), options)
+}
+
+/// How much a difference in each channel counts when comparing colors.
+///
+/// Parsed from `equal`, `luminance`, or custom `red,green,blue` weights.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "String", into = "String"))]
+pub struct ChannelWeights {
+    pub red: f64,
+    pub green: f64,
+    pub blue: f64,
+}
+
+impl ChannelWeights {
+    /// Every channel counts the same.
+    pub const EQUAL: ChannelWeights = ChannelWeights::new(1.0, 1.0, 1.0);
+    /// Channels count by their contribution to (Rec. 601) luminance.
+    pub const LUMINANCE: ChannelWeights = ChannelWeights::new(0.299, 0.587, 0.114);
+
+    pub const fn new(red: f64, green: f64, blue: f64) -> ChannelWeights {
+        Self { red, green, blue }
+    }
+}
+
+impl Default for ChannelWeights {
+    fn default() -> Self {
+        ChannelWeights::EQUAL
+    }
+}
+
+impl FromStr for ChannelWeights {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "equal" => return Ok(ChannelWeights::EQUAL),
+            "luminance" => return Ok(ChannelWeights::LUMINANCE),
+            _ => {}
+        }
+
+        let weights: Vec<f64> = s
+            .split(',')
+            .map(|w| w.trim().parse::<f64>())
+            .collect::<Result<_, _>>()
+            .map_err(|e| format!("Invalid channel weights '{s}': {e}"))?;
+        match weights[..] {
+            [red, green, blue] if weights.iter().all(|w| w.is_finite() && *w >= 0.0) => {
+                Ok(ChannelWeights::new(red, green, blue))
+            }
+            _ => Err(format!(
+                "Invalid channel weights '{s}': expected equal, luminance, or red,green,blue"
+            )),
+        }
+    }
+}
+
+impl TryFrom<String> for ChannelWeights {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<ChannelWeights> for String {
+    fn from(weights: ChannelWeights) -> Self {
+        if weights == ChannelWeights::EQUAL {
+            "equal".to_string()
+        } else if weights == ChannelWeights::LUMINANCE {
+            "luminance".to_string()
+        } else {
+            let ChannelWeights { red, green, blue } = weights;
+            format!("{red},{green},{blue}")
         }
     }
 }
 
 /// Data describing the image, suitable for comparison between images.
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct ImageInfo {
     width: u32,
     height: u32,
-    colors: Vec<ColorInfo>,
+    /// The red, green, and blue channels of each sample, one after another.
+    samples: Vec<u8>,
+    /// Coarse summary of the colors, if it was stored during analysis.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    summary: Option<Summary>,
+    /// Percentage taken off this image's differences from cells, making it
+    /// more likely to be chosen. Set for each build rather than stored.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    bonus: u8,
+    /// Percentage of this image's differences from cells counted, from the
+    /// weight of its folder. Set for each build rather than stored.
+    #[cfg_attr(feature = "serde", serde(skip, default = "full_cost"))]
+    cost_share: u32,
+    /// Points (in target pixels) in the cells this image is pinned to. Set
+    /// for each build rather than stored.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pins: Vec<(u32, u32)>,
+    /// Areas of the target this image must not be drawn in. Set for each
+    /// build rather than stored.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    exclusions: Vec<Rectangle>,
+}
+
+/// The cost share of an image whose folder isn't weighted.
+const FULL_COST: u32 = 100;
+
+#[cfg(feature = "serde")]
+fn full_cost() -> u32 {
+    FULL_COST
 }
 
 impl ImageInfo {
-    pub fn diff(&self, other: &ImageInfo) -> Vec<i32> {
-        let (this, that) = (&self.colors, &other.colors);
+    /// Describe an image of the given size from a copy of it already scaled
+    /// to its samples.
+    pub(crate) fn sampled<I>(width: u32, height: u32, tiny: &I, options: &AnalysisOptions) -> Self
+    where
+        I: GenericImageView<Pixel = Rgba<u8>>,
+    {
+        let samples: Vec<u8> = tiny
+            .pixels()
+            .flat_map(|(_, _, p)| {
+                let vals = p.channels();
+                [vals[0], vals[1], vals[2]]
+            })
+            .collect();
+
+        let summary = options.summarise.then(|| Summary::of(&samples));
+
+        ImageInfo {
+            width,
+            height,
+            samples,
+            summary,
+            bonus: 0,
+            cost_share: FULL_COST,
+            pins: Vec::new(),
+            exclusions: Vec::new(),
+        }
+    }
+
+    pub fn diff(&self, other: &ImageInfo, weights: &ChannelWeights) -> Vec<i64> {
+        assert!(self.samples.len() == other.samples.len());
+
+        self.colors()
+            .zip(other.colors())
+            .map(|(a, b)| a.weighted_sqr_diff(&b, weights))
+            .collect()
+    }
+
+    /// The total of `diff`, without allocating, for comparing many images.
+    ///
+    /// Weights are applied to the totals of each channel, rather than to
+    /// each sample, so fractional weights may round differently.
+    pub fn diff_sum(&self, other: &ImageInfo, weights: &ChannelWeights) -> i64 {
+        assert!(self.samples.len() == other.samples.len());
+
+        let sums = channel_sqr_diff_sums(&self.samples, &other.samples);
+        weigh_channels(sums, weights).round() as i64
+    }
+
+    /// `diff_sum`, or `None` as soon as the running total is more than the
+    /// threshold, for giving up on images that can't beat the best match so
+    /// far without comparing all of their samples.
+    pub fn diff_less_than(
+        &self,
+        other: &ImageInfo,
+        weights: &ChannelWeights,
+        threshold: i64,
+    ) -> Option<i64> {
+        assert!(self.samples.len() == other.samples.len());
+
+        let mut sums = [0u64; 3];
+        let chunks = self.samples.chunks(CHECKED_EVERY);
+        for (this, that) in chunks.zip(other.samples.chunks(CHECKED_EVERY)) {
+            for (sum, s) in sums.iter_mut().zip(channel_sqr_diff_sums(this, that)) {
+                *sum += s;
+            }
+            let total = weigh_channels(sums, weights).round() as i64;
+            if total > threshold {
+                return None;
+            }
+        }
+        Some(weigh_channels(sums, weights).round() as i64)
+    }
+
+    /// The difference of each channel of each sample between this and other.
+    pub fn residual(&self, other: &ImageInfo) -> Vec<[i32; 3]> {
+        assert!(self.samples.len() == other.samples.len());
+
+        self.samples
+            .chunks_exact(3)
+            .zip(other.samples.chunks_exact(3))
+            .map(|(a, b)| [0, 1, 2].map(|c| a[c] as i32 - b[c] as i32))
+            .collect()
+    }
+
+    /// A copy of this info with the given amounts added to the channels of
+    /// each sample, clamped to valid colors.
+    pub fn offset(&self, offsets: &[[i32; 3]]) -> ImageInfo {
+        assert!(self.samples.len() == offsets.len() * 3);
+
+        let samples = self
+            .samples
+            .iter()
+            .zip(offsets.iter().flatten())
+            .map(|(v, d)| (*v as i32 + d).clamp(0, 255) as u8)
+            .collect();
+
+        ImageInfo {
+            width: self.width,
+            height: self.height,
+            samples,
+            summary: None,
+            bonus: self.bonus,
+            cost_share: self.cost_share,
+            pins: self.pins.clone(),
+            exclusions: self.exclusions.clone(),
+        }
+    }
+
+    /// The red, green, and blue channels of each sample, one after another.
+    pub(crate) fn samples(&self) -> &[u8] {
+        &self.samples
+    }
+
+    /// Length (in pixels) of the shorter side of the image analysed, the
+    /// most a tile drawn from it can be across without scaling it up.
+    pub(crate) fn shortest_side(&self) -> u32 {
+        self.width.min(self.height)
+    }
+
+    /// Share of this image's differences from cells counted, from the
+    /// weight of its folder, where 1 counts them in full.
+    pub(crate) fn cost_share(&self) -> f64 {
+        f64::from(self.cost_share) / f64::from(FULL_COST)
+    }
+
+    /// Percentage taken off this image's differences from cells.
+    pub(crate) fn bonus(&self) -> u8 {
+        self.bonus
+    }
+
+    /// Take a percentage, up to 100, off this image's differences from
+    /// cells.
+    pub(crate) fn set_bonus(&mut self, percent: u8) {
+        self.bonus = percent.min(100);
+    }
+
+    /// The difference from a cell as counted for this image, scaled by the
+    /// weight of its folder.
+    pub(crate) fn weigh(&self, difference: i64) -> i64 {
+        if self.cost_share == FULL_COST {
+            difference
+        } else {
+            difference * i64::from(self.cost_share) / i64::from(FULL_COST)
+        }
+    }
 
-        assert!(this.len() == that.len());
+    /// Divide this image's differences from cells by the weight of its
+    /// folder, so that images in heavier folders are more likely chosen.
+    pub(crate) fn set_weight(&mut self, weight: f64) {
+        let share = (f64::from(FULL_COST) / weight).round();
+        self.cost_share = share.clamp(1.0, f64::from(u32::MAX)) as u32;
+    }
+
+    /// Pin this image to the cell holding the point (in target pixels).
+    pub(crate) fn pin(&mut self, at: (u32, u32)) {
+        self.pins.push(at);
+    }
+
+    /// Keep this image out of the area of the target.
+    pub(crate) fn exclude(&mut self, area: Rectangle) {
+        self.exclusions.push(area);
+    }
+
+    /// Whether this image is pinned to the cell.
+    pub(crate) fn is_pinned_to(&self, cell: &Rectangle) -> bool {
+        self.pins.iter().any(|at| cell.contains(*at))
+    }
+
+    /// Whether this image is kept out of any part of the cell.
+    pub(crate) fn is_excluded_from(&self, cell: &Rectangle) -> bool {
+        self.exclusions
+            .iter()
+            .any(|area| cell.intersect(area).is_some())
+    }
+
+    /// A coarse summary of the colors, stored or worked out from the samples.
+    pub fn summary(&self) -> Cow<'_, Summary> {
+        match &self.summary {
+            Some(summary) => Cow::Borrowed(summary),
+            None => Cow::Owned(Summary::of(&self.samples)),
+        }
+    }
+
+    /// The mean color of the samples.
+    pub(crate) fn mean_color(&self) -> [u8; 3] {
+        mean_color(&self.samples)
+    }
+
+    /// The color of each sample.
+    fn colors(&self) -> impl Iterator<Item = ColorInfo> + '_ {
+        self.samples
+            .chunks_exact(3)
+            .map(|c| ColorInfo::new(c[0], c[1], c[2]))
+    }
+}
+
+/// The mean color of some red, green, and blue samples.
+pub(crate) fn mean_color(samples: &[u8]) -> [u8; 3] {
+    let count = (samples.len() / 3).max(1);
+    let mut sums = [0usize; 3];
+    for color in samples.chunks_exact(3) {
+        for (sum, v) in sums.iter_mut().zip(color) {
+            *sum += usize::from(*v);
+        }
+    }
+    sums.map(|sum| ((sum + count / 2) / count) as u8)
+}
+
+/// Number of samples summed side by side when comparing images.
+const LANES: usize = 8;
+
+/// Number of channel values compared between checks of the running total
+/// against the threshold, a few runs of the lanes at a time.
+const CHECKED_EVERY: usize = 3 * LANES * 4;
 
-        let pairs: Vec<(&ColorInfo, &ColorInfo)> = this.iter().zip(that.iter()).collect();
+/// The total of the squared differences of each channel, each scaled by its
+/// weight.
+fn weigh_channels([red, green, blue]: [u64; 3], weights: &ChannelWeights) -> f64 {
+    weights.red * red as f64 + weights.green * green as f64 + weights.blue * blue as f64
+}
+
+/// Sum the squared differences of each channel of the paired samples.
+///
+/// The channels of several samples are summed side by side so the compiler
+/// can vectorise the loop. Each total covers at most 255² / 8 samples of up
+/// to 255², which fits in a `u32`.
+fn channel_sqr_diff_sums(this: &[u8], that: &[u8]) -> [u64; 3] {
+    let sqr = |a: u8, b: u8| u32::from(a.abs_diff(b)).pow(2);
+    let mut totals = [0u32; 3 * LANES];
+
+    let (these, those) = (this.chunks_exact(3 * LANES), that.chunks_exact(3 * LANES));
+    let remainder = these.remainder().iter().zip(those.remainder());
+    for (a, b) in these.zip(those) {
+        for ((total, a), b) in totals.iter_mut().zip(a).zip(b) {
+            *total += sqr(*a, *b);
+        }
+    }
+    for (i, (a, b)) in remainder.enumerate() {
+        totals[i] += sqr(*a, *b);
+    }
 
-        pairs.iter().map(|(a, b)| a.sqr_diff(b)).collect()
+    let mut sums = [0u64; 3];
+    for (i, total) in totals.iter().enumerate() {
+        sums[i % 3] += u64::from(*total);
     }
+    sums
 }
 
 /// Data describing the color of a pixel.
 #[derive(PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct ColorInfo {
     red: u8,
     blue: u8,
@@ -79,7 +466,7 @@ impl ColorInfo {
 
     #[allow(dead_code)]
     fn abs_diff(&self, other: &ColorInfo) -> i32 {
-        let df = |a, b| num::abs(a - b);
+        let df = |a: i32, b: i32| (a - b).abs();
         df(self.red as i32, other.red as i32)
             + df(self.green as i32, other.green as i32)
             + df(self.blue as i32, other.blue as i32)
@@ -87,14 +474,24 @@ impl ColorInfo {
 
     #[allow(dead_code)]
     fn sqr_diff(&self, other: &ColorInfo) -> i32 {
-        let df = |a, b| num::pow(a - b, 2);
+        let df = |a: i32, b: i32| (a - b).pow(2);
         df(self.red as i32, other.red as i32)
             + df(self.green as i32, other.green as i32)
             + df(self.blue as i32, other.blue as i32)
     }
+
+    /// Squared difference with each channel scaled by its weight.
+    fn weighted_sqr_diff(&self, other: &ColorInfo, weights: &ChannelWeights) -> i64 {
+        let df = |a: u8, b: u8| f64::from(a.abs_diff(b)).powi(2);
+        let weighted = weights.red * df(self.red, other.red)
+            + weights.green * df(self.green, other.green)
+            + weights.blue * df(self.blue, other.blue);
+        weighted.round() as i64
+    }
 }
 
 #[cfg(test)]
+#[allow(clippy::identity_op)]
 mod test {
     use super::*;
 
@@ -132,11 +529,79 @@ mod test {
             ImageInfo {
                 width: size,
                 height: size,
-                colors: vec![ctx.black],
+                samples: vec![ctx.black.red, ctx.black.green, ctx.black.blue],
+                summary: None,
+                bonus: 0,
+                cost_share: FULL_COST,
+                pins: Vec::new(),
+                exclusions: Vec::new(),
             }
         );
     }
 
+    #[test]
+    fn test_samples_only_the_drawn_square_of_tiles_with_matched_layout() {
+        // Red, green, and blue thirds, of which the green is drawn.
+        let img = RgbaImage::from_fn(30, 10, |x, _| match x / 10 {
+            0 => image::Rgba([255, 0, 0, 255]),
+            1 => image::Rgba([0, 255, 0, 255]),
+            _ => image::Rgba([0, 0, 255, 255]),
+        });
+        let whole = AnalysisOptions {
+            linear_light: false,
+            ..AnalysisOptions::new(Some(1))
+        };
+        let matched = AnalysisOptions {
+            matched_layout: true,
+            ..whole
+        };
+
+        assert_eq!(analyse_tile(&img, &whole), analyse(&img, &whole));
+        assert_eq!(analyse_tile(&img, &matched).samples, vec![0, 255, 0]);
+    }
+
+    // Analysis is written as JSON in library indexes.
+    #[cfg(feature = "index")]
+    #[test]
+    fn test_round_trips_analysis_through_json() {
+        let options = AnalysisOptions {
+            channel_weights: ChannelWeights::new(0.5, 1.0, 0.25),
+            summarise: true,
+            ..AnalysisOptions::new(Some(2))
+        };
+        let info = analyse(
+            &RgbaImage::from_pixel(4, 4, image::Rgba([1, 2, 3, 255])),
+            &options,
+        );
+        let round_trip = |json: String| serde_json::from_str::<(AnalysisOptions, ImageInfo)>(&json);
+
+        let json = serde_json::to_string(&(options, &info)).unwrap();
+
+        assert!(json.contains(r#""channel_weights":"0.5,1,0.25""#));
+        assert_eq!(round_trip(json).unwrap(), (options, info));
+        let luminance = serde_json::to_string(&ChannelWeights::LUMINANCE).unwrap();
+        assert_eq!(luminance, r#""luminance""#);
+    }
+
+    #[test]
+    fn test_averages_samples_in_linear_light_unless_disabled() {
+        let checks = RgbaImage::from_fn(4, 4, |x, y| {
+            let v = if (x + y) % 2 == 0 { 255 } else { 0 };
+            image::Rgba([v, v, v, 255])
+        });
+        let linear = AnalysisOptions::new(Some(1));
+        let encoded = AnalysisOptions {
+            linear_light: false,
+            ..AnalysisOptions::new(Some(1))
+        };
+
+        assert_eq!(analyse(&checks, &linear).samples, vec![188, 188, 188]);
+        assert!(analyse(&checks, &encoded)
+            .samples
+            .iter()
+            .all(|v| v.abs_diff(128) <= 1));
+    }
+
     #[test]
     fn test_absolute_image_color_difference() {
         let ctx = setup();
@@ -177,6 +642,38 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_weighted_image_color_difference() {
+        let ctx = setup();
+        let equal = ChannelWeights::EQUAL;
+        assert_eq!(
+            ctx.black.weighted_sqr_diff(&ctx.grey, &equal),
+            i64::from(ctx.black.sqr_diff(&ctx.grey))
+        );
+
+        let luminance = ChannelWeights::LUMINANCE;
+        assert!(
+            ctx.black.weighted_sqr_diff(&ctx.blue, &luminance)
+                > ctx.black.weighted_sqr_diff(&ctx.red, &luminance)
+        );
+        let custom = ChannelWeights::new(2.0, 0.0, 0.0);
+        assert_eq!(
+            ctx.black.weighted_sqr_diff(&ctx.red, &custom),
+            2 * 255 * 255
+        );
+        assert_eq!(ctx.black.weighted_sqr_diff(&ctx.blue, &custom), 0);
+    }
+
+    #[test]
+    fn test_parses_channel_weights() {
+        assert_eq!("equal".parse(), Ok(ChannelWeights::EQUAL));
+        assert_eq!("luminance".parse(), Ok(ChannelWeights::LUMINANCE));
+        assert_eq!("1, 2,0.5".parse(), Ok(ChannelWeights::new(1.0, 2.0, 0.5)));
+        assert!("1,2".parse::<ChannelWeights>().is_err());
+        assert!("1,-2,3".parse::<ChannelWeights>().is_err());
+        assert!("bright".parse::<ChannelWeights>().is_err());
+    }
+
     #[test]
     fn test_returns_zero_diffs_for_identical_images() {
         let size = 100;
@@ -188,11 +685,70 @@ mod test {
         let result1 = analyse(&img1, &opts);
         let result2 = analyse(&img2, &opts);
 
-        let diffs = result1.diff(&result2);
+        let diffs = result1.diff(&result2, &ChannelWeights::default());
 
         assert_eq!(diffs, vec![0, 0, 0, 0]);
     }
 
+    #[test]
+    fn test_offsets_by_residual() {
+        let opts = AnalysisOptions::new(Some(1));
+        let dark = analyse(
+            &RgbaImage::from_pixel(10, 10, image::Rgba([10, 20, 30, 255])),
+            &opts,
+        );
+        let light = analyse(
+            &RgbaImage::from_pixel(10, 10, image::Rgba([250, 20, 0, 255])),
+            &opts,
+        );
+
+        let residual = light.residual(&dark);
+
+        assert_eq!(residual, vec![[240, 0, -30]]);
+        assert_eq!(dark.offset(&residual), light);
+        assert_eq!(
+            dark.offset(&[[-20, 300, 0]]).colors().collect::<Vec<_>>(),
+            vec![ColorInfo::new(0, 255, 30)]
+        );
+    }
+
+    #[test]
+    fn test_sums_diffs_without_allocating() {
+        let opts = AnalysisOptions::new(Some(5));
+        let gradient = |offset: u32| {
+            RgbaImage::from_fn(50, 50, |x, y| {
+                image::Rgba([(x * 5) as u8, (y * 5 + offset) as u8, (x + y) as u8, 255])
+            })
+        };
+        let result1 = analyse(&gradient(0), &opts);
+        let result2 = analyse(&gradient(7), &opts);
+
+        for weights in [ChannelWeights::EQUAL, ChannelWeights::new(2.0, 0.0, 3.0)] {
+            let expected: i64 = result1.diff(&result2, &weights).iter().sum();
+            assert_eq!(result1.diff_sum(&result2, &weights), expected);
+        }
+        assert_eq!(result1.diff_sum(&result1, &ChannelWeights::EQUAL), 0);
+    }
+
+    #[test]
+    fn test_gives_up_diffs_past_the_threshold() {
+        let opts = AnalysisOptions::new(Some(20));
+        let gradient = |offset: u32| {
+            RgbaImage::from_fn(50, 50, |x, y| {
+                image::Rgba([(x * 5) as u8, (y * 5 + offset) as u8, (x + y) as u8, 255])
+            })
+        };
+        let (a, b) = (analyse(&gradient(0), &opts), analyse(&gradient(30), &opts));
+        let weights = ChannelWeights::LUMINANCE;
+        let full = a.diff_sum(&b, &weights);
+
+        assert_eq!(a.diff_less_than(&b, &weights, full), Some(full));
+        assert_eq!(a.diff_less_than(&b, &weights, i64::MAX), Some(full));
+        assert_eq!(a.diff_less_than(&b, &weights, full - 1), None);
+        assert_eq!(a.diff_less_than(&b, &weights, 0), None);
+        assert_eq!(a.diff_less_than(&a, &weights, 0), Some(0));
+    }
+
     #[test]
     fn test_returns_diff_of_each_sample() {
         let size = 100;
@@ -204,7 +760,7 @@ mod test {
         let result1 = analyse(&img1, &opts);
         let result2 = analyse(&img2, &opts);
 
-        let diffs = result1.diff(&result2);
+        let diffs = result1.diff(&result2, &ChannelWeights::default());
 
         assert_eq!(diffs.len(), 4);
         for d in diffs {
@@ -0,0 +1,230 @@
+use std::io::{Cursor, Error as IoError, ErrorKind, Result as IoResult};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Instant;
+
+use image::io::Reader;
+use image::{ImageError, RgbaImage};
+use tokio::sync::Semaphore;
+use tokio::task::{spawn_blocking, JoinSet};
+
+use crate::analysis::{analyse_tile, AnalysisOptions, ImageInfo};
+use crate::{
+    color, finish, heif, library, plan_with_library, strategy_options, CancelToken, LoadedLibrary,
+    MosaicOptions, MosaicResult,
+};
+
+/// Number of library images read and analysed at once.
+const CONCURRENCY: usize = 16;
+
+/// Build a mosaic as `mosaic_cancellable` does, without
+/// blocking the async runtime it is called from.
+///
+/// Images are read asynchronously, a few at a time, while decoding,
+/// analysing, and drawing run on the runtime's blocking threads. Must be
+/// called within a Tokio runtime.
+pub async fn mosaic_async(
+    target_path: &Path,
+    lib_dirs: &[PathBuf],
+    options: &MosaicOptions,
+    cancel: &CancelToken,
+) -> IoResult<MosaicResult> {
+    let target = read_image(target_path.to_owned()).await?;
+    let library = load_library_async(lib_dirs, options, cancel).await?;
+    let images = Arc::new(library.images);
+    let mut result = mosaic_with_library_async(target, images, options, cancel).await?;
+    result.stats.refused = library.refused;
+    Ok(result)
+}
+
+/// Find and analyse the images in the given libraries, reusing the stored
+/// analysis of prepared or indexed libraries, for building many mosaics from.
+/// Images too large to decode safely are left out with the reason why.
+pub async fn load_library_async(
+    lib_dirs: &[PathBuf],
+    options: &MosaicOptions,
+    cancel: &CancelToken,
+) -> IoResult<LoadedLibrary> {
+    let analysis = strategy_options(options).analysis;
+    let mut loaded = LoadedLibrary::default();
+    for lib_dir in lib_dirs {
+        let (lib_dir, stored_cancel) = (lib_dir.clone(), cancel.clone());
+        let filter = options.filter.clone();
+        // Listing a library may download its images, so is done on a
+        // blocking thread, as is reading any analysis stored with it.
+        let (stored, unanalysed) = blocking(move || {
+            let library = library::open(&lib_dir, &filter)?;
+            if library.stores_analysis() {
+                Ok((library.load(&analysis, &stored_cancel)?, Vec::new()))
+            } else {
+                Ok((LoadedLibrary::default(), library.images()?))
+            }
+        })
+        .await?;
+        loaded.extend(stored);
+        loaded.extend(analyse_paths(unanalysed, analysis, cancel).await?);
+    }
+    Ok(loaded)
+}
+
+/// Build a mosaic of the target from an already analysed library, without
+/// blocking the async runtime it is called from.
+pub async fn mosaic_with_library_async(
+    target: RgbaImage,
+    library: Arc<Vec<(PathBuf, ImageInfo)>>,
+    options: &MosaicOptions,
+    cancel: &CancelToken,
+) -> IoResult<MosaicResult> {
+    let (options, cancel) = (options.clone(), cancel.clone());
+    blocking(move || {
+        let started = Instant::now();
+        let plan = plan_with_library(target, &library, &options, &cancel)?;
+        finish(plan, started.elapsed(), &options, &cancel, None)
+    })
+    .await
+}
+
+/// Analyse the images, reading a few at a time, returning the analysis of
+/// those that could be read in the order of the paths. Images too large to
+/// decode safely are left out with the reason why.
+async fn analyse_paths(
+    paths: Vec<PathBuf>,
+    options: AnalysisOptions,
+    cancel: &CancelToken,
+) -> IoResult<LoadedLibrary> {
+    let permits = Arc::new(Semaphore::new(CONCURRENCY));
+    let mut tasks = JoinSet::new();
+    for (i, path) in paths.into_iter().enumerate() {
+        let (permits, cancel) = (permits.clone(), cancel.clone());
+        tasks.spawn(async move {
+            let _permit = permits.acquire_owned().await.ok()?;
+            if cancel.is_cancelled() {
+                return None;
+            }
+            let bytes = tokio::fs::read(&path).await.ok()?;
+            let named = path.clone();
+            let info = blocking(move || Ok(analyse_tile(&decode(&named, bytes)?, &options))).await;
+            match info {
+                Ok(info) => Some((i, Ok((path, info)))),
+                Err(e) if e.kind() == ErrorKind::OutOfMemory => {
+                    Some((i, Err((path, e.to_string()))))
+                }
+                Err(_) => None,
+            }
+        });
+    }
+
+    let mut analysed = Vec::new();
+    while let Some(task) = tasks.join_next().await {
+        analysed.extend(task.ok().flatten());
+    }
+    cancel.check()?;
+
+    // Keep the order of the paths, whichever task finished first.
+    analysed.sort_by_key(|(i, _)| *i);
+    let mut library = LoadedLibrary::default();
+    for (_, image) in analysed {
+        match image {
+            Ok(image) => library.images.push(image),
+            Err(refused) => library.refused.push(refused),
+        }
+    }
+    Ok(library)
+}
+
+/// Read an image file asynchronously, decoding it on a blocking thread.
+async fn read_image(path: PathBuf) -> IoResult<RgbaImage> {
+    let bytes = tokio::fs::read(&path).await?;
+    blocking(move || decode(&path, bytes)).await
+}
+
+/// Decode the bytes of the image read from the path, failing with an
+/// `OutOfMemory` error naming the file if it is too large to decode safely,
+/// or an `Unsupported` one if it is in a format this build can't decode.
+fn decode(path: &Path, bytes: Vec<u8>) -> IoResult<RgbaImage> {
+    let decoded = if heif::sniff(&bytes).is_some() {
+        #[cfg(feature = "heif")]
+        {
+            heif::decode(&bytes)
+        }
+        #[cfg(not(feature = "heif"))]
+        return Err(heif::unsupported(path).unwrap_or_else(|| ErrorKind::Unsupported.into()));
+    } else {
+        color::decode(Reader::new(Cursor::new(bytes)))
+    };
+    decoded.map_err(|e| match color::name_refused(path, e) {
+        ImageError::IoError(e) => e,
+        e => IoError::other(e),
+    })
+}
+
+/// Run the work on the runtime's blocking threads.
+async fn blocking<T, F>(work: F) -> IoResult<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> IoResult<T> + Send + 'static,
+{
+    spawn_blocking(work).await.map_err(IoError::other)?
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_support::fixture;
+    use crate::{load_library, mosaic};
+    use tokio::runtime::{Builder, Runtime};
+
+    fn runtime() -> Runtime {
+        Builder::new_current_thread().enable_all().build().unwrap()
+    }
+
+    fn options() -> MosaicOptions {
+        MosaicOptions {
+            analysis_size: 2,
+            cell_size: 10,
+            tile_size: 10,
+            ..MosaicOptions::default()
+        }
+    }
+
+    #[test]
+    fn test_builds_the_same_mosaic_as_blocking_builds() {
+        let (target, libraries) = (fixture("target.png"), [fixture("library")]);
+        let options = options();
+
+        let built = runtime()
+            .block_on(mosaic_async(
+                &target,
+                &libraries,
+                &options,
+                &CancelToken::new(),
+            ))
+            .unwrap();
+
+        let blocking = mosaic(&target, &libraries, &options).unwrap();
+        assert_eq!(built.image, blocking.image);
+        assert_eq!(built.placements, blocking.placements);
+    }
+
+    #[test]
+    fn test_analyses_libraries_in_order() {
+        let libraries = [fixture("library")];
+        let options = options();
+        let cancel = CancelToken::new();
+
+        let analysed = runtime()
+            .block_on(load_library_async(&libraries, &options, &cancel))
+            .unwrap();
+
+        let analysis = strategy_options(&options).analysis;
+        assert_eq!(
+            analysed,
+            load_library(&libraries, &options.filter, &analysis, &cancel).unwrap()
+        );
+        cancel.cancel();
+        let error = runtime()
+            .block_on(load_library_async(&libraries, &options, &cancel))
+            .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Interrupted);
+    }
+}
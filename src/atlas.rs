@@ -0,0 +1,150 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use image::{imageops, ImageResult, RgbaImage};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::core::{Dimensions, PixelRegion};
+use crate::resize::Resize;
+use crate::{at_size, load_image};
+
+/// The distinct tiles of a mosaic packed into one image, with an index of
+/// where each cell's tile is in it.
+pub struct Atlas {
+    pub image: RgbaImage,
+    pub index: AtlasIndex,
+}
+
+/// Description of the layout of a mosaic in terms of an atlas image.
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct AtlasIndex {
+    /// Width of the mosaic.
+    pub width: u32,
+    /// Height of the mosaic.
+    pub height: u32,
+    /// The tiles in the atlas image.
+    pub tiles: Vec<AtlasTile>,
+    /// The cells of the mosaic and which tile to draw in them.
+    pub cells: Vec<AtlasCell>,
+}
+
+/// A tile's library image and where it is in the atlas image.
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct AtlasTile {
+    pub source: PathBuf,
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// A cell of the mosaic and the index of its tile in `AtlasIndex::tiles`.
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct AtlasCell {
+    pub x: i64,
+    pub y: i64,
+    pub width: u32,
+    pub height: u32,
+    pub tile: usize,
+}
+
+/// Build an atlas of the distinct tiles of the mosaic.
+pub fn build_atlas(
+    (width, height): Dimensions,
+    tiles: &[(PathBuf, PixelRegion)],
+    resize: Resize,
+) -> ImageResult<Atlas> {
+    let index = index_atlas((width, height), tiles);
+
+    let (atlas_width, atlas_height) = atlas_size(&index.tiles);
+    let mut image = RgbaImage::new(atlas_width, atlas_height);
+    for tile in &index.tiles {
+        let img = load_image(&tile.source)?;
+        let thumb = at_size(img, tile.width, tile.height, resize);
+        imageops::overlay(&mut image, &thumb, tile.x.into(), tile.y.into());
+    }
+
+    Ok(Atlas { image, index })
+}
+
+/// Lay out the distinct tiles in a square grid of equally sized slots, big
+/// enough for the largest region.
+fn index_atlas((width, height): Dimensions, tiles: &[(PathBuf, PixelRegion)]) -> AtlasIndex {
+    let slot_width = tiles.iter().map(|(_, r)| r.width).max().unwrap_or(0);
+    let slot_height = tiles.iter().map(|(_, r)| r.height).max().unwrap_or(0);
+
+    let mut sources: Vec<&PathBuf> = Vec::new();
+    let mut positions: HashMap<&PathBuf, usize> = HashMap::new();
+    let cells = tiles
+        .iter()
+        .map(|(path, region)| {
+            let tile = *positions.entry(path).or_insert_with(|| {
+                sources.push(path);
+                sources.len() - 1
+            });
+            AtlasCell {
+                x: region.x,
+                y: region.y,
+                width: region.width,
+                height: region.height,
+                tile,
+            }
+        })
+        .collect();
+
+    let columns = (sources.len() as f64).sqrt().ceil() as u32;
+    let tiles = sources
+        .iter()
+        .enumerate()
+        .map(|(i, source)| AtlasTile {
+            source: source.to_path_buf(),
+            x: (i as u32 % columns) * slot_width,
+            y: (i as u32 / columns) * slot_height,
+            width: slot_width,
+            height: slot_height,
+        })
+        .collect();
+
+    AtlasIndex {
+        width,
+        height,
+        tiles,
+        cells,
+    }
+}
+
+/// The size of the image needed to hold the given tiles.
+fn atlas_size(tiles: &[AtlasTile]) -> Dimensions {
+    let width = tiles.iter().map(|t| t.x + t.width).max().unwrap_or(0);
+    let height = tiles.iter().map(|t| t.y + t.height).max().unwrap_or(0);
+    (width, height)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_packs_each_distinct_tile_once() {
+        let (a, b, c) = (PathBuf::from("a"), PathBuf::from("b"), PathBuf::from("c"));
+        let tiles = vec![
+            (a.clone(), PixelRegion::new(0, 0, 10, 10)),
+            (b.clone(), PixelRegion::new(10, 0, 10, 10)),
+            (a.clone(), PixelRegion::new(0, 10, 10, 10)),
+            (c.clone(), PixelRegion::new(10, 10, 10, 10)),
+        ];
+
+        let index = index_atlas((20, 20), &tiles);
+
+        let slots: Vec<(&PathBuf, u32, u32)> =
+            index.tiles.iter().map(|t| (&t.source, t.x, t.y)).collect();
+        assert_eq!(slots, vec![(&a, 0, 0), (&b, 10, 0), (&c, 0, 10)]);
+        let cell_tiles: Vec<usize> = index.cells.iter().map(|c| c.tile).collect();
+        assert_eq!(cell_tiles, vec![0, 1, 0, 2]);
+        assert_eq!(atlas_size(&index.tiles), (20, 20));
+    }
+}
@@ -0,0 +1,202 @@
+use std::str::FromStr;
+
+use image::imageops::{self, FilterType};
+use image::{Pixel, Rgba, RgbaImage};
+#[cfg(feature = "serde")]
+use serde::Deserialize;
+
+use crate::mask::Color;
+
+/// Default spread (in target pixels) of the blur for a blurred background.
+const DEFAULT_BLUR: f32 = 10.0;
+
+/// What to fill any part of the mosaic no tile covers with, such as the
+/// margin left where the cells don't fit the target, gaps between tiles, or
+/// cells left out by a mask. It is laid beneath the tiles, showing through
+/// where they are transparent.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "String"))]
+pub enum Background {
+    /// A single color.
+    Color(Color),
+    /// The average color of the target around each uncovered pixel, so that
+    /// margins and gaps blend into the picture.
+    Average,
+    /// A vertical gradient from the first color at the top to the second at
+    /// the bottom.
+    Gradient(Color, Color),
+    /// The target enlarged and blurred by the given spread (in target
+    /// pixels).
+    Blurred(f32),
+}
+
+impl FromStr for Background {
+    type Err = String;
+
+    /// Parse a background written as `#rrggbb`, `average`,
+    /// `gradient:#rrggbb:#rrggbb`, or `blurred[:spread]`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!("Invalid background '{s}': expected #rrggbb, average, gradient:#rrggbb:#rrggbb, or blurred[:spread]")
+        };
+        let parts: Vec<&str> = s.split(':').collect();
+        match parts[..] {
+            ["average"] => Ok(Background::Average),
+            ["gradient", top, bottom] => Ok(Background::Gradient(
+                top.parse().map_err(|_| invalid())?,
+                bottom.parse().map_err(|_| invalid())?,
+            )),
+            ["blurred"] => Ok(Background::Blurred(DEFAULT_BLUR)),
+            ["blurred", spread] => match spread.parse::<f32>() {
+                Ok(spread) if spread.is_finite() && spread >= 0.0 => {
+                    Ok(Background::Blurred(spread))
+                }
+                _ => Err(invalid()),
+            },
+            [color] => color.parse().map(Background::Color).map_err(|_| invalid()),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl TryFrom<String> for Background {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// Fill in the transparent parts of the mosaic with the background, taking
+/// average colors from the target over areas of about the cell size.
+pub(crate) fn fill(
+    img: &mut RgbaImage,
+    background: Background,
+    target: &RgbaImage,
+    cell_size: u32,
+) {
+    let (width, height) = img.dimensions();
+    match background {
+        Background::Color(Color(color)) => lay_under(img, |_, _| color),
+        Background::Average => {
+            let (target_width, target_height) = target.dimensions();
+            let cell_size = cell_size.max(1);
+            let averages = imageops::resize(
+                target,
+                target_width.div_ceil(cell_size).max(1),
+                target_height.div_ceil(cell_size).max(1),
+                FilterType::Triangle,
+            );
+            let local = imageops::resize(&averages, width, height, FilterType::Triangle);
+            lay_under(img, |x, y| local.get_pixel(x, y).to_rgb().0);
+        }
+        Background::Gradient(Color(top), Color(bottom)) => {
+            let share = |y: u32| f64::from(y) / f64::from(height.saturating_sub(1).max(1));
+            lay_under(img, |_, y| {
+                let t = share(y);
+                [0, 1, 2].map(|c| {
+                    let (a, b) = (f64::from(top[c]), f64::from(bottom[c]));
+                    (a + (b - a) * t).round() as u8
+                })
+            });
+        }
+        Background::Blurred(spread) => {
+            let blurred = imageops::blur(target, spread);
+            let enlarged = imageops::resize(&blurred, width, height, FilterType::Triangle);
+            lay_under(img, |x, y| enlarged.get_pixel(x, y).to_rgb().0);
+        }
+    }
+}
+
+/// Lay an opaque layer of the given color at each pixel beneath the image.
+fn lay_under<F: Fn(u32, u32) -> [u8; 3]>(img: &mut RgbaImage, color: F) {
+    for (x, y, pixel) in img.enumerate_pixels_mut() {
+        if pixel[3] == u8::MAX {
+            continue;
+        }
+        let [r, g, b] = color(x, y);
+        let mut filled = Rgba([r, g, b, u8::MAX]);
+        filled.blend(pixel);
+        *pixel = filled;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_fills_with_local_target_colors() {
+        assert_eq!("average".parse(), Ok(Background::Average));
+        assert_eq!(
+            "#102030".parse(),
+            Ok(Background::Color(Color([16, 32, 48])))
+        );
+        assert!("mean".parse::<Background>().is_err());
+        assert_eq!(
+            "gradient:#000000:#ffffff".parse(),
+            Ok(Background::Gradient(
+                Color([0, 0, 0]),
+                Color([255, 255, 255])
+            ))
+        );
+        assert_eq!("blurred".parse(), Ok(Background::Blurred(DEFAULT_BLUR)));
+        assert_eq!("blurred:3.5".parse(), Ok(Background::Blurred(3.5)));
+        assert!("blurred:-1".parse::<Background>().is_err());
+        assert!("gradient:#000000".parse::<Background>().is_err());
+
+        // Red on the left, blue on the right, drawn at twice the size with
+        // only the top left covered.
+        let target = RgbaImage::from_fn(40, 20, |x, _| {
+            Rgba(if x < 20 {
+                [255, 0, 0, 255]
+            } else {
+                [0, 0, 255, 255]
+            })
+        });
+        let mut img = RgbaImage::new(80, 40);
+        img.put_pixel(0, 0, Rgba([0, 255, 0, 255]));
+
+        fill(&mut img, Background::Average, &target, 20);
+
+        assert_eq!(*img.get_pixel(0, 0), Rgba([0, 255, 0, 255]));
+        let (left, right) = (img.get_pixel(5, 30), img.get_pixel(75, 30));
+        assert!(left[0] > 200 && left[2] < 55 && left[3] == 255);
+        assert!(right[2] > 200 && right[0] < 55 && right[3] == 255);
+    }
+
+    #[test]
+    fn test_fills_with_blurred_target() {
+        let target = RgbaImage::from_fn(20, 10, |x, _| {
+            Rgba(if x < 10 {
+                [255, 0, 0, 255]
+            } else {
+                [0, 0, 255, 255]
+            })
+        });
+        let mut img = RgbaImage::new(40, 20);
+
+        fill(&mut img, Background::Blurred(4.0), &target, 5);
+
+        let (edge, seam) = (img.get_pixel(0, 10), img.get_pixel(20, 10));
+        assert!(edge[0] > edge[2] && edge[3] == 255);
+        assert!(seam[0] > 64 && seam[2] > 64);
+    }
+
+    #[test]
+    fn test_fills_with_gradients() {
+        let mut img = RgbaImage::new(2, 3);
+        img.put_pixel(1, 1, Rgba([0, 255, 0, 255]));
+        let (black, white) = (Color([0, 0, 0]), Color([255, 255, 255]));
+
+        let target = RgbaImage::new(1, 1);
+
+        fill(&mut img, Background::Gradient(black, white), &target, 1);
+
+        assert_eq!(*img.get_pixel(0, 0), Rgba([0, 0, 0, 255]));
+        assert_eq!(*img.get_pixel(0, 1), Rgba([128, 128, 128, 255]));
+        assert_eq!(*img.get_pixel(1, 1), Rgba([0, 255, 0, 255]));
+        assert_eq!(*img.get_pixel(1, 2), Rgba([255, 255, 255, 255]));
+    }
+}
@@ -0,0 +1,122 @@
+use std::collections::HashSet;
+use std::io::{Error as IoError, ErrorKind, Result as IoResult};
+use std::path::{Path, PathBuf};
+
+use image::{ImageFormat, RgbaImage};
+
+use crate::{
+    find_paths, load_image, load_library, plan_with_library, render, strategy_options, CancelToken,
+    MosaicOptions,
+};
+
+/// Build a mosaic of each target from the same libraries, passing each
+/// build to `on_build` along with its target.
+///
+/// The libraries are only analysed once for all the targets, and a target
+/// that can't be built doesn't stop the rest.
+pub fn mosaic_batch<P, F>(
+    targets: &[PathBuf],
+    lib_dirs: &[P],
+    options: &MosaicOptions,
+    mut on_build: F,
+) -> IoResult<()>
+where
+    P: AsRef<Path>,
+    F: FnMut(&Path, IoResult<RgbaImage>),
+{
+    let cancel = CancelToken::new();
+    let library = load_library(
+        lib_dirs,
+        &options.filter,
+        &strategy_options(options).analysis,
+        &cancel,
+    )?;
+    for target_path in targets {
+        let build = load_image(target_path)
+            .map_err(IoError::other)
+            .and_then(|target| plan_with_library(target, &library.images, options, &cancel))
+            .and_then(|plan| render(plan, options, &cancel));
+        on_build(target_path, build);
+    }
+    Ok(())
+}
+
+/// The images in a directory of targets, in name order.
+///
+/// Fails if two targets have the same name but for their extension, since
+/// their mosaics would be written to the same file.
+pub fn find_targets(dir: &Path) -> IoResult<Vec<PathBuf>> {
+    let mut targets: Vec<PathBuf> = find_paths(dir)?
+        .into_iter()
+        .filter(|p| p.is_file() && ImageFormat::from_path(p).is_ok())
+        .collect();
+    targets.sort();
+    let mut stems = HashSet::new();
+    for target in &targets {
+        if !stems.insert(target.file_stem()) {
+            let message = format!("Targets must have distinct names: {}", target.display());
+            return Err(IoError::new(ErrorKind::InvalidInput, message));
+        }
+    }
+    Ok(targets)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_support::{fixture, scratch_dir};
+    use image::{DynamicImage, Rgba};
+    use std::fs::{remove_dir_all, write};
+
+    #[test]
+    fn test_builds_each_target_from_one_library() {
+        let options = MosaicOptions {
+            analysis_size: 2,
+            cell_size: 10,
+            tile_size: 10,
+            ..MosaicOptions::default()
+        };
+        let targets = [
+            fixture("target.png"),
+            fixture("missing.png"),
+            fixture("mask.png"),
+        ];
+
+        let mut builds = Vec::new();
+        mosaic_batch(
+            &targets,
+            &[fixture("library")],
+            &options,
+            |target, build| builds.push((target.to_owned(), build.map(|image| image.dimensions()))),
+        )
+        .unwrap();
+
+        assert_eq!(builds.len(), 3);
+        assert_eq!(builds[0].0, targets[0]);
+        assert!(builds[0].1.is_ok());
+        assert!(builds[1].1.is_err());
+        assert!(builds[2].1.is_ok());
+    }
+
+    #[test]
+    fn test_finds_targets_in_name_order() {
+        let dir = scratch_dir("batch-targets");
+        let pixel = RgbaImage::from_pixel(1, 1, Rgba([0, 0, 0, 255]));
+        for name in ["b.png", "a.png"] {
+            pixel.save(dir.join(name)).unwrap();
+        }
+        write(dir.join("notes.txt"), "not an image").unwrap();
+
+        assert_eq!(
+            find_targets(&dir).unwrap(),
+            vec![dir.join("a.png"), dir.join("b.png")]
+        );
+        DynamicImage::from(pixel)
+            .to_rgb8()
+            .save(dir.join("a.jpg"))
+            .unwrap();
+        let error = find_targets(&dir).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+        remove_dir_all(dir).unwrap();
+    }
+}
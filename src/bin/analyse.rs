@@ -0,0 +1,98 @@
+use clap::Parser;
+use std::io::Error as IoError;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::Instant;
+use tiler::{index_library, library_images, migrate_index, Failure, MosaicOptions};
+
+/// Analyse directories of library images into an index file that mosaics can use instead
+#[derive(Parser)]
+struct Args {
+    /// Index file to write, ending in .idx
+    index: PathBuf,
+    /// Directories of library images, or manifests of image URLs, to analyse
+    #[arg(required_unless_present_any = ["library_list", "migrate"])]
+    tiles_dirs: Vec<PathBuf>,
+    /// File listing library image paths or URLs one per line, or - to read paths from stdin
+    #[arg(long)]
+    library_list: Vec<PathBuf>,
+    /// Number of samples along each side when analysing images
+    #[arg(long, default_value_t = MosaicOptions::default().analysis_size)]
+    analysis_size: u8,
+    /// Store a tile of each image in the index, so it can be used without the images
+    #[arg(long)]
+    thumbnails: bool,
+    /// Size (in pixels) of the stored tiles
+    #[arg(long, default_value_t = MosaicOptions::default().tile_size, requires = "thumbnails")]
+    tile_size: u32,
+    /// Rewrite an index written by an earlier version in the current format, instead of analysing
+    #[arg(long, conflicts_with_all = ["tiles_dirs", "library_list", "analysis_size", "thumbnails"])]
+    migrate: bool,
+}
+
+/// Analyse a library
+///
+/// # Usage
+///
+/// analyse lib.idx <tiles_dir>...
+/// analyse --thumbnails --library-list photos.txt lib.idx
+/// analyse --migrate lib.idx
+///
+/// The index can then be used as a mosaic's tiles directory, or with
+/// `mosaic --index lib.idx`, without scanning or analysing the library again.
+/// Indexes written by earlier versions are read as they are, but migrating
+/// them saves converting them on every build.
+///
+/// # Exit codes
+///
+/// Exits with 2 if the index doesn't end in .idx, 3 if the library can't be
+/// read or the index written, and 4 if an index to migrate can't be decoded,
+/// after printing what failed on stderr.
+fn main() -> ExitCode {
+    let args = Args::parse();
+    if args.index.extension().is_none_or(|e| e != "idx") {
+        eprintln!("Index files must end in .idx");
+        return ExitCode::from(Failure::BadInput.exit_code());
+    }
+    match run(args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err((step, error)) => {
+            eprintln!("{step}: {error}");
+            ExitCode::from(Failure::of(&error).exit_code())
+        }
+    }
+}
+
+/// Analyse or migrate whatever the arguments ask for, or the step that failed.
+fn run(args: Args) -> Result<(), (&'static str, IoError)> {
+    if args.migrate {
+        let migrated = migrate_index(&args.index).map_err(|e| ("Error migrating", e))?;
+        if migrated {
+            eprintln!("Migrated {}", args.index.display());
+        } else {
+            eprintln!("{} is already current", args.index.display());
+        }
+        return Ok(());
+    }
+    let options = MosaicOptions {
+        tile_size: args.tile_size,
+        analysis_size: args.analysis_size,
+        ..MosaicOptions::default()
+    };
+
+    let libraries: Vec<PathBuf> = args
+        .tiles_dirs
+        .into_iter()
+        .chain(args.library_list)
+        .collect();
+    let started = Instant::now();
+    let count = index_library(&libraries, &args.index, &options, args.thumbnails)
+        .map_err(|e| ("Error analysing", e))?;
+    eprintln!("Indexed {count} images in {:.2?}", started.elapsed());
+    let listed = library_images(&libraries, &options.filter).map_or(0, |images| images.len());
+    let skipped = listed.saturating_sub(count);
+    if skipped > 0 {
+        eprintln!("Skipped {skipped} library images that couldn't be read");
+    }
+    Ok(())
+}
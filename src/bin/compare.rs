@@ -0,0 +1,120 @@
+use clap::Parser;
+use std::io::{Error as IoError, ErrorKind};
+use std::path::PathBuf;
+use std::process::ExitCode;
+use tiler::{
+    compare, comparison_table, load_compare_config, strategy_names, CompareConfig,
+    EvaluationOptions, Failure, MosaicOptions, Variant,
+};
+
+/// Build mosaics of a target with several strategies or settings and compare them
+#[derive(Parser)]
+struct Args {
+    /// Directory to write each mosaic to, as <label>.jpg
+    out_dir: PathBuf,
+    /// Target image to recreate as mosaics
+    #[arg(required_unless_present = "config")]
+    target: Option<PathBuf>,
+    /// Directories of library images, or manifests of image URLs, to use as tiles
+    #[arg(required_unless_present_any = ["config", "library_list"])]
+    tiles_dirs: Vec<PathBuf>,
+    /// File listing library image paths or URLs one per line, or - to read paths from stdin
+    #[arg(long)]
+    library_list: Vec<PathBuf>,
+    /// Strategies to compare with the default settings, all of them if not given
+    #[arg(long, value_delimiter = ',', value_parser = strategy_names())]
+    strategies: Vec<String>,
+    /// Size (in target pixels) of the cells whose colors are compared
+    #[arg(long, default_value_t = EvaluationOptions::default().cell_size)]
+    cell_size: u32,
+    /// Read the target, libraries, and labelled settings to compare from a TOML file
+    #[arg(long, conflicts_with_all = ["target", "tiles_dirs", "library_list", "strategies"])]
+    config: Option<PathBuf>,
+}
+
+impl Args {
+    /// The comparison described by the command line arguments.
+    fn into_config(self) -> CompareConfig {
+        let strategies = if self.strategies.is_empty() {
+            strategy_names().into_iter().map(String::from).collect()
+        } else {
+            self.strategies
+        };
+        CompareConfig {
+            target: self.target.unwrap_or_default(),
+            libraries: self
+                .tiles_dirs
+                .into_iter()
+                .chain(self.library_list)
+                .collect(),
+            variants: strategies
+                .into_iter()
+                .map(|strategy| Variant {
+                    label: strategy.clone(),
+                    mosaic: MosaicOptions {
+                        strategy,
+                        ..MosaicOptions::default()
+                    },
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Compare mosaics
+///
+/// # Usage
+///
+/// compare <out_dir> <target> <tiles_dir>...
+/// compare --strategies independent,holistic <out_dir> <target> <tiles_dir>...
+/// compare --config compare.toml <out_dir>
+///
+/// Writes each mosaic to the output directory and prints a table of how
+/// closely each recreates the target and how long it took to build.
+///
+/// # Exit codes
+///
+/// Exits with 2 if the config can't be parsed, 3 if a file can't be read or
+/// a mosaic written, 4 if an image can't be decoded, and 5 if there are no
+/// library images to build with, after printing what failed on stderr.
+fn main() -> ExitCode {
+    let args = Args::parse();
+    match run(args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err((step, error)) => {
+            eprintln!("{step}: {error}");
+            ExitCode::from(Failure::of(&error).exit_code())
+        }
+    }
+}
+
+/// Build and compare the mosaics the arguments ask for, or the step that failed.
+fn run(args: Args) -> Result<(), (&'static str, IoError)> {
+    let out_dir = args.out_dir.clone();
+    let evaluation = EvaluationOptions {
+        cell_size: args.cell_size,
+        ..EvaluationOptions::default()
+    };
+
+    let config = match &args.config {
+        // A config that can't be parsed is bad input, like a bad argument.
+        Some(path) => load_compare_config(path)
+            .map_err(|e| match e.kind() {
+                ErrorKind::InvalidData => IoError::new(ErrorKind::InvalidInput, e),
+                _ => e,
+            })
+            .map_err(|e| ("Error reading config", e))?,
+        None => args.into_config(),
+    };
+
+    let comparisons = compare(
+        &config.target,
+        &config.libraries,
+        &config.variants,
+        &out_dir,
+        &evaluation,
+    )
+    .map_err(|e| ("Error comparing", e))?;
+    print!("{}", comparison_table(&comparisons));
+    Ok(())
+}
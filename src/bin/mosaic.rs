@@ -1,28 +1,702 @@
-use std::env;
-use tiler::{mosaic, save};
+use clap::{ArgGroup, CommandFactory, Parser, ValueEnum};
+use clap_complete::{generate, Shell};
+use clap_mangen::Man;
+use image::{ImageResult, RgbaImage};
+use std::fs::{create_dir_all, rename, File};
+use std::io::{stdout, Error as IoError, ErrorKind, Result as IoResult, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use tiler::{
+    auto_options, evaluate, export_pages, find_targets, library_coverage, library_images,
+    load_config, mosaic, mosaic_atlas, mosaic_batch, mosaic_heatmap, mosaic_resumable, mosaic_svg,
+    plan_stats, planned_cell_size, save, strategy_names, unsupported_images, watch, Background,
+    Blend, BlendMode, BuildConfig, ChannelWeights, Color, Corner, CostWeights, Date,
+    EvaluationOptions, Exclusion, Failure, FolderWeight, Frame, Glob, HeatmapKind, Jitter,
+    LibraryFilter, Mark, Mask, MaskShape, Mipmaps, MosaicOptions, MosaicStats, OutputFormat,
+    PageSize, Penalty, Pin, PostProcess, Preference, PrintLayout, ProcessingOrder, ProtectedArea,
+    Refinement, ResizeFilter, Seed, SmallTiles, TextMark, TextShape, TieBreak, Watermark,
+};
+
+/// Create a mosaic of the target from directories of library images
+#[derive(Parser)]
+#[command(name = "mosaic")]
+#[command(group(ArgGroup::new("shape").args(["mask", "mask_text"])))]
+#[command(group(ArgGroup::new("stamp").args(["watermark", "watermark_text"])))]
+struct Args {
+    /// Target image to recreate as a mosaic
+    #[arg(required_unless_present_any = ["config", "completions", "man"])]
+    target: Option<PathBuf>,
+    /// Directories of library images, or manifests of image URLs, to use as tiles
+    #[arg(required_unless_present_any = ["config", "library_list", "index", "completions", "man"])]
+    tiles_dirs: Vec<PathBuf>,
+    /// File listing library image paths or URLs one per line, or - to read paths from stdin
+    #[arg(long)]
+    library_list: Vec<PathBuf>,
+    /// Index file of an already analysed library, written by analyse, to use as tiles
+    #[arg(long)]
+    index: Vec<PathBuf>,
+    /// Write an SVG layout instead of a JPEG, linking or embedding the tiles
+    #[arg(long, value_enum)]
+    svg: Option<SvgMode>,
+    /// Strategy used to choose the tile for each cell
+    #[arg(long, default_value = "independent", value_parser = strategy_names())]
+    strategy: String,
+    /// How much each channel counts when matching: equal, luminance, or red,green,blue
+    #[arg(long, default_value = "equal")]
+    channel_weights: ChannelWeights,
+    /// Order cells are visited in by the holistic strategy
+    #[arg(long, value_enum, default_value_t)]
+    order: ProcessingOrder,
+    /// Seed for random choices, such as the random order, building the same mosaic each time unless --refine-time-limit is reached
+    #[arg(long, default_value_t)]
+    seed: Seed,
+    /// How to choose between tiles that match a cell equally well
+    #[arg(long, value_enum, default_value_t)]
+    tie_break: TieBreak,
+    /// Size (in target pixels) of the cells the target is split into
+    #[arg(long, default_value_t = MosaicOptions::default().cell_size, value_parser = clap::value_parser!(u32).range(1..))]
+    cell_size: u32,
+    /// Size (in output pixels) each cell is drawn at
+    #[arg(long, default_value_t = MosaicOptions::default().tile_size, value_parser = clap::value_parser!(u32).range(1..))]
+    tile_size: u32,
+    /// About how many cells to split the target into, sizing them to suit its aspect ratio
+    #[arg(long, conflicts_with = "cell_size", value_parser = clap::value_parser!(u32).range(1..))]
+    cell_budget: Option<u32>,
+    /// About how many cells to split the target into by resizing it first, whatever its resolution, keeping the cell size
+    #[arg(long, conflicts_with = "cell_budget", value_parser = clap::value_parser!(u32).range(1..))]
+    working_cells: Option<u32>,
+    /// Choose the cell and tile sizes from the size of the target and the number of library images
+    #[arg(long, conflicts_with_all = ["cell_size", "tile_size", "cell_budget", "batch"])]
+    auto: bool,
+    /// Number of closest tiles by color summary the pruned strategy compares
+    #[arg(long, default_value_t = MosaicOptions::default().candidates)]
+    candidates: usize,
+    /// Rule out tiles by comparing coarser versions of their samples first, choosing the same tiles faster on large libraries
+    #[arg(long)]
+    pyramid: bool,
+    /// Have the holistic strategy also choose tiles in the reverse order, keeping the cheaper choices
+    #[arg(long)]
+    reverse_pass: bool,
+    /// How likely each cell is to get its second or third best tile, from 0 (never) to 1 (as likely as the best), to break up repeated patterns
+    #[arg(long, default_value_t, value_parser = parse_temperature)]
+    temperature: f64,
+    /// How much each cost term counts, as term=weight pairs of color, preference, reuse, or spatial
+    #[arg(long)]
+    cost: Option<CostWeights>,
+    /// Number of changes to try when refining the chosen tiles
+    #[arg(long, default_value_t)]
+    refine_iterations: u32,
+    /// Time limit (in milliseconds) for refining the chosen tiles, which makes the mosaic depend on the machine's speed when reached, whatever the seed
+    #[arg(long, default_value_t)]
+    refine_time_limit: u64,
+    /// Match each tile's brightness and contrast to its cell
+    #[arg(long)]
+    match_luminance: bool,
+    /// Average colors as encoded rather than in linear light, like older versions
+    #[arg(long)]
+    no_linear_light: bool,
+    /// Filter to resize tiles with when drawing them; sharper filters are slower
+    #[arg(long, value_enum, default_value_t)]
+    resize_filter: ResizeFilter,
+    /// Filter to resize library images and target cells with when analysing them
+    #[arg(long, value_enum, default_value_t)]
+    analysis_filter: ResizeFilter,
+    /// Compare each cell with only the part of each tile drawn over it, sample for sample
+    #[arg(long)]
+    matched_layout: bool,
+    /// Reduce the target to this many colors before matching, for a posterised look
+    #[arg(long, value_parser = clap::value_parser!(u16).range(2..=256))]
+    quantise: Option<u16>,
+    /// Only use library images taken on or after this day (YYYY-MM-DD)
+    #[arg(long)]
+    taken_from: Option<Date>,
+    /// Only use library images taken on or before this day (YYYY-MM-DD)
+    #[arg(long)]
+    taken_until: Option<Date>,
+    /// Only use library images with this keyword, or any of these if repeated
+    #[arg(long = "keyword")]
+    keywords: Vec<String>,
+    /// Only use library images rated at least this many stars
+    #[arg(long)]
+    min_rating: Option<u8>,
+    /// Only use library images whose names match this pattern, or any of these if repeated, such as "2023-*"
+    #[arg(long)]
+    include: Vec<Glob>,
+    /// Leave out library images whose names match this pattern, such as "*.thumb.jpg"
+    #[arg(long)]
+    exclude: Vec<Glob>,
+    /// What to do with library images smaller than the tiles drawn from them, which look blurry
+    #[arg(long, value_enum, default_value_t)]
+    small_tiles: SmallTiles,
+    /// Library image to favour over closer matches, such as a photo of someone the mosaic is for
+    #[arg(long)]
+    prefer: Vec<PathBuf>,
+    /// Percentage taken off the differences of favoured images from each cell, up to 100
+    #[arg(long, default_value_t = 25, value_parser = clap::value_parser!(u8).range(0..=100))]
+    prefer_bonus: u8,
+    /// Weight for the library images in a folder, as folder=weight, such as family=2 to favour them or stock=0.5 to use them less
+    #[arg(long = "folder-weight")]
+    folder_weights: Vec<FolderWeight>,
+    /// Library image to draw in the cell holding a point, as tile=x,y in target pixels, such as couple.jpg=960,540
+    #[arg(long = "pin")]
+    pins: Vec<Pin>,
+    /// Area a library image must not be drawn in, as tile=x,y,width,height in target pixels, such as ex.jpg=0,0,400,300
+    #[arg(long = "exclusion")]
+    exclusions: Vec<Exclusion>,
+    /// Area (x,y,width,height, or the corners of a polygon as x,y;x,y;x,y in target pixels), such as a face, to draw with smaller cells
+    #[arg(long)]
+    protect: Vec<ProtectedArea>,
+    /// Number of smaller cells along each side that cells over protected areas are split into
+    #[arg(long, default_value_t = 2, requires = "protect")]
+    protect_divisions: u32,
+    /// How much each cost term counts in protected areas, as term=weight pairs
+    #[arg(long, requires = "protect")]
+    protect_cost: Option<CostWeights>,
+    /// Weight added to a tile in a protected area drawn right next to itself anywhere in the mosaic
+    #[arg(long, requires = "protect")]
+    protect_penalty: Option<i64>,
+    /// Protect patches of skin tone shaped like faces too, found by their color rather than by a face detector (needs the skin-tones feature)
+    #[arg(long)]
+    protect_skin_tones: bool,
+    /// Frame each tile in a white card with a deeper bottom margin, like an instant photo
+    #[arg(long)]
+    frame: bool,
+    /// Font to caption each framed tile with its file name in
+    #[arg(long, requires = "frame")]
+    caption_font: Option<PathBuf>,
+    /// Turn each tile by a random angle up to this many degrees either way, for a hand-placed look
+    #[arg(long)]
+    jitter: Option<f64>,
+    /// Pixels each edge of each tile is drawn inside its cell, leaving gaps, or outside it if negative, so tiles overlap
+    #[arg(long, default_value_t, allow_negative_numbers = true)]
+    tile_inset: i32,
+    /// Pixels over which each tile fades in over its neighbours above and to the left, hiding seams
+    #[arg(long, default_value_t)]
+    feather: u32,
+    /// Blend tiles with the target beneath them, so it ghosts through
+    #[arg(long, value_enum)]
+    blend: Option<BlendMode>,
+    /// How much the blend replaces the plain tiles, from 0 to 1
+    #[arg(long, default_value_t = 1.0, requires = "blend")]
+    blend_opacity: f64,
+    /// Fill for any gaps between tiles and margins, laid beneath the tiles: a color (#rrggbb), average for the target's color around them, gradient:#rrggbb:#rrggbb from top to bottom, or blurred[:spread] for a blurred copy of the target
+    #[arg(long)]
+    background: Option<Background>,
+    /// Finish the mosaic with these steps in turn: unsharp-mask:sigma[:threshold], contrast:percent, saturation:scale, or vignette:strength
+    #[arg(long)]
+    post: Vec<PostProcess>,
+    /// Only draw tiles where this image is white, or opaque if it has transparency
+    #[arg(long)]
+    mask: Option<PathBuf>,
+    /// Only draw tiles inside the letters of this text, drawn centred over the target
+    #[arg(long, requires = "font")]
+    mask_text: Option<String>,
+    /// TrueType or OpenType font to draw the mask text in
+    #[arg(long, requires = "mask_text")]
+    font: Option<PathBuf>,
+    /// Height (in target pixels) of each line of mask text, as large as fits if not given
+    #[arg(long, requires = "mask_text")]
+    font_size: Option<f32>,
+    /// Share of a cell (0 to 1) the mask must cover for the cell to get a tile
+    #[arg(long, default_value_t = 0.5, requires = "shape")]
+    mask_threshold: f64,
+    /// Color (#rrggbb) to fill the cells left out by the mask, instead of leaving them transparent
+    #[arg(long, requires = "shape")]
+    mask_background: Option<Color>,
+    /// Directory to keep smaller copies of library images in, to draw tiles from instead of the originals
+    #[arg(long)]
+    mipmaps: Option<PathBuf>,
+    /// Image to stamp in a corner of the mosaic, such as a logo
+    #[arg(long)]
+    watermark: Option<PathBuf>,
+    /// Text to stamp in a corner of the mosaic, such as an attribution
+    #[arg(long, requires = "watermark_font")]
+    watermark_text: Option<String>,
+    /// TrueType or OpenType font to draw the watermark text in
+    #[arg(long, requires = "watermark_text")]
+    watermark_font: Option<PathBuf>,
+    /// Corner to stamp the watermark in
+    #[arg(long, value_enum, default_value_t, requires = "stamp")]
+    watermark_corner: Corner,
+    /// How opaque the watermark is, from 0 to 1
+    #[arg(long, default_value_t = 0.5, requires = "stamp")]
+    watermark_opacity: f64,
+    /// Write an atlas of the distinct tiles instead, and its JSON index to this path
+    #[arg(long, conflicts_with = "svg")]
+    atlas: Option<PathBuf>,
+    /// Keep rebuilding the mosaic into this JPEG whenever the target or library images change
+    #[arg(long, conflicts_with_all = ["svg", "atlas"])]
+    watch: Option<PathBuf>,
+    /// Split the mosaic into printable pages, written as a PDF if this ends in .pdf or as PNGs in this directory
+    #[arg(long, conflicts_with_all = ["svg", "atlas", "watch"])]
+    print: Option<PathBuf>,
+    /// Treat the target as a directory of targets, writing the mosaic of each to this directory as <name>.jpg
+    #[arg(long, conflicts_with_all = ["svg", "atlas", "watch", "print"])]
+    batch: Option<PathBuf>,
+    /// Paper size for printing: a4, a3, or WIDTHxHEIGHT in millimetres
+    #[arg(long, default_value = "a4", requires = "print")]
+    page_size: PageSize,
+    /// Print pages in landscape
+    #[arg(long, requires = "print")]
+    landscape: bool,
+    /// Mosaic pixels printed per inch
+    #[arg(long, default_value_t = PrintLayout::default().dpi, requires = "print", value_parser = clap::value_parser!(u32).range(1..))]
+    dpi: u32,
+    /// Blank margin (in millimetres) around each printed page
+    #[arg(long, default_value_t = PrintLayout::default().margin_mm, requires = "print")]
+    margin: f64,
+    /// How much (in millimetres) neighbouring printed pages overlap
+    #[arg(long, default_value_t = PrintLayout::default().overlap_mm, requires = "print")]
+    overlap: f64,
+    /// Mark where to trim each printed page
+    #[arg(long, requires = "print")]
+    crop_marks: bool,
+    /// Keep checkpoints of the build in this directory, carrying on from any left there by an interrupted build of the same mosaic
+    #[arg(long, conflicts_with_all = ["svg", "atlas", "watch", "batch", "heatmap"])]
+    resume: Option<PathBuf>,
+    /// Report how closely the mosaic recreates the target, and how its tiles are used, on stderr
+    #[arg(long, conflicts_with_all = ["svg", "atlas", "watch", "print", "batch"])]
+    report: bool,
+    /// Report how well the library covers the main colors of the target on stdout, instead of building
+    #[arg(long, conflicts_with_all = ["svg", "atlas", "watch", "print", "batch", "report", "heatmap"])]
+    coverage: bool,
+    /// Report how the tiles would be used and how well they would match on stdout, instead of building
+    #[arg(long, conflicts_with_all = ["svg", "atlas", "watch", "print", "batch", "report", "coverage", "heatmap", "resume"])]
+    dry_run: bool,
+    /// Also write an image to this path coloring each cell by how poorly it is matched, or how often its tile is used
+    #[arg(long, conflicts_with_all = ["svg", "atlas", "watch", "print", "batch"])]
+    heatmap: Option<PathBuf>,
+    /// What the heatmap colors each cell by
+    #[arg(long, value_enum, default_value_t, requires = "heatmap")]
+    heatmap_kind: HeatmapKind,
+    /// Read the whole build description from a TOML file
+    #[arg(long, conflicts_with_all = ["target", "tiles_dirs", "library_list", "index", "svg", "strategy", "channel_weights", "order", "seed", "tie_break", "cell_size", "tile_size", "cell_budget", "working_cells", "candidates", "pyramid", "reverse_pass", "temperature", "cost", "refine_iterations", "refine_time_limit", "match_luminance", "no_linear_light", "resize_filter", "analysis_filter", "matched_layout", "quantise", "taken_from", "taken_until", "keywords", "min_rating", "include", "exclude", "small_tiles", "prefer", "folder_weights", "pins", "exclusions", "protect", "protect_skin_tones", "frame", "jitter", "tile_inset", "feather", "blend", "background", "post", "mask", "mask_text", "watermark", "watermark_text", "mipmaps", "atlas"])]
+    config: Option<PathBuf>,
+    /// Write a completion script for this shell on stdout, instead of building
+    #[arg(long, value_enum, exclusive = true)]
+    completions: Option<Shell>,
+    /// Write a man page on stdout, instead of building
+    #[arg(long, exclusive = true)]
+    man: bool,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum SvgMode {
+    Linked,
+    Embedded,
+}
+
+impl From<SvgMode> for OutputFormat {
+    fn from(mode: SvgMode) -> Self {
+        match mode {
+            SvgMode::Linked => OutputFormat::SvgLinked,
+            SvgMode::Embedded => OutputFormat::SvgEmbedded,
+        }
+    }
+}
+
+impl Args {
+    /// The print layout described by the command line arguments.
+    fn print_layout(&self) -> PrintLayout {
+        PrintLayout {
+            page: self.page_size,
+            landscape: self.landscape,
+            dpi: self.dpi,
+            margin_mm: self.margin,
+            overlap_mm: self.overlap,
+            crop_marks: self.crop_marks,
+        }
+    }
+
+    /// The build described by the command line arguments.
+    fn into_config(self) -> BuildConfig {
+        let shape = match (self.mask, self.mask_text) {
+            (Some(path), _) => Some(MaskShape::Image(path)),
+            (None, Some(content)) => Some(MaskShape::Text(TextShape {
+                content,
+                font: self.font.unwrap_or_default(),
+                size: self.font_size,
+            })),
+            (None, None) => None,
+        };
+        let mark = match (self.watermark, self.watermark_text) {
+            (Some(path), _) => Some(Mark::Image(path)),
+            (None, Some(content)) => Some(Mark::Text(TextMark::new(
+                content,
+                self.watermark_font.unwrap_or_default(),
+            ))),
+            (None, None) => None,
+        };
+        BuildConfig {
+            target: self.target.unwrap_or_default(),
+            libraries: self
+                .tiles_dirs
+                .into_iter()
+                .chain(self.library_list)
+                .chain(self.index)
+                .collect(),
+            output: self.svg.map(Into::into).unwrap_or_default(),
+            mosaic: MosaicOptions {
+                strategy: self.strategy,
+                channel_weights: self.channel_weights,
+                order: self.order,
+                seed: self.seed,
+                tie_break: self.tie_break,
+                cell_size: self.cell_size,
+                tile_size: self.tile_size,
+                cell_budget: self.cell_budget,
+                working_cells: self.working_cells,
+                candidates: self.candidates,
+                pyramid: self.pyramid,
+                reverse_pass: self.reverse_pass,
+                temperature: self.temperature,
+                cost: self.cost.unwrap_or_default(),
+                refinement: Refinement {
+                    iterations: self.refine_iterations,
+                    time_limit_ms: self.refine_time_limit,
+                    ..Refinement::default()
+                },
+                match_luminance: self.match_luminance,
+                linear_light: !self.no_linear_light,
+                resize_filter: self.resize_filter,
+                analysis_filter: self.analysis_filter,
+                matched_layout: self.matched_layout,
+                quantise: self.quantise,
+                preferred: if self.prefer.is_empty() {
+                    Vec::new()
+                } else {
+                    vec![Preference {
+                        tiles: self.prefer,
+                        bonus: self.prefer_bonus,
+                    }]
+                },
+                folder_weights: self.folder_weights,
+                pins: self.pins,
+                exclusions: self.exclusions,
+                protected: self
+                    .protect
+                    .into_iter()
+                    .map(|area| ProtectedArea {
+                        divisions: self.protect_divisions,
+                        cost: self.protect_cost,
+                        penalty: self.protect_penalty.map(|amount| Penalty {
+                            amount,
+                            ..Penalty::default()
+                        }),
+                        ..area
+                    })
+                    .collect(),
+                protect_skin_tones: self.protect_skin_tones,
+                frame: self.frame.then(|| Frame {
+                    caption_font: self.caption_font,
+                    ..Frame::default()
+                }),
+                jitter: self.jitter.map(|max_angle| Jitter { max_angle }),
+                tile_inset: self.tile_inset,
+                feather: self.feather,
+                blend: self.blend.map(|mode| Blend {
+                    mode,
+                    opacity: self.blend_opacity,
+                }),
+                background: self.background,
+                post_process: self.post,
+                watermark: mark.map(|mark| Watermark {
+                    opacity: self.watermark_opacity,
+                    ..Watermark::new(mark, self.watermark_corner)
+                }),
+                filter: LibraryFilter {
+                    taken_from: self.taken_from,
+                    taken_until: self.taken_until,
+                    keywords: self.keywords,
+                    min_rating: self.min_rating,
+                    include: self.include,
+                    exclude: self.exclude,
+                },
+                small_tiles: self.small_tiles,
+                mipmaps: self.mipmaps.map(Mipmaps::new),
+                mask: shape.map(|shape| Mask {
+                    shape,
+                    threshold: self.mask_threshold,
+                    background: self.mask_background,
+                }),
+                ..MosaicOptions::default()
+            },
+        }
+    }
+}
 
 /// Create a mosaic
 ///
 /// # Usage
 ///
-/// mosaic <target> <tiles_dir> > output.jpg
+/// mosaic <target> <tiles_dir>... > output.jpg
+/// mosaic --svg linked <target> <tiles_dir>... > output.svg
+/// mosaic --strategy holistic <target> <tiles_dir>... > output.jpg
+/// mosaic --atlas index.json <target> <tiles_dir>... > atlas.jpg
+/// mosaic --config build.toml > output.jpg
+/// mosaic --auto <target> <tiles_dir>... > output.jpg
+/// find photos -name '*.jpg' | mosaic --library-list - <target> > output.jpg
+/// mosaic --index lib.idx <target> > output.jpg
+/// mosaic --report <target> <tiles_dir>... > output.jpg
+/// mosaic --coverage <target> <tiles_dir>...
+/// mosaic --dry-run <target> <tiles_dir>...
+/// mosaic --heatmap heatmap.png --heatmap-kind reuse <target> <tiles_dir>... > output.jpg
+/// mosaic --resume checkpoints <target> <tiles_dir>... > output.jpg
+/// mosaic --watch output.jpg <target> <tiles_dir>...
+/// mosaic --print pages.pdf --page-size a3 --crop-marks <target> <tiles_dir>...
+/// mosaic --batch mosaics <targets_dir> <tiles_dir>...
+/// mosaic --completions bash > mosaic.bash
+/// mosaic --man > mosaic.1
 ///
-/// # Panics
+/// # Exit codes
 ///
-/// Panics if lib directory path is not supplied as argument.
-fn main() {
-    let args: Vec<String> = env::args().collect();
+/// Exits with 2 for bad arguments or settings, 3 if a file can't be read or
+/// written, 4 if an image can't be decoded, and 5 if there are no library
+/// images to build with, after printing what failed on stderr. When
+/// watching, failed builds are reported and watching goes on, and likewise
+/// for the targets of a batch.
+fn main() -> ExitCode {
+    let args = Args::parse();
+    match run(args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(Failed { step, error }) => {
+            eprintln!("{step}: {error}");
+            ExitCode::from(Failure::of(&error).exit_code())
+        }
+    }
+}
 
-    let Some(target_path) = args.get(1) else {
-        panic!("No target image path given")
-    };
-    let Some(lib_path) = args.get(2) else {
-        panic!("No library images path given")
+/// A step of the run that failed, and the error it failed with.
+struct Failed {
+    step: &'static str,
+    error: IoError,
+}
+
+/// Name the step a result comes from, should it fail.
+trait Step<T> {
+    fn step(self, step: &'static str) -> Result<T, Failed>;
+}
+
+impl<T> Step<T> for IoResult<T> {
+    fn step(self, step: &'static str) -> Result<T, Failed> {
+        self.map_err(|error| Failed { step, error })
+    }
+}
+
+impl<T> Step<T> for ImageResult<T> {
+    fn step(self, step: &'static str) -> Result<T, Failed> {
+        self.map_err(IoError::other).step(step)
+    }
+}
+
+/// Build whatever the arguments ask for.
+fn run(args: Args) -> Result<(), Failed> {
+    if let Some(shell) = args.completions {
+        generate(shell, &mut Args::command(), "mosaic", &mut stdout());
+        return Ok(());
+    }
+    if args.man {
+        return Man::new(Args::command())
+            .render(&mut stdout())
+            .step("Writing the man page");
+    }
+
+    let watch_output = args.watch.clone();
+    let batch_dir = args.batch.clone();
+    let report = args.report;
+    let coverage = args.coverage;
+    let dry_run = args.dry_run;
+    let auto = args.auto;
+    let resume = args.resume.clone();
+    let heatmap = args.heatmap.clone().map(|path| (path, args.heatmap_kind));
+    let print_output = args.print.clone().map(|path| (path, args.print_layout()));
+
+    if let Some(index_path) = args.atlas.clone() {
+        let mut config = args.into_config();
+        if auto {
+            use_suggested_sizes(&mut config)?;
+        }
+        let atlas = mosaic_atlas(&config.target, &config.libraries, &config.mosaic)
+            .step("Error building")?;
+        let index_file = File::create(index_path).step("Error saving")?;
+        serde_json::to_writer_pretty(index_file, &atlas.index)
+            .map_err(IoError::from)
+            .step("Error saving")?;
+        save(&atlas.image, "/dev/stdout").step("Error saving")?;
+        return Ok(());
+    }
+
+    let mut config = match &args.config {
+        // A config that can't be parsed is bad input, like a bad argument.
+        Some(path) => load_config(path)
+            .map_err(|e| match e.kind() {
+                ErrorKind::InvalidData => IoError::new(ErrorKind::InvalidInput, e),
+                _ => e,
+            })
+            .step("Error reading config")?,
+        None => args.into_config(),
     };
-    let Ok(output_image) = mosaic(target_path, lib_path) else {
-        panic!("Error building")
+    if auto {
+        use_suggested_sizes(&mut config)?;
+    }
+    let (target, libraries, options) = (&config.target, &config.libraries, &config.mosaic);
+    let build = || match &resume {
+        Some(dir) => mosaic_resumable(target, libraries, options, dir),
+        None => mosaic(target, libraries, options),
     };
-    let Ok(_) = save(&output_image, "/dev/stdout") else {
-        panic!("Error saving")
+
+    if let Ok(unsupported) = unsupported_images(libraries, &options.filter) {
+        if let Some(example) = unsupported.first() {
+            eprintln!(
+                "Skipping {} HEIC or AVIF images this build can't decode, such as {}",
+                unsupported.len(),
+                example.display()
+            );
+        }
+    }
+
+    if coverage {
+        let coverage = library_coverage(target, libraries, options).step("Error analysing")?;
+        println!("{coverage}");
+        return Ok(());
+    }
+
+    if dry_run {
+        let stats = plan_stats(target, libraries, options).step("Error planning")?;
+        println!("{stats}");
+        return Ok(());
+    }
+
+    if let Some(out_dir) = batch_dir {
+        if config.output.svg_images().is_some() {
+            return Err(bad_input("Batches only write JPEG mosaics"));
+        }
+        let targets = find_targets(target).step("Error finding targets")?;
+        create_dir_all(&out_dir).step("Error saving")?;
+        mosaic_batch(&targets, libraries, options, |target, build| {
+            let name = target.file_stem().unwrap_or_default().to_string_lossy();
+            let output = out_dir.join(format!("{name}.jpg"));
+            match build {
+                Ok(image) => match save(&image, &output.to_string_lossy()) {
+                    Ok(_) => eprintln!("Wrote {}", output.display()),
+                    Err(e) => eprintln!("Error saving {}: {e}", output.display()),
+                },
+                Err(e) => eprintln!("Error building {}: {e}", target.display()),
+            }
+        })
+        .step("Error building")?;
+        return Ok(());
+    }
+
+    if let Some(output) = watch_output {
+        if config.output.svg_images().is_some() {
+            return Err(bad_input("Watching only writes JPEG mosaics"));
+        }
+        watch(target, libraries, options, |build| match build {
+            Ok(image) => match replace_output(&image, &output) {
+                Ok(_) => eprintln!("Wrote {}", output.display()),
+                Err(e) => eprintln!("Error saving: {e}"),
+            },
+            Err(e) => eprintln!("Error building: {e}"),
+        })
+        .step("Error watching")?;
+        return Ok(());
+    }
+
+    if let Some((path, layout)) = print_output {
+        let result = build().step("Error building")?;
+        let pages = export_pages(&result.image, &layout, &path).step("Error saving")?;
+        eprintln!("Wrote {pages} pages to {}", path.display());
+        print_summary(&result.stats, libraries, &options.filter);
+        return Ok(());
+    }
+
+    if let Some(images) = config.output.svg_images() {
+        let svg = mosaic_svg(target, libraries, options, images).step("Error building")?;
+        stdout().write_all(svg.as_bytes()).step("Error saving")?;
+        return Ok(());
+    }
+
+    let (output_image, stats) = match heatmap {
+        Some((path, kind)) => {
+            let (output_image, heatmap) =
+                mosaic_heatmap(target, libraries, options, kind).step("Error building")?;
+            heatmap.save(path).step("Error saving heatmap")?;
+            (output_image, None)
+        }
+        None => {
+            let result = build().step("Error building")?;
+            (result.image, Some(result.stats))
+        }
     };
+    save(&output_image, "/dev/stdout").step("Error saving")?;
+    if let Some(stats) = &stats {
+        print_summary(stats, libraries, &options.filter);
+    }
+    if report {
+        let evaluation = EvaluationOptions {
+            cell_size: planned_cell_size(target, options).step("Error evaluating")?,
+            ..EvaluationOptions::default()
+        };
+        let quality = evaluate(target, &output_image, &evaluation).step("Error evaluating")?;
+        eprintln!("{quality}");
+        if let Some(stats) = stats {
+            eprintln!("{}", stats.plan);
+        }
+    }
+    Ok(())
+}
+
+/// Parse a temperature, which must be from 0 to 1.
+fn parse_temperature(arg: &str) -> Result<f64, String> {
+    let temperature: f64 = arg.parse().map_err(|e| format!("{e}"))?;
+    if !(0.0..=1.0).contains(&temperature) {
+        return Err(format!("{temperature} is not from 0 to 1"));
+    }
+    Ok(temperature)
+}
+
+/// A failure for arguments that can't be used together.
+fn bad_input(message: &str) -> Failed {
+    Failed {
+        step: "Error in arguments",
+        error: IoError::new(ErrorKind::InvalidInput, message),
+    }
+}
+
+/// Print a summary of the build on stderr, along with the library images
+/// too large to decode safely and how many others couldn't be read.
+fn print_summary(stats: &MosaicStats, libraries: &[PathBuf], filter: &LibraryFilter) {
+    for (_, error) in &stats.refused {
+        eprintln!("Skipping library image: {error}");
+    }
+    eprintln!("{stats}");
+    let listed = library_images(libraries, filter).map_or(0, |images| images.len());
+    let skipped = listed
+        .saturating_sub(stats.library_size)
+        .saturating_sub(stats.refused.len());
+    if skipped > 0 {
+        eprintln!("Skipped {skipped} library images that couldn't be read");
+    }
+}
+
+/// Save the mosaic beside the output then move it into place, so that
+/// anything showing the output never sees a partly written file.
+fn replace_output(image: &RgbaImage, output: &Path) -> IoResult<()> {
+    let partial = output.with_extension("partial");
+    save(image, &partial.to_string_lossy()).map_err(IoError::other)?;
+    rename(partial, output)
+}
+
+/// Switch the build to the cell and tile sizes suggested for its target and
+/// libraries, reporting them on stderr.
+fn use_suggested_sizes(config: &mut BuildConfig) -> Result<(), Failed> {
+    let options =
+        auto_options(&config.target, &config.libraries, &config.mosaic).step("Error analysing")?;
+    eprintln!(
+        "Using cells of {} pixels drawn at {} pixels",
+        options.cell_size, options.tile_size
+    );
+    config.mosaic = options;
+    Ok(())
 }
@@ -0,0 +1,69 @@
+use clap::Parser;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::Instant;
+use tiler::{library_images, prepare, Failure, MosaicOptions};
+
+/// Prepare directories of library images as ready-sized tiles for mosaics
+#[derive(Parser)]
+struct Args {
+    /// Directory to write the prepared library to
+    out_dir: PathBuf,
+    /// Directories of library images, or manifests of image URLs, to prepare
+    #[arg(required_unless_present = "library_list")]
+    tiles_dirs: Vec<PathBuf>,
+    /// File listing library image paths or URLs one per line, or - to read paths from stdin
+    #[arg(long)]
+    library_list: Vec<PathBuf>,
+    /// Size (in pixels) of the prepared tiles
+    #[arg(long, default_value_t = MosaicOptions::default().tile_size, value_parser = clap::value_parser!(u32).range(1..))]
+    tile_size: u32,
+    /// Number of samples along each side when analysing tiles
+    #[arg(long, default_value_t = MosaicOptions::default().analysis_size)]
+    analysis_size: u8,
+}
+
+/// Prepare a library
+///
+/// # Usage
+///
+/// prepare <out_dir> <tiles_dir>...
+/// prepare --library-list photos.txt <out_dir>
+///
+/// The prepared directory can then be used as a mosaic's tiles directory.
+///
+/// # Exit codes
+///
+/// Exits with 2 if the output directory holds images but isn't a prepared
+/// library, 3 if the library can't be read or the prepared library written,
+/// and 5 if there are no library images to prepare, after printing what
+/// failed on stderr.
+fn main() -> ExitCode {
+    let args = Args::parse();
+    let options = MosaicOptions {
+        tile_size: args.tile_size,
+        analysis_size: args.analysis_size,
+        ..MosaicOptions::default()
+    };
+
+    let libraries: Vec<PathBuf> = args
+        .tiles_dirs
+        .into_iter()
+        .chain(args.library_list)
+        .collect();
+    let started = Instant::now();
+    let count = match prepare(&libraries, &args.out_dir, &options) {
+        Ok(count) => count,
+        Err(error) => {
+            eprintln!("Error preparing: {error}");
+            return ExitCode::from(Failure::of(&error).exit_code());
+        }
+    };
+    eprintln!("Prepared {count} tiles in {:.2?}", started.elapsed());
+    let listed = library_images(&libraries, &options.filter).map_or(0, |images| images.len());
+    let skipped = listed.saturating_sub(count);
+    if skipped > 0 {
+        eprintln!("Skipped {skipped} library images that couldn't be read");
+    }
+    ExitCode::SUCCESS
+}
@@ -0,0 +1,58 @@
+use clap::Parser;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use tiler::{parse_library, strategy_names, Failure, MosaicOptions, MosaicService};
+
+/// Build mosaics of uploaded targets over HTTP, from libraries analysed on start
+#[derive(Parser)]
+struct Args {
+    /// Address to listen on
+    #[arg(long, default_value = "127.0.0.1:8080")]
+    listen: String,
+    /// Library to build mosaics from, given as NAME=DIR (repeatable)
+    #[arg(long = "library", required = true, value_parser = parse_library)]
+    libraries: Vec<(String, PathBuf)>,
+    /// Strategy used when a job doesn't name one
+    #[arg(long, default_value = "independent", value_parser = strategy_names())]
+    strategy: String,
+}
+
+/// Serve mosaics
+///
+/// # Usage
+///
+/// serve --library holiday=tiles_lib/ --listen 0.0.0.0:8080
+/// curl --data-binary @target.jpg 'localhost:8080/jobs?library=holiday'
+/// curl localhost:8080/jobs/0/mosaic > mosaic.jpg
+///
+/// # Exit codes
+///
+/// Exits with 3 if the libraries can't be read or the address can't be
+/// listened on, and 5 if a library has no images, after printing what failed
+/// on stderr.
+fn main() -> ExitCode {
+    let args = Args::parse();
+
+    let options = MosaicOptions {
+        strategy: args.strategy,
+        ..MosaicOptions::default()
+    };
+    let result = MosaicService::new(&args.libraries, options)
+        .map_err(|e| ("Error loading libraries", e))
+        .and_then(|service| {
+            for (_, error) in service.refused() {
+                eprintln!("Skipping library image: {error}");
+            }
+            eprintln!("Listening on {}", args.listen);
+            service
+                .serve(&args.listen)
+                .map_err(|e| ("Error serving", e))
+        });
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err((step, error)) => {
+            eprintln!("{step}: {error}");
+            ExitCode::from(Failure::of(&error).exit_code())
+        }
+    }
+}
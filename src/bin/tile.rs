@@ -1,5 +1,6 @@
 use std::env;
-use tiler::{save, tile};
+use std::process::ExitCode;
+use tiler::{save, tile, Failure};
 
 /// Create a tile from a source image
 ///
@@ -7,19 +8,27 @@ use tiler::{save, tile};
 ///
 /// tile <source_path> > tile.jpg
 ///
-/// # Panics
+/// # Exit codes
 ///
-/// Panics if source file path is not supplied as argument.
-fn main() {
+/// Exits with 2 if no source path is given, 3 if the source can't be read or
+/// the tile written, and 4 if the source can't be decoded.
+fn main() -> ExitCode {
     let args: Vec<String> = env::args().collect();
 
     let Some(lib_path) = args.get(1) else {
-        panic!("No target given")
-    };
-    let Ok(output_image) = tile(lib_path) else {
-        panic!("Error converting")
-    };
-    let Ok(_) = save(&output_image, "/dev/stdout") else {
-        panic!("Error saving")
+        eprintln!("Usage: tile <source_path> > tile.jpg");
+        return ExitCode::from(Failure::BadInput.exit_code());
     };
+    let result = tile(lib_path)
+        .map_err(|e| ("Error converting", e))
+        .and_then(|output_image| {
+            save(&output_image, "/dev/stdout").map_err(|e| ("Error saving", e))
+        });
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err((step, error)) => {
+            eprintln!("{step}: {error}");
+            ExitCode::from(Failure::of_image(&error).exit_code())
+        }
+    }
 }
@@ -0,0 +1,110 @@
+#[cfg(feature = "cli")]
+use clap::ValueEnum;
+use image::imageops::interpolate_bilinear;
+use image::RgbaImage;
+#[cfg(feature = "serde")]
+use serde::Deserialize;
+
+/// How tiles are combined with the target drawn beneath them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "cli", derive(ValueEnum))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
+pub enum BlendMode {
+    /// Darkens, so the target's shadows show through the tiles.
+    Multiply,
+    /// Lightens, so the target's highlights show through the tiles.
+    Screen,
+    /// Gently lightens or darkens the target by the tiles, so the target
+    /// shows through most strongly.
+    SoftLight,
+}
+
+impl BlendMode {
+    /// The blend of a tile's channel over the target's, from 0 to 1.
+    fn apply(&self, target: f64, tile: f64) -> f64 {
+        match self {
+            BlendMode::Multiply => target * tile,
+            BlendMode::Screen => 1.0 - (1.0 - target) * (1.0 - tile),
+            BlendMode::SoftLight => (1.0 - 2.0 * tile) * target * target + 2.0 * tile * target,
+        }
+    }
+}
+
+/// Tiles blended with the target beneath them, so that it ghosts through.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(deny_unknown_fields))]
+pub struct Blend {
+    /// How tiles and the target are combined.
+    pub mode: BlendMode,
+    /// How much the blend replaces the plain tiles, from 0 to 1.
+    #[cfg_attr(feature = "serde", serde(default = "default_opacity"))]
+    pub opacity: f64,
+}
+
+#[cfg(feature = "serde")]
+fn default_opacity() -> f64 {
+    1.0
+}
+
+/// Blend the tiles drawn in the image with the target stretched beneath
+/// them, leaving any part no tile covers as it is.
+pub(crate) fn composite(img: &mut RgbaImage, target: &RgbaImage, blend: &Blend) {
+    let (width, height) = img.dimensions();
+    let (target_width, target_height) = target.dimensions();
+    if target_width == 0 || target_height == 0 {
+        return;
+    }
+    let scale_x = target_width as f32 / width.max(1) as f32;
+    let scale_y = target_height as f32 / height.max(1) as f32;
+    let opacity = blend.opacity.clamp(0.0, 1.0);
+    for (x, y, pixel) in img.enumerate_pixels_mut() {
+        if pixel[3] == 0 {
+            continue;
+        }
+        // The middle of the pixel, in target pixels.
+        let target_x = ((x as f32 + 0.5) * scale_x - 0.5).clamp(0.0, (target_width - 1) as f32);
+        let target_y = ((y as f32 + 0.5) * scale_y - 0.5).clamp(0.0, (target_height - 1) as f32);
+        let Some(base) = interpolate_bilinear(target, target_x, target_y) else {
+            continue;
+        };
+        for c in 0..3 {
+            let tile = f64::from(pixel[c]) / 255.0;
+            let blended = blend.mode.apply(f64::from(base[c]) / 255.0, tile);
+            let mixed = tile + (blended - tile) * opacity;
+            pixel[c] = (mixed * 255.0).round().clamp(0.0, 255.0) as u8;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use image::Rgba;
+
+    #[test]
+    fn test_blends_tiles_with_the_target() {
+        let target = RgbaImage::from_pixel(2, 2, Rgba([255, 128, 0, 255]));
+        let tiles = || {
+            let mut img = RgbaImage::from_pixel(4, 4, Rgba([128, 128, 128, 255]));
+            img.put_pixel(3, 3, Rgba([0, 0, 0, 0]));
+            img
+        };
+        let blended = |mode, opacity| {
+            let mut img = tiles();
+            composite(&mut img, &target, &Blend { mode, opacity });
+            img
+        };
+
+        let multiplied = blended(BlendMode::Multiply, 1.0);
+        assert_eq!(*multiplied.get_pixel(0, 0), Rgba([128, 64, 0, 255]));
+        assert_eq!(*multiplied.get_pixel(3, 3), Rgba([0, 0, 0, 0]));
+        let screened = blended(BlendMode::Screen, 1.0);
+        assert_eq!(*screened.get_pixel(0, 0), Rgba([255, 192, 128, 255]));
+        let soft = blended(BlendMode::SoftLight, 1.0);
+        assert_eq!(*soft.get_pixel(0, 0), Rgba([255, 128, 0, 255]));
+        let half = blended(BlendMode::Multiply, 0.5);
+        assert_eq!(*half.get_pixel(0, 0), Rgba([128, 96, 64, 255]));
+    }
+}
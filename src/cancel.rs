@@ -0,0 +1,52 @@
+use std::io::{Error as IoError, ErrorKind, Result as IoResult};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// A way to stop a build early, such as when its window is closed or its
+/// job deleted.
+///
+/// Clones share the same state, so one can be kept to cancel the build given
+/// another. Builds check it between images and cells, then fail with an
+/// `Interrupted` error, dropping what they had built so far.
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> CancelToken {
+        CancelToken::default()
+    }
+
+    /// Ask any builds given this token, or a clone of it, to stop.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Fail if the build has been cancelled.
+    pub(crate) fn check(&self) -> IoResult<()> {
+        if self.is_cancelled() {
+            return Err(IoError::new(ErrorKind::Interrupted, "Build cancelled"));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_clones_share_cancellation() {
+        let token = CancelToken::new();
+        let kept = token.clone();
+
+        assert!(token.check().is_ok());
+        kept.cancel();
+
+        assert!(token.is_cancelled());
+        assert_eq!(token.check().unwrap_err().kind(), ErrorKind::Interrupted);
+    }
+}
@@ -0,0 +1,244 @@
+#[cfg(feature = "checkpoint")]
+use std::fs::{create_dir_all, read_to_string, rename, File};
+use std::fs::{read_dir, remove_file};
+use std::io::{Error as IoError, ErrorKind, Result as IoResult};
+use std::path::{Path, PathBuf};
+
+use image::{imageops, RgbaImage};
+#[cfg(feature = "checkpoint")]
+use serde::{Deserialize, Serialize};
+
+use crate::cancel::CancelToken;
+use crate::core::Dimensions;
+use crate::library::fnv1a;
+use crate::mipmap::save_copy;
+#[cfg(feature = "checkpoint")]
+use crate::{
+    core::PixelRegion, load_image, undersized::SmallTiles, working::to_working_resolution,
+};
+use crate::{MosaicOptions, Plan};
+
+/// About how many rows (in output pixels) are drawn between checkpoints.
+pub(crate) const CHECKPOINT_ROWS: u32 = 1024;
+
+/// Name of the file the plan is kept in.
+const PLAN_FILE: &str = "plan.json";
+
+/// A directory keeping the plan of a mosaic and each band of it once drawn,
+/// so that a long render that is stopped carries on where it left off.
+pub(crate) struct Checkpoint {
+    dir: PathBuf,
+    /// Hash of the build, to tell whether what is kept is for this build.
+    #[cfg_attr(not(feature = "checkpoint"), allow(dead_code))]
+    key: u64,
+}
+
+/// The plan as kept, without the target image it was made for.
+#[cfg(feature = "checkpoint")]
+#[derive(Serialize, Deserialize)]
+struct KeptPlan {
+    key: u64,
+    ratio: f32,
+    size: Dimensions,
+    tiles: Vec<(PathBuf, PixelRegion)>,
+    #[serde(default)]
+    costs: Vec<i64>,
+    #[serde(default)]
+    library_size: usize,
+    #[serde(default)]
+    filtered_out: usize,
+    #[serde(default)]
+    refused: Vec<(PathBuf, String)>,
+    #[serde(default)]
+    undersized: usize,
+    #[serde(default)]
+    small_tiles: SmallTiles,
+}
+
+impl Checkpoint {
+    /// Keep checkpoints of the build described in the directory.
+    pub(crate) fn new(dir: &Path, build: &str) -> Checkpoint {
+        Checkpoint {
+            dir: dir.to_owned(),
+            key: fnv1a(build.as_bytes()),
+        }
+    }
+
+    /// The plan kept for this build, if any, for the target, resized to its
+    /// working resolution as it was when planned.
+    #[cfg(feature = "checkpoint")]
+    pub(crate) fn plan(
+        &self,
+        target_path: &Path,
+        options: &MosaicOptions,
+    ) -> IoResult<Option<Plan>> {
+        let text = match read_to_string(self.dir.join(PLAN_FILE)) {
+            Ok(text) => text,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let kept: KeptPlan = serde_json::from_str(&text)?;
+        // Plans kept before costs were kept are made again.
+        if kept.key != self.key || kept.costs.len() != kept.tiles.len() {
+            return Ok(None);
+        }
+        let target = load_image(target_path).map_err(IoError::other)?;
+        let (target, _) = to_working_resolution(target, options);
+        Ok(Some(Plan {
+            target,
+            ratio: kept.ratio,
+            size: kept.size,
+            tiles: kept.tiles,
+            costs: kept.costs,
+            library_size: kept.library_size,
+            filtered_out: kept.filtered_out,
+            refused: kept.refused,
+            undersized: kept.undersized,
+            small_tiles: kept.small_tiles,
+        }))
+    }
+
+    /// Keep the plan, removing anything kept for another build.
+    #[cfg(feature = "checkpoint")]
+    pub(crate) fn save_plan(&self, plan: &Plan) -> IoResult<()> {
+        self.clear()?;
+        create_dir_all(&self.dir)?;
+        let kept = KeptPlan {
+            key: self.key,
+            ratio: plan.ratio,
+            size: plan.size,
+            tiles: plan.tiles.clone(),
+            costs: plan.costs.clone(),
+            library_size: plan.library_size,
+            filtered_out: plan.filtered_out,
+            refused: plan.refused.clone(),
+            undersized: plan.undersized,
+            small_tiles: plan.small_tiles,
+        };
+        let path = self.dir.join(PLAN_FILE);
+        let partial = path.with_extension("partial");
+        serde_json::to_writer(File::create(&partial)?, &kept)?;
+        rename(partial, path)
+    }
+
+    /// Without the `checkpoint` feature there is nothing to keep plans with,
+    /// so builds can't be resumed.
+    #[cfg(not(feature = "checkpoint"))]
+    pub(crate) fn plan(
+        &self,
+        _target_path: &Path,
+        _options: &MosaicOptions,
+    ) -> IoResult<Option<Plan>> {
+        Err(unkept())
+    }
+
+    #[cfg(not(feature = "checkpoint"))]
+    pub(crate) fn save_plan(&self, _plan: &Plan) -> IoResult<()> {
+        Err(unkept())
+    }
+
+    /// Build an image of the given size a band of rows at a time, using the
+    /// kept copy of each band drawn before and keeping each band drawn now.
+    pub(crate) fn draw<F>(
+        &self,
+        (width, height): Dimensions,
+        bands: Vec<(u32, u32)>,
+        cancel: &CancelToken,
+        draw: F,
+    ) -> IoResult<RgbaImage>
+    where
+        F: Fn((u32, u32)) -> IoResult<RgbaImage>,
+    {
+        let mut output = RgbaImage::new(width, height);
+        for (top, bottom) in bands {
+            let path = self.dir.join(format!("band-{top}-{bottom}.png"));
+            let kept = image::open(&path).ok().map(|img| img.to_rgba8());
+            let band = match kept {
+                Some(band) if band.dimensions() == (width, bottom - top) => band,
+                _ => {
+                    let band = draw((top, bottom))?;
+                    cancel.check()?;
+                    save_copy(&band, &path)?;
+                    band
+                }
+            };
+            imageops::replace(&mut output, &band, 0, top.into());
+        }
+        Ok(output)
+    }
+
+    /// Remove the plan and bands kept, along with any left partly written,
+    /// leaving anything else in the directory.
+    pub(crate) fn clear(&self) -> IoResult<()> {
+        let entries = match read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        for entry in entries {
+            let path = entry?.path();
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            let plan = name == PLAN_FILE || name == "plan.partial";
+            let band =
+                name.starts_with("band-") && (name.ends_with(".png") || name.ends_with(".partial"));
+            if plan || band {
+                remove_file(&path)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The error for keeping a plan in a build without the `checkpoint` feature.
+#[cfg(not(feature = "checkpoint"))]
+fn unkept() -> IoError {
+    IoError::new(
+        ErrorKind::Unsupported,
+        "Resuming builds needs the checkpoint feature",
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use image::Rgba;
+    use std::cell::Cell;
+    use std::env::temp_dir;
+    use std::fs::remove_dir_all;
+
+    #[test]
+    fn test_keeps_drawn_bands() {
+        let dir = temp_dir().join(format!("tiler-checkpoint-{}", std::process::id()));
+        let checkpoint = Checkpoint::new(&dir, "build");
+        let bands = vec![(0, 2), (2, 4)];
+        let calls = Cell::new(0);
+        let draw = |(top, bottom): (u32, u32)| {
+            calls.set(calls.get() + 1);
+            Ok(RgbaImage::from_pixel(
+                3,
+                bottom - top,
+                Rgba([top as u8, 0, 0, 255]),
+            ))
+        };
+
+        // A cancelled build keeps nothing.
+        let cancel = CancelToken::new();
+        cancel.cancel();
+        let error = checkpoint.draw((3, 4), bands.clone(), &cancel, draw);
+        assert_eq!(error.unwrap_err().kind(), ErrorKind::Interrupted);
+
+        let cancel = CancelToken::new();
+        let first = checkpoint
+            .draw((3, 4), bands.clone(), &cancel, draw)
+            .unwrap();
+        let second = checkpoint.draw((3, 4), bands, &cancel, draw).unwrap();
+
+        assert_eq!(calls.get(), 3);
+        assert_eq!(first, second);
+        assert_eq!(*second.get_pixel(1, 3), Rgba([2, 0, 0, 255]));
+
+        checkpoint.clear().unwrap();
+        assert_eq!(read_dir(&dir).unwrap().count(), 0);
+        remove_dir_all(dir).unwrap();
+    }
+}
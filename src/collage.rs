@@ -0,0 +1,156 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+
+use image::RgbaImage;
+
+use crate::analysis::ImageInfo;
+use crate::core::{product, Dimensions, PixelRegion, Rectangle, TileLocation};
+use crate::matching::CellAnalyser;
+use crate::strategy::{StrategyOptions, TilingStrategy, EXCLUDED, PINNED};
+
+/// Sizes (in cells) of the square footprints tried, largest first.
+const FOOTPRINTS: [u32; 2] = [3, 2];
+
+/// How much worse than its cells' own best matches, on average, a tile can
+/// match a footprint and still be drawn across it.
+const FOOTPRINT_TOLERANCE: f64 = 1.2;
+
+/// Mix tiles covering 1×1, 2×2, and 3×3 cells, drawing one image across a
+/// larger footprint wherever it matches that area about as well as separate
+/// tiles match its cells.
+pub struct CollageTileStrategy<'a, T> {
+    options: &'a StrategyOptions,
+    analysis: &'a HashMap<&'a T, ImageInfo>,
+}
+
+impl<'a, T: Ord + Hash> CollageTileStrategy<'a, T> {
+    pub fn new(
+        analysis: &'a HashMap<&'a T, ImageInfo>,
+        options: &'a StrategyOptions,
+    ) -> CollageTileStrategy<'a, T> {
+        CollageTileStrategy { options, analysis }
+    }
+
+    /// The best matching tile for the area, and its weight.
+    fn best_tile(&self, analyser: &CellAnalyser, r: &Rectangle) -> (&'a T, i64) {
+        let target_info = analyser.analyse(r);
+        self.analysis
+            .iter()
+            .map(|(tile, info)| {
+                let weight = self.options.weight(info, &target_info, r);
+                (*tile, weight)
+            })
+            .min_by(|a, b| self.options.compare_weights(a, b))
+            .unwrap()
+    }
+}
+
+impl<T: Ord + Hash> TilingStrategy<T> for CollageTileStrategy<'_, T> {
+    fn choose(
+        &self,
+        target: &RgbaImage,
+        cell_size: &Dimensions,
+    ) -> Vec<TileLocation<'_, T, PixelRegion>> {
+        let (cw, ch) = *cell_size;
+        let (width, height) = target.dimensions();
+        let (columns, rows) = (width.div_ceil(cw), height.div_ceil(ch));
+        let rectangle =
+            |column, row, size| Rectangle::new(column * cw, row * ch, size * cw, size * ch);
+
+        let analyser = CellAnalyser::new(target, *cell_size, &self.options.analysis);
+        let singles: Vec<(&T, i64)> = product(0..rows, 0..columns)
+            .take_while(|_| !self.options.cancel.is_cancelled())
+            .map(|(row, column)| self.best_tile(&analyser, &rectangle(column, row, 1)))
+            .collect();
+        if self.options.cancel.is_cancelled() {
+            return Vec::new();
+        }
+        let cell = |column: u32, row: u32| (row * columns + column) as usize;
+        let mut covered = vec![false; singles.len()];
+
+        let mut tiles = Vec::with_capacity(singles.len());
+        for size in FOOTPRINTS {
+            for (row, column) in product(
+                0..(rows + 1).saturating_sub(size),
+                0..(columns + 1).saturating_sub(size),
+            ) {
+                if self.options.cancel.is_cancelled() {
+                    return tiles;
+                }
+                let cells: Vec<usize> = product(row..row + size, column..column + size)
+                    .map(|(r, c)| cell(c, r))
+                    .collect();
+                // Pinned cells keep their own tile.
+                if cells.iter().any(|&i| covered[i] || singles[i].1 == PINNED) {
+                    continue;
+                }
+
+                let r = rectangle(column, row, size);
+                let (tile, weight) = self.best_tile(&analyser, &r);
+                let singles_weight = cells
+                    .iter()
+                    .map(|&i| singles[i].1)
+                    .fold(0, i64::saturating_add);
+                let mean = singles_weight as f64 / cells.len() as f64;
+                if weight != EXCLUDED && weight as f64 <= mean * FOOTPRINT_TOLERANCE {
+                    cells.iter().for_each(|&i| covered[i] = true);
+                    tiles.push((tile, PixelRegion::from(&r)));
+                }
+            }
+        }
+
+        for (row, column) in product(0..rows, 0..columns) {
+            let i = cell(column, row);
+            if !covered[i] {
+                tiles.push((singles[i].0, PixelRegion::from(&rectangle(column, row, 1))));
+            }
+        }
+        tiles
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::analysis::{analyse, AnalysisOptions};
+    use image::Rgba;
+
+    #[test]
+    fn test_draws_large_tiles_over_even_areas() {
+        let (red, blue) = ("red".to_string(), "blue".to_string());
+        let options = StrategyOptions {
+            analysis: AnalysisOptions::new(Some(2)),
+            ..StrategyOptions::default()
+        };
+        let solid = |c| analyse(&RgbaImage::from_pixel(20, 20, Rgba(c)), &options.analysis);
+        let analysis = HashMap::from([
+            (&red, solid([255, 0, 0, 255])),
+            (&blue, solid([0, 0, 255, 255])),
+        ]);
+        // Three columns of red, then a column of blue.
+        let target = RgbaImage::from_fn(40, 30, |x, _| {
+            if x < 30 {
+                Rgba([255, 0, 0, 255])
+            } else {
+                Rgba([0, 0, 255, 255])
+            }
+        });
+
+        let strategy = CollageTileStrategy::new(&analysis, &options);
+        let tiles: Vec<(&String, (i64, i64, u32))> = strategy
+            .choose(&target, &(10, 10))
+            .into_iter()
+            .map(|(t, r)| (t, (r.x, r.y, r.width)))
+            .collect();
+
+        assert_eq!(
+            tiles,
+            vec![
+                (&red, (0, 0, 30)),
+                (&blue, (30, 0, 10)),
+                (&blue, (30, 10, 10)),
+                (&blue, (30, 20, 10)),
+            ]
+        );
+    }
+}
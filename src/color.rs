@@ -0,0 +1,277 @@
+use std::io::{BufRead, Error as IoError, ErrorKind, Seek};
+use std::path::Path;
+use std::sync::OnceLock;
+
+use image::codecs::jpeg::JpegDecoder;
+use image::codecs::png::PngDecoder;
+use image::codecs::tiff::TiffDecoder;
+use image::codecs::webp::WebPDecoder;
+use image::imageops::{self, FilterType};
+use image::io::{Limits, Reader};
+use image::{
+    DynamicImage, ImageDecoder, ImageError, ImageFormat, ImageResult, Rgba, Rgba32FImage, RgbaImage,
+};
+#[cfg(feature = "icc")]
+use qcms::{DataType, Intent, Profile, Transform};
+
+use crate::tonemap;
+
+/// Signature of ICC profiles for RGB color spaces, at bytes 16..20.
+#[cfg(feature = "icc")]
+const RGB_SIGNATURE: &[u8] = b"RGB ";
+
+/// Largest width or height (in pixels) of image that is decoded.
+const MAX_SIDE: u32 = 40_000;
+
+/// Most memory (in bytes) the pixels of a decoded image may take up, so that
+/// an image that is small on disk but huge once decoded, such as a
+/// decompression bomb, is refused rather than exhausting memory.
+const MAX_ALLOC: u64 = 1 << 30;
+
+/// Refuse an image of the given size, from a decoder the limits can't be set
+/// on, if it is too large to decode safely.
+#[cfg(feature = "heif")]
+pub(crate) fn check_limits(width: u32, height: u32) -> ImageResult<()> {
+    let mut limits = limits();
+    limits.check_dimensions(width, height)?;
+    limits.reserve(u64::from(width) * u64::from(height) * 4)
+}
+
+/// The limits images are decoded within.
+fn limits() -> Limits {
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_SIDE);
+    limits.max_image_height = Some(MAX_SIDE);
+    limits.max_alloc = Some(MAX_ALLOC);
+    limits
+}
+
+/// Decode an image into 8-bit sRGB, converting it from any embedded color
+/// profile and tone mapping any high dynamic range, and refusing images too
+/// large to decode safely.
+pub(crate) fn decode<R: BufRead + Seek>(reader: Reader<R>) -> ImageResult<RgbaImage> {
+    let mut reader = reader.with_guessed_format()?;
+    let (img, icc) = match reader.format() {
+        Some(ImageFormat::Png) => with_profile(PngDecoder::new(reader.into_inner())?)?,
+        Some(ImageFormat::Jpeg) => with_profile(JpegDecoder::new(reader.into_inner())?)?,
+        Some(ImageFormat::Tiff) => with_profile(TiffDecoder::new(reader.into_inner())?)?,
+        Some(ImageFormat::WebP) => with_profile(WebPDecoder::new(reader.into_inner())?)?,
+        _ => {
+            reader.limits(limits());
+            (reader.decode()?, None)
+        }
+    };
+
+    let mut img = tonemap::into_rgba8(img);
+    apply_profile(&mut img, icc.as_deref());
+    Ok(img)
+}
+
+fn with_profile<'a, D: ImageDecoder<'a>>(
+    mut decoder: D,
+) -> ImageResult<(DynamicImage, Option<Vec<u8>>)> {
+    let mut limits = limits();
+    limits.reserve(decoder.total_bytes())?;
+    decoder.set_limits(limits)?;
+    let icc = decoder.icc_profile();
+    Ok((DynamicImage::from_decoder(decoder)?, icc))
+}
+
+/// The error from decoding the image at the path, naming the file if it was
+/// refused for being too large to decode safely.
+pub(crate) fn name_refused(path: &Path, error: ImageError) -> ImageError {
+    match error {
+        ImageError::Limits(limit) => ImageError::IoError(IoError::new(
+            ErrorKind::OutOfMemory,
+            format!("{} is too large to decode safely: {limit}", path.display()),
+        )),
+        error => error,
+    }
+}
+
+/// Convert the image to sRGB from the color space of its ICC profile, if it
+/// has one that isn't sRGB already.
+#[cfg(feature = "icc")]
+pub(crate) fn apply_profile(img: &mut RgbaImage, icc: Option<&[u8]>) {
+    if let Some(profile) = icc.and_then(rgb_profile) {
+        convert_to_srgb(img, &profile);
+    }
+}
+
+/// Without the `icc` feature there is nothing to read profiles with, so
+/// images are taken to be sRGB already.
+#[cfg(not(feature = "icc"))]
+pub(crate) fn apply_profile(_img: &mut RgbaImage, _icc: Option<&[u8]>) {}
+
+/// The RGB color profile in the ICC data, unless it is sRGB already or
+/// can't be read.
+#[cfg(feature = "icc")]
+fn rgb_profile(icc: &[u8]) -> Option<Box<Profile>> {
+    // Decoded images are always RGB, so other profiles (such as the gray
+    // profiles of grayscale images) can't be applied to them.
+    if icc.get(16..20) != Some(RGB_SIGNATURE) {
+        return None;
+    }
+    Profile::new_from_slice(icc, false).filter(|p| !p.is_sRGB())
+}
+
+/// Convert the image's colors from the profile's color space to sRGB.
+#[cfg(feature = "icc")]
+fn convert_to_srgb(img: &mut RgbaImage, profile: &Profile) {
+    let mut srgb = Profile::new_sRGB();
+    srgb.precache_output_transform();
+    if let Some(transform) = Transform::new(profile, &srgb, DataType::RGBA8, Intent::default()) {
+        transform.apply(img);
+    }
+}
+
+// Linear light
+
+/// Linear light values of each sRGB encoded value.
+fn decoding_table() -> &'static [f32; 256] {
+    static TABLE: OnceLock<[f32; 256]> = OnceLock::new();
+    TABLE.get_or_init(|| {
+        std::array::from_fn(|v| {
+            let v = v as f32 / 255.0;
+            if v <= 0.04045 {
+                v / 12.92
+            } else {
+                ((v + 0.055) / 1.055).powf(2.4)
+            }
+        })
+    })
+}
+
+/// The sRGB encoded value of the linear light value.
+fn encode(linear: f32) -> u8 {
+    let v = if linear <= 0.003_130_8 {
+        linear * 12.92
+    } else {
+        1.055 * linear.powf(1.0 / 2.4) - 0.055
+    };
+    (v * 255.0).round().clamp(0.0, 255.0) as u8
+}
+
+/// Resize the image, averaging the source pixels under each pixel in linear
+/// light so that fine detail keeps its brightness.
+pub(crate) fn resize(img: &RgbaImage, width: u32, height: u32) -> RgbaImage {
+    let decode = decoding_table();
+    let (src_width, src_height) = img.dimensions();
+    let raw = img.as_raw();
+
+    // The source pixels under a pixel, at least one when enlarging.
+    let span = |d: u32, dst: u32, src: u32| {
+        let start = (u64::from(d) * u64::from(src) / u64::from(dst)) as u32;
+        let end = (u64::from(d + 1) * u64::from(src) / u64::from(dst)) as u32;
+        start..end.max(start + 1).min(src)
+    };
+
+    RgbaImage::from_fn(width, height, |x, y| {
+        let mut sum = [0f32; 4];
+        let mut count = 0;
+        for sy in span(y, height, src_height) {
+            let row = sy as usize * src_width as usize * 4;
+            for sx in span(x, width, src_width) {
+                let p = &raw[row + sx as usize * 4..][..4];
+                sum[0] += decode[p[0] as usize];
+                sum[1] += decode[p[1] as usize];
+                sum[2] += decode[p[2] as usize];
+                sum[3] += f32::from(p[3]);
+                count += 1;
+            }
+        }
+        let n = count.max(1) as f32;
+        Rgba([
+            encode(sum[0] / n),
+            encode(sum[1] / n),
+            encode(sum[2] / n),
+            (sum[3] / n).round() as u8,
+        ])
+    })
+}
+
+/// Resize the image with the filter, blending colors in linear light.
+pub(crate) fn resize_filtered(
+    img: &RgbaImage,
+    width: u32,
+    height: u32,
+    filter: FilterType,
+) -> RgbaImage {
+    let decode = decoding_table();
+    let linear = Rgba32FImage::from_fn(img.width(), img.height(), |x, y| {
+        let [r, g, b, a] = img.get_pixel(x, y).0;
+        Rgba([
+            decode[r as usize],
+            decode[g as usize],
+            decode[b as usize],
+            f32::from(a) / 255.0,
+        ])
+    });
+    // Floating point channels are kept between 0 and 1 as they're resized.
+    let resized = imageops::resize(&linear, width, height, filter);
+    RgbaImage::from_fn(width, height, |x, y| {
+        let [r, g, b, a] = resized.get_pixel(x, y).0;
+        Rgba([encode(r), encode(g), encode(b), (a * 255.0).round() as u8])
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[cfg(feature = "icc")]
+    use qcms::{CIE_xyY, CIE_xyYTRIPLE};
+
+    #[test]
+    fn test_encoding_round_trips() {
+        for v in 0..=255u8 {
+            assert_eq!(encode(decoding_table()[v as usize]), v);
+        }
+    }
+
+    #[test]
+    fn test_averages_in_linear_light() {
+        let checks = RgbaImage::from_fn(4, 4, |x, y| {
+            let v = if (x + y) % 2 == 0 { 255 } else { 0 };
+            Rgba([v, v, v, 255])
+        });
+
+        let resized = resize(&checks, 1, 1);
+
+        // Half black and half white is half as bright, not a value of 128.
+        assert_eq!(resized.get_pixel(0, 0), &Rgba([188, 188, 188, 255]));
+        assert_eq!(
+            resize(&checks, 8, 8).get_pixel(3, 3),
+            checks.get_pixel(1, 1)
+        );
+    }
+
+    #[cfg(feature = "icc")]
+    #[test]
+    fn test_converts_profiles_to_srgb() {
+        let xy = |x, y| CIE_xyY { x, y, Y: 1.0 };
+        let linear_srgb = Profile::new_rgb_with_gamma_set(
+            xy(0.3127, 0.3290),
+            CIE_xyYTRIPLE {
+                red: xy(0.64, 0.33),
+                green: xy(0.30, 0.60),
+                blue: xy(0.15, 0.06),
+            },
+            1.0,
+            1.0,
+            1.0,
+        )
+        .unwrap();
+        let mut img = RgbaImage::from_pixel(1, 1, Rgba([55, 55, 55, 255]));
+
+        convert_to_srgb(&mut img, &linear_srgb);
+
+        // A linear value of 55 is about 0.216, which sRGB encodes as 128.
+        let Rgba([r, g, b, a]) = *img.get_pixel(0, 0);
+        assert!(
+            [r, g, b].iter().all(|v| v.abs_diff(128) <= 2),
+            "{r} {g} {b}"
+        );
+        assert_eq!(a, 255);
+        assert!(rgb_profile(b"too short").is_none());
+    }
+}
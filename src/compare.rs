@@ -0,0 +1,199 @@
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+use std::fs::create_dir_all;
+use std::io::{Error as IoError, ErrorKind, Result as IoResult};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::evaluate::measure;
+use crate::{
+    load_image, load_library, plan_with_library, render, save, strategy_options, CancelToken,
+    EvaluationOptions, MosaicOptions, Quality,
+};
+
+/// Mosaic settings to build and compare, under a label.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(deny_unknown_fields))]
+pub struct Variant {
+    /// Name the mosaic is written and reported under.
+    pub label: String,
+    /// Settings the mosaic is built with.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub mosaic: MosaicOptions,
+}
+
+/// How one variant turned out.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct Comparison {
+    pub label: String,
+    /// Where the mosaic was written.
+    pub output: PathBuf,
+    /// How closely the mosaic recreates the target.
+    pub quality: Quality,
+    /// Time taken to choose and draw the tiles, not counting analysing the
+    /// library, which is shared between variants.
+    pub duration: Duration,
+}
+
+/// Build a mosaic of the target for each variant, writing each to the
+/// output directory as `<label>.jpg`, and measure how closely they recreate
+/// the target.
+///
+/// The library is only analysed once for all the variants analysing it the
+/// same way.
+pub fn compare<P: AsRef<Path>>(
+    target_path: &Path,
+    lib_dirs: &[P],
+    variants: &[Variant],
+    out_dir: &Path,
+    evaluation: &EvaluationOptions,
+) -> IoResult<Vec<Comparison>> {
+    let mut labels = HashSet::new();
+    for Variant { label, .. } in variants {
+        let plain = !label.is_empty() && !label.contains(['/', '\\']);
+        if !plain || !labels.insert(label) {
+            let message = format!("Labels must be distinct file names: {label}");
+            return Err(IoError::new(ErrorKind::InvalidInput, message));
+        }
+    }
+
+    let target = load_image(target_path).map_err(IoError::other)?;
+    create_dir_all(out_dir)?;
+    let cancel = CancelToken::new();
+    let mut libraries = HashMap::new();
+    let mut comparisons = Vec::with_capacity(variants.len());
+    for Variant { label, mosaic } in variants {
+        let key = (
+            mosaic.analysis_size,
+            mosaic.linear_light,
+            mosaic.analysis_filter,
+            mosaic.matched_layout,
+            mosaic.filter.include.clone(),
+            mosaic.filter.exclude.clone(),
+        );
+        let library = match libraries.entry(key) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(load_library(
+                lib_dirs,
+                &mosaic.filter,
+                &strategy_options(mosaic).analysis,
+                &cancel,
+            )?),
+        };
+
+        let start = Instant::now();
+        let plan = plan_with_library(target.clone(), &library.images, mosaic, &cancel)?;
+        let image = render(plan, mosaic, &cancel)?;
+        let duration = start.elapsed();
+
+        let output = out_dir.join(format!("{label}.jpg"));
+        save(&image, &output.to_string_lossy()).map_err(IoError::other)?;
+        comparisons.push(Comparison {
+            label: label.clone(),
+            output,
+            quality: measure(&target, &image, evaluation),
+            duration,
+        });
+    }
+    Ok(comparisons)
+}
+
+/// A table of the quality and timing of each variant, one per line.
+pub fn comparison_table(comparisons: &[Comparison]) -> String {
+    let width = comparisons
+        .iter()
+        .map(|c| c.label.len())
+        .chain(["variant".len()])
+        .max()
+        .unwrap_or_default();
+    let mut table = format!(
+        "{:<width$}  {:>9}  {:>10}  {:>11}  {:>6}  {:>9}\n",
+        "variant", "time (ms)", "mean error", "worst error", "SSIM", "PSNR (dB)"
+    );
+    for c in comparisons {
+        let _ = writeln!(
+            table,
+            "{:<width$}  {:>9}  {:>10.2}  {:>11.2}  {:>6.4}  {:>9.2}",
+            c.label,
+            c.duration.as_millis(),
+            c.quality.mean_cell_error,
+            c.quality.worst_cell_error,
+            c.quality.ssim,
+            c.quality.psnr
+        );
+    }
+    table
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_support::fixture;
+    use std::env::temp_dir;
+    use std::fs::remove_dir_all;
+
+    fn variant(label: &str, strategy: &str) -> Variant {
+        Variant {
+            label: label.to_string(),
+            mosaic: MosaicOptions {
+                analysis_size: 2,
+                cell_size: 10,
+                tile_size: 10,
+                strategy: strategy.to_string(),
+                ..MosaicOptions::default()
+            },
+        }
+    }
+
+    #[test]
+    fn test_builds_and_measures_each_variant() {
+        let out_dir = temp_dir().join(format!("tiler-compare-{}", std::process::id()));
+        let _ = remove_dir_all(&out_dir);
+        let variants = [variant("a", "independent"), variant("b", "holistic")];
+
+        let comparisons = compare(
+            &fixture("target.png"),
+            &[fixture("library")],
+            &variants,
+            &out_dir,
+            &EvaluationOptions::default(),
+        )
+        .unwrap();
+
+        let labels: Vec<&str> = comparisons.iter().map(|c| c.label.as_str()).collect();
+        assert_eq!(labels, vec!["a", "b"]);
+        assert!(comparisons.iter().all(|c| c.output.is_file()));
+        let table = comparison_table(&comparisons);
+        assert_eq!(table.lines().count(), 3);
+        assert!(table.lines().nth(2).unwrap().starts_with("b        "));
+        remove_dir_all(out_dir).unwrap();
+    }
+
+    #[test]
+    fn test_rejects_labels_that_are_not_distinct_file_names() {
+        let out_dir = temp_dir().join("tiler-compare-never-written");
+        let compare_labels = |labels: &[&str]| {
+            let variants: Vec<Variant> = labels.iter().map(|l| variant(l, "independent")).collect();
+            compare(
+                &fixture("target.png"),
+                &[fixture("library")],
+                &variants,
+                &out_dir,
+                &EvaluationOptions::default(),
+            )
+            .err()
+            .map(|e| e.kind())
+        };
+
+        assert_eq!(compare_labels(&["a", "a"]), Some(ErrorKind::InvalidInput));
+        assert_eq!(compare_labels(&["../a"]), Some(ErrorKind::InvalidInput));
+        assert_eq!(compare_labels(&[""]), Some(ErrorKind::InvalidInput));
+        assert!(!out_dir.exists());
+    }
+}
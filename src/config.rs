@@ -0,0 +1,593 @@
+use std::fs::read_to_string;
+use std::io::{Error as IoError, ErrorKind, Result as IoResult};
+use std::path::{Path, PathBuf};
+
+#[cfg(feature = "config")]
+use serde::de::DeserializeOwned;
+#[cfg(feature = "serde")]
+use serde::Deserialize;
+
+#[cfg(feature = "svg")]
+use crate::svg::SvgImages;
+use crate::{
+    Exclusion, FolderWeight, Frame, Mark, Mask, MaskShape, Mipmaps, MosaicOptions, Pin, Preference,
+    TextMark, TextShape, Variant, Watermark,
+};
+
+/// Description of a mosaic build, read from a TOML file.
+///
+/// Relative paths are resolved against the directory holding the file.
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(deny_unknown_fields))]
+pub struct BuildConfig {
+    /// Target image to recreate as a mosaic.
+    pub target: PathBuf,
+    /// Directories of library images, or manifests of image URLs, to use as tiles.
+    pub libraries: Vec<PathBuf>,
+    /// Format of the built mosaic.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub output: OutputFormat,
+    /// Settings controlling how the mosaic is built.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub mosaic: MosaicOptions,
+}
+
+/// Description of several mosaic builds of one target to compare, read from
+/// a TOML file.
+///
+/// Relative paths are resolved against the directory holding the file.
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(deny_unknown_fields))]
+pub struct CompareConfig {
+    /// Target image to recreate as mosaics.
+    pub target: PathBuf,
+    /// Directories of library images, or manifests of image URLs, to use as tiles.
+    pub libraries: Vec<PathBuf>,
+    /// Settings to build a mosaic with for each comparison.
+    pub variants: Vec<Variant>,
+}
+
+/// The kinds of output a mosaic build can produce.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
+pub enum OutputFormat {
+    #[default]
+    Jpeg,
+    #[cfg(feature = "svg")]
+    SvgLinked,
+    #[cfg(feature = "svg")]
+    SvgEmbedded,
+}
+
+impl OutputFormat {
+    /// How tiles are referenced, if this is an SVG format.
+    #[cfg(feature = "svg")]
+    pub fn svg_images(&self) -> Option<SvgImages> {
+        match self {
+            OutputFormat::Jpeg => None,
+            OutputFormat::SvgLinked => Some(SvgImages::Linked),
+            OutputFormat::SvgEmbedded => Some(SvgImages::Embedded),
+        }
+    }
+}
+
+/// Load a build description from a TOML file.
+pub fn load_config(path: &Path) -> IoResult<BuildConfig> {
+    let text = read_to_string(path)?;
+    let config: BuildConfig = parse_config(&text)?;
+    let base = path.parent().unwrap_or(Path::new(""));
+    Ok(config.relative_to(base))
+}
+
+/// Load a description of builds to compare from a TOML file.
+pub fn load_compare_config(path: &Path) -> IoResult<CompareConfig> {
+    let text = read_to_string(path)?;
+    let config: CompareConfig = parse_config(&text)?;
+    let base = path.parent().unwrap_or(Path::new(""));
+    Ok(config.relative_to(base))
+}
+
+#[cfg(feature = "config")]
+fn parse_config<C: DeserializeOwned>(text: &str) -> IoResult<C> {
+    toml::from_str(text).map_err(|e| IoError::new(ErrorKind::InvalidData, e))
+}
+
+/// Without the `config` feature there is no TOML parser to read configs.
+#[cfg(not(feature = "config"))]
+fn parse_config<C>(_text: &str) -> IoResult<C> {
+    Err(IoError::new(
+        ErrorKind::Unsupported,
+        "Reading config files needs the config feature",
+    ))
+}
+
+impl BuildConfig {
+    /// Resolve the relative paths in this config against the given directory.
+    fn relative_to(self, base: &Path) -> Self {
+        Self {
+            target: base.join(self.target),
+            libraries: self.libraries.iter().map(|l| base.join(l)).collect(),
+            mosaic: self.mosaic.relative_to(base),
+            ..self
+        }
+    }
+}
+
+impl CompareConfig {
+    /// Resolve the relative paths in this config against the given directory.
+    fn relative_to(self, base: &Path) -> Self {
+        Self {
+            target: base.join(self.target),
+            libraries: self.libraries.iter().map(|l| base.join(l)).collect(),
+            variants: self
+                .variants
+                .into_iter()
+                .map(|v| Variant {
+                    mosaic: v.mosaic.relative_to(base),
+                    ..v
+                })
+                .collect(),
+        }
+    }
+}
+
+impl MosaicOptions {
+    /// Resolve the relative paths in these settings against the given
+    /// directory.
+    fn relative_to(self, base: &Path) -> Self {
+        Self {
+            mask: self.mask.map(|m| Mask {
+                shape: match m.shape {
+                    MaskShape::Image(path) => MaskShape::Image(base.join(path)),
+                    MaskShape::Text(text) => MaskShape::Text(TextShape {
+                        font: base.join(&text.font),
+                        ..text
+                    }),
+                },
+                ..m
+            }),
+            pins: self
+                .pins
+                .into_iter()
+                .map(|p| Pin {
+                    tile: base.join(&p.tile),
+                    ..p
+                })
+                .collect(),
+            exclusions: self
+                .exclusions
+                .into_iter()
+                .map(|e| Exclusion {
+                    tiles: e.tiles.iter().map(|t| base.join(t)).collect(),
+                    ..e
+                })
+                .collect(),
+            frame: self.frame.map(|f| Frame {
+                caption_font: f.caption_font.map(|font| base.join(font)),
+                ..f
+            }),
+            preferred: self
+                .preferred
+                .into_iter()
+                .map(|p| Preference {
+                    tiles: p.tiles.iter().map(|t| base.join(t)).collect(),
+                    ..p
+                })
+                .collect(),
+            folder_weights: self
+                .folder_weights
+                .into_iter()
+                .map(|w| FolderWeight {
+                    folder: base.join(&w.folder),
+                    ..w
+                })
+                .collect(),
+            watermark: self.watermark.map(|w| Watermark {
+                mark: match w.mark {
+                    Mark::Image(path) => Mark::Image(base.join(path)),
+                    Mark::Text(text) => Mark::Text(TextMark {
+                        font: base.join(&text.font),
+                        ..text
+                    }),
+                },
+                ..w
+            }),
+            mipmaps: self.mipmaps.map(|m| Mipmaps {
+                dir: base.join(m.dir),
+                ..m
+            }),
+            ..self
+        }
+    }
+}
+
+#[cfg(all(test, feature = "config"))]
+mod test {
+    use super::*;
+    use crate::{
+        Background, Blend, BlendMode, CellRadii, ChannelWeights, Color, Corner, CostWeights, Date,
+        Exclusion, FolderWeight, Jitter, LibraryFilter, Penalty, Pin, PostProcess, Preference,
+        ProcessingOrder, ProtectedArea, Refinement, ResizeFilter, Seed, SmallTiles, TieBreak,
+    };
+
+    #[test]
+    fn test_parses_full_build_description() {
+        let config = parse_config::<BuildConfig>(
+            r#"
+            target = "target.jpg"
+            libraries = ["family", "/photos/stock"]
+            output = "svg-linked"
+
+            [mosaic]
+            analysis_size = 8
+            channel_weights = "luminance"
+            cell_size = 10
+            cell_budget = 2000
+            working_cells = 1500
+            tile_size = 50
+            strategy = "holistic"
+            order = "serpentine"
+            seed = 7
+            tie_break = "random"
+            candidates = 50
+            pyramid = true
+            reverse_pass = true
+            temperature = 0.3
+            match_luminance = true
+            linear_light = false
+            resize_filter = "lanczos3"
+            analysis_filter = "catmull-rom"
+            matched_layout = true
+            small_tiles = "exclude"
+            quantise = 8
+            protect_skin_tones = true
+            tile_inset = -2
+            feather = 3
+            blend = { mode = "soft-light", opacity = 0.6 }
+            background = '#202020'
+            post_process = [
+                { unsharp-mask = { sigma = 1.5 } },
+                { contrast = 10.0 },
+                { vignette = 0.2 },
+            ]
+
+            [mosaic.watermark]
+            mark = { text = { content = "Jo Bloggs", font = "fonts/sans.ttf" } }
+            corner = "top-left"
+            opacity = 0.8
+
+            [mosaic.penalty]
+            amount = 500
+            radius = 20
+            similar_within = 300
+            cell_radii = { horizontal = 3.0, vertical = 1.5 }
+
+            [mosaic.cost]
+            reuse = 5000
+            spatial = 0.5
+
+            [mosaic.refinement]
+            iterations = 1000
+            temperature = 10.0
+
+            [mosaic.mask]
+            shape = { image = "heart.png" }
+            background = '#ffffff'
+
+            [mosaic.mipmaps]
+            dir = "cache/mipmaps"
+
+            [mosaic.filter]
+            taken_from = "2023-01-01"
+            keywords = ["beach"]
+            min_rating = 3
+            include = ["2023-*"]
+            exclude = ["*.thumb.jpg"]
+
+            [[mosaic.pins]]
+            tile = "family/us.jpg"
+            at = [320, 240]
+
+            [[mosaic.exclusions]]
+            tiles = ["family/ex.jpg"]
+            x = 0
+            y = 0
+            width = 100
+            height = 50
+
+            [[mosaic.preferred]]
+            tiles = ["family/gran.jpg"]
+            bonus = 30
+
+            [[mosaic.folder_weights]]
+            folder = "stock"
+            weight = 0.5
+
+            [[mosaic.protected]]
+            x = 300
+            y = 200
+            width = 80
+            height = 100
+            divisions = 3
+
+            [[mosaic.protected]]
+            outline = [[10, 10], [60, 10], [35, 50]]
+            divisions = 1
+            cost = { reuse = 1000 }
+            penalty = { amount = 2000000 }
+
+            [mosaic.frame]
+            bottom = 0.25
+            color = '#fafafa'
+            caption_font = "fonts/hand.ttf"
+
+            [mosaic.jitter]
+            max_angle = 3.0
+            "#,
+        )
+        .unwrap()
+        .relative_to(Path::new("builds"));
+
+        assert_eq!(
+            config,
+            BuildConfig {
+                target: PathBuf::from("builds/target.jpg"),
+                libraries: vec![
+                    PathBuf::from("builds/family"),
+                    PathBuf::from("/photos/stock")
+                ],
+                output: OutputFormat::SvgLinked,
+                mosaic: MosaicOptions {
+                    analysis_size: 8,
+                    channel_weights: ChannelWeights::LUMINANCE,
+                    cell_size: 10,
+                    cell_budget: Some(2000),
+                    working_cells: Some(1500),
+                    tile_size: 50,
+                    strategy: "holistic".to_string(),
+                    penalty: Penalty {
+                        amount: 500,
+                        radius: 20,
+                        similar_within: Some(300),
+                        cell_radii: Some(CellRadii {
+                            horizontal: 3.0,
+                            vertical: 1.5,
+                        }),
+                    },
+                    order: ProcessingOrder::Serpentine,
+                    seed: Seed(7),
+                    tie_break: TieBreak::Random,
+                    candidates: 50,
+                    pyramid: true,
+                    reverse_pass: true,
+                    temperature: 0.3,
+                    cost: CostWeights {
+                        reuse: 5000.0,
+                        spatial: 0.5,
+                        ..CostWeights::default()
+                    },
+                    refinement: Refinement {
+                        iterations: 1000,
+                        temperature: 10.0,
+                        ..Refinement::default()
+                    },
+                    match_luminance: true,
+                    linear_light: false,
+                    resize_filter: ResizeFilter::Lanczos3,
+                    analysis_filter: ResizeFilter::CatmullRom,
+                    matched_layout: true,
+                    quantise: Some(8),
+                    mask: Some(Mask {
+                        shape: MaskShape::Image(PathBuf::from("builds/heart.png")),
+                        threshold: 0.5,
+                        background: Some(Color([255, 255, 255])),
+                    }),
+                    pins: vec![Pin {
+                        tile: PathBuf::from("builds/family/us.jpg"),
+                        at: (320, 240),
+                    }],
+                    exclusions: vec![Exclusion {
+                        tiles: vec![PathBuf::from("builds/family/ex.jpg")],
+                        x: 0,
+                        y: 0,
+                        width: 100,
+                        height: 50,
+                    }],
+                    preferred: vec![Preference {
+                        tiles: vec![PathBuf::from("builds/family/gran.jpg")],
+                        bonus: 30,
+                    }],
+                    folder_weights: vec![FolderWeight {
+                        folder: PathBuf::from("builds/stock"),
+                        weight: 0.5,
+                    }],
+                    protected: vec![
+                        ProtectedArea {
+                            x: 300,
+                            y: 200,
+                            width: 80,
+                            height: 100,
+                            outline: Vec::new(),
+                            divisions: 3,
+                            cost: None,
+                            penalty: None,
+                        },
+                        ProtectedArea {
+                            x: 0,
+                            y: 0,
+                            width: 0,
+                            height: 0,
+                            outline: vec![[10, 10], [60, 10], [35, 50]],
+                            divisions: 1,
+                            cost: Some(CostWeights {
+                                reuse: 1000.0,
+                                ..CostWeights::default()
+                            }),
+                            penalty: Some(Penalty {
+                                amount: 2_000_000,
+                                ..Penalty::default()
+                            }),
+                        }
+                    ],
+                    protect_skin_tones: true,
+                    frame: Some(Frame {
+                        bottom: 0.25,
+                        color: Color([250, 250, 250]),
+                        caption_font: Some(PathBuf::from("builds/fonts/hand.ttf")),
+                        ..Frame::default()
+                    }),
+                    jitter: Some(Jitter { max_angle: 3.0 }),
+                    tile_inset: -2,
+                    feather: 3,
+                    blend: Some(Blend {
+                        mode: BlendMode::SoftLight,
+                        opacity: 0.6,
+                    }),
+                    background: Some(Background::Color(Color([32, 32, 32]))),
+                    post_process: vec![
+                        PostProcess::UnsharpMask {
+                            sigma: 1.5,
+                            threshold: 0,
+                        },
+                        PostProcess::Contrast(10.0),
+                        PostProcess::Vignette(0.2),
+                    ],
+                    watermark: Some(Watermark {
+                        opacity: 0.8,
+                        ..Watermark::new(
+                            Mark::Text(TextMark {
+                                content: "Jo Bloggs".to_string(),
+                                font: PathBuf::from("builds/fonts/sans.ttf"),
+                                size: 24.0,
+                                color: Color([255, 255, 255]),
+                            }),
+                            Corner::TopLeft,
+                        )
+                    }),
+                    filter: LibraryFilter {
+                        taken_from: Some(Date {
+                            year: 2023,
+                            month: 1,
+                            day: 1,
+                        }),
+                        keywords: vec!["beach".to_string()],
+                        min_rating: Some(3),
+                        include: vec!["2023-*".parse().unwrap()],
+                        exclude: vec!["*.thumb.jpg".parse().unwrap()],
+                        ..LibraryFilter::default()
+                    },
+                    small_tiles: SmallTiles::Exclude,
+                    mipmaps: Some(Mipmaps::new(PathBuf::from("builds/cache/mipmaps"))),
+                },
+            }
+        );
+    }
+
+    #[test]
+    fn test_parses_comparison_of_variants() {
+        let config = parse_config::<CompareConfig>(
+            r#"
+            target = "target.jpg"
+            libraries = ["family"]
+
+            [[variants]]
+            label = "independent"
+
+            [[variants]]
+            label = "masked"
+            mosaic = { strategy = "holistic", mask = { shape = { image = "heart.png" } } }
+            "#,
+        )
+        .unwrap()
+        .relative_to(Path::new("builds"));
+
+        assert_eq!(config.target, PathBuf::from("builds/target.jpg"));
+        assert_eq!(
+            config.variants,
+            vec![
+                Variant {
+                    label: "independent".to_string(),
+                    mosaic: MosaicOptions::default(),
+                },
+                Variant {
+                    label: "masked".to_string(),
+                    mosaic: MosaicOptions {
+                        strategy: "holistic".to_string(),
+                        mask: Some(Mask {
+                            shape: MaskShape::Image(PathBuf::from("builds/heart.png")),
+                            threshold: 0.5,
+                            background: None,
+                        }),
+                        ..MosaicOptions::default()
+                    },
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_defaults_missing_settings() {
+        let config = parse_config::<BuildConfig>(
+            r#"
+            target = "target.jpg"
+            libraries = ["tiles"]
+
+            [mosaic]
+            strategy = "holistic"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.output, OutputFormat::Jpeg);
+        assert_eq!(
+            config.mosaic,
+            MosaicOptions {
+                strategy: "holistic".to_string(),
+                ..MosaicOptions::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_resolves_text_mask_font() {
+        let config = parse_config::<BuildConfig>(
+            r#"
+            target = "target.jpg"
+            libraries = ["tiles"]
+
+            [mosaic.mask.shape.text]
+            content = "LOVE"
+            font = "fonts/bold.ttf"
+            "#,
+        )
+        .unwrap()
+        .relative_to(Path::new("builds"));
+
+        assert_eq!(
+            config.mosaic.mask.unwrap().shape,
+            MaskShape::Text(TextShape {
+                content: "LOVE".to_string(),
+                font: PathBuf::from("builds/fonts/bold.ttf"),
+                size: None,
+            })
+        );
+    }
+
+    #[test]
+    fn test_rejects_unknown_settings() {
+        let result = parse_config::<BuildConfig>(
+            r#"
+            target = "target.jpg"
+            libraries = ["tiles"]
+
+            [mosaic]
+            cell_sise = 10
+            "#,
+        );
+
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+}
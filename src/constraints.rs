@@ -0,0 +1,543 @@
+use std::collections::HashMap;
+use std::fs::canonicalize;
+use std::io::{Error as IoError, ErrorKind, Result as IoResult};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+#[cfg(feature = "serde")]
+use serde::Deserialize;
+
+use crate::analysis::ImageInfo;
+use crate::core::Rectangle;
+
+/// A library image fixed to the cell of the target holding a point, such as
+/// a portrait at the centre.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(deny_unknown_fields))]
+pub struct Pin {
+    /// Library image to draw.
+    pub tile: PathBuf,
+    /// Point (in target pixels) inside the cell to draw it in.
+    pub at: (u32, u32),
+}
+
+impl FromStr for Pin {
+    type Err = String;
+
+    /// Parse a pin written as `tile=x,y`, such as `couple.jpg=960,540`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid pin '{s}': expected tile=x,y");
+        let (tile, at) = s.rsplit_once('=').ok_or_else(invalid)?;
+        match numbers(at)[..] {
+            [Some(x), Some(y)] if !tile.is_empty() => Ok(Pin {
+                tile: PathBuf::from(tile),
+                at: (x, y),
+            }),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// An area of the target some library images must not be drawn in.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(deny_unknown_fields))]
+pub struct Exclusion {
+    /// Library images kept out of the area.
+    pub tiles: Vec<PathBuf>,
+    /// Left edge (in target pixels) of the area.
+    pub x: u32,
+    /// Top edge (in target pixels) of the area.
+    pub y: u32,
+    /// Width (in target pixels) of the area.
+    pub width: u32,
+    /// Height (in target pixels) of the area.
+    pub height: u32,
+}
+
+impl FromStr for Exclusion {
+    type Err = String;
+
+    /// Parse an exclusion of one library image written as
+    /// `tile=x,y,width,height`, such as `ex.jpg=0,0,400,300`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid exclusion '{s}': expected tile=x,y,width,height");
+        let (tile, area) = s.rsplit_once('=').ok_or_else(invalid)?;
+        match numbers(area)[..] {
+            [Some(x), Some(y), Some(width), Some(height)] if !tile.is_empty() => Ok(Exclusion {
+                tiles: vec![PathBuf::from(tile)],
+                x,
+                y,
+                width,
+                height,
+            }),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// The comma separated numbers, each `None` if it isn't one.
+fn numbers(s: &str) -> Vec<Option<u32>> {
+    s.split(',').map(|n| n.trim().parse().ok()).collect()
+}
+
+/// Library images to favour over closer matches, such as photos of the
+/// people a mosaic is for.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(deny_unknown_fields))]
+pub struct Preference {
+    /// Library images to favour.
+    pub tiles: Vec<PathBuf>,
+    /// Percentage taken off the images' differences from each cell, from 0
+    /// (no preference) to 100 (chosen over any other match).
+    pub bonus: u8,
+}
+
+/// A weight for the library images in a folder, such as 2 for curated photos
+/// or 0.5 for filler, dividing their differences from each cell.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(deny_unknown_fields))]
+pub struct FolderWeight {
+    /// Folder of library images, including those in its subfolders.
+    pub folder: PathBuf,
+    /// How much more likely the images are to be chosen, above 0.
+    pub weight: f64,
+}
+
+impl FromStr for FolderWeight {
+    type Err = String;
+
+    /// Parse a weight written as `folder=weight`, such as `family=2`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid folder weight '{s}': expected folder=weight");
+        let (folder, weight) = s.rsplit_once('=').ok_or_else(invalid)?;
+        match weight.trim().parse::<f64>() {
+            Ok(weight) if weight.is_finite() && weight > 0.0 && !folder.is_empty() => {
+                Ok(FolderWeight {
+                    folder: PathBuf::from(folder),
+                    weight,
+                })
+            }
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// Pin library images to their cells and keep them out of their excluded
+/// areas, for every strategy to weigh, failing if any of them isn't in the
+/// library.
+pub(crate) fn apply_constraints(
+    pins: &[Pin],
+    exclusions: &[Exclusion],
+    library: &mut HashMap<&PathBuf, ImageInfo>,
+) -> IoResult<()> {
+    let find = &finder(library);
+    let pinned = pins
+        .iter()
+        .map(|pin| Ok((find(&pin.tile)?, pin.at)))
+        .collect::<IoResult<Vec<_>>>()?;
+    let excluded = exclusions
+        .iter()
+        .flat_map(|e| {
+            let area = Rectangle::new(e.x, e.y, e.width, e.height);
+            e.tiles.iter().map(move |tile| Ok((find(tile)?, area)))
+        })
+        .collect::<IoResult<Vec<_>>>()?;
+    for (tile, at) in pinned {
+        if let Some(info) = library.get_mut(tile) {
+            info.pin(at);
+        }
+    }
+    for (tile, area) in excluded {
+        if let Some(info) = library.get_mut(tile) {
+            info.exclude(area);
+        }
+    }
+    Ok(())
+}
+
+/// Give the preferred library images their bonus, failing if any of them
+/// isn't in the library.
+pub(crate) fn apply_preferences(
+    preferences: &[Preference],
+    library: &mut HashMap<&PathBuf, ImageInfo>,
+) -> IoResult<()> {
+    let find = finder(library);
+    let bonuses = preferences
+        .iter()
+        .flat_map(|p| p.tiles.iter().map(|tile| Ok((find(tile)?, p.bonus))))
+        .collect::<IoResult<Vec<_>>>()?;
+    for (tile, bonus) in bonuses {
+        if let Some(info) = library.get_mut(tile) {
+            info.set_bonus(bonus);
+        }
+    }
+    Ok(())
+}
+
+/// Weigh the library images in each weighted folder, by the deepest folder
+/// holding them, failing if a folder holds none of them.
+pub(crate) fn apply_folder_weights(
+    weights: &[FolderWeight],
+    library: &mut HashMap<&PathBuf, ImageInfo>,
+) -> IoResult<()> {
+    if weights.is_empty() {
+        return Ok(());
+    }
+    let folders: Vec<(PathBuf, &FolderWeight)> =
+        weights.iter().map(|w| (identity(&w.folder), w)).collect();
+    let mut used = vec![false; folders.len()];
+    for (path, info) in library.iter_mut() {
+        let path = identity(path);
+        let deepest = folders
+            .iter()
+            .enumerate()
+            .filter(|(_, (folder, _))| path.starts_with(folder))
+            .max_by_key(|(_, (folder, _))| folder.components().count());
+        if let Some((i, (_, folder_weight))) = deepest {
+            info.set_weight(folder_weight.weight);
+            used[i] = true;
+        }
+    }
+    match folders.iter().zip(used).find(|(_, used)| !used) {
+        Some(((_, unused), _)) => {
+            let message = format!("No library images in: {}", unused.folder.display());
+            Err(IoError::new(ErrorKind::InvalidInput, message))
+        }
+        None => Ok(()),
+    }
+}
+
+/// Look up library tiles by path, failing for paths not in the library.
+fn finder<'a>(
+    library: &HashMap<&'a PathBuf, ImageInfo>,
+) -> impl Fn(&Path) -> IoResult<&'a PathBuf> {
+    let tiles: HashMap<PathBuf, &'a PathBuf> =
+        library.keys().map(|path| (identity(path), *path)).collect();
+    move |path| {
+        tiles.get(&identity(path)).copied().ok_or_else(|| {
+            let message = format!("Not in the library: {}", path.display());
+            IoError::new(ErrorKind::InvalidInput, message)
+        })
+    }
+}
+
+/// The path to compare library images by, so that differently written paths
+/// to the same file match.
+fn identity(path: &Path) -> PathBuf {
+    canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::analysis::{analyse, AnalysisOptions};
+    #[cfg(feature = "rand")]
+    use crate::refine::Refinement;
+    use crate::strategy::{build_strategy, strategy_names, Penalty, StrategyOptions};
+    use crate::test_support::fixture;
+    use image::{Rgba, RgbaImage};
+
+    /// Solid red, dark red, darker red, and blue tiles.
+    fn reds_and_blue<'a>(
+        names: &'a [String; 4],
+        options: &StrategyOptions,
+    ) -> HashMap<&'a String, ImageInfo> {
+        let colors = [[255, 0, 0], [200, 0, 0], [150, 0, 0], [0, 0, 255]];
+        names
+            .iter()
+            .zip(colors)
+            .map(|(name, [r, g, b])| {
+                let img = RgbaImage::from_pixel(20, 20, Rgba([r, g, b, 255]));
+                (name, analyse(&img, &options.analysis))
+            })
+            .collect()
+    }
+
+    /// The tiles the strategy chooses for a red target of three cells in a
+    /// row, by the left edge of their cells.
+    fn choose_along_red_row(
+        name: &str,
+        analysis: &HashMap<&String, ImageInfo>,
+        options: &StrategyOptions,
+    ) -> Vec<(String, i64)> {
+        let target = RgbaImage::from_pixel(60, 20, Rgba([255, 0, 0, 255]));
+        let strategy = build_strategy(name, analysis, options).unwrap();
+        let mut tiles: Vec<(String, i64)> = strategy
+            .choose(&target, &(20, 20))
+            .into_iter()
+            .map(|(t, r)| (t.clone(), r.x))
+            .collect();
+        tiles.sort_by_key(|(_, x)| *x);
+        tiles
+    }
+
+    #[test]
+    fn test_pins_tiles_and_keeps_excluded_tiles_out() {
+        let names = ["red", "dark red", "darker red", "blue"].map(String::from);
+        let [red, dark_red, _, blue] = &names;
+        let options = StrategyOptions {
+            analysis: AnalysisOptions::new(Some(1)),
+            penalty: Penalty {
+                amount: 0,
+                ..Penalty::default()
+            },
+            ..StrategyOptions::default()
+        };
+        let mut analysis = reds_and_blue(&names, &options);
+        // Blue in the middle cell, red kept out of the last two cells.
+        analysis.get_mut(blue).unwrap().pin((25, 10));
+        analysis
+            .get_mut(red)
+            .unwrap()
+            .exclude(Rectangle::new(30, 0, 30, 20));
+
+        for name in strategy_names() {
+            assert_eq!(
+                choose_along_red_row(name, &analysis, &options),
+                [(red, 0), (blue, 20), (dark_red, 40)].map(|(t, x)| (t.clone(), x)),
+                "{name}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_penalises_duplicates_of_pinned_tiles() {
+        let names = ["red", "dark red", "darker red", "blue"].map(String::from);
+        let [red, ..] = &names;
+        let options = StrategyOptions {
+            analysis: AnalysisOptions::new(Some(1)),
+            ..StrategyOptions::default()
+        };
+        let mut analysis = reds_and_blue(&names, &options);
+        // Red in the last cell, chosen after the others without the pin.
+        analysis.get_mut(red).unwrap().pin((45, 10));
+
+        let tiles = choose_along_red_row("holistic", &analysis, &options);
+
+        assert_eq!(tiles[2], (red.clone(), 40));
+        assert!(tiles[..2].iter().all(|(t, _)| t != red), "{tiles:?}");
+        assert_ne!(tiles[0].0, tiles[1].0);
+    }
+
+    #[test]
+    fn test_spreads_out_tiles_replacing_excluded_ones() {
+        let names = ["red", "dark red", "darker red", "blue"].map(String::from);
+        let [red, dark_red, darker_red, _] = &names;
+        let options = StrategyOptions {
+            analysis: AnalysisOptions::new(Some(1)),
+            ..StrategyOptions::default()
+        };
+        let mut analysis = reds_and_blue(&names, &options);
+        analysis
+            .get_mut(red)
+            .unwrap()
+            .exclude(Rectangle::new(0, 0, 40, 20));
+
+        let tiles = choose_along_red_row("holistic", &analysis, &options);
+
+        // The closest replacement isn't used for both excluded cells.
+        assert_eq!(
+            tiles,
+            [(dark_red, 0), (darker_red, 20), (red, 40)].map(|(t, x)| (t.clone(), x))
+        );
+    }
+
+    #[cfg(feature = "rand")]
+    #[test]
+    fn test_refinement_keeps_pins_and_exclusions() {
+        let names = ["red", "dark red", "darker red", "blue"].map(String::from);
+        let [red, _, _, blue] = &names;
+        let options = StrategyOptions {
+            analysis: AnalysisOptions::new(Some(1)),
+            refinement: Refinement {
+                iterations: 200,
+                temperature: 1_000_000.0,
+                ..Refinement::default()
+            },
+            ..StrategyOptions::default()
+        };
+        let mut analysis = reds_and_blue(&names, &options);
+        analysis.get_mut(blue).unwrap().pin((25, 10));
+        analysis
+            .get_mut(red)
+            .unwrap()
+            .exclude(Rectangle::new(40, 0, 20, 20));
+
+        // Worse changes are accepted early on, but never ones breaking them.
+        let tiles = choose_along_red_row("independent", &analysis, &options);
+
+        assert_eq!(tiles[1], (blue.clone(), 20));
+        assert_ne!(&tiles[2].0, red);
+    }
+
+    #[cfg(feature = "rand")]
+    #[test]
+    fn test_temperature_keeps_pins_and_exclusions() {
+        let names = ["red", "dark red", "darker red", "blue"].map(String::from);
+        let [red, dark_red, darker_red, blue] = &names;
+        let options = StrategyOptions {
+            analysis: AnalysisOptions::new(Some(1)),
+            temperature: 1.0,
+            ..StrategyOptions::default()
+        };
+        let mut analysis = reds_and_blue(&names, &options);
+        analysis.get_mut(blue).unwrap().pin((5, 5));
+        for tile in [red, dark_red, blue] {
+            analysis
+                .get_mut(tile)
+                .unwrap()
+                .exclude(Rectangle::new(20, 0, 40, 20));
+        }
+
+        // Without the exclusions the last cells could have any of the reds.
+        let tiles = choose_along_red_row("independent", &analysis, &options);
+
+        assert_eq!(
+            tiles,
+            [(blue, 0), (darker_red, 20), (darker_red, 40)].map(|(t, x)| (t.clone(), x))
+        );
+    }
+
+    #[test]
+    fn test_preferred_tiles_beat_closer_matches() {
+        let (red, dark_red) = ("red".to_string(), "dark red".to_string());
+        let options = StrategyOptions {
+            analysis: AnalysisOptions::new(Some(1)),
+            ..StrategyOptions::default()
+        };
+        let solid = |c| analyse(&RgbaImage::from_pixel(20, 20, Rgba(c)), &options.analysis);
+        let mut analysis = HashMap::from([
+            (&red, solid([255, 0, 0, 255])),
+            (&dark_red, solid([200, 0, 0, 255])),
+        ]);
+        let target = RgbaImage::from_pixel(20, 20, Rgba([230, 0, 0, 255]));
+        let choose = |analysis: &HashMap<&String, ImageInfo>| {
+            let strategy = build_strategy("independent", analysis, &options).unwrap();
+            let tiles: Vec<String> = strategy
+                .choose(&target, &(20, 20))
+                .into_iter()
+                .map(|(t, _)| t.clone())
+                .collect();
+            tiles
+        };
+
+        assert_eq!(choose(&analysis), vec![red.clone()]);
+        // Off by 30 rather than 25, but the bonus more than makes up for it.
+        analysis.get_mut(&dark_red).unwrap().set_bonus(50);
+        assert_eq!(choose(&analysis), vec![dark_red.clone()]);
+
+        // Likewise when its folder is weighed twice as heavily instead.
+        analysis.get_mut(&dark_red).unwrap().set_bonus(0);
+        analysis.get_mut(&dark_red).unwrap().set_weight(2.0);
+        assert_eq!(choose(&analysis), vec![dark_red.clone()]);
+    }
+
+    #[test]
+    fn test_resolves_constraints_to_library_tiles() {
+        let dir = fixture("library");
+        let (first, second) = (dir.join("0.png"), dir.join("1.png"));
+        let info = analyse(&RgbaImage::new(1, 1), &AnalysisOptions::new(Some(1)));
+        let library = HashMap::from([(&first, info.clone()), (&second, info)]);
+        let pin = |tile: PathBuf| Pin { tile, at: (0, 0) };
+
+        // The same file, written differently.
+        let mut library = library;
+        let exclusion = Exclusion {
+            tiles: vec![dir.join("./0.png")],
+            x: 10,
+            y: 0,
+            width: 10,
+            height: 10,
+        };
+        let pins = [pin(dir.join("../library/1.png"))];
+        apply_constraints(&pins, &[exclusion], &mut library).unwrap();
+        let cell = Rectangle::new(0, 0, 10, 10);
+        assert!(library[&second].is_pinned_to(&cell) && !library[&first].is_pinned_to(&cell));
+        assert!(library[&first].is_excluded_from(&Rectangle::new(5, 5, 10, 10)));
+        assert!(!library[&first].is_excluded_from(&cell));
+
+        let missing = apply_constraints(&[pin(dir.join("missing.png"))], &[], &mut library);
+        assert_eq!(
+            missing.err().map(|e| e.kind()),
+            Some(ErrorKind::InvalidInput)
+        );
+
+        let prefer = |tiles| Preference { tiles, bonus: 40 };
+        apply_preferences(&[prefer(vec![dir.join("./0.png")])], &mut library).unwrap();
+        assert_eq!((library[&first].bonus(), library[&second].bonus()), (40, 0));
+        let missing = apply_preferences(&[prefer(vec![dir.join("missing.png")])], &mut library);
+        assert_eq!(
+            missing.err().map(|e| e.kind()),
+            Some(ErrorKind::InvalidInput)
+        );
+    }
+
+    #[test]
+    fn test_parses_pins_and_exclusions() {
+        assert_eq!(
+            "photos/us.jpg=960, 540".parse(),
+            Ok(Pin {
+                tile: PathBuf::from("photos/us.jpg"),
+                at: (960, 540)
+            })
+        );
+        assert!("us.jpg=960".parse::<Pin>().is_err());
+        assert!("=960,540".parse::<Pin>().is_err());
+        assert_eq!(
+            "ex.jpg=0,0,400,300".parse(),
+            Ok(Exclusion {
+                tiles: vec![PathBuf::from("ex.jpg")],
+                x: 0,
+                y: 0,
+                width: 400,
+                height: 300
+            })
+        );
+        assert!("ex.jpg=0,0,400".parse::<Exclusion>().is_err());
+        assert!("ex.jpg=0,0,-4,300".parse::<Exclusion>().is_err());
+    }
+
+    #[test]
+    fn test_weighs_tiles_by_folder() {
+        assert_eq!(
+            "photos/family=2".parse(),
+            Ok(FolderWeight {
+                folder: PathBuf::from("photos/family"),
+                weight: 2.0
+            })
+        );
+        assert!("family".parse::<FolderWeight>().is_err());
+        assert!("family=0".parse::<FolderWeight>().is_err());
+        assert!("=2".parse::<FolderWeight>().is_err());
+
+        let fixtures = fixture("");
+        let (tile, mask) = (fixtures.join("library/0.png"), fixtures.join("mask.png"));
+        let info = analyse(&RgbaImage::new(1, 1), &AnalysisOptions::new(Some(1)));
+        let mut library = HashMap::from([(&tile, info.clone()), (&mask, info)]);
+        let weight = |folder: &Path, weight| FolderWeight {
+            folder: folder.to_path_buf(),
+            weight,
+        };
+
+        // The deepest folder holding an image decides its weight.
+        let weights = [
+            weight(&fixtures, 0.5),
+            weight(&fixtures.join("library"), 2.0),
+        ];
+        apply_folder_weights(&weights, &mut library).unwrap();
+        assert_eq!(
+            (library[&tile].weigh(900), library[&mask].weigh(900)),
+            (450, 1800)
+        );
+
+        let missing = apply_folder_weights(&[weight(&fixtures.join("missing"), 2.0)], &mut library);
+        assert_eq!(
+            missing.err().map(|e| e.kind()),
+            Some(ErrorKind::InvalidInput)
+        );
+    }
+}
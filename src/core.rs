@@ -1,18 +1,29 @@
+use std::num::TryFromIntError;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
 /// Alias for width and height
 pub type Dimensions = (u32, u32);
 
 /// Convenience type alias for a tile and where to draw it
 pub type TileLocation<'a, T, U> = (&'a T, U);
 
-#[derive(Eq, PartialEq, Debug, Hash)]
+/// An area of an image, such as a cell of the target, in pixels from its
+/// top left corner.
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Rectangle {
+    /// Pixels from the left edge of the image.
     pub x: u32,
+    /// Pixels from the top edge of the image.
     pub y: u32,
     pub width: u32,
     pub height: u32,
 }
 
 impl Rectangle {
+    /// The area of the given size with its top left corner at the point.
     pub fn new(x: u32, y: u32, width: u32, height: u32) -> Self {
         Self {
             x,
@@ -21,17 +32,77 @@ impl Rectangle {
             height,
         }
     }
+
+    /// Number of pixels in the rectangle.
+    pub fn area(&self) -> u64 {
+        u64::from(self.width) * u64::from(self.height)
+    }
+
+    /// Whether the pixel at the point is in the rectangle.
+    pub fn contains(&self, (x, y): (u32, u32)) -> bool {
+        PixelRegion::from(self).contains((x.into(), y.into()))
+    }
+
+    /// The part of the rectangle also in the other one, if any.
+    pub fn intersect(&self, other: &Rectangle) -> Option<Rectangle> {
+        let overlap = PixelRegion::from(self).intersect(&PixelRegion::from(other))?;
+        Rectangle::try_from(overlap).ok()
+    }
+}
+
+impl TryFrom<PixelRegion> for Rectangle {
+    type Error = TryFromIntError;
+
+    /// The rectangle covering the same pixels, failing if the region starts
+    /// off the image or too far from its corner for a `Rectangle`.
+    fn try_from(region: PixelRegion) -> Result<Self, Self::Error> {
+        let x = u32::try_from(region.x)?;
+        let y = u32::try_from(region.y)?;
+        Ok(Rectangle::new(x, y, region.width, region.height))
+    }
+}
+
+/// The column and row of a cell in the grid a target is split into.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct CellCoords {
+    pub column: u32,
+    pub row: u32,
+}
+
+impl CellCoords {
+    /// The coordinates of the cell, given the size of the grid's cells.
+    pub fn of(cell: &Rectangle, (width, height): Dimensions) -> Self {
+        Self {
+            column: cell.x / width.max(1),
+            row: cell.y / height.max(1),
+        }
+    }
+
+    /// The coordinates the given number of columns across and rows down,
+    /// or `None` if that is off the left or top of the grid.
+    pub fn offset(&self, columns: i64, rows: i64) -> Option<Self> {
+        let column = u32::try_from(i64::from(self.column) + columns).ok()?;
+        let row = u32::try_from(i64::from(self.row) + rows).ok()?;
+        Some(Self { column, row })
+    }
 }
 
-/// The position of a tile expressed in terms of pixel coords.
+/// The position of a tile expressed in terms of pixel coords, such as the
+/// output region of a mosaic's placements. Unlike a `Rectangle` it may
+/// start off the top or left of the image.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct PixelRegion {
+    /// Pixels from the left edge of the image, negative if it starts off it.
     pub x: i64,
+    /// Pixels from the top edge of the image, negative if it starts off it.
     pub y: i64,
     pub width: u32,
     pub height: u32,
 }
 
 impl PixelRegion {
+    /// The region of the given size with its top left corner at the point.
     pub fn new(x: i64, y: i64, width: u32, height: u32) -> Self {
         Self {
             x,
@@ -41,56 +112,315 @@ impl PixelRegion {
         }
     }
 
-    pub fn from(r: &Rectangle) -> Self {
-        Self::new(r.x.into(), r.y.into(), r.width, r.height)
+    /// Position of the column just past the right edge.
+    pub fn right(&self) -> i64 {
+        self.x + i64::from(self.width)
     }
 
-    pub fn scale(&self, ratio: u32) -> Self {
+    /// Position of the row just below the bottom edge.
+    pub fn bottom(&self) -> i64 {
+        self.y + i64::from(self.height)
+    }
+
+    /// Number of pixels in the region.
+    pub fn area(&self) -> u64 {
+        u64::from(self.width) * u64::from(self.height)
+    }
+
+    /// Whether the pixel at the point is in the region.
+    pub fn contains(&self, (x, y): (i64, i64)) -> bool {
+        (self.x..self.right()).contains(&x) && (self.y..self.bottom()).contains(&y)
+    }
+
+    /// The part of the region also in the other one, if any.
+    pub fn intersect(&self, other: &PixelRegion) -> Option<PixelRegion> {
+        let (left, top) = (self.x.max(other.x), self.y.max(other.y));
+        let (right, bottom) = (
+            self.right().min(other.right()),
+            self.bottom().min(other.bottom()),
+        );
+        if left >= right || top >= bottom {
+            return None;
+        }
+        let width = u32::try_from(right - left).ok()?;
+        let height = u32::try_from(bottom - top).ok()?;
+        Some(PixelRegion::new(left, top, width, height))
+    }
+
+    /// The part of the region on an image of the given size, if any.
+    pub fn clip_to(&self, (width, height): Dimensions) -> Option<Rectangle> {
+        let inside = self.intersect(&PixelRegion::new(0, 0, width, height))?;
+        Rectangle::try_from(inside).ok()
+    }
+
+    /// The region moved across and down by the amounts, or `None` if that
+    /// would overflow.
+    pub fn translate(&self, dx: i64, dy: i64) -> Option<PixelRegion> {
+        let x = self.x.checked_add(dx)?;
+        let y = self.y.checked_add(dy)?;
+        x.checked_add(i64::from(self.width))?;
+        y.checked_add(i64::from(self.height))?;
+        Some(PixelRegion::new(x, y, self.width, self.height))
+    }
+
+    /// Scale the region by a ratio that needn't be whole. Each edge is placed
+    /// at the scaled position of the pixel it starts from, rounded down, so
+    /// that neighbouring regions share their edges whatever the ratio.
+    pub fn scale_f32(&self, ratio: f32) -> Self {
+        self.map_edges(|v| scaled_position(v, ratio))
+    }
+
+    /// The region that scaling by the ratio turns into this one, for ratios
+    /// of at least 1.
+    pub fn unscale_f32(&self, ratio: f32) -> Self {
+        self.map_edges(|v| unscaled_position(v, ratio))
+    }
+
+    /// The region between the edges each moved by the function, which
+    /// mustn't move them past each other, clamping sizes too large to hold.
+    fn map_edges(&self, f: impl Fn(i64) -> i64) -> Self {
+        let (left, top) = (f(self.x), f(self.y));
+        let (right, bottom) = (f(self.right()), f(self.bottom()));
+        let length = |start: i64, end: i64| u32::try_from(end - start).unwrap_or(u32::MAX);
+        Self::new(left, top, length(left, right), length(top, bottom))
+    }
+
+    /// Move each edge inwards by the amount, or outwards if it is negative,
+    /// keeping at least a pixel.
+    pub fn inset(&self, amount: i32) -> Self {
+        let shrink = |length: u32| (i64::from(length) - 2 * i64::from(amount)).max(1) as u32;
         Self::new(
-            self.x * (ratio as i64),
-            self.y * (ratio as i64),
-            self.width * ratio,
-            self.height * ratio,
+            self.x + i64::from(amount),
+            self.y + i64::from(amount),
+            shrink(self.width),
+            shrink(self.height),
         )
     }
 }
 
+impl From<&Rectangle> for PixelRegion {
+    fn from(r: &Rectangle) -> Self {
+        Self::new(r.x.into(), r.y.into(), r.width, r.height)
+    }
+}
+
+impl From<Rectangle> for PixelRegion {
+    fn from(r: Rectangle) -> Self {
+        Self::from(&r)
+    }
+}
+
+/// The position, in whole pixels, that a position lands on when scaled by
+/// the ratio. Positions within rounding error of a whole pixel count as on
+/// it, so that cells land on multiples of the tile size even when the ratio
+/// can't be held exactly as a float.
+fn scaled_position(v: i64, ratio: f32) -> i64 {
+    let position = v as f64 * f64::from(ratio);
+    let nearest = position.round();
+    if (position - nearest).abs() <= position.abs() * 1e-6 {
+        nearest as i64
+    } else {
+        position.floor() as i64
+    }
+}
+
+/// The first position that scaling by the ratio places at or after the
+/// given one, which is the position it was scaled from for ratios of at
+/// least 1.
+fn unscaled_position(v: i64, ratio: f32) -> i64 {
+    let mut position = (v as f64 / f64::from(ratio)).floor() as i64;
+    while scaled_position(position, ratio) < v {
+        position += 1;
+    }
+    while scaled_position(position - 1, ratio) >= v {
+        position -= 1;
+    }
+    position
+}
+
 /// Extension trait for TileLocation (since it's a built in type)
 pub trait TileLocationExtensions<T, U> {
     /// Scale the size and position of the tile location
-    fn scale(&self, ratio: u32) -> TileLocation<T, U>;
+    fn scale(&self, ratio: f32) -> TileLocation<'_, T, U>;
 }
 
 impl<T> TileLocationExtensions<T, PixelRegion> for TileLocation<'_, T, PixelRegion> {
-    fn scale(&self, ratio: u32) -> TileLocation<T, PixelRegion> {
+    fn scale(&self, ratio: f32) -> TileLocation<'_, T, PixelRegion> {
         let (p, region) = self;
-        (p, region.scale(ratio))
+        (p, region.scale_f32(ratio))
     }
 }
 
-/// Extension trait for homogenous tuple pairs (since it's a built in type)
-pub trait TupleExtensions<T> {
-    /// Map the values inside the tuple
-    fn map<F, U>(&self, f: F) -> (U, U)
-    where
-        F: Fn(&T) -> U;
-
-    /// Scale the numeric values inside the tuple
-    fn scale(&self, ratio: T) -> (T, T);
-}
-
-impl<T> TupleExtensions<T> for (T, T)
+/// Every pair of an item from the outer iterator and one from the inner, in
+/// row major order.
+pub(crate) fn product<A, B, I>(outer: A, inner: I) -> impl Iterator<Item = (A::Item, B::Item)>
 where
-    T: std::ops::Mul<Output = T> + Copy,
+    A: IntoIterator,
+    A::Item: Clone,
+    I: IntoIterator<IntoIter = B>,
+    B: Iterator + Clone,
 {
-    fn map<F, U>(&self, f: F) -> (U, U)
-    where
-        F: Fn(&T) -> U,
-    {
-        (f(&self.0), f(&self.1))
+    let inner = inner.into_iter();
+    outer
+        .into_iter()
+        .flat_map(move |a| inner.clone().map(move |b| (a.clone(), b)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn corners(r: &PixelRegion) -> (i64, i64, i64, i64) {
+        let (x, y) = (r.x, r.y);
+        (x, y, x + i64::from(r.width), y + i64::from(r.height))
+    }
+
+    proptest! {
+        #[test]
+        fn test_scaling_keeps_neighbouring_regions_touching(
+            x in 0u32..1000,
+            y in 0u32..1000,
+            width in 1u32..100,
+            height in 1u32..100,
+            ratio in 1u32..20,
+        ) {
+            let region = PixelRegion::from(&Rectangle::new(x, y, width, height));
+            let right = PixelRegion::from(&Rectangle::new(x + width, y, width, height));
+            let below = PixelRegion::from(&Rectangle::new(x, y + height, width, height));
+
+            let (left, top, end_x, end_y) = corners(&region.scale_f32(ratio as f32));
+
+            prop_assert_eq!(corners(&region.scale_f32(1.0)), corners(&region));
+            prop_assert_eq!((left, top), (region.x * i64::from(ratio), region.y * i64::from(ratio)));
+            prop_assert_eq!(end_x, corners(&right.scale_f32(ratio as f32)).0);
+            prop_assert_eq!(end_y, corners(&below.scale_f32(ratio as f32)).1);
+        }
+
+        #[test]
+        fn test_scaling_composes(
+            x in -1000i64..1000,
+            y in -1000i64..1000,
+            width in 0u32..100,
+            height in 0u32..100,
+            a in 1u32..20,
+            b in 1u32..20,
+        ) {
+            let region = PixelRegion::new(x, y, width, height);
+
+            prop_assert_eq!(
+                corners(&region.scale_f32(a as f32).scale_f32(b as f32)),
+                corners(&region.scale_f32((a * b) as f32))
+            );
+        }
+
+        #[test]
+        fn test_scaling_by_fractions_keeps_neighbouring_regions_touching(
+            x in 0u32..1000,
+            y in 0u32..1000,
+            width in 1u32..100,
+            height in 1u32..100,
+            ratio in 1.0f32..20.0,
+        ) {
+            let region = PixelRegion::from(&Rectangle::new(x, y, width, height));
+            let right = PixelRegion::from(&Rectangle::new(x + width, y, width, height));
+            let below = PixelRegion::from(&Rectangle::new(x, y + height, width, height));
+
+            let (_, _, scaled_x, scaled_y) = corners(&region.scale_f32(ratio));
+
+            prop_assert_eq!(scaled_x, corners(&right.scale_f32(ratio)).0);
+            prop_assert_eq!(scaled_y, corners(&below.scale_f32(ratio)).1);
+            let whole = ratio.floor() as i64;
+            let (left, top, end_x, end_y) = corners(&region);
+            prop_assert_eq!(
+                corners(&region.scale_f32(ratio.floor())),
+                (left * whole, top * whole, end_x * whole, end_y * whole)
+            );
+            prop_assert_eq!(corners(&region.scale_f32(ratio).unscale_f32(ratio)), corners(&region));
+        }
     }
 
-    fn scale(&self, ratio: T) -> (T, T) {
-        self.map(|v| *v * ratio)
+    #[test]
+    fn test_insets_and_outsets_regions() {
+        let region = PixelRegion::new(10, 20, 30, 40);
+
+        assert_eq!(region.inset(5), PixelRegion::new(15, 25, 20, 30));
+        assert_eq!(region.inset(-5), PixelRegion::new(5, 15, 40, 50));
+        assert_eq!(region.inset(0), region);
+        assert_eq!(region.inset(20).width, 1);
+    }
+
+    #[test]
+    fn test_converts_between_rectangles_and_regions() {
+        let rectangle = Rectangle::new(3, 4, 10, 20);
+        let region = PixelRegion::from(&rectangle);
+
+        assert_eq!(region, PixelRegion::new(3, 4, 10, 20));
+        assert_eq!(Rectangle::try_from(region), Ok(rectangle));
+        assert!(Rectangle::try_from(PixelRegion::new(-1, 4, 10, 20)).is_err());
+        assert!(Rectangle::try_from(PixelRegion::new(1 << 40, 0, 1, 1)).is_err());
+    }
+
+    #[test]
+    fn test_measures_and_moves_regions() {
+        let region = PixelRegion::new(-5, 10, 20, 10);
+
+        assert_eq!(
+            (region.right(), region.bottom(), region.area()),
+            (15, 20, 200)
+        );
+        assert!(region.contains((-5, 10)));
+        assert!(region.contains((14, 19)));
+        assert!(!region.contains((15, 19)));
+        assert!(!region.contains((0, 20)));
+        assert_eq!(
+            region.intersect(&PixelRegion::new(10, 0, 100, 15)),
+            Some(PixelRegion::new(10, 10, 5, 5))
+        );
+        assert_eq!(region.intersect(&PixelRegion::new(15, 10, 5, 5)), None);
+        assert_eq!(
+            region.translate(5, -10),
+            Some(PixelRegion::new(0, 0, 20, 10))
+        );
+        assert_eq!(region.translate(i64::MAX, 0), None);
+        assert_eq!(
+            PixelRegion::new(i64::MAX - 5, 0, 10, 1).translate(0, 0),
+            None
+        );
+        assert_eq!(region.clip_to((10, 15)), Some(Rectangle::new(0, 10, 10, 5)));
+        assert_eq!(region.clip_to((10, 5)), None);
+        let widest = PixelRegion::new(0, 0, u32::MAX, 1).scale_f32(2.0);
+        assert_eq!((widest.width, widest.height), (u32::MAX, 2));
+
+        let rectangle = Rectangle::new(0, 0, 10, 10);
+        assert_eq!(rectangle.area(), 100);
+        assert!(rectangle.contains((9, 0)) && !rectangle.contains((10, 0)));
+        assert_eq!(
+            rectangle.intersect(&Rectangle::new(5, 8, 10, 10)),
+            Some(Rectangle::new(5, 8, 5, 2))
+        );
+        assert_eq!(rectangle.intersect(&Rectangle::new(10, 0, 5, 5)), None);
+    }
+
+    #[test]
+    fn test_scaling_by_fractions_tiles_rows_of_cells_exactly() {
+        for cell_size in 1u32..=30 {
+            for tile_size in cell_size..=200 {
+                let ratio = tile_size as f32 / cell_size as f32;
+                let cells: Vec<PixelRegion> = (0..12)
+                    .map(|i| PixelRegion::new(i64::from(i * cell_size), 0, cell_size, cell_size))
+                    .map(|cell| cell.scale_f32(ratio))
+                    .collect();
+                let case = format!("tiles of {tile_size} over cells of {cell_size}");
+
+                for (i, cell) in cells.iter().enumerate() {
+                    let start = i as i64 * i64::from(tile_size);
+                    assert_eq!((cell.x, cell.width), (start, tile_size), "{case}");
+                    assert_eq!(cell.height, tile_size, "{case}");
+                }
+                let part = PixelRegion::new(i64::from(cell_size) * 3, 0, 1, 1);
+                assert_eq!(part.scale_f32(ratio).unscale_f32(ratio), part, "{case}");
+            }
+        }
     }
 }
@@ -0,0 +1,258 @@
+use std::str::FromStr;
+
+#[cfg(feature = "serde")]
+use serde::Deserialize;
+
+use crate::analysis::ImageInfo;
+use crate::core::Dimensions;
+use crate::strategy::Penalty;
+
+/// A term of the cost of drawing a tile in a cell, lower being better.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Term {
+    /// How far the tile's samples are from the cell's.
+    Color,
+    /// A preferred tile's bonus, taken off its color difference.
+    Preference,
+    /// One for each other cell the tile is drawn in, however far away.
+    Reuse,
+    /// The duplicate penalty for each other cell the tile is drawn in,
+    /// falling with distance.
+    Spatial,
+}
+
+impl Term {
+    /// Every term, in the order costs are added up.
+    pub const ALL: [Term; 4] = [Term::Color, Term::Preference, Term::Reuse, Term::Spatial];
+
+    fn name(&self) -> &'static str {
+        match self {
+            Term::Color => "color",
+            Term::Preference => "preference",
+            Term::Reuse => "reuse",
+            Term::Spatial => "spatial",
+        }
+    }
+
+    /// This term's part of the cost of the tile matching a cell it differs
+    /// from by the given difference.
+    fn of_match(&self, tile: &ImageInfo, difference: i64) -> i64 {
+        match self {
+            Term::Color => difference,
+            Term::Preference => -(difference * i64::from(tile.bonus()) / 100),
+            Term::Reuse | Term::Spatial => 0,
+        }
+    }
+
+    /// This term's part of the cost added for a duplicate of the tile drawn
+    /// the given offset (in pixels across and down) away, from a cell of the
+    /// given size.
+    fn of_duplicate(&self, penalty: &Penalty, offset: (u32, u32), cell: Dimensions) -> i64 {
+        match self {
+            Term::Reuse => 1,
+            Term::Spatial => penalty.at(offset, cell),
+            Term::Color | Term::Preference => 0,
+        }
+    }
+}
+
+/// How much each term counts towards the cost of drawing a tile in a cell.
+///
+/// Parsed from `term=weight` pairs separated by commas, such as
+/// `color=1,reuse=5000`, any terms not given keeping their default weight.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
+pub struct CostWeights {
+    /// Scale of the color difference.
+    pub color: f64,
+    /// Scale of a preferred tile's bonus.
+    pub preference: f64,
+    /// Weight (in the same units as color differences) added for each other
+    /// cell the tile is drawn in, by strategies that track duplicates.
+    pub reuse: f64,
+    /// Scale of the duplicate penalty, by strategies that track duplicates.
+    pub spatial: f64,
+}
+
+impl Default for CostWeights {
+    fn default() -> Self {
+        Self {
+            color: 1.0,
+            preference: 1.0,
+            reuse: 0.0,
+            spatial: 1.0,
+        }
+    }
+}
+
+impl CostWeights {
+    /// How much the term counts.
+    pub fn weight(&self, term: Term) -> f64 {
+        match term {
+            Term::Color => self.color,
+            Term::Preference => self.preference,
+            Term::Reuse => self.reuse,
+            Term::Spatial => self.spatial,
+        }
+    }
+
+    fn weight_mut(&mut self, term: Term) -> &mut f64 {
+        match term {
+            Term::Color => &mut self.color,
+            Term::Preference => &mut self.preference,
+            Term::Reuse => &mut self.reuse,
+            Term::Spatial => &mut self.spatial,
+        }
+    }
+
+    /// The cost of drawing the tile in a cell it differs from by the given
+    /// difference, before any duplicates are counted.
+    pub(crate) fn of_match(&self, tile: &ImageInfo, difference: i64) -> i64 {
+        let difference = tile.weigh(difference);
+        self.add_up(|term| term.of_match(tile, difference))
+    }
+
+    /// The largest difference from a cell the tile can have for the cost of
+    /// drawing it there to still be at most the given cost, allowing for the
+    /// rounding of each term, or `None` if any difference might be.
+    pub(crate) fn difference_within(&self, tile: &ImageInfo, cost: i64) -> Option<i64> {
+        if self.color < 0.0 || self.preference < 0.0 {
+            return None;
+        }
+        // Each unit of counted difference adds at least this much, and
+        // rounding takes at most a unit of difference and one of cost off.
+        let rate = self.color - self.preference * f64::from(tile.bonus()) / 100.0;
+        let share = tile.cost_share();
+        if rate <= 0.0 || share <= 0.0 {
+            return None;
+        }
+        let most = ((cost as f64 + 1.0) / rate + 1.0) / share;
+        Some((most.ceil() as i64).saturating_add(1))
+    }
+
+    /// The cost added for a duplicate of a tile drawn the given offset (in
+    /// pixels across and down) away, from a cell of the given size.
+    pub(crate) fn of_duplicate(
+        &self,
+        penalty: &Penalty,
+        offset: (u32, u32),
+        cell: Dimensions,
+    ) -> i64 {
+        self.add_up(|term| term.of_duplicate(penalty, offset, cell))
+    }
+
+    /// Add up the terms, each scaled by its weight.
+    fn add_up<F: Fn(Term) -> i64>(&self, cost: F) -> i64 {
+        Term::ALL
+            .iter()
+            .map(|term| scale(cost(*term), self.weight(*term)))
+            .fold(0, i64::saturating_add)
+    }
+}
+
+impl FromStr for CostWeights {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut weights = CostWeights::default();
+        for pair in s.split(',') {
+            let invalid = || {
+                format!("Invalid cost weights '{s}': expected term=weight pairs of color, preference, reuse, or spatial")
+            };
+            let (name, weight) = pair.split_once('=').ok_or_else(invalid)?;
+            let term = Term::ALL
+                .into_iter()
+                .find(|t| t.name() == name.trim())
+                .ok_or_else(invalid)?;
+            let weight: f64 = weight.trim().parse().map_err(|_| invalid())?;
+            if !weight.is_finite() || weight < 0.0 {
+                return Err(invalid());
+            }
+            *weights.weight_mut(term) = weight;
+        }
+        Ok(weights)
+    }
+}
+
+/// The cost scaled by the weight, exactly when the weight is one.
+fn scale(cost: i64, weight: f64) -> i64 {
+    if weight == 1.0 {
+        cost
+    } else {
+        (cost as f64 * weight).round() as i64
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::analysis::{analyse, AnalysisOptions};
+    use image::{Rgba, RgbaImage};
+
+    #[test]
+    fn test_parses_weights() {
+        assert_eq!(
+            "reuse=5000, spatial=0.5".parse(),
+            Ok(CostWeights {
+                reuse: 5000.0,
+                spatial: 0.5,
+                ..CostWeights::default()
+            })
+        );
+        assert!("shape=2".parse::<CostWeights>().is_err());
+        assert!("color".parse::<CostWeights>().is_err());
+        assert!("color=-1".parse::<CostWeights>().is_err());
+    }
+
+    #[test]
+    fn test_combines_weighted_terms() {
+        let options = AnalysisOptions::new(Some(1));
+        let mut tile = analyse(&RgbaImage::from_pixel(1, 1, Rgba([0, 0, 0, 255])), &options);
+        tile.set_bonus(25);
+        let penalty = Penalty {
+            amount: 1000,
+            radius: 10,
+            similar_within: None,
+            cell_radii: None,
+        };
+
+        let defaults = CostWeights::default();
+        assert_eq!(defaults.of_match(&tile, 400), 300);
+        assert_eq!(defaults.of_duplicate(&penalty, (4, 6), (10, 10)), 500);
+
+        let weights = CostWeights {
+            color: 2.0,
+            preference: 0.0,
+            reuse: 50.0,
+            spatial: 0.5,
+        };
+        assert_eq!(weights.of_match(&tile, 400), 800);
+        assert_eq!(weights.of_duplicate(&penalty, (4, 6), (10, 10)), 300);
+    }
+
+    #[test]
+    fn test_limits_differences_within_a_cost() {
+        let options = AnalysisOptions::new(Some(1));
+        let mut tile = analyse(&RgbaImage::from_pixel(1, 1, Rgba([0, 0, 0, 255])), &options);
+        tile.set_bonus(30);
+        tile.set_weight(3.0);
+        let weights = CostWeights {
+            color: 1.5,
+            preference: 0.7,
+            ..CostWeights::default()
+        };
+
+        for cost in [0, 1, 17, 400, 123_456] {
+            let limit = weights.difference_within(&tile, cost).unwrap();
+            let within = (0..limit * 2).filter(|d| weights.of_match(&tile, *d) <= cost);
+            assert!(within.max().unwrap_or(0) <= limit, "{cost}");
+        }
+        let free = CostWeights {
+            color: 0.5,
+            preference: 2.0,
+            ..CostWeights::default()
+        };
+        assert_eq!(free.difference_within(&tile, 100), None);
+    }
+}
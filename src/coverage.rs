@@ -0,0 +1,222 @@
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::path::PathBuf;
+
+use image::{imageops, Pixel, RgbaImage};
+#[cfg(feature = "serde")]
+use serde::Serialize;
+
+use crate::analysis::{mean_color, ImageInfo};
+use crate::matching::grid;
+use crate::summary::clusters;
+
+/// Number of clusters the colors of the target's cells are grouped into.
+const REGIONS: usize = 8;
+
+/// Distance between colors within which a library image is a good match.
+const GOOD_MATCH: f64 = 40.0;
+
+/// How well the library covers the colors of a target, to show which photos
+/// would improve its mosaics most.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct Coverage {
+    /// The main colors of the target's cells, the most common first.
+    pub regions: Vec<ColorRegion>,
+}
+
+/// A group of similarly colored cells of the target, and how well the
+/// library matches them.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct ColorRegion {
+    /// Mean color of the cells.
+    pub color: [u8; 3],
+    /// Rough name of the color, such as "dark blue".
+    pub name: String,
+    /// Share (0 to 1) of the target's cells in the group.
+    pub share: f64,
+    /// Distance from the color to the mean color of the closest library
+    /// image, from 0 (the same) to about 441 (black and white).
+    pub nearest: f64,
+    /// Number of library images within a good match of the color.
+    pub matches: usize,
+}
+
+impl Coverage {
+    /// The regions with no library image a good match for them.
+    pub fn gaps(&self) -> impl Iterator<Item = &ColorRegion> {
+        self.regions.iter().filter(|r| r.matches == 0)
+    }
+}
+
+impl Display for Coverage {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        writeln!(f, "Share  Color    Closest  Matches  Name")?;
+        for r in &self.regions {
+            let [red, green, blue] = r.color;
+            writeln!(
+                f,
+                "{:>4.0}%  #{red:02x}{green:02x}{blue:02x}  {:>7.1}  {:>7}  {}",
+                r.share * 100.0,
+                r.nearest,
+                r.matches,
+                r.name
+            )?;
+        }
+        let gaps: Vec<String> = self
+            .gaps()
+            .map(|r| {
+                format!(
+                    "{:.0}% of the target is {} with no close library image; add more {} photos",
+                    r.share * 100.0,
+                    r.name,
+                    r.name
+                )
+            })
+            .collect();
+        if gaps.is_empty() {
+            write!(f, "The library has good matches for all the main colors")
+        } else {
+            write!(f, "{}", gaps.join("\n"))
+        }
+    }
+}
+
+/// Group the colors of the target's cells and find how well the library
+/// images match each group.
+pub(crate) fn measure(
+    target: &RgbaImage,
+    cell_size: u32,
+    library: &[(PathBuf, ImageInfo)],
+) -> Coverage {
+    let cells: Vec<[u8; 3]> = grid(target, &(cell_size, cell_size))
+        .iter()
+        .map(|r| {
+            let cell = imageops::crop_imm(target, r.x, r.y, r.width, r.height).to_image();
+            let samples: Vec<u8> = cell.pixels().flat_map(|p| p.to_rgb().0).collect();
+            mean_color(&samples)
+        })
+        .collect();
+    let library: Vec<[u8; 3]> = library.iter().map(|(_, info)| info.mean_color()).collect();
+
+    let total = cells.len().max(1) as f64;
+    let regions = clusters(&cells, REGIONS.min(cells.len()))
+        .into_iter()
+        .filter(|(_, size)| *size > 0)
+        .map(|(color, size)| {
+            let distances: Vec<f64> = library.iter().map(|c| distance(color, *c)).collect();
+            ColorRegion {
+                color,
+                name: name(color),
+                share: size as f64 / total,
+                nearest: distances.iter().copied().fold(f64::INFINITY, f64::min),
+                matches: distances.iter().filter(|d| **d <= GOOD_MATCH).count(),
+            }
+        })
+        .collect();
+    Coverage { regions }
+}
+
+fn distance(a: [u8; 3], b: [u8; 3]) -> f64 {
+    a.iter()
+        .zip(b)
+        .map(|(a, b)| (f64::from(*a) - f64::from(b)).powi(2))
+        .sum::<f64>()
+        .sqrt()
+}
+
+/// A rough name for the color, by its hue and lightness.
+fn name([r, g, b]: [u8; 3]) -> String {
+    let (r, g, b) = (
+        f64::from(r) / 255.0,
+        f64::from(g) / 255.0,
+        f64::from(b) / 255.0,
+    );
+    let (max, min) = (r.max(g).max(b), r.min(g).min(b));
+    let lightness = (max + min) / 2.0;
+    let delta = max - min;
+    let saturation = if delta == 0.0 {
+        0.0
+    } else {
+        delta / (1.0 - (2.0 * lightness - 1.0).abs())
+    };
+
+    if saturation < 0.15 || delta < 0.06 {
+        return match lightness {
+            l if l < 0.15 => "black",
+            l if l < 0.4 => "dark gray",
+            l if l < 0.7 => "gray",
+            l if l < 0.9 => "light gray",
+            _ => "white",
+        }
+        .to_string();
+    }
+
+    let sector = if max == r {
+        (g - b) / delta
+    } else if max == g {
+        (b - r) / delta + 2.0
+    } else {
+        (r - g) / delta + 4.0
+    };
+    let hue = match (sector * 60.0).rem_euclid(360.0) {
+        h if h < 15.0 => "red",
+        h if h < 45.0 => "orange",
+        h if h < 70.0 => "yellow",
+        h if h < 160.0 => "green",
+        h if h < 200.0 => "cyan",
+        h if h < 260.0 => "blue",
+        h if h < 300.0 => "purple",
+        h if h < 340.0 => "pink",
+        _ => "red",
+    };
+    match lightness {
+        l if l < 0.3 => format!("dark {hue}"),
+        l if l > 0.7 => format!("light {hue}"),
+        _ => hue.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::analysis::{analyse, AnalysisOptions};
+    use image::Rgba;
+
+    #[test]
+    fn test_names_colors() {
+        assert_eq!(name([20, 30, 110]), "dark blue");
+        assert_eq!(name([250, 120, 10]), "orange");
+        assert_eq!(name([200, 250, 200]), "light green");
+        assert_eq!(name([128, 128, 130]), "gray");
+        assert_eq!(name([5, 5, 5]), "black");
+    }
+
+    #[test]
+    fn test_finds_colors_the_library_lacks() {
+        // A target that is three quarters red and a quarter dark blue.
+        let target = RgbaImage::from_fn(40, 40, |x, y| {
+            if x >= 20 && y >= 20 {
+                Rgba([20, 30, 110, 255])
+            } else {
+                Rgba([220, 30, 30, 255])
+            }
+        });
+        let options = AnalysisOptions::new(Some(2));
+        let red = RgbaImage::from_pixel(10, 10, Rgba([210, 40, 30, 255]));
+        let library = vec![(PathBuf::from("red.png"), analyse(&red, &options))];
+
+        let coverage = measure(&target, 10, &library);
+
+        assert_eq!(coverage.regions.len(), 2);
+        assert_eq!(coverage.regions[0].color, [220, 30, 30]);
+        assert_eq!(coverage.regions[0].share, 0.75);
+        assert_eq!(coverage.regions[0].matches, 1);
+        let gaps: Vec<&ColorRegion> = coverage.gaps().collect();
+        assert_eq!(gaps.len(), 1);
+        assert_eq!(gaps[0].name, "dark blue");
+        assert!(coverage
+            .to_string()
+            .contains("25% of the target is dark blue"));
+    }
+}
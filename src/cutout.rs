@@ -0,0 +1,126 @@
+use std::collections::{HashMap, VecDeque};
+
+use image::{Rgba, RgbaImage};
+
+/// Share of an image's edge pixels that must be close to one color for the
+/// image to count as having a flat background.
+const FLAT_EDGE_SHARE: f64 = 0.5;
+
+/// Make the flat background of an image, such as a product shot or a scan,
+/// transparent, so it can be drawn as a cutout rather than a rectangle.
+///
+/// The background is the color most of the edge of the image is within the
+/// tolerance (in each channel) of, and is cleared wherever it reaches in
+/// from the edge. Images without such an edge, like most photos, are
+/// returned unchanged.
+pub fn knock_out_background(img: &RgbaImage, tolerance: u8) -> RgbaImage {
+    let mut cutout = img.clone();
+    let edge = edge_pixels(img.width(), img.height());
+    let Some(background) = background(img, &edge, tolerance) else {
+        return cutout;
+    };
+
+    let close = |pixel: &Rgba<u8>| {
+        pixel.0[3] > 0
+            && pixel.0[..3]
+                .iter()
+                .zip(background)
+                .all(|(c, b)| c.abs_diff(b) <= tolerance)
+    };
+    let mut queue: VecDeque<(u32, u32)> = edge.into_iter().collect();
+    while let Some((x, y)) = queue.pop_front() {
+        if !close(cutout.get_pixel(x, y)) {
+            continue;
+        }
+        cutout.get_pixel_mut(x, y).0[3] = 0;
+        let neighbours = [
+            (x.wrapping_sub(1), y),
+            (x + 1, y),
+            (x, y.wrapping_sub(1)),
+            (x, y + 1),
+        ];
+        queue.extend(
+            neighbours
+                .into_iter()
+                .filter(|(x, y)| *x < img.width() && *y < img.height()),
+        );
+    }
+    cutout
+}
+
+/// The positions of the pixels around the edge of an image of the given size.
+fn edge_pixels(width: u32, height: u32) -> Vec<(u32, u32)> {
+    (0..width)
+        .flat_map(|x| [(x, 0), (x, height.saturating_sub(1))])
+        .chain((0..height).flat_map(|y| [(0, y), (width.saturating_sub(1), y)]))
+        .filter(|(x, y)| *x < width && *y < height)
+        .collect()
+}
+
+/// The color of the flat background around the edge of an image, if it has
+/// one.
+fn background(img: &RgbaImage, edge: &[(u32, u32)], tolerance: u8) -> Option<[u8; 3]> {
+    // Count edge colors in bins the size of the tolerance, then take the mean
+    // of the pixels in the fullest bin.
+    let bin = u16::from(tolerance) + 1;
+    let mut bins: HashMap<[u16; 3], Vec<[u8; 3]>> = HashMap::new();
+    for (x, y) in edge {
+        let [r, g, b, a] = img.get_pixel(*x, *y).0;
+        if a > 0 {
+            let key = [r, g, b].map(|c| u16::from(c) / bin);
+            bins.entry(key).or_default().push([r, g, b]);
+        }
+    }
+    let fullest = bins.into_values().max_by_key(Vec::len)?;
+    let mean = [0, 1, 2].map(|c| {
+        let sum: u32 = fullest.iter().map(|p| u32::from(p[c])).sum();
+        (sum / fullest.len() as u32) as u8
+    });
+
+    let near = edge
+        .iter()
+        .map(|(x, y)| img.get_pixel(*x, *y).0)
+        .filter(|p| {
+            p[..3]
+                .iter()
+                .zip(mean)
+                .all(|(c, m)| c.abs_diff(m) <= tolerance)
+        })
+        .count();
+    (near as f64 >= FLAT_EDGE_SHARE * edge.len() as f64).then_some(mean)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_knocks_out_flat_backgrounds_reaching_the_edge() {
+        let (white, red) = (Rgba([250, 252, 255, 255]), Rgba([200, 0, 0, 255]));
+        // A red square on white, with a white hole inside the square.
+        let img = RgbaImage::from_fn(10, 10, |x, y| {
+            let square = (2..8).contains(&x) && (2..8).contains(&y);
+            let hole = (4..6).contains(&x) && (4..6).contains(&y);
+            if square && !hole {
+                red
+            } else {
+                white
+            }
+        });
+
+        let cutout = knock_out_background(&img, 10);
+
+        assert_eq!(cutout.get_pixel(0, 0).0[3], 0);
+        assert_eq!(cutout.get_pixel(9, 5).0[3], 0);
+        assert_eq!(*cutout.get_pixel(3, 3), red);
+        // Enclosed by the square, so not part of the background.
+        assert_eq!(*cutout.get_pixel(4, 4), white);
+    }
+
+    #[test]
+    fn test_leaves_images_without_a_flat_background() {
+        let img = RgbaImage::from_fn(10, 10, |x, y| Rgba([x as u8 * 25, y as u8 * 25, 0, 255]));
+
+        assert_eq!(knock_out_background(&img, 10), img);
+    }
+}
@@ -0,0 +1,108 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+
+use image::RgbaImage;
+
+use crate::analysis::ImageInfo;
+use crate::core::{Dimensions, PixelRegion, TileLocation};
+use crate::matching::{grid, CellAnalyser, CellGrid};
+use crate::strategy::{StrategyOptions, TilingStrategy};
+
+/// Floyd–Steinberg weights (in sixteenths) for spreading a cell's error to
+/// the cells right, below left, below, and below right of it.
+const FLOYD_STEINBERG: [(i64, i64, i32); 4] = [(1, 0, 7), (-1, 1, 3), (0, 1, 5), (1, 1, 1)];
+
+/// Choose tiles row by row, carrying the color error between each cell and
+/// its tile into the neighbouring cells still to be chosen, so the average
+/// color of the mosaic tracks the target even with few library images.
+pub struct DiffusionTileStrategy<'a, T> {
+    options: &'a StrategyOptions,
+    analysis: &'a HashMap<&'a T, ImageInfo>,
+}
+
+impl<'a, T> DiffusionTileStrategy<'a, T> {
+    pub fn new(
+        analysis: &'a HashMap<&'a T, ImageInfo>,
+        options: &'a StrategyOptions,
+    ) -> DiffusionTileStrategy<'a, T> {
+        DiffusionTileStrategy { options, analysis }
+    }
+}
+
+impl<T: Ord + Hash> TilingStrategy<T> for DiffusionTileStrategy<'_, T> {
+    fn choose(
+        &self,
+        target: &RgbaImage,
+        cell_size: &Dimensions,
+    ) -> Vec<TileLocation<'_, T, PixelRegion>> {
+        let analyser = CellAnalyser::new(target, *cell_size, &self.options.analysis);
+        let mut cells = grid(target, cell_size);
+        cells.sort_by_key(|r| (r.y, r.x));
+        let grid = CellGrid::new(cells, *cell_size);
+
+        let samples = (self.options.analysis.sample_size as usize).pow(2);
+        let mut errors = vec![vec![[0; 3]; samples]; grid.cells.len()];
+
+        let mut tiles = Vec::with_capacity(grid.cells.len());
+        for (i, r) in grid.cells.iter().enumerate() {
+            if self.options.cancel.is_cancelled() {
+                break;
+            }
+            let wanted = analyser.analyse(r).offset(&errors[i]);
+            let weighted = self.analysis.iter().map(|(tile, info)| {
+                let weight = self.options.weight(info, &wanted, r);
+                (*tile, weight)
+            });
+            let (best_tile, _) = self.options.pick(weighted, r).unwrap();
+
+            let residual = wanted.residual(&self.analysis[best_tile]);
+            let coords = grid.coords(i);
+            for (dx, dy, weight) in FLOYD_STEINBERG {
+                let neighbour = coords.offset(dx, dy).and_then(|c| grid.get(c));
+                if let Some(j) = neighbour {
+                    for (error, diff) in errors[j].iter_mut().zip(&residual) {
+                        for c in 0..3 {
+                            error[c] += diff[c] * weight / 16;
+                        }
+                    }
+                }
+            }
+
+            tiles.push((best_tile, PixelRegion::from(r)));
+        }
+        tiles
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::analysis::{analyse, AnalysisOptions};
+    use image::Rgba;
+
+    #[test]
+    fn test_diffuses_error_into_following_cells() {
+        let (black, white) = ("black".to_string(), "white".to_string());
+        let options = StrategyOptions {
+            analysis: AnalysisOptions::new(Some(1)),
+            ..StrategyOptions::default()
+        };
+        let solid = |v| {
+            analyse(
+                &RgbaImage::from_pixel(20, 20, Rgba([v, v, v, 255])),
+                &options.analysis,
+            )
+        };
+        let analysis = HashMap::from([(&black, solid(0)), (&white, solid(255))]);
+        let target = RgbaImage::from_pixel(40, 20, Rgba([128, 128, 128, 255]));
+
+        let strategy = DiffusionTileStrategy::new(&analysis, &options);
+        let tiles: Vec<&String> = strategy
+            .choose(&target, &(20, 20))
+            .iter()
+            .map(|(t, _)| *t)
+            .collect();
+
+        assert_eq!(tiles, vec![&white, &black]);
+    }
+}
@@ -0,0 +1,215 @@
+use std::fmt::{Display, Formatter, Result as FmtResult};
+
+use image::imageops::{self, FilterType};
+use image::{Pixel, RgbaImage};
+#[cfg(feature = "serde")]
+use serde::Serialize;
+
+use crate::core::product;
+
+/// Size (in pixels) of the square windows structural similarity is measured
+/// over.
+const WINDOW: u32 = 8;
+
+/// Settings for measuring how closely a mosaic recreates its target.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct EvaluationOptions {
+    /// Size (in target pixels) of the cells whose mean colors are compared.
+    pub cell_size: u32,
+    /// Longest side (in pixels) the images are scaled down to, at most, before
+    /// comparing them as a whole.
+    pub compare_size: u32,
+}
+
+impl Default for EvaluationOptions {
+    fn default() -> Self {
+        Self {
+            cell_size: 20,
+            compare_size: 256,
+        }
+    }
+}
+
+/// Objective measures of how closely a mosaic recreates its target, for
+/// comparing strategies and settings.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct Quality {
+    /// Mean distance between the mean colors of each cell of the mosaic and
+    /// the target, from 0 (the same) to about 441 (black and white).
+    pub mean_cell_error: f64,
+    /// Largest distance between the mean colors of a cell of the mosaic and
+    /// the target.
+    pub worst_cell_error: f64,
+    /// Mean structural similarity of the brightness, where 1 is identical.
+    pub ssim: f64,
+    /// Peak signal to noise ratio (in decibels) of the colors, higher is
+    /// closer and infinite for identical images.
+    pub psnr: f64,
+}
+
+impl Display for Quality {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        writeln!(f, "Mean cell color error:  {:.2}", self.mean_cell_error)?;
+        writeln!(f, "Worst cell color error: {:.2}", self.worst_cell_error)?;
+        writeln!(f, "SSIM:                   {:.4}", self.ssim)?;
+        write!(f, "PSNR:                   {:.2} dB", self.psnr)
+    }
+}
+
+/// Measure how closely the mosaic, of any size, recreates the target.
+pub(crate) fn measure(
+    target: &RgbaImage,
+    mosaic: &RgbaImage,
+    options: &EvaluationOptions,
+) -> Quality {
+    let (width, height) = target.dimensions();
+    let mosaic = resized(mosaic, width, height);
+    let errors = cell_errors(target, &mosaic, options.cell_size.max(1));
+
+    let longest = width.max(height).max(1);
+    let scale = (f64::from(options.compare_size.max(1)) / f64::from(longest)).min(1.0);
+    let (w, h) = (
+        ((f64::from(width) * scale).round() as u32).max(1),
+        ((f64::from(height) * scale).round() as u32).max(1),
+    );
+    let (target, mosaic) = (resized(target, w, h), resized(&mosaic, w, h));
+
+    Quality {
+        mean_cell_error: errors.iter().sum::<f64>() / errors.len().max(1) as f64,
+        worst_cell_error: errors.iter().copied().fold(0.0, f64::max),
+        ssim: ssim(&target, &mosaic),
+        psnr: psnr(&target, &mosaic),
+    }
+}
+
+fn resized(img: &RgbaImage, width: u32, height: u32) -> RgbaImage {
+    if img.dimensions() == (width, height) {
+        img.clone()
+    } else {
+        imageops::resize(img, width, height, FilterType::Triangle)
+    }
+}
+
+/// The distance between the mean colors of each cell of two images of the
+/// same size.
+fn cell_errors(a: &RgbaImage, b: &RgbaImage, cell_size: u32) -> Vec<f64> {
+    let (width, height) = a.dimensions();
+    let mean = |img: &RgbaImage, x: u32, y: u32| {
+        let cell = imageops::crop_imm(img, x, y, cell_size, cell_size).to_image();
+        let count = f64::from(cell.width() * cell.height()).max(1.0);
+        let mut sums = [0.0; 3];
+        for pixel in cell.pixels() {
+            for (sum, channel) in sums.iter_mut().zip(pixel.to_rgb().0) {
+                *sum += f64::from(channel);
+            }
+        }
+        sums.map(|sum| sum / count)
+    };
+
+    product(
+        (0..height).step_by(cell_size as usize),
+        (0..width).step_by(cell_size as usize),
+    )
+    .map(|(y, x)| {
+        let (ma, mb) = (mean(a, x, y), mean(b, x, y));
+        ma.iter()
+            .zip(mb)
+            .map(|(ca, cb)| (ca - cb).powi(2))
+            .sum::<f64>()
+            .sqrt()
+    })
+    .collect()
+}
+
+/// The mean structural similarity (SSIM) of the brightness of two images of
+/// the same size, over windows tiling the images.
+fn ssim(a: &RgbaImage, b: &RgbaImage) -> f64 {
+    const C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+    const C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+    let luma = |img: &RgbaImage, x, y| f64::from(img.get_pixel(x, y).to_luma()[0]);
+
+    let (width, height) = a.dimensions();
+    let mut total = 0.0;
+    let mut windows = 0;
+    for (wy, wx) in product(
+        (0..height).step_by(WINDOW as usize),
+        (0..width).step_by(WINDOW as usize),
+    ) {
+        let pixels: Vec<(f64, f64)> =
+            product(wy..(wy + WINDOW).min(height), wx..(wx + WINDOW).min(width))
+                .map(|(y, x)| (luma(a, x, y), luma(b, x, y)))
+                .collect();
+        let n = pixels.len() as f64;
+        let mean_a = pixels.iter().map(|p| p.0).sum::<f64>() / n;
+        let mean_b = pixels.iter().map(|p| p.1).sum::<f64>() / n;
+        let (mut var_a, mut var_b, mut covar) = (0.0, 0.0, 0.0);
+        for (pa, pb) in &pixels {
+            var_a += (pa - mean_a).powi(2) / n;
+            var_b += (pb - mean_b).powi(2) / n;
+            covar += (pa - mean_a) * (pb - mean_b) / n;
+        }
+        total += ((2.0 * mean_a * mean_b + C1) * (2.0 * covar + C2))
+            / ((mean_a.powi(2) + mean_b.powi(2) + C1) * (var_a + var_b + C2));
+        windows += 1;
+    }
+    total / f64::from(windows.max(1))
+}
+
+/// The peak signal to noise ratio of the colors of two images of the same
+/// size.
+fn psnr(a: &RgbaImage, b: &RgbaImage) -> f64 {
+    let (count, squared) = a
+        .pixels()
+        .zip(b.pixels())
+        .flat_map(|(pa, pb)| pa.to_rgb().0.into_iter().zip(pb.to_rgb().0))
+        .fold((0.0, 0.0), |(count, squared), (ca, cb)| {
+            (
+                count + 1.0,
+                squared + (f64::from(ca) - f64::from(cb)).powi(2),
+            )
+        });
+    let mse = squared / f64::max(count, 1.0);
+    10.0 * (255.0 * 255.0 / mse).log10()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use image::Rgba;
+
+    #[test]
+    fn test_measures_matching_mosaics_as_close() {
+        let target = RgbaImage::from_fn(40, 20, |x, y| Rgba([x as u8 * 6, y as u8 * 12, 0, 255]));
+        // The mosaic is larger, like a mosaic drawn at a larger tile size.
+        let mosaic = imageops::resize(&target, 80, 40, FilterType::Nearest);
+
+        let quality = measure(&target, &mosaic, &EvaluationOptions::default());
+
+        assert!(quality.mean_cell_error < 2.0);
+        assert!(quality.ssim > 0.95);
+        assert!(quality.psnr > 30.0);
+        let same = measure(&target, &target, &EvaluationOptions::default());
+        assert_eq!((same.mean_cell_error, same.ssim), (0.0, 1.0));
+        assert!(same.psnr.is_infinite());
+    }
+
+    #[test]
+    fn test_measures_cell_color_errors() {
+        let (black, white) = (Rgba([0, 0, 0, 255]), Rgba([255, 255, 255, 255]));
+        let target = RgbaImage::from_pixel(40, 20, black);
+        // The right cell is white, the left black like the target.
+        let mosaic = RgbaImage::from_fn(40, 20, |x, _| if x < 20 { black } else { white });
+        let options = EvaluationOptions {
+            cell_size: 20,
+            compare_size: 40,
+        };
+
+        let quality = measure(&target, &mosaic, &options);
+
+        let white_error = (3.0 * 255.0 * 255.0f64).sqrt();
+        assert!((quality.worst_cell_error - white_error).abs() < 1e-9);
+        assert!((quality.mean_cell_error - white_error / 2.0).abs() < 1e-9);
+        assert!((quality.psnr - 10.0 * 2f64.log10()).abs() < 1e-9);
+    }
+}
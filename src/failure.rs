@@ -0,0 +1,123 @@
+use std::error::Error;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::io::{Error as IoError, ErrorKind};
+
+use image::ImageError;
+
+/// The error, wrapped in an `InvalidInput` I/O error, for a build with no
+/// library images to draw tiles from.
+#[derive(Debug)]
+pub struct EmptyLibrary {
+    /// Whether there were library images, but the filter left them all out.
+    pub filtered: bool,
+    /// Whether there were library images, but all those passing the filter
+    /// were left out for being smaller than the tiles.
+    pub undersized: bool,
+}
+
+impl EmptyLibrary {
+    pub(crate) fn error(filtered: bool, undersized: bool) -> IoError {
+        let empty = EmptyLibrary {
+            filtered,
+            undersized,
+        };
+        IoError::new(ErrorKind::InvalidInput, empty)
+    }
+}
+
+impl Display for EmptyLibrary {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        if self.undersized {
+            write!(f, "No library images are as large as the tiles")
+        } else if self.filtered {
+            write!(f, "No library images pass the filter")
+        } else {
+            write!(f, "No library images found")
+        }
+    }
+}
+
+impl Error for EmptyLibrary {}
+
+/// Broadly why something failed, so that the binaries can exit with a code
+/// scripts can react to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Failure {
+    /// The arguments or settings given can't be used.
+    BadInput,
+    /// A file couldn't be read or written.
+    Io,
+    /// An image couldn't be decoded.
+    Decode,
+    /// There were no library images to build with.
+    EmptyLibrary,
+}
+
+impl Failure {
+    /// Why the error happened.
+    pub fn of(error: &IoError) -> Failure {
+        if let Some(inner) = error.get_ref() {
+            if inner.is::<EmptyLibrary>() {
+                return Failure::EmptyLibrary;
+            }
+            if let Some(error) = inner.downcast_ref::<ImageError>() {
+                return Failure::of_image(error);
+            }
+        }
+        match error.kind() {
+            ErrorKind::InvalidInput => Failure::BadInput,
+            ErrorKind::InvalidData | ErrorKind::Unsupported | ErrorKind::OutOfMemory => {
+                Failure::Decode
+            }
+            _ => Failure::Io,
+        }
+    }
+
+    /// Why the image error happened.
+    pub fn of_image(error: &ImageError) -> Failure {
+        match error {
+            ImageError::IoError(error) => Failure::of(error),
+            ImageError::Decoding(_) | ImageError::Unsupported(_) | ImageError::Limits(_) => {
+                Failure::Decode
+            }
+            ImageError::Encoding(_) | ImageError::Parameter(_) => Failure::Io,
+        }
+    }
+
+    /// The code to exit with, 2 for bad input as for bad arguments.
+    pub fn exit_code(self) -> u8 {
+        match self {
+            Failure::BadInput => 2,
+            Failure::Io => 3,
+            Failure::Decode => 4,
+            Failure::EmptyLibrary => 5,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use image::error::{DecodingError, ImageFormatHint};
+
+    #[test]
+    fn test_tells_failures_apart() {
+        let decoding =
+            ImageError::Decoding(DecodingError::from_format_hint(ImageFormatHint::Unknown));
+        let missing = IoError::new(ErrorKind::NotFound, "missing.jpg");
+
+        assert_eq!(
+            Failure::of(&EmptyLibrary::error(false, false)),
+            Failure::EmptyLibrary
+        );
+        assert_eq!(Failure::of(&IoError::other(decoding)), Failure::Decode);
+        assert_eq!(
+            Failure::of(&IoError::other(ImageError::IoError(missing))),
+            Failure::Io
+        );
+        let unknown = IoError::new(ErrorKind::InvalidInput, "Unknown strategy: best");
+        assert_eq!(Failure::of(&unknown), Failure::BadInput);
+        assert_eq!(Failure::BadInput.exit_code(), 2);
+        assert_eq!(Failure::EmptyLibrary.exit_code(), 5);
+    }
+}
@@ -0,0 +1,97 @@
+use std::io::Result as IoResult;
+
+use image::RgbaImage;
+
+use crate::core::PixelRegion;
+use crate::resize::Resize;
+use crate::Drawable;
+
+/// Feather the tiles' edges by the width (in output pixels), sorting them to
+/// be drawn a row at a time so each fades in over those above and to its
+/// left.
+pub(crate) fn feather<T: Drawable>(mut tiles: Vec<T>, width: u32) -> Vec<Feathered<T>> {
+    tiles.sort_by_key(|t| (t.region().y, t.region().x));
+    tiles
+        .into_iter()
+        .map(|inner| {
+            let region = *inner.region();
+            Feathered {
+                inner,
+                width,
+                extent: PixelRegion {
+                    x: region.x - i64::from(width),
+                    y: region.y - i64::from(width),
+                    width: region.width + width,
+                    height: region.height + width,
+                },
+            }
+        })
+        .collect()
+}
+
+/// A drawable stretched up and to the left over its neighbours, fading in
+/// across the part that overlaps them.
+pub(crate) struct Feathered<T> {
+    inner: T,
+    /// Width (in output pixels) of the fade.
+    width: u32,
+    extent: PixelRegion,
+}
+
+impl<T: Drawable> Drawable for Feathered<T> {
+    fn region(&self) -> &PixelRegion {
+        &self.extent
+    }
+
+    fn render(&self, resize: Resize) -> IoResult<RgbaImage> {
+        let img = self.inner.render(resize)?;
+        let mut img = resize.apply(&img, self.extent.width, self.extent.height);
+        let ramp = |i: u32| ((f64::from(i) + 0.5) / f64::from(self.width)).min(1.0);
+        for (x, y, pixel) in img.enumerate_pixels_mut() {
+            let alpha = f64::from(pixel[3]) * ramp(x) * ramp(y);
+            pixel[3] = alpha.round() as u8;
+        }
+        Ok(img)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{build_image, CancelToken, ResizeFilter};
+    use image::Rgba;
+
+    struct Block(PixelRegion, [u8; 4]);
+
+    impl Drawable for Block {
+        fn region(&self) -> &PixelRegion {
+            &self.0
+        }
+
+        fn render(&self, _resize: Resize) -> IoResult<RgbaImage> {
+            Ok(RgbaImage::from_pixel(
+                self.0.width,
+                self.0.height,
+                Rgba(self.1),
+            ))
+        }
+    }
+
+    #[test]
+    fn test_fades_tiles_over_earlier_neighbours() {
+        // Drawn out of order, to show they are sorted.
+        let tiles = vec![
+            Block(PixelRegion::new(10, 0, 10, 10), [0, 0, 255, 255]),
+            Block(PixelRegion::new(0, 0, 10, 10), [255, 0, 0, 255]),
+        ];
+        let resize = Resize::new(ResizeFilter::Nearest, false);
+
+        let img = build_image((20, 10), feather(tiles, 4), resize, &CancelToken::new()).unwrap();
+
+        assert_eq!(*img.get_pixel(0, 0), Rgba([255, 0, 0, 255]));
+        assert_eq!(*img.get_pixel(5, 5), Rgba([255, 0, 0, 255]));
+        assert_eq!(*img.get_pixel(15, 5), Rgba([0, 0, 255, 255]));
+        let seam = img.get_pixel(7, 5);
+        assert!(seam[0] > 0 && seam[2] > 0 && seam[3] == 255);
+    }
+}
@@ -0,0 +1,211 @@
+use std::io::Result as IoResult;
+use std::path::PathBuf;
+
+use image::{imageops, Rgba, RgbaImage};
+#[cfg(feature = "serde")]
+use serde::Deserialize;
+
+use crate::at_size;
+use crate::core::Dimensions;
+use crate::mask::Color;
+use crate::resize::Resize;
+use crate::text::{draw_text, load_font, FontVec};
+
+/// Color captions are written in.
+const CAPTION_COLOR: [u8; 3] = [60, 60, 60];
+
+/// Opacity of the darkest part of the shadow, from 0 to 255.
+const SHADOW_OPACITY: u8 = 90;
+
+/// Share of each tile the shadow is offset by, down and to the right.
+const SHADOW_OFFSET: f64 = 0.03;
+
+/// A card framing each tile like an instant photo, for a "polaroid wall".
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
+pub struct Frame {
+    /// Width of the border at the top and sides, as a share of the card.
+    pub border: f64,
+    /// Height of the margin at the bottom, as a share of the card.
+    pub bottom: f64,
+    /// Color of the card.
+    pub color: Color,
+    /// Whether to draw a soft shadow beneath each card.
+    pub shadow: bool,
+    /// TrueType or OpenType font to caption each tile with its file name in,
+    /// left without captions if not given.
+    pub caption_font: Option<PathBuf>,
+}
+
+impl Default for Frame {
+    fn default() -> Self {
+        Self {
+            border: 0.05,
+            bottom: 0.18,
+            color: Color([255, 255, 255]),
+            shadow: true,
+            caption_font: None,
+        }
+    }
+}
+
+/// Draws framed tiles, with the caption font loaded once for all of them.
+pub(crate) struct Framer<'a> {
+    frame: &'a Frame,
+    font: Option<FontVec>,
+}
+
+impl<'a> Framer<'a> {
+    pub(crate) fn new(frame: &'a Frame) -> IoResult<Framer<'a>> {
+        let font = frame.caption_font.as_deref().map(load_font).transpose()?;
+        Ok(Framer { frame, font })
+    }
+
+    /// Draw the image as a framed photo of the given size, adjusting the
+    /// photo once it is at size.
+    pub(crate) fn draw(
+        &self,
+        img: RgbaImage,
+        caption: &str,
+        (width, height): Dimensions,
+        resize: Resize,
+        adjust: impl FnOnce(&mut RgbaImage),
+    ) -> RgbaImage {
+        let offset = if self.frame.shadow {
+            (f64::from(width.min(height)) * SHADOW_OFFSET).round() as u32
+        } else {
+            0
+        };
+        let (card_width, card_height) = (width - offset, height - offset);
+        let shorter = f64::from(card_width.min(card_height));
+        let border = (shorter * self.frame.border).round() as u32;
+        let bottom = ((f64::from(card_height) * self.frame.bottom).round() as u32).max(border);
+
+        let [r, g, b] = self.frame.color.0;
+        let mut card = RgbaImage::from_pixel(card_width, card_height, Rgba([r, g, b, 255]));
+        let photo_width = card_width.saturating_sub(2 * border);
+        let photo_height = card_height.saturating_sub(border + bottom);
+        if photo_width > 0 && photo_height > 0 {
+            let mut photo = at_size(
+                cropped_to(img, photo_width, photo_height),
+                photo_width,
+                photo_height,
+                resize,
+            );
+            adjust(&mut photo);
+            imageops::overlay(&mut card, &photo, border.into(), border.into());
+        }
+        if let Some(font) = &self.font {
+            // Written in the middle half of the bottom margin.
+            let area = (photo_width, bottom / 2);
+            let coverage = draw_text(font, caption, None, area);
+            let top = card_height - bottom + bottom / 4;
+            for (x, y, c) in coverage.enumerate_pixels() {
+                let pixel = card.get_pixel_mut(border + x, top + y);
+                for (channel, ink) in pixel.0.iter_mut().zip(CAPTION_COLOR) {
+                    let mixed = u32::from(*channel) * u32::from(255 - c[0])
+                        + u32::from(ink) * u32::from(c[0]);
+                    *channel = (mixed / 255) as u8;
+                }
+            }
+        }
+
+        let mut framed = RgbaImage::new(width, height);
+        if offset > 0 {
+            let shadow = Rgba([0, 0, 0, SHADOW_OPACITY]);
+            let mut layer = RgbaImage::new(width, height);
+            for (x, y, pixel) in layer.enumerate_pixels_mut() {
+                if x >= offset && y >= offset {
+                    *pixel = shadow;
+                }
+            }
+            framed = imageops::blur(&layer, offset as f32 / 2.0);
+        }
+        imageops::overlay(&mut framed, &card, 0, 0);
+        framed
+    }
+}
+
+/// The middle of the image, cropped to the aspect ratio of the given size.
+fn cropped_to(img: RgbaImage, width: u32, height: u32) -> RgbaImage {
+    let (w, h) = img.dimensions();
+    let (aspect, wanted) = (
+        f64::from(w) / f64::from(h),
+        f64::from(width) / f64::from(height),
+    );
+    let (crop_width, crop_height) = if aspect > wanted {
+        ((f64::from(h) * wanted).round() as u32, h)
+    } else {
+        (w, (f64::from(w) / wanted).round() as u32)
+    };
+    let (crop_width, crop_height) = (crop_width.clamp(1, w), crop_height.clamp(1, h));
+    if (crop_width, crop_height) == (w, h) {
+        return img;
+    }
+    let (x, y) = ((w - crop_width) / 2, (h - crop_height) / 2);
+    imageops::crop_imm(&img, x, y, crop_width, crop_height).to_image()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ResizeFilter;
+
+    #[test]
+    fn test_frames_photos_in_a_card() {
+        let frame = Frame {
+            border: 0.1,
+            bottom: 0.3,
+            shadow: false,
+            ..Frame::default()
+        };
+        let red = RgbaImage::from_pixel(50, 50, Rgba([255, 0, 0, 255]));
+
+        let framed = Framer::new(&frame).unwrap().draw(
+            red,
+            "",
+            (100, 100),
+            Resize::new(ResizeFilter::Fast, false),
+            |_| {},
+        );
+
+        let white = Rgba([255, 255, 255, 255]);
+        assert_eq!(framed.dimensions(), (100, 100));
+        assert_eq!(*framed.get_pixel(5, 5), white);
+        assert_eq!(*framed.get_pixel(50, 50), Rgba([255, 0, 0, 255]));
+        // The photo stops where the larger bottom margin starts.
+        assert_eq!(framed.get_pixel(50, 69).0[0..2], [255, 0]);
+        assert_eq!(*framed.get_pixel(50, 70), white);
+        assert_eq!(*framed.get_pixel(95, 50), white);
+    }
+
+    #[test]
+    fn test_casts_shadows_down_and_right() {
+        let frame = Frame::default();
+        let blue = RgbaImage::from_pixel(10, 10, Rgba([0, 0, 255, 255]));
+
+        let framed = Framer::new(&frame).unwrap().draw(
+            blue,
+            "",
+            (100, 100),
+            Resize::new(ResizeFilter::Fast, false),
+            |_| {},
+        );
+
+        // The card is 97 pixels square, the shadow showing beyond it.
+        assert_eq!(framed.get_pixel(50, 50).0, [0, 0, 255, 255]);
+        let (side, corner) = (framed.get_pixel(98, 50).0[3], framed.get_pixel(98, 0).0[3]);
+        assert!(side > 40 && corner < side / 4);
+    }
+
+    #[test]
+    fn test_crops_to_aspect_ratio() {
+        let img = RgbaImage::from_fn(40, 20, |x, _| Rgba([x as u8, 0, 0, 255]));
+
+        let cropped = cropped_to(img, 10, 10);
+
+        assert_eq!(cropped.dimensions(), (20, 20));
+        assert_eq!(cropped.get_pixel(0, 0).0[0], 10);
+    }
+}
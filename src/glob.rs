@@ -0,0 +1,110 @@
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::path::Path;
+use std::str::FromStr;
+
+#[cfg(feature = "serde")]
+use serde::Deserialize;
+
+/// A pattern for the names of library images, where `*` stands for any run
+/// of characters and `?` for any one character, such as `*.thumb.jpg`.
+///
+/// Patterns holding a `/` are matched against the whole path of an image,
+/// and others against just its file name.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "String"))]
+pub struct Glob(String);
+
+impl Glob {
+    /// Whether the path matches the pattern.
+    pub fn matches(&self, path: &Path) -> bool {
+        let name = if self.0.contains('/') {
+            path.to_string_lossy()
+        } else {
+            match path.file_name() {
+                Some(name) => name.to_string_lossy(),
+                None => return false,
+            }
+        };
+        wildcard_match(
+            &self.0.chars().collect::<Vec<_>>(),
+            &name.chars().collect::<Vec<_>>(),
+        )
+    }
+}
+
+/// Match the text against the pattern, going back to just after the last
+/// `*` whenever a character doesn't match.
+pub(crate) fn wildcard_match(pattern: &[char], text: &[char]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    let mut star = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, t));
+                p += 1;
+            }
+            Some(c) if *c == '?' || *c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match star {
+                Some((star_p, star_t)) => {
+                    star = Some((star_p, star_t + 1));
+                    p = star_p + 1;
+                    t = star_t + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+impl FromStr for Glob {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() {
+            Err("Invalid pattern: expected a file name pattern such as *.jpg".to_string())
+        } else {
+            Ok(Glob(s.to_string()))
+        }
+    }
+}
+
+impl TryFrom<String> for Glob {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl Display for Glob {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_matches_names_and_paths() {
+        let glob = |s: &str| s.parse::<Glob>().unwrap();
+        let path = Path::new("photos/2023-05/beach.thumb.jpg");
+
+        assert!(glob("*.thumb.jpg").matches(path));
+        assert!(glob("beach.*").matches(path));
+        assert!(glob("b?ach*").matches(path));
+        assert!(!glob("*.png").matches(path));
+        assert!(!glob("2023-*").matches(path));
+        assert!(glob("*/2023-*/*").matches(path));
+        assert!(!glob("*/2022-*/*").matches(path));
+        assert!(glob("*a*a*").matches(Path::new("banana")));
+        assert!(!glob("*a*a*a*a").matches(Path::new("banana")));
+        assert!("".parse::<Glob>().is_err());
+    }
+}
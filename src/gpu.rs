@@ -0,0 +1,332 @@
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::io::{Error as IoError, Result as IoResult};
+use std::path::PathBuf;
+
+use image::RgbaImage;
+
+use crate::cancel::CancelToken;
+use crate::core::{Dimensions, PixelRegion};
+use crate::resize::Resize;
+use crate::{at_size, load_image};
+
+const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
+
+/// Draw each tile as a quad filling its viewport.
+const SHADER: &str = r"
+@group(0) @binding(0) var tile: texture_2d<f32>;
+@group(0) @binding(1) var tile_sampler: sampler;
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+};
+
+@vertex
+fn vs_main(@builtin(vertex_index) i: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32(i & 1u), f32(i >> 1u));
+    var out: VertexOutput;
+    out.position = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return textureSample(tile, tile_sampler, in.uv);
+}
+";
+
+/// Build the mosaic image on the GPU, uploading each distinct tile once as a
+/// texture and drawing it into each of its regions.
+///
+/// Returns `None` if there is no usable GPU, or the image is too big for it,
+/// so the caller can fall back to drawing on the CPU. Fails if a tile can't
+/// be read, or the build is cancelled.
+pub fn build_image(
+    size: Dimensions,
+    tiles: &[(PathBuf, PixelRegion)],
+    resize: Resize,
+    cancel: &CancelToken,
+) -> IoResult<Option<RgbaImage>> {
+    let (width, height) = size;
+    if width == 0 || height == 0 || !tiles.iter().all(|(_, r)| fits(r, size)) {
+        return Ok(None);
+    }
+
+    let Some((device, queue)) = pollster::block_on(connect()) else {
+        return Ok(None);
+    };
+    let limits = device.limits();
+    let padded_row = padded_bytes_per_row(width);
+    if width.max(height) > limits.max_texture_dimension_2d
+        || u64::from(padded_row) * u64::from(height) > limits.max_buffer_size
+    {
+        return Ok(None);
+    }
+
+    let compositor = Compositor::new(&device);
+    let output = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("mosaic"),
+        size: extent(width, height),
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: FORMAT,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+        view_formats: &[],
+    });
+
+    // Upload each distinct tile at the size of its region once.
+    let mut bind_groups: HashMap<(&PathBuf, Dimensions), wgpu::BindGroup> = HashMap::new();
+    for (path, region) in tiles {
+        cancel.check()?;
+        if let Entry::Vacant(entry) = bind_groups.entry((path, (region.width, region.height))) {
+            let img = load_image(path).map_err(IoError::other)?;
+            let thumb = at_size(img, region.width, region.height, resize);
+            entry.insert(compositor.upload(&device, &queue, &thumb));
+        }
+    }
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+    {
+        let view = output.create_view(&wgpu::TextureViewDescriptor::default());
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("mosaic"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &view,
+                depth_slice: None,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            ..Default::default()
+        });
+        pass.set_pipeline(&compositor.pipeline);
+        for (path, region) in tiles {
+            let bind_group = &bind_groups[&(path, (region.width, region.height))];
+            pass.set_bind_group(0, bind_group, &[]);
+            pass.set_viewport(
+                region.x as f32,
+                region.y as f32,
+                region.width as f32,
+                region.height as f32,
+                0.0,
+                1.0,
+            );
+            pass.draw(0..4, 0..1);
+        }
+    }
+
+    let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("mosaic readback"),
+        size: u64::from(padded_row) * u64::from(height),
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    });
+    encoder.copy_texture_to_buffer(
+        output.as_image_copy(),
+        wgpu::TexelCopyBufferInfo {
+            buffer: &buffer,
+            layout: wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(padded_row),
+                rows_per_image: None,
+            },
+        },
+        extent(width, height),
+    );
+    queue.submit([encoder.finish()]);
+
+    Ok(read_back(&device, &buffer, size, padded_row))
+}
+
+/// Connect to the default GPU, if there is one.
+async fn connect() -> Option<(wgpu::Device, wgpu::Queue)> {
+    let instance = wgpu::Instance::new(wgpu::InstanceDescriptor::new_without_display_handle());
+    let adapter = instance
+        .request_adapter(&wgpu::RequestAdapterOptions::default())
+        .await
+        .ok()?;
+    let descriptor = wgpu::DeviceDescriptor {
+        label: Some("tiler"),
+        required_limits: adapter.limits(),
+        ..Default::default()
+    };
+    adapter.request_device(&descriptor).await.ok()
+}
+
+/// The pipeline and sampler used to draw tiles.
+struct Compositor {
+    pipeline: wgpu::RenderPipeline,
+    sampler: wgpu::Sampler,
+}
+
+impl Compositor {
+    fn new(device: &wgpu::Device) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("tile"),
+            source: wgpu::ShaderSource::Wgsl(SHADER.into()),
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("tile"),
+            layout: None,
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                compilation_options: Default::default(),
+                buffers: &[],
+            },
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleStrip,
+                ..Default::default()
+            },
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                compilation_options: Default::default(),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: FORMAT,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            multiview_mask: None,
+            cache: None,
+        });
+        // Tiles are drawn at their own size, so sample texels exactly.
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor::default());
+        Self { pipeline, sampler }
+    }
+
+    /// Upload the image as a texture ready to be drawn.
+    fn upload(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        img: &RgbaImage,
+    ) -> wgpu::BindGroup {
+        let (width, height) = img.dimensions();
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("tile"),
+            size: extent(width, height),
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: FORMAT,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        queue.write_texture(
+            texture.as_image_copy(),
+            img.as_raw(),
+            wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(4 * width),
+                rows_per_image: None,
+            },
+            extent(width, height),
+        );
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("tile"),
+            layout: &self.pipeline.get_bind_group_layout(0),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&self.sampler),
+                },
+            ],
+        })
+    }
+}
+
+/// Copy the rendered rows out of the (padded) readback buffer.
+fn read_back(
+    device: &wgpu::Device,
+    buffer: &wgpu::Buffer,
+    (width, height): Dimensions,
+    padded_row: u32,
+) -> Option<RgbaImage> {
+    let slice = buffer.slice(..);
+    slice.map_async(wgpu::MapMode::Read, |_| {});
+    device.poll(wgpu::PollType::wait_indefinitely()).ok()?;
+
+    let data = slice.get_mapped_range().ok()?;
+    let row = 4 * width as usize;
+    let pixels = data
+        .chunks(padded_row as usize)
+        .flat_map(|r| &r[..row])
+        .copied()
+        .collect();
+    RgbaImage::from_raw(width, height, pixels)
+}
+
+/// Whether the region lies within an image of the given size.
+fn fits(region: &PixelRegion, (width, height): Dimensions) -> bool {
+    region.x >= 0
+        && region.y >= 0
+        && region.width > 0
+        && region.height > 0
+        && region.x + i64::from(region.width) <= i64::from(width)
+        && region.y + i64::from(region.height) <= i64::from(height)
+}
+
+/// Rows copied out of a texture must be a multiple of 256 bytes long.
+fn padded_bytes_per_row(width: u32) -> u32 {
+    let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+    (4 * width).div_ceil(align) * align
+}
+
+fn extent(width: u32, height: u32) -> wgpu::Extent3d {
+    wgpu::Extent3d {
+        width,
+        height,
+        depth_or_array_layers: 1,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ResizeFilter;
+
+    #[test]
+    fn test_pads_rows_for_copying() {
+        assert_eq!(padded_bytes_per_row(64), 256);
+        assert_eq!(padded_bytes_per_row(65), 512);
+    }
+
+    #[test]
+    fn test_only_draws_regions_inside_the_image() {
+        assert!(fits(&PixelRegion::new(10, 10, 10, 10), (20, 20)));
+        assert!(!fits(&PixelRegion::new(-1, 0, 10, 10), (20, 20)));
+        assert!(!fits(&PixelRegion::new(15, 0, 10, 10), (20, 20)));
+    }
+
+    #[test]
+    fn test_fails_on_unreadable_tiles_and_cancelled_builds() {
+        let tiles = [(PathBuf::from("missing.png"), PixelRegion::new(0, 0, 10, 10))];
+        let resize = Resize::new(ResizeFilter::default(), true);
+        let cancel = CancelToken::new();
+
+        // Without a GPU nothing is drawn, and the CPU draws the image instead.
+        let built = build_image((10, 10), &tiles, resize, &cancel);
+        assert!(matches!(built, Ok(None) | Err(_)));
+        cancel.cancel();
+        let built = build_image((10, 10), &tiles, resize, &cancel);
+        match built {
+            Ok(built) => assert!(built.is_none()),
+            Err(e) => assert_eq!(e.kind(), std::io::ErrorKind::Interrupted),
+        }
+    }
+}
@@ -0,0 +1,156 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+#[cfg(feature = "cli")]
+use clap::ValueEnum;
+use image::{imageops, GenericImageView, Pixel, Rgba, RgbaImage};
+
+use crate::Plan;
+
+/// Colors from cool to hot that heatmap values run through.
+const RAMP: [[f64; 3]; 5] = [
+    [0.0, 0.0, 128.0],
+    [0.0, 160.0, 255.0],
+    [0.0, 200.0, 80.0],
+    [255.0, 220.0, 0.0],
+    [220.0, 0.0, 0.0],
+];
+
+/// What a heatmap colors each cell of the mosaic by.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(ValueEnum))]
+pub enum HeatmapKind {
+    /// How far the mean color of the drawn tile is from its cell's.
+    #[default]
+    Error,
+    /// How many times the cell's tile is used across the mosaic.
+    Reuse,
+}
+
+/// Draw a heatmap the size of the target, each cell colored from blue for
+/// the lowest value to red for the highest, and cells without a tile left
+/// transparent.
+pub(crate) fn draw(plan: &Plan, mosaic: &RgbaImage, kind: HeatmapKind) -> RgbaImage {
+    let values: Vec<f64> = match kind {
+        HeatmapKind::Error => plan
+            .tiles
+            .iter()
+            .map(|(_, region)| {
+                let (x, y) = (region.x.max(0) as u32, region.y.max(0) as u32);
+                let drawn = imageops::crop_imm(mosaic, x, y, region.width, region.height);
+                let cell = plan.target_cell(region).to_image();
+                distance(mean_color(&cell), mean_color(&drawn.to_image()))
+            })
+            .collect(),
+        HeatmapKind::Reuse => {
+            let mut uses: HashMap<&PathBuf, usize> = HashMap::new();
+            for (path, _) in &plan.tiles {
+                *uses.entry(path).or_default() += 1;
+            }
+            plan.tiles
+                .iter()
+                .map(|(path, _)| uses[path] as f64)
+                .collect()
+        }
+    };
+    let highest = values.iter().copied().fold(0.0, f64::max);
+
+    let mut heatmap = RgbaImage::new(plan.target.width(), plan.target.height());
+    for ((_, region), value) in plan.tiles.iter().zip(values) {
+        let share = if highest > 0.0 { value / highest } else { 0.0 };
+        let cell = plan.target_cell(region);
+        let ((left, top), (width, height)) = (cell.offsets(), cell.dimensions());
+        let color = ramp(share);
+        for y in top..top + height {
+            for x in left..left + width {
+                heatmap.put_pixel(x, y, color);
+            }
+        }
+    }
+    heatmap
+}
+
+/// The mean color of the pixels of the image.
+fn mean_color(img: &RgbaImage) -> [f64; 3] {
+    let count = f64::from(img.width() * img.height()).max(1.0);
+    let mut sums = [0.0; 3];
+    for pixel in img.pixels() {
+        for (sum, channel) in sums.iter_mut().zip(pixel.to_rgb().0) {
+            *sum += f64::from(channel);
+        }
+    }
+    sums.map(|sum| sum / count)
+}
+
+fn distance(a: [f64; 3], b: [f64; 3]) -> f64 {
+    a.iter()
+        .zip(b)
+        .map(|(a, b)| (a - b).powi(2))
+        .sum::<f64>()
+        .sqrt()
+}
+
+/// The color of a share (0 to 1) of the way along the ramp.
+fn ramp(share: f64) -> Rgba<u8> {
+    let position = share.clamp(0.0, 1.0) * (RAMP.len() - 1) as f64;
+    let i = (position.floor() as usize).min(RAMP.len() - 2);
+    let t = position - i as f64;
+    let [r, g, b] = [0, 1, 2].map(|c| (RAMP[i][c] * (1.0 - t) + RAMP[i + 1][c] * t).round() as u8);
+    Rgba([r, g, b, 255])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::PixelRegion;
+    use crate::undersized::SmallTiles;
+
+    #[test]
+    fn test_ramps_from_cool_to_hot() {
+        assert_eq!(ramp(0.0), Rgba([0, 0, 128, 255]));
+        assert_eq!(ramp(0.5), Rgba([0, 200, 80, 255]));
+        assert_eq!(ramp(1.0), Rgba([220, 0, 0, 255]));
+        assert_eq!(ramp(2.0), ramp(1.0));
+    }
+
+    #[test]
+    fn test_colors_cells_by_reuse_and_error() {
+        let target = RgbaImage::from_fn(4, 2, |x, _| {
+            if x < 2 {
+                Rgba([0, 0, 0, 255])
+            } else {
+                Rgba([200, 200, 200, 255])
+            }
+        });
+        let (a, b) = (PathBuf::from("a.png"), PathBuf::from("b.png"));
+        let plan = Plan {
+            target,
+            ratio: 10.0,
+            size: (40, 20),
+            tiles: vec![
+                (a.clone(), PixelRegion::new(0, 0, 10, 10)),
+                (a.clone(), PixelRegion::new(10, 0, 10, 10)),
+                (b.clone(), PixelRegion::new(20, 0, 10, 10)),
+                (a, PixelRegion::new(30, 0, 10, 10)),
+            ],
+            costs: vec![0; 4],
+            library_size: 2,
+            filtered_out: 0,
+            refused: Vec::new(),
+            undersized: 0,
+            small_tiles: SmallTiles::default(),
+        };
+        // Black tiles everywhere, matching only the left half.
+        let mosaic = RgbaImage::from_pixel(40, 20, Rgba([0, 0, 0, 255]));
+
+        let reuse = draw(&plan, &mosaic, HeatmapKind::Reuse);
+        assert_eq!(reuse.dimensions(), (4, 2));
+        assert_eq!(*reuse.get_pixel(0, 0), ramp(1.0));
+        assert_eq!(*reuse.get_pixel(2, 0), ramp(1.0 / 3.0));
+        assert_eq!(reuse.get_pixel(0, 1).0[3], 0);
+
+        let error = draw(&plan, &mosaic, HeatmapKind::Error);
+        assert_eq!(*error.get_pixel(0, 0), ramp(0.0));
+        assert_eq!(*error.get_pixel(3, 0), ramp(1.0));
+    }
+}
@@ -0,0 +1,209 @@
+#[cfg(feature = "heif")]
+use std::error::Error;
+use std::fs::File;
+use std::io::{Error as IoError, ErrorKind, Read, Result as IoResult};
+use std::path::{Path, PathBuf};
+
+#[cfg(feature = "heif")]
+use image::error::{DecodingError, ImageFormatHint};
+#[cfg(feature = "heif")]
+use image::{ImageError, ImageResult, RgbaImage};
+#[cfg(feature = "heif")]
+use libheif_rs::{ColorSpace, HeifContext, LibHeif, RgbChroma};
+
+#[cfg(feature = "heif")]
+use crate::color;
+use crate::library;
+use crate::metadata::LibraryFilter;
+
+/// Brands of HEIF files holding HEVC coded images, as phones take photos in.
+const HEIC_BRANDS: [&[u8; 4]; 8] = [
+    b"heic", b"heix", b"hevc", b"hevx", b"heim", b"heis", b"mif1", b"msf1",
+];
+
+/// Brands of HEIF files holding AV1 coded images.
+const AVIF_BRANDS: [&[u8; 4]; 2] = [b"avif", b"avis"];
+
+/// An image format in a HEIF container, which only builds with the `heif`
+/// feature can decode.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum HeifFormat {
+    Heic,
+    Avif,
+}
+
+impl HeifFormat {
+    fn name(&self) -> &'static str {
+        match self {
+            HeifFormat::Heic => "HEIC",
+            HeifFormat::Avif => "AVIF",
+        }
+    }
+}
+
+/// The HEIF format of a file starting with the bytes, if it is one, from
+/// the brands listed in the `ftyp` box it starts with.
+pub(crate) fn sniff(header: &[u8]) -> Option<HeifFormat> {
+    if header.get(4..8) != Some(b"ftyp") {
+        return None;
+    }
+    let size = u32::from_be_bytes(header.get(0..4)?.try_into().ok()?) as usize;
+    let end = size.clamp(16, header.len().max(16));
+    // The major brand, then the minor version, then the compatible brands.
+    let brands: Vec<&[u8]> = header
+        .get(8..12)
+        .into_iter()
+        .chain(header.get(16..end).unwrap_or_default().chunks_exact(4))
+        .collect();
+    let listed = |known: &[&[u8; 4]]| brands.iter().any(|b| known.iter().any(|k| &k[..] == *b));
+    if listed(&AVIF_BRANDS) {
+        Some(HeifFormat::Avif)
+    } else if listed(&HEIC_BRANDS) {
+        Some(HeifFormat::Heic)
+    } else {
+        None
+    }
+}
+
+/// The HEIF format of the file, if it is one.
+fn sniff_file(path: &Path) -> Option<HeifFormat> {
+    let mut header = Vec::with_capacity(64);
+    File::open(path)
+        .ok()?
+        .take(64)
+        .read_to_end(&mut header)
+        .ok()?;
+    sniff(&header)
+}
+
+/// Load the image from the file with libheif, if it is a HEIF image.
+#[cfg(feature = "heif")]
+pub(crate) fn load(path: &Path) -> Option<ImageResult<RgbaImage>> {
+    sniff_file(path)?;
+    let img = std::fs::read(path)
+        .map_err(ImageError::IoError)
+        .and_then(|bytes| decode(&bytes));
+    Some(img.map_err(|e| color::name_refused(path, e)))
+}
+
+/// Decode the primary image of a HEIF file into 8-bit sRGB, converting it
+/// from any embedded color profile, and refusing images too large to decode
+/// safely.
+#[cfg(feature = "heif")]
+pub(crate) fn decode(bytes: &[u8]) -> ImageResult<RgbaImage> {
+    let context = HeifContext::read_from_bytes(bytes).map_err(decoding_error)?;
+    let handle = context.primary_image_handle().map_err(decoding_error)?;
+    color::check_limits(handle.width(), handle.height())?;
+    let decoded = LibHeif::new()
+        .decode(&handle, ColorSpace::Rgb(RgbChroma::Rgba), None)
+        .map_err(decoding_error)?;
+    let planes = decoded.planes();
+    let plane = planes
+        .interleaved
+        .ok_or_else(|| decoding_error("no interleaved RGBA plane"))?;
+
+    // Rows may be padded out to the stride.
+    let row = plane.width as usize * 4;
+    let mut pixels = Vec::with_capacity(row * plane.height as usize);
+    for line in plane.data.chunks(plane.stride.max(1)) {
+        pixels.extend_from_slice(line.get(..row).unwrap_or(line));
+    }
+    let mut img = RgbaImage::from_raw(plane.width, plane.height, pixels)
+        .ok_or_else(|| decoding_error("fewer pixels than its size"))?;
+    let icc = handle.color_profile_raw().map(|profile| profile.data);
+    color::apply_profile(&mut img, icc.as_deref());
+    Ok(img)
+}
+
+#[cfg(feature = "heif")]
+fn decoding_error(error: impl Into<Box<dyn Error + Send + Sync>>) -> ImageError {
+    ImageError::Decoding(DecodingError::new(
+        ImageFormatHint::Name("HEIF".to_string()),
+        error,
+    ))
+}
+
+/// An `Unsupported` error naming the file if it is a HEIF image and this
+/// build can't decode HEIF images, being without the `heif` feature.
+pub(crate) fn unsupported(path: &Path) -> Option<IoError> {
+    if cfg!(feature = "heif") {
+        return None;
+    }
+    let format = sniff_file(path)?;
+    let message = format!(
+        "{} is a {} image, which this build can't decode",
+        path.display(),
+        format.name()
+    );
+    Some(IoError::new(ErrorKind::Unsupported, message))
+}
+
+/// Find the images in the given libraries in HEIC or AVIF format, which this
+/// build can't decode and so skips, of those whose names pass the filter.
+/// There are none with the `heif` feature.
+pub fn unsupported_images<P: AsRef<Path>>(
+    lib_dirs: &[P],
+    filter: &LibraryFilter,
+) -> IoResult<Vec<PathBuf>> {
+    if cfg!(feature = "heif") {
+        return Ok(Vec::new());
+    }
+    let mut images = library::library_images(lib_dirs, filter)?;
+    images.retain(|p| sniff_file(p).is_some());
+    Ok(images)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_support::fixture;
+
+    /// The start of a file with an `ftyp` box of the brands.
+    fn ftyp(major: &[u8; 4], compatible: &[&[u8; 4]]) -> Vec<u8> {
+        let size = 16 + 4 * compatible.len() as u32;
+        let mut header = size.to_be_bytes().to_vec();
+        header.extend(b"ftyp");
+        header.extend(major);
+        header.extend([0; 4]);
+        compatible.iter().for_each(|b| header.extend(*b));
+        header.extend(b"\0\0\0\x08meta");
+        header
+    }
+
+    #[test]
+    fn test_recognises_heif_images() {
+        assert_eq!(sniff(&ftyp(b"heic", &[b"mif1"])), Some(HeifFormat::Heic));
+        assert_eq!(sniff(&ftyp(b"avif", &[])), Some(HeifFormat::Avif));
+        assert_eq!(
+            sniff(&ftyp(b"mif1", &[b"miaf", b"avif"])),
+            Some(HeifFormat::Avif)
+        );
+        assert_eq!(sniff(&ftyp(b"isom", &[b"mp41"])), None);
+        assert_eq!(sniff(b"\xff\xd8\xff\xe0"), None);
+    }
+
+    #[cfg(feature = "heif")]
+    #[test]
+    fn test_decodes_heic_images() {
+        let img = crate::load_image(&fixture("red.heic")).unwrap();
+
+        assert_eq!(img.dimensions(), (16, 16));
+        // HEVC is lossy, so the color is only close to the one encoded.
+        let [r, g, b, a] = img.get_pixel(8, 8).0;
+        assert!(
+            r.abs_diff(200) <= 4 && g.abs_diff(40) <= 4 && b.abs_diff(40) <= 4 && a == 255,
+            "{:?}",
+            img.get_pixel(8, 8)
+        );
+    }
+
+    #[cfg(not(feature = "heif"))]
+    #[test]
+    fn test_names_heic_images_it_cant_decode() {
+        let path = fixture("red.heic");
+
+        let error = crate::load_image(&path).unwrap_err().to_string();
+        assert!(error.contains("red.heic is a HEIC image"), "{error}");
+        assert_eq!(unsupported(&path).unwrap().kind(), ErrorKind::Unsupported);
+    }
+}
@@ -0,0 +1,156 @@
+use std::fs::read_to_string;
+use std::io::{ErrorKind, Result as IoResult};
+use std::path::{Component, Path};
+
+use crate::glob::wildcard_match;
+
+/// Name of the file in a library directory listing what to leave out of
+/// the library, in the same syntax as `.gitignore`.
+pub(crate) const IGNORE_FILE: &str = ".tilerignore";
+
+/// The rules of a library's ignore file, such as `trash/` or `*.tmp`.
+///
+/// Lines starting with `#` and blank lines are skipped. A leading `!`
+/// brings back what an earlier rule left out, a trailing `/` only matches
+/// folders, and a rule with any other `/` is matched from the library root
+/// rather than against any name. `*` and `?` don't match `/`, while a `**`
+/// part matches any number of folders. Later rules win.
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct Ignore {
+    rules: Vec<Rule>,
+}
+
+#[derive(Debug, PartialEq)]
+struct Rule {
+    parts: Vec<Vec<char>>,
+    negated: bool,
+    folders_only: bool,
+    anchored: bool,
+}
+
+impl Ignore {
+    /// Read the ignore file in the library root, ignoring nothing if there
+    /// isn't one.
+    pub(crate) fn read(root: &Path) -> IoResult<Ignore> {
+        match read_to_string(root.join(IGNORE_FILE)) {
+            Ok(text) => Ok(Ignore::parse(&text)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Ignore::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn parse(text: &str) -> Ignore {
+        let rules = text
+            .lines()
+            .map(str::trim_end)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| {
+                let (negated, line) = match line.strip_prefix('!') {
+                    Some(rest) => (true, rest),
+                    None => (false, line.strip_prefix('\\').unwrap_or(line)),
+                };
+                let (folders_only, line) = match line.strip_suffix('/') {
+                    Some(rest) => (true, rest),
+                    None => (false, line),
+                };
+                let anchored = line.contains('/');
+                let parts = line
+                    .trim_start_matches('/')
+                    .split('/')
+                    .map(|part| part.chars().collect())
+                    .collect();
+                Rule {
+                    parts,
+                    negated,
+                    folders_only,
+                    anchored,
+                }
+            })
+            .collect();
+        Ignore { rules }
+    }
+
+    /// Whether the path, relative to the library root, is left out, either
+    /// itself or by being in a folder that is.
+    pub(crate) fn ignores(&self, relative: &Path, is_dir: bool) -> bool {
+        if self.rules.is_empty() {
+            return false;
+        }
+        let names: Vec<Vec<char>> = relative
+            .components()
+            .filter_map(|c| match c {
+                Component::Normal(name) => Some(name.to_string_lossy().chars().collect()),
+                _ => None,
+            })
+            .collect();
+        (1..=names.len()).any(|end| {
+            let folder = end < names.len() || is_dir;
+            self.decides(&names[..end], folder)
+        })
+    }
+
+    /// Whether the last rule matching the path leaves it out.
+    fn decides(&self, names: &[Vec<char>], is_dir: bool) -> bool {
+        self.rules
+            .iter()
+            .rev()
+            .find(|rule| rule.matches(names, is_dir))
+            .is_some_and(|rule| !rule.negated)
+    }
+}
+
+impl Rule {
+    fn matches(&self, names: &[Vec<char>], is_dir: bool) -> bool {
+        if self.folders_only && !is_dir {
+            return false;
+        }
+        if self.anchored {
+            parts_match(&self.parts, names)
+        } else {
+            names
+                .last()
+                .is_some_and(|name| wildcard_match(&self.parts[0], name))
+        }
+    }
+}
+
+/// Match the names of a path against the parts of a rule, each `**` part
+/// standing for any number of names.
+fn parts_match(parts: &[Vec<char>], names: &[Vec<char>]) -> bool {
+    match parts.split_first() {
+        None => names.is_empty(),
+        Some((part, rest)) if part.iter().eq(['*', '*'].iter()) => {
+            (0..=names.len()).any(|skip| parts_match(rest, &names[skip..]))
+        }
+        Some((part, rest)) => names
+            .split_first()
+            .is_some_and(|(name, names)| wildcard_match(part, name) && parts_match(rest, names)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_ignores_like_gitignore() {
+        let ignore = Ignore::parse(
+            "# Kept out of every build\n\ntrash/\n*.tmp\n!keep.tmp\n/exports\nraw/**/*.cr2\n",
+        );
+        let ignores = |path: &str, is_dir| ignore.ignores(Path::new(path), is_dir);
+
+        assert!(ignores("trash", true));
+        assert!(ignores("trash/photo.jpg", false));
+        assert!(!ignores("trash", false));
+        assert!(ignores("photo.tmp", false));
+        assert!(ignores("holiday/photo.tmp", false));
+        assert!(!ignores("keep.tmp", false));
+        assert!(ignores("exports", true));
+        assert!(!ignores("holiday/exports", true));
+        assert!(ignores("raw/2023/may/beach.cr2", false));
+        assert!(ignores("raw/beach.cr2", false));
+        assert!(!ignores("beach.cr2", false));
+        assert!(!ignores("photo.jpg", false));
+        assert!(!Ignore::default().ignores(Path::new("trash"), true));
+    }
+}
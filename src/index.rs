@@ -0,0 +1,425 @@
+use std::fs::{create_dir_all, read, rename, File};
+use std::io::{BufReader, BufWriter, Cursor, Error as IoError, ErrorKind, Result as IoResult};
+use std::path::{Path, PathBuf};
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use image::ImageOutputFormat;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::analysis::{analyse_tile, AnalysisOptions, ImageInfo, ANALYSIS_VERSION};
+use crate::cancel::CancelToken;
+use crate::library::{analyse_sources, cached_fetch, fnv1a, LoadedLibrary, TileLibrary};
+use crate::resize::ResizeFilter;
+use crate::{build_tile, load_image};
+
+/// Version of the index format written by this build. Indexes written in
+/// earlier versions are migrated as they're read.
+const INDEX_VERSION: u32 = 2;
+
+/// Steps migrating an index from each earlier version to the next, the
+/// first from version 1.
+const MIGRATIONS: [fn(Value) -> IoResult<Value>; INDEX_VERSION as usize - 1] = [analysis_grouped];
+
+/// The contents of a library index file.
+#[derive(Serialize, Deserialize)]
+struct LibraryIndex {
+    version: u32,
+    analysis: IndexedAnalysis,
+    images: Vec<IndexedImage>,
+}
+
+/// How the images of an index were analysed.
+#[derive(Serialize, Deserialize, PartialEq)]
+struct IndexedAnalysis {
+    /// Version of the way images were analysed.
+    version: u32,
+    size: u8,
+    linear_light: bool,
+    /// Missing from indexes written before the filter could be chosen.
+    #[serde(default)]
+    filter: ResizeFilter,
+    matched_layout: bool,
+}
+
+impl IndexedAnalysis {
+    fn of(options: &AnalysisOptions) -> IndexedAnalysis {
+        IndexedAnalysis {
+            version: ANALYSIS_VERSION,
+            size: options.sample_size,
+            linear_light: options.linear_light,
+            filter: options.filter,
+            matched_layout: options.matched_layout,
+        }
+    }
+}
+
+/// A library image, its analysis, and optionally a tile to draw it with.
+#[derive(Serialize, Deserialize)]
+struct IndexedImage {
+    /// Relative to the index if the image is beside or beneath it.
+    path: PathBuf,
+    /// FNV-1a hash of the image file, in hex.
+    hash: String,
+    info: ImageInfo,
+    /// Base64 PNG tile, drawn when the image itself isn't at hand.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    thumbnail: Option<String>,
+}
+
+/// Analyse the library images and write an index of their analysis to the
+/// given file, with a tile of each at the thumbnail size if given.
+///
+/// Images that can't be loaded are skipped. Returns the number of images.
+pub fn write_index(
+    lib_paths: &[PathBuf],
+    index_path: &Path,
+    thumbnail_size: Option<u32>,
+    options: &AnalysisOptions,
+) -> IoResult<usize> {
+    let base = match index_path.parent() {
+        Some(dir) if dir != Path::new("") => dir.canonicalize()?,
+        _ => Path::new(".").canonicalize()?,
+    };
+
+    let mut images = Vec::new();
+    for (path, info) in analyse_sources(lib_paths.to_vec(), options, &CancelToken::new())?.images {
+        let bytes = read(&path)?;
+        let thumbnail = match thumbnail_size {
+            Some(size) => {
+                let img = load_image(&path).map_err(IoError::other)?;
+                let tile = build_tile(&img, (size, size), options.resize());
+                let mut png = Cursor::new(Vec::new());
+                tile.write_to(&mut png, ImageOutputFormat::Png)
+                    .map_err(IoError::other)?;
+                Some(STANDARD.encode(png.into_inner()))
+            }
+            None => None,
+        };
+        let path = path.canonicalize()?;
+        images.push(IndexedImage {
+            path: path.strip_prefix(&base).map(Path::to_owned).unwrap_or(path),
+            hash: format!("{:016x}", fnv1a(&bytes)),
+            info,
+            thumbnail,
+        });
+    }
+
+    let count = images.len();
+    let index = LibraryIndex {
+        version: INDEX_VERSION,
+        analysis: IndexedAnalysis::of(options),
+        images,
+    };
+    save_index(&index, index_path)?;
+    Ok(count)
+}
+
+/// Rewrite an index file in the current version, returning whether it
+/// needed migrating.
+pub fn migrate_index(index_path: &Path) -> IoResult<bool> {
+    let value = read_index(index_path)?;
+    let version = index_version(&value)?;
+    if version == INDEX_VERSION {
+        return Ok(false);
+    }
+    save_index(&migrate(value, version)?, index_path)?;
+    Ok(true)
+}
+
+/// Write the index beside its file, then move it into place, so that a
+/// failed write doesn't lose the index it replaces.
+fn save_index(index: &LibraryIndex, index_path: &Path) -> IoResult<()> {
+    let partial = index_path.with_extension("partial");
+    let writer = BufWriter::new(File::create(&partial)?);
+    serde_json::to_writer(writer, index).map_err(IoError::other)?;
+    rename(partial, index_path)
+}
+
+/// The untyped contents of an index file, of any version.
+fn read_index(index_path: &Path) -> IoResult<Value> {
+    let reader = BufReader::new(File::open(index_path)?);
+    serde_json::from_reader(reader).map_err(|e| IoError::new(ErrorKind::InvalidData, e))
+}
+
+/// The version of an index, checked to be one this build can read.
+fn index_version(value: &Value) -> IoResult<u32> {
+    let invalid = |message: String| IoError::new(ErrorKind::InvalidData, message);
+    let version = value
+        .get("version")
+        .and_then(Value::as_u64)
+        .and_then(|v| u32::try_from(v).ok())
+        .filter(|v| *v >= 1)
+        .ok_or_else(|| invalid("Index has no version".to_string()))?;
+    if version > INDEX_VERSION {
+        return Err(invalid(format!(
+            "Index is version {version}, newer than the version {INDEX_VERSION} this build reads"
+        )));
+    }
+    Ok(version)
+}
+
+/// The index in the current version, from its contents in the given version.
+fn migrate(mut value: Value, version: u32) -> IoResult<LibraryIndex> {
+    for step in &MIGRATIONS[version as usize - 1..] {
+        value = step(value)?;
+    }
+    serde_json::from_value(value).map_err(|e| IoError::new(ErrorKind::InvalidData, e))
+}
+
+/// Version 2 groups the analysis settings, adding the version of the way
+/// images were analysed, which was 1 for all version 1 indexes.
+fn analysis_grouped(mut value: Value) -> IoResult<Value> {
+    let Some(fields) = value.as_object_mut() else {
+        return Err(IoError::new(
+            ErrorKind::InvalidData,
+            "Index is not an object",
+        ));
+    };
+    let mut analysis = Map::from_iter([("version".to_string(), Value::from(1))]);
+    for (old, new) in [
+        ("analysis_size", "size"),
+        ("linear_light", "linear_light"),
+        ("matched_layout", "matched_layout"),
+    ] {
+        let setting = fields.remove(old).unwrap_or_default();
+        analysis.insert(new.to_string(), setting);
+    }
+    fields.insert("analysis".to_string(), Value::Object(analysis));
+    fields.insert("version".to_string(), Value::from(2));
+    Ok(value)
+}
+
+/// Library images listed in an index file, used without scanning or
+/// analysing them again.
+///
+/// Images that are missing are drawn from their thumbnail, if the index has
+/// one, which is written into a cache directory beside the index. Images
+/// that are missing without a thumbnail are skipped.
+pub struct IndexedLibrary {
+    index: PathBuf,
+    cache_dir: PathBuf,
+}
+
+impl IndexedLibrary {
+    pub fn new(index: &Path) -> IndexedLibrary {
+        IndexedLibrary {
+            index: index.to_owned(),
+            cache_dir: index.with_extension("cache"),
+        }
+    }
+
+    /// The index in the current version.
+    fn read(&self) -> IoResult<LibraryIndex> {
+        let in_context = |e: IoError| {
+            let message = format!("{}: {e}", self.index.display());
+            IoError::new(e.kind(), message)
+        };
+        let value = read_index(&self.index).map_err(in_context)?;
+        let version = index_version(&value).map_err(in_context)?;
+        migrate(value, version).map_err(in_context)
+    }
+
+    /// Where to draw the image from, if anywhere.
+    fn locate(&self, image: &IndexedImage) -> Option<PathBuf> {
+        let base = self.index.parent().unwrap_or(Path::new(""));
+        let path = base.join(&image.path);
+        if path.is_file() {
+            return Some(path);
+        }
+        let thumbnail = image.thumbnail.as_ref()?;
+        create_dir_all(&self.cache_dir).ok()?;
+        cached_fetch(&self.cache_dir, &image.hash, || {
+            STANDARD
+                .decode(thumbnail)
+                .map_err(|e| IoError::new(ErrorKind::InvalidData, e))
+        })
+        .ok()
+    }
+}
+
+impl TileLibrary for IndexedLibrary {
+    fn images(&self) -> IoResult<Vec<PathBuf>> {
+        let index = self.read()?;
+        Ok(index.images.iter().filter_map(|i| self.locate(i)).collect())
+    }
+
+    /// Images are re-analysed if they were indexed with a different analysis
+    /// size or way of sampling, or by a build analysing images differently.
+    fn load(&self, options: &AnalysisOptions, cancel: &CancelToken) -> IoResult<LoadedLibrary> {
+        let index = self.read()?;
+        let reanalyse = index.analysis != IndexedAnalysis::of(options);
+        let mut library = Vec::with_capacity(index.images.len());
+        for image in index.images {
+            cancel.check()?;
+            let Some(path) = self.locate(&image) else {
+                continue;
+            };
+            let info = if reanalyse {
+                let img = load_image(&path).map_err(IoError::other)?;
+                analyse_tile(&img, options)
+            } else {
+                image.info
+            };
+            library.push((path, info));
+        }
+        Ok(library.into())
+    }
+
+    #[cfg(feature = "async")]
+    fn stores_analysis(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::analysis::analyse;
+    use crate::test_support::scratch_dir;
+    use image::{Rgba, RgbaImage};
+    use std::fs::{remove_dir_all, remove_file, write};
+
+    /// A scratch directory with an empty `photos` directory in it.
+    fn scratch_library(name: &str) -> PathBuf {
+        let dir = scratch_dir(&format!("index-{name}"));
+        create_dir_all(dir.join("photos")).unwrap();
+        dir
+    }
+
+    fn write_images(dir: &Path) -> Vec<PathBuf> {
+        let colors = [[255, 0, 0], [0, 0, 255]];
+        colors
+            .iter()
+            .enumerate()
+            .map(|(i, [r, g, b])| {
+                let path = dir.join(format!("photos/{i}.png"));
+                RgbaImage::from_pixel(8, 4, Rgba([*r, *g, *b, 255]))
+                    .save(&path)
+                    .unwrap();
+                path
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_loads_indexed_images_relative_to_the_index() {
+        let dir = scratch_library("relative");
+        let paths = write_images(&dir);
+        let index = dir.join("lib.idx");
+        let options = AnalysisOptions::new(Some(2));
+
+        assert_eq!(write_index(&paths, &index, None, &options).unwrap(), 2);
+        let text = std::fs::read_to_string(&index).unwrap();
+        assert!(text.contains(r#""path":"photos/0.png""#));
+
+        let library = IndexedLibrary::new(&index)
+            .load(&options, &CancelToken::new())
+            .unwrap()
+            .images;
+        assert_eq!(library.len(), 2);
+        assert_eq!(library[1].0, dir.join("photos/1.png"));
+        let blue = RgbaImage::from_pixel(8, 4, Rgba([0, 0, 255, 255]));
+        assert_eq!(library[1].1, analyse(&blue, &options));
+        remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_draws_missing_images_from_thumbnails() {
+        let dir = scratch_library("thumbnails");
+        let paths = write_images(&dir);
+        let index = dir.join("lib.idx");
+        let options = AnalysisOptions::new(Some(2));
+        write_index(&paths, &index, Some(4), &options).unwrap();
+        remove_file(&paths[0]).unwrap();
+
+        let library = IndexedLibrary::new(&index)
+            .load(&options, &CancelToken::new())
+            .unwrap()
+            .images;
+
+        assert_eq!(library.len(), 2);
+        assert!(library[0].0.starts_with(dir.join("lib.cache")));
+        let thumbnail = load_image(&library[0].0).unwrap();
+        assert_eq!(thumbnail.dimensions(), (4, 4));
+        assert_eq!(*thumbnail.get_pixel(0, 0), Rgba([255, 0, 0, 255]));
+        assert_eq!(library[1].0, paths[1]);
+        remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_rejects_newer_or_unversioned_indexes() {
+        let dir = scratch_library("version");
+        let index = dir.join("lib.idx");
+        let error = |text: &str| {
+            write(&index, text).unwrap();
+            IndexedLibrary::new(&index).images().unwrap_err()
+        };
+
+        let newer = error(r#"{"version":99,"images":[]}"#);
+        assert_eq!(newer.kind(), ErrorKind::InvalidData);
+        assert!(newer.to_string().contains("newer"));
+        assert_eq!(error(r#"{"images":[]}"#).kind(), ErrorKind::InvalidData);
+        assert!(migrate_index(&index).is_err());
+        remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_migrates_version_1_indexes() {
+        let dir = scratch_library("migrate");
+        let paths = write_images(&dir);
+        let options = AnalysisOptions::new(Some(2));
+        let red = analyse(&load_image(&paths[0]).unwrap(), &options);
+        let v1 = serde_json::json!({
+            "version": 1,
+            "analysis_size": 2,
+            "linear_light": true,
+            "matched_layout": false,
+            "images": [{"path": "photos/0.png", "hash": "0", "info": red}],
+        });
+        let index = dir.join("lib.idx");
+        write(&index, v1.to_string()).unwrap();
+
+        let library = IndexedLibrary::new(&index)
+            .load(&options, &CancelToken::new())
+            .unwrap()
+            .images;
+        assert_eq!(library, vec![(paths[0].clone(), red)]);
+
+        assert!(migrate_index(&index).unwrap());
+        let migrated = read_index(&index).unwrap();
+        assert_eq!(migrated["version"], INDEX_VERSION);
+        assert_eq!(migrated["analysis"]["size"], 2);
+        assert!(!migrate_index(&index).unwrap());
+        assert!(!dir.join("lib.partial").exists());
+        remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_reanalyses_images_analysed_by_other_builds() {
+        let dir = scratch_library("reanalyse");
+        let paths = write_images(&dir);
+        let index = dir.join("lib.idx");
+        let options = AnalysisOptions::new(Some(2));
+        write_index(&paths, &index, None, &options).unwrap();
+        // As if analysed by a build sampling images differently.
+        let mut value = read_index(&index).unwrap();
+        value["analysis"]["version"] = Value::from(ANALYSIS_VERSION + 1);
+        value["images"][0]["info"] = serde_json::to_value(analyse(
+            &RgbaImage::from_pixel(2, 2, Rgba([0, 255, 0, 255])),
+            &options,
+        ))
+        .unwrap();
+        write(&index, value.to_string()).unwrap();
+
+        let library = IndexedLibrary::new(&index)
+            .load(&options, &CancelToken::new())
+            .unwrap()
+            .images;
+
+        let red = analyse(&load_image(&paths[0]).unwrap(), &options);
+        assert_eq!(library[0].1, red);
+        remove_dir_all(dir).unwrap();
+    }
+}
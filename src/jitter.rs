@@ -0,0 +1,219 @@
+#[cfg(feature = "rand")]
+use std::io::Result as IoResult;
+
+#[cfg(feature = "rand")]
+use image::{Rgba, RgbaImage};
+#[cfg(feature = "rand")]
+use rand::Rng;
+#[cfg(feature = "serde")]
+use serde::Deserialize;
+
+#[cfg(feature = "rand")]
+use crate::{core::PixelRegion, resize::Resize, seed::Seed, Drawable};
+
+/// Small random turns of each tile, for a hand-placed look.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
+pub struct Jitter {
+    /// Largest angle (in degrees) each tile is turned by, either way.
+    pub max_angle: f64,
+}
+
+impl Default for Jitter {
+    fn default() -> Self {
+        Self { max_angle: 5.0 }
+    }
+}
+
+/// Turn each drawable by a random angle within the jitter's limit, the same
+/// angles for the same seed.
+#[cfg(feature = "rand")]
+pub(crate) fn turn<T: Drawable>(tiles: Vec<T>, jitter: &Jitter, seed: Seed) -> Vec<Turned<T>> {
+    let limit = jitter.max_angle.abs().to_radians();
+    let mut rng = seed.rng("jitter");
+    tiles
+        .into_iter()
+        .map(|inner| {
+            let angle = if limit > 0.0 {
+                rng.gen_range(-limit..=limit)
+            } else {
+                0.0
+            };
+            let extent = turned_extent(inner.region(), angle);
+            Turned {
+                inner,
+                angle,
+                extent,
+            }
+        })
+        .collect()
+}
+
+/// A drawable turned about its centre, drawn over the area it then covers.
+#[cfg(feature = "rand")]
+pub(crate) struct Turned<T> {
+    inner: T,
+    /// Clockwise angle, in radians.
+    angle: f64,
+    extent: PixelRegion,
+}
+
+#[cfg(feature = "rand")]
+impl<T: Drawable> Drawable for Turned<T> {
+    fn region(&self) -> &PixelRegion {
+        &self.extent
+    }
+
+    fn render(&self, resize: Resize) -> IoResult<RgbaImage> {
+        let img = self.inner.render(resize)?;
+        Ok(rotate(
+            &img,
+            self.angle,
+            self.extent.width,
+            self.extent.height,
+        ))
+    }
+}
+
+/// The area a region covers once turned about its centre by the angle.
+#[cfg(feature = "rand")]
+fn turned_extent(region: &PixelRegion, angle: f64) -> PixelRegion {
+    let (w, h) = (f64::from(region.width), f64::from(region.height));
+    let (sin, cos) = (angle.sin().abs(), angle.cos().abs());
+    let width = (w * cos + h * sin - 1e-9).ceil() as u32;
+    let height = (w * sin + h * cos - 1e-9).ceil() as u32;
+    PixelRegion {
+        x: region.x - (i64::from(width) - i64::from(region.width)).div_euclid(2),
+        y: region.y - (i64::from(height) - i64::from(region.height)).div_euclid(2),
+        width,
+        height,
+    }
+}
+
+/// The image turned clockwise about its centre by the angle (in radians),
+/// centred in an image of the given size, transparent where it doesn't
+/// reach.
+#[cfg(feature = "rand")]
+fn rotate(img: &RgbaImage, angle: f64, width: u32, height: u32) -> RgbaImage {
+    let (sin, cos) = angle.sin_cos();
+    let (source_x, source_y) = (f64::from(img.width()) / 2.0, f64::from(img.height()) / 2.0);
+    let (centre_x, centre_y) = (f64::from(width) / 2.0, f64::from(height) / 2.0);
+    RgbaImage::from_fn(width, height, |x, y| {
+        // Turn the centre of each pixel back to where it came from.
+        let (dx, dy) = (f64::from(x) + 0.5 - centre_x, f64::from(y) + 0.5 - centre_y);
+        let sx = dx * cos + dy * sin + source_x - 0.5;
+        let sy = -dx * sin + dy * cos + source_y - 0.5;
+        sample(img, sx, sy)
+    })
+}
+
+/// The color at a point of the image, blending the four nearest pixels with
+/// their alpha premultiplied, and transparent outside the image.
+#[cfg(feature = "rand")]
+fn sample(img: &RgbaImage, x: f64, y: f64) -> Rgba<u8> {
+    let (x0, y0) = (x.floor(), y.floor());
+    let (fx, fy) = (x - x0, y - y0);
+    let mut sums = [0.0; 4];
+    for (dx, dy, share) in [
+        (0, 0, (1.0 - fx) * (1.0 - fy)),
+        (1, 0, fx * (1.0 - fy)),
+        (0, 1, (1.0 - fx) * fy),
+        (1, 1, fx * fy),
+    ] {
+        let (px, py) = (x0 as i64 + dx, y0 as i64 + dy);
+        if px < 0 || py < 0 || px >= img.width().into() || py >= img.height().into() {
+            continue;
+        }
+        let [r, g, b, a] = img.get_pixel(px as u32, py as u32).0;
+        let alpha = f64::from(a) * share;
+        sums[0] += f64::from(r) * alpha;
+        sums[1] += f64::from(g) * alpha;
+        sums[2] += f64::from(b) * alpha;
+        sums[3] += alpha;
+    }
+    if sums[3] <= 0.0 {
+        return Rgba([0, 0, 0, 0]);
+    }
+    let [r, g, b] = [0, 1, 2].map(|c| (sums[c] / sums[3]).round() as u8);
+    Rgba([r, g, b, sums[3].round() as u8])
+}
+
+#[cfg(all(test, feature = "rand"))]
+mod test {
+    use super::*;
+    use crate::ResizeFilter;
+
+    struct Block(PixelRegion);
+
+    impl Drawable for Block {
+        fn region(&self) -> &PixelRegion {
+            &self.0
+        }
+
+        fn render(&self, _resize: Resize) -> IoResult<RgbaImage> {
+            Ok(RgbaImage::from_pixel(
+                self.0.width,
+                self.0.height,
+                Rgba([200, 0, 0, 255]),
+            ))
+        }
+    }
+
+    #[test]
+    fn test_turns_tiles_within_the_limit_repeatably() {
+        let blocks = || (0..20).map(|i| Block(PixelRegion::new(i * 10, 0, 10, 10)));
+        let jitter = Jitter::default();
+        let angles = |seed| -> Vec<f64> {
+            turn(blocks().collect(), &jitter, seed)
+                .iter()
+                .map(|t| t.angle)
+                .collect()
+        };
+
+        let first = angles(Seed(1));
+        assert_eq!(first, angles(Seed(1)));
+        assert_ne!(first, angles(Seed(2)));
+        assert!(first.iter().all(|a| a.abs() <= 5f64.to_radians()));
+        assert!(first.iter().any(|a| *a != 0.0));
+    }
+
+    #[test]
+    fn test_covers_the_turned_area() {
+        let region = PixelRegion::new(100, 50, 40, 20);
+
+        assert_eq!(turned_extent(&region, 0.0), region);
+        // A quarter turn swaps the sides about the centre.
+        let quarter = turned_extent(&region, std::f64::consts::FRAC_PI_2);
+        assert_eq!(quarter, PixelRegion::new(110, 40, 20, 40));
+        let slight = turned_extent(&region, 5f64.to_radians());
+        assert!(slight.width > 40 && slight.height > 20);
+    }
+
+    #[test]
+    fn test_rotates_images_into_their_extent() {
+        let region = PixelRegion::new(0, 0, 20, 20);
+        let angle = std::f64::consts::FRAC_PI_4;
+        let turned = Turned {
+            inner: Block(region),
+            angle,
+            extent: turned_extent(&region, angle),
+        };
+
+        let img = turned
+            .render(Resize::new(ResizeFilter::Fast, false))
+            .unwrap();
+        let (width, height) = img.dimensions();
+
+        assert_eq!((width, height), (29, 29));
+        assert_eq!(*img.get_pixel(14, 14), Rgba([200, 0, 0, 255]));
+        // The corners of the extent are beyond the turned edges.
+        assert_eq!(img.get_pixel(0, 0).0[3], 0);
+        assert_eq!(img.get_pixel(14, 2).0[3], 255);
+        assert_eq!(rotate(&block_image(), 0.0, 4, 4), block_image());
+    }
+
+    fn block_image() -> RgbaImage {
+        RgbaImage::from_fn(4, 4, |x, y| Rgba([x as u8 * 60, y as u8 * 60, 0, 255]))
+    }
+}
@@ -1,51 +1,541 @@
+mod adjust;
 mod analysis;
+#[cfg(feature = "async")]
+mod asynchronous;
+mod atlas;
+mod background;
+mod batch;
+mod blend;
+mod cancel;
+mod checkpoint;
+mod collage;
+mod color;
+mod compare;
+mod config;
+mod constraints;
 mod core;
+mod cost;
+mod coverage;
+mod cutout;
+mod diffusion;
+mod evaluate;
+mod failure;
+mod feather;
+mod frame;
+mod glob;
+#[cfg(feature = "gpu")]
+mod gpu;
+mod heatmap;
+mod heif;
+mod ignore;
+#[cfg(feature = "index")]
+mod index;
+mod jitter;
+mod library;
+mod mask;
 mod matching;
+mod metadata;
+mod mipmap;
+mod order;
+mod postprocess;
+#[cfg(feature = "index")]
+mod prepare;
+mod print;
+mod protect;
+mod pruned;
+mod pyramid;
+mod quantise;
+#[cfg(feature = "raw")]
+mod raw;
+mod refine;
+mod resize;
+mod result;
+#[cfg(feature = "s3")]
+mod s3;
+mod seed;
+#[cfg(feature = "serve")]
+mod serve;
+#[cfg(feature = "skin-tones")]
+mod skin;
+mod stats;
+mod strategy;
+mod suggest;
+mod summary;
+#[cfg(feature = "svg")]
+mod svg;
+#[cfg(test)]
+mod test_support;
+mod text;
+mod ties;
 mod tiling;
+mod tonemap;
+mod undersized;
+#[cfg(feature = "watch")]
+mod watch;
+mod watermark;
+mod working;
 
-use analysis::{analyse, ImageInfo};
 use image::ImageFormat::Jpeg;
-use image::{imageops, DynamicImage, GenericImageView, ImageResult, RgbaImage, SubImage};
+use image::{
+    imageops, GenericImageView, ImageBuffer, ImageError, ImageResult, Rgba, RgbaImage, SubImage,
+};
+#[cfg(feature = "serde")]
+use serde::Deserialize;
 use std::collections::HashMap;
 use std::fs::read_dir;
-use std::io::Result as IoResult;
+use std::io::{Error as IoError, ErrorKind, Result as IoResult};
 use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
 
-use crate::analysis::AnalysisOptions;
-use crate::core::{Dimensions, PixelRegion, TileLocation, TileLocationExtensions, TupleExtensions};
-use crate::matching::MatchingTileStrategy;
+use crate::adjust::{match_luminance, LuminanceStats};
+use crate::checkpoint::{Checkpoint, CHECKPOINT_ROWS};
+use crate::constraints::{apply_constraints, apply_folder_weights, apply_preferences};
+use crate::core::TileLocationExtensions;
+use crate::frame::Framer;
+use crate::matching::CellAnalyser;
+use crate::protect::ProtectedTileStrategy;
+use crate::strategy::{build_strategy, StrategyOptions};
 use crate::tiling::choose_tile_area;
+use crate::working::{to_working_resolution, working_dimensions};
 
-// Public actions
+pub use crate::analysis::{analyse, AnalysisOptions, ChannelWeights, ImageInfo};
+#[cfg(feature = "async")]
+pub use crate::asynchronous::{load_library_async, mosaic_async, mosaic_with_library_async};
+pub use crate::atlas::{Atlas, AtlasCell, AtlasIndex, AtlasTile};
+pub use crate::background::Background;
+pub use crate::batch::{find_targets, mosaic_batch};
+pub use crate::blend::{Blend, BlendMode};
+pub use crate::cancel::CancelToken;
+pub use crate::compare::{compare, comparison_table, Comparison, Variant};
+pub use crate::config::{
+    load_compare_config, load_config, BuildConfig, CompareConfig, OutputFormat,
+};
+pub use crate::constraints::{Exclusion, FolderWeight, Pin, Preference};
+pub use crate::core::{CellCoords, Dimensions, PixelRegion, Rectangle};
+pub use crate::cost::{CostWeights, Term};
+pub use crate::coverage::{ColorRegion, Coverage};
+pub use crate::cutout::knock_out_background;
+pub use crate::evaluate::{EvaluationOptions, Quality};
+pub use crate::failure::{EmptyLibrary, Failure};
+pub use crate::frame::Frame;
+pub use crate::glob::Glob;
+pub use crate::heatmap::HeatmapKind;
+pub use crate::heif::unsupported_images;
+#[cfg(feature = "index")]
+pub use crate::index::migrate_index;
+pub use crate::jitter::Jitter;
+pub use crate::library::{analyse_library, library_images, LibrarySource, LoadedLibrary};
+pub use crate::mask::{Color, Mask, MaskShape};
+pub use crate::metadata::{Date, LibraryFilter};
+pub use crate::mipmap::Mipmaps;
+pub use crate::order::ProcessingOrder;
+pub use crate::postprocess::PostProcess;
+pub use crate::print::{export_pages, PageSize, PrintLayout};
+pub use crate::protect::ProtectedArea;
+pub use crate::refine::Refinement;
+pub use crate::resize::{Resize, ResizeFilter};
+pub use crate::result::{MosaicResult, MosaicStats};
+pub use crate::seed::Seed;
+#[cfg(feature = "serve")]
+pub use crate::serve::{parse_library, MosaicService};
+pub use crate::stats::{CostStats, PlanStats};
+pub use crate::strategy::{strategy_names, CellRadii, Penalty};
+pub use crate::suggest::suggest_parameters;
+#[cfg(feature = "svg")]
+pub use crate::svg::SvgImages;
+pub use crate::text::TextShape;
+pub use crate::ties::TieBreak;
+pub use crate::undersized::SmallTiles;
+#[cfg(feature = "watch")]
+pub use crate::watch::watch;
+pub use crate::watermark::{Corner, Mark, TextMark, Watermark};
+
+// Options
 
-/// Build and return a mosaic image from the given tiles.
-pub fn mosaic(target_path: &str, lib_path: &str) -> IoResult<RgbaImage> {
-    let analysis_size = 20;
-    let cell_size = 20;
-    let tile_size = 100;
+/// Settings controlling how a mosaic is built.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
+pub struct MosaicOptions {
+    /// Number of samples along each side when comparing images.
+    pub analysis_size: u8,
+    /// How much a difference in each channel counts when comparing images.
+    pub channel_weights: ChannelWeights,
+    /// Size (in target pixels) of each cell of the target.
+    pub cell_size: u32,
+    /// About how many cells to split the target into, choosing the cell size
+    /// from the target's dimensions instead of using `cell_size`.
+    pub cell_budget: Option<u32>,
+    /// About how many cells to split the target into by resizing it before
+    /// splitting it into cells of `cell_size`, so that the mosaic is as fine
+    /// whatever the resolution of the target image.
+    pub working_cells: Option<u32>,
+    /// Size (in output pixels) each cell is drawn at, which needn't be a
+    /// multiple of the cell size.
+    pub tile_size: u32,
+    /// Name of the strategy used to choose tiles, see `strategy_names`.
+    pub strategy: String,
+    /// Penalty for placing the same tile near itself.
+    pub penalty: Penalty,
+    /// Order cells are visited in when choosing tiles.
+    pub order: ProcessingOrder,
+    /// Seed for any random choices.
+    pub seed: Seed,
+    /// How to choose between tiles that match a cell equally well.
+    pub tie_break: TieBreak,
+    /// Budget for polishing the chosen tiles.
+    pub refinement: Refinement,
+    /// Number of closest tiles by color summary the pruned strategy compares.
+    pub candidates: usize,
+    /// Whether the independent strategy rules out tiles by comparing coarser
+    /// versions of their samples first, choosing the same tiles faster on
+    /// large libraries.
+    pub pyramid: bool,
+    /// Whether the holistic strategy also chooses tiles in the reverse
+    /// order, keeping whichever choices cost less in total, so that the
+    /// cells chosen first don't take all the best tiles.
+    pub reverse_pass: bool,
+    /// How likely each cell is to get its second or third best tile instead
+    /// of its best, from 0 (never) to 1 (as likely as the best), to break up
+    /// repeated patterns across flat areas such as skies.
+    pub temperature: f64,
+    /// How much color, preference, reuse, and spatial terms count towards
+    /// the cost of drawing a tile in a cell.
+    pub cost: CostWeights,
+    /// Whether to adjust each tile's brightness and contrast to match its cell.
+    pub match_luminance: bool,
+    /// Whether to average colors in linear light when analysing and resizing.
+    pub linear_light: bool,
+    /// Filter tiles are resized with when drawn.
+    pub resize_filter: ResizeFilter,
+    /// Filter images and cells are resized with when analysed.
+    pub analysis_filter: ResizeFilter,
+    /// Whether to compare each cell with only the part of each tile drawn
+    /// over it, sample for sample, rather than with the whole tile image.
+    pub matched_layout: bool,
+    /// Number of colors to reduce the target to before matching, for a
+    /// posterised look with fewer changes of tile across flat areas.
+    pub quantise: Option<u16>,
+    /// Mask shaping the mosaic, leaving out the cells it doesn't cover.
+    pub mask: Option<Mask>,
+    /// Library images fixed to cells of the target.
+    pub pins: Vec<Pin>,
+    /// Areas of the target some library images must not be drawn in.
+    pub exclusions: Vec<Exclusion>,
+    /// Library images to favour over closer matches.
+    pub preferred: Vec<Preference>,
+    /// Weights for the library images in folders, favouring curated photos
+    /// over filler without leaving the filler out.
+    pub folder_weights: Vec<FolderWeight>,
+    /// Areas of the target, such as faces, drawn with smaller cells.
+    pub protected: Vec<ProtectedArea>,
+    /// Whether to find the patches of skin tone in the target shaped roughly
+    /// like faces (with the `skin-tones` feature) and protect them as well as
+    /// the given areas. They are found by color, not by a face detector.
+    pub protect_skin_tones: bool,
+    /// Card to frame each tile in like an instant photo, if any.
+    pub frame: Option<Frame>,
+    /// Small random turns of each tile, if any.
+    pub jitter: Option<Jitter>,
+    /// Distance (in output pixels) each edge of each tile is drawn inside its
+    /// cell, leaving gaps between tiles, or outside it if negative, so that
+    /// neighbouring tiles overlap.
+    pub tile_inset: i32,
+    /// Width (in output pixels) over which each tile fades in over the tiles
+    /// above and to its left, hiding the seams between them, or 0 for hard
+    /// edges.
+    pub feather: u32,
+    /// How tiles are blended with the target beneath them, if at all.
+    pub blend: Option<Blend>,
+    /// What to fill any part of the mosaic no tile covers with, left
+    /// transparent if not given.
+    pub background: Option<Background>,
+    /// Finishing touches applied to the whole mosaic in turn once drawn.
+    pub post_process: Vec<PostProcess>,
+    /// Mark stamped in a corner of the finished mosaic, if any.
+    pub watermark: Option<Watermark>,
+    /// Which library images to build with, by their metadata.
+    pub filter: LibraryFilter,
+    /// What to do with library images smaller than the tiles drawn from
+    /// them.
+    pub small_tiles: SmallTiles,
+    /// Smaller copies of library images to draw tiles from, if kept.
+    pub mipmaps: Option<Mipmaps>,
+}
 
-    let target = load_image(Path::new(target_path)).unwrap();
-    let lib_paths = find_paths(lib_path)?;
+impl Default for MosaicOptions {
+    fn default() -> Self {
+        Self {
+            analysis_size: 20,
+            channel_weights: ChannelWeights::default(),
+            cell_size: 20,
+            cell_budget: None,
+            working_cells: None,
+            tile_size: 100,
+            strategy: "independent".to_string(),
+            penalty: Penalty::default(),
+            order: ProcessingOrder::default(),
+            seed: Seed::default(),
+            tie_break: TieBreak::default(),
+            refinement: Refinement::default(),
+            candidates: 20,
+            pyramid: false,
+            reverse_pass: false,
+            temperature: 0.0,
+            cost: CostWeights::default(),
+            match_luminance: false,
+            linear_light: true,
+            resize_filter: ResizeFilter::default(),
+            analysis_filter: ResizeFilter::default(),
+            matched_layout: false,
+            quantise: None,
+            mask: None,
+            pins: Vec::new(),
+            exclusions: Vec::new(),
+            preferred: Vec::new(),
+            folder_weights: Vec::new(),
+            protected: Vec::new(),
+            protect_skin_tones: false,
+            frame: None,
+            jitter: None,
+            tile_inset: 0,
+            feather: 0,
+            blend: None,
+            background: None,
+            post_process: Vec::new(),
+            watermark: None,
+            filter: LibraryFilter::default(),
+            small_tiles: SmallTiles::default(),
+            mipmaps: None,
+        }
+    }
+}
 
-    let analysis_options = AnalysisOptions::new(Some(analysis_size));
-    let lib_info = analyse_available_images(&lib_paths, &analysis_options);
+// Public actions
 
-    let strategy = MatchingTileStrategy::new(&lib_info, &analysis_options);
-    let tiles = strategy.choose(&target, &(cell_size, cell_size));
+/// Build a mosaic from the tiles in the given directories, returning the
+/// image along with where each tile is drawn and how the build went.
+pub fn mosaic<P: AsRef<Path>>(
+    target_path: &Path,
+    lib_dirs: &[P],
+    options: &MosaicOptions,
+) -> IoResult<MosaicResult> {
+    mosaic_cancellable(target_path, lib_dirs, options, &CancelToken::new())
+}
+
+/// Build a mosaic as `mosaic` does, failing with an `Interrupted` error
+/// soon after the token is cancelled.
+pub fn mosaic_cancellable<P: AsRef<Path>>(
+    target_path: &Path,
+    lib_dirs: &[P],
+    options: &MosaicOptions,
+    cancel: &CancelToken,
+) -> IoResult<MosaicResult> {
+    let started = Instant::now();
+    let plan = plan_mosaic(target_path, lib_dirs, options, cancel)?;
+    finish(plan, started.elapsed(), options, cancel, None)
+}
+
+/// Build a mosaic as `mosaic` does, keeping the plan and each band of the
+/// image once drawn in the checkpoint directory. A build that is stopped
+/// carries on from what is kept there when run again with the same target,
+/// libraries and options, and what is kept is removed once the mosaic is
+/// built.
+///
+/// Changes to the library images since the plan was kept are not noticed.
+pub fn mosaic_resumable<P: AsRef<Path>>(
+    target_path: &Path,
+    lib_dirs: &[P],
+    options: &MosaicOptions,
+    checkpoint_dir: &Path,
+) -> IoResult<MosaicResult> {
+    let cancel = CancelToken::new();
+    let libraries: Vec<&Path> = lib_dirs.iter().map(AsRef::as_ref).collect();
+    let build = format!("{target_path:?} {libraries:?} {options:?}");
+    let checkpoint = Checkpoint::new(checkpoint_dir, &build);
+    let started = Instant::now();
+    let plan = match checkpoint.plan(target_path, options)? {
+        Some(plan) => plan,
+        None => {
+            let plan = plan_mosaic(target_path, lib_dirs, options, &cancel)?;
+            checkpoint.save_plan(&plan)?;
+            plan
+        }
+    };
+    let result = finish(plan, started.elapsed(), options, &cancel, Some(&checkpoint))?;
+    checkpoint.clear()?;
+    Ok(result)
+}
 
-    let ratio = tile_size / cell_size;
-    let tiles = tiles.iter().map(|t| t.scale(ratio)).collect();
-    let output_size = target.dimensions().scale(ratio);
+/// Build and return a mosaic image as `mosaic` does, along with a heatmap
+/// the size of the target coloring each cell by how poorly it is matched or
+/// how often its tile is used, to show where the library falls short.
+pub fn mosaic_heatmap<P: AsRef<Path>>(
+    target_path: &Path,
+    lib_dirs: &[P],
+    options: &MosaicOptions,
+    kind: HeatmapKind,
+) -> IoResult<(RgbaImage, RgbaImage)> {
+    let cancel = CancelToken::new();
+    let plan = plan_mosaic(target_path, lib_dirs, options, &cancel)?;
+    let image = render_with(&plan, options, &cancel, None)?;
+    let heatmap = heatmap::draw(&plan, &image, kind);
+    Ok((image, heatmap))
+}
 
-    let output_image = build_image(output_size, tiles);
+/// Build and return an SVG document laying out the mosaic tiles.
+#[cfg(feature = "svg")]
+pub fn mosaic_svg<P: AsRef<Path>>(
+    target_path: &Path,
+    lib_dirs: &[P],
+    options: &MosaicOptions,
+    images: SvgImages,
+) -> IoResult<String> {
+    let plan = plan_mosaic(target_path, lib_dirs, options, &CancelToken::new())?;
+    svg::build_svg(plan.size, &plan.tiles, images, resize(options)).map_err(IoError::other)
+}
 
-    Ok(output_image)
+/// Build and return an atlas of the distinct tiles of the mosaic, with an
+/// index of which tile to draw in each cell.
+pub fn mosaic_atlas<P: AsRef<Path>>(
+    target_path: &Path,
+    lib_dirs: &[P],
+    options: &MosaicOptions,
+) -> IoResult<Atlas> {
+    let plan = plan_mosaic(target_path, lib_dirs, options, &CancelToken::new())?;
+    atlas::build_atlas(plan.size, &plan.tiles, resize(options)).map_err(IoError::other)
 }
 
 /// Build and return a tile image from the given target.
 pub fn tile(lib_path: &str) -> ImageResult<RgbaImage> {
     let size = (128, 128);
-    load_image(Path::new(lib_path)).map(|img| build_tile(&img, size))
+    load_image(Path::new(lib_path))
+        .map(|img| build_tile(&img, size, Resize::new(ResizeFilter::Fast, true)))
+}
+
+/// Measure how closely a built mosaic recreates its target.
+pub fn evaluate(
+    target_path: &Path,
+    mosaic: &RgbaImage,
+    options: &EvaluationOptions,
+) -> IoResult<Quality> {
+    let target = load_image(target_path).map_err(IoError::other)?;
+    Ok(evaluate::measure(&target, mosaic, options))
+}
+
+/// Find how well the library images cover the main colors of the target's
+/// cells, to show which colors of photo the library lacks.
+pub fn library_coverage<P: AsRef<Path>>(
+    target_path: &Path,
+    lib_dirs: &[P],
+    options: &MosaicOptions,
+) -> IoResult<Coverage> {
+    let target = load_image(target_path).map_err(IoError::other)?;
+    let (target, _) = to_working_resolution(target, options);
+    let cancel = CancelToken::new();
+    let mut library = load_library(
+        lib_dirs,
+        &options.filter,
+        &strategy_options(options).analysis,
+        &cancel,
+    )?
+    .images;
+    library.retain(|(path, _)| options.filter.accepts(path));
+    let cell_size = cell_size(options, target.dimensions());
+    Ok(coverage::measure(&target, cell_size, &library))
+}
+
+/// The size (in target pixels) of the cells a mosaic of the target is planned
+/// with, once any cell budget or working resolution has changed the cell size
+/// of the options.
+pub fn planned_cell_size(target_path: &Path, options: &MosaicOptions) -> IoResult<u32> {
+    let target = image::image_dimensions(target_path).map_err(IoError::other)?;
+    let working = working_dimensions(target, options);
+    let size = cell_size(options, working);
+    // Cells are planned at the working resolution, so are scaled back up to
+    // the target's.
+    let scale = f64::from(target.0) / f64::from(working.0.max(1));
+    Ok(((f64::from(size) * scale).round() as u32).max(1))
+}
+
+/// Plan the mosaic without drawing it, describing how the tiles would be used
+/// and how well they would match, to judge a build before running it.
+pub fn plan_stats<P: AsRef<Path>>(
+    target_path: &Path,
+    lib_dirs: &[P],
+    options: &MosaicOptions,
+) -> IoResult<PlanStats> {
+    let plan = plan_mosaic(target_path, lib_dirs, options, &CancelToken::new())?;
+    Ok(PlanStats::new(&plan.tiles, &plan.costs))
+}
+
+/// The options with the cell and tile sizes suggested for the target and
+/// the number of images in the libraries, see `suggest_parameters`.
+pub fn auto_options<P: AsRef<Path>>(
+    target_path: &Path,
+    lib_dirs: &[P],
+    options: &MosaicOptions,
+) -> IoResult<MosaicOptions> {
+    let target = image::image_dimensions(target_path).map_err(IoError::other)?;
+    let mut library_size = 0;
+    for lib_dir in lib_dirs {
+        let images = library::open(lib_dir.as_ref(), &options.filter)?.images()?;
+        library_size += images.iter().filter(|p| options.filter.accepts(p)).count();
+    }
+    let suggested = suggest_parameters(target, library_size);
+    Ok(MosaicOptions {
+        cell_size: suggested.cell_size,
+        cell_budget: suggested.cell_budget,
+        tile_size: suggested.tile_size,
+        ..options.clone()
+    })
+}
+
+/// Prepare the images in the given library directories for repeated builds,
+/// returning how many tiles were written to the output directory.
+#[cfg(feature = "index")]
+pub fn prepare<P: AsRef<Path>>(
+    lib_dirs: &[P],
+    out_dir: &Path,
+    options: &MosaicOptions,
+) -> IoResult<usize> {
+    let analysis_options = AnalysisOptions {
+        summarise: true,
+        linear_light: options.linear_light,
+        filter: options.analysis_filter,
+        ..AnalysisOptions::new(Some(options.analysis_size))
+    };
+    let mut lib_paths = Vec::new();
+    for lib_dir in lib_dirs {
+        lib_paths.extend(library::open(lib_dir.as_ref(), &options.filter)?.images()?);
+    }
+    prepare::write_prepared_library(&lib_paths, out_dir, options.tile_size, &analysis_options)
+}
+
+/// Analyse the images in the given libraries and write an index of them to
+/// the given file, which can then be used as a library without scanning or
+/// analysing it again. Returns how many images were indexed.
+///
+/// With thumbnails, the index also holds a tile of each image, so it can be
+/// used where the images themselves aren't.
+#[cfg(feature = "index")]
+pub fn index_library<P: AsRef<Path>>(
+    lib_dirs: &[P],
+    index_path: &Path,
+    options: &MosaicOptions,
+    thumbnails: bool,
+) -> IoResult<usize> {
+    let analysis_options = AnalysisOptions {
+        summarise: true,
+        ..strategy_options(options).analysis
+    };
+    let mut lib_paths = Vec::new();
+    for lib_dir in lib_dirs {
+        lib_paths.extend(library::open(lib_dir.as_ref(), &options.filter)?.images()?);
+    }
+    let thumbnail_size = thumbnails.then_some(options.tile_size);
+    index::write_index(&lib_paths, index_path, thumbnail_size, &analysis_options)
 }
 
 /// Save the given image as a JPEG
@@ -53,37 +543,386 @@ pub fn save(image: &RgbaImage, p: &str) -> ImageResult<()> {
     image.save_with_format(p, Jpeg)
 }
 
+// Planning
+
+/// The tiles chosen to build a mosaic of a target, and where to draw them.
+#[derive(Clone)]
+struct Plan {
+    /// The target image.
+    target: RgbaImage,
+    /// How many output pixels are drawn for each target pixel, which needn't
+    /// be whole.
+    ratio: f32,
+    /// Size of the output image.
+    size: Dimensions,
+    /// The tiles and the output regions to draw them in.
+    tiles: Vec<(PathBuf, PixelRegion)>,
+    /// The cost of each tile in its cell, in the same order.
+    costs: Vec<i64>,
+    /// Number of library images read.
+    library_size: usize,
+    /// Number of library images left out by the filter.
+    filtered_out: usize,
+    /// Library images left out for being too large to decode safely, and
+    /// why.
+    refused: Vec<(PathBuf, String)>,
+    /// Number of library images smaller than the tiles, flagged by the
+    /// small tile policy.
+    undersized: usize,
+    /// What was done with the library images smaller than the tiles.
+    small_tiles: SmallTiles,
+}
+
+impl Plan {
+    /// The part of the target covered by the given output region.
+    fn target_cell(&self, region: &PixelRegion) -> SubImage<&RgbaImage> {
+        let cell = region.unscale_f32(self.ratio);
+        let cell = cell
+            .clip_to(self.target.dimensions())
+            .unwrap_or(Rectangle::new(0, 0, 0, 0));
+        imageops::crop_imm(&self.target, cell.x, cell.y, cell.width, cell.height)
+    }
+}
+
+/// Choose the library image to draw in each cell of the target.
+fn plan_mosaic<P: AsRef<Path>>(
+    target_path: &Path,
+    lib_dirs: &[P],
+    options: &MosaicOptions,
+    cancel: &CancelToken,
+) -> IoResult<Plan> {
+    let target = load_image(target_path).map_err(IoError::other)?;
+    let library = load_library(
+        lib_dirs,
+        &options.filter,
+        &strategy_options(options).analysis,
+        cancel,
+    )?;
+    let plan = plan_with_library(target, &library.images, options, cancel)?;
+    Ok(Plan {
+        refused: library.refused,
+        ..plan
+    })
+}
+
+/// Choose the image to draw in each cell of the target from an already
+/// analysed library.
+fn plan_with_library(
+    target: RgbaImage,
+    library: &[(PathBuf, ImageInfo)],
+    options: &MosaicOptions,
+    cancel: &CancelToken,
+) -> IoResult<Plan> {
+    let (target, options) = to_working_resolution(target, options);
+    let options: &MosaicOptions = &options;
+    refuse_random(options)?;
+    let cell_size = cell_size(options, target.dimensions());
+
+    let strategy_options = StrategyOptions {
+        cancel: cancel.clone(),
+        ..strategy_options(options)
+    };
+    let mut lib_info: HashMap<&PathBuf, ImageInfo> = library
+        .iter()
+        .filter(|(path, _)| options.filter.accepts(path))
+        .map(|(path, info)| (path, info.clone()))
+        .collect();
+    if lib_info.is_empty() {
+        return Err(EmptyLibrary::error(!library.is_empty(), false));
+    }
+    let ratio = output_ratio(options.tile_size, cell_size);
+    let drawn = PixelRegion::new(0, 0, cell_size, cell_size)
+        .scale_f32(ratio)
+        .width;
+    let undersized = options.small_tiles.apply(&mut lib_info, drawn);
+    if lib_info.is_empty() {
+        return Err(EmptyLibrary::error(true, true));
+    }
+    apply_preferences(&options.preferred, &mut lib_info)?;
+    apply_folder_weights(&options.folder_weights, &mut lib_info)?;
+    apply_constraints(&options.pins, &options.exclusions, &mut lib_info)?;
+
+    let protected = protected_areas(&target, options)?;
+    let Some(mut strategy) = build_strategy(&options.strategy, &lib_info, &strategy_options) else {
+        let message = format!("Unknown strategy: {}", options.strategy);
+        return Err(IoError::new(ErrorKind::InvalidInput, message));
+    };
+    if !protected.is_empty() {
+        strategy = Box::new(ProtectedTileStrategy::new(
+            strategy,
+            &lib_info,
+            &strategy_options,
+            &protected,
+        ));
+    }
+    let quantised = options
+        .quantise
+        .map(|colors| quantise::quantise(&target, colors));
+    let matched = quantised.as_ref().unwrap_or(&target);
+    let mut tiles = strategy.choose(matched, &(cell_size, cell_size));
+    cancel.check()?;
+    // Cells every tile is excluded from are left out.
+    tiles.retain(|(path, region)| {
+        let cell = region.clip_to(target.dimensions());
+        !cell.is_some_and(|r| lib_info[path].is_excluded_from(&r))
+    });
+    if let Some(mask) = &options.mask {
+        let coverage = mask::load_coverage(mask, target.dimensions())?;
+        tiles.retain(|(_, region)| mask::covers(&coverage, region, mask.threshold));
+    }
+    let analyser = CellAnalyser::new(matched, (cell_size, cell_size), &strategy_options.analysis);
+    let costs = tiles
+        .iter()
+        .map(|(path, region)| match region.clip_to(target.dimensions()) {
+            Some(r) => strategy_options.match_cost(&lib_info[path], &analyser.analyse(&r)),
+            None => 0,
+        })
+        .collect();
+
+    let tiles = tiles
+        .iter()
+        .map(|t| t.scale(ratio))
+        .map(|(p, region)| (p.to_owned(), region))
+        .collect();
+    let (width, height) = target.dimensions();
+    let output = PixelRegion::new(0, 0, width, height).scale_f32(ratio);
+    let size = (output.width, output.height);
+
+    Ok(Plan {
+        target,
+        ratio,
+        size,
+        tiles,
+        costs,
+        library_size: library.len(),
+        filtered_out: library
+            .iter()
+            .filter(|(path, _)| !options.filter.accepts(path))
+            .count(),
+        refused: Vec::new(),
+        undersized,
+        small_tiles: options.small_tiles,
+    })
+}
+
+/// How many output pixels are drawn for each target pixel for cells of the
+/// given size to be drawn at the tile size, never shrinking the target.
+fn output_ratio(tile_size: u32, cell_size: u32) -> f32 {
+    (tile_size as f32 / cell_size.max(1) as f32).max(1.0)
+}
+
+/// The size of the cells to split a target of the given size into.
+///
+/// With a budget, the square cells are sized so that about that many cover
+/// the target whatever its aspect ratio, so wide panoramas get many columns
+/// and few rows rather than a cell size that suits neither.
+fn cell_size(options: &MosaicOptions, (width, height): Dimensions) -> u32 {
+    let Some(budget) = options.cell_budget else {
+        return options.cell_size.max(1);
+    };
+    let area = f64::from(width) * f64::from(height);
+    let size = (area / f64::from(budget.max(1))).sqrt().round() as u32;
+    size.clamp(1, width.max(height).max(1))
+}
+
+/// The settings for choosing tiles from the mosaic settings.
+fn strategy_options(options: &MosaicOptions) -> StrategyOptions {
+    StrategyOptions {
+        analysis: AnalysisOptions {
+            channel_weights: options.channel_weights,
+            linear_light: options.linear_light,
+            filter: options.analysis_filter,
+            matched_layout: options.matched_layout,
+            ..AnalysisOptions::new(Some(options.analysis_size))
+        },
+        penalty: options.penalty,
+        order: options.order,
+        seed: options.seed,
+        tie_break: options.tie_break,
+        refinement: options.refinement,
+        candidates: options.candidates,
+        pyramid: options.pyramid,
+        cost: options.cost,
+        reverse_pass: options.reverse_pass,
+        temperature: options.temperature,
+        cancel: CancelToken::default(),
+    }
+}
+
+/// Draw the planned tiles into the mosaic image.
+fn render(plan: Plan, options: &MosaicOptions, cancel: &CancelToken) -> IoResult<RgbaImage> {
+    render_with(&plan, options, cancel, None)
+}
+
+/// Draw the planned tiles into the mosaic image, describing the mosaic and
+/// how long it took to plan and draw.
+fn finish(
+    plan: Plan,
+    planning: Duration,
+    options: &MosaicOptions,
+    cancel: &CancelToken,
+    checkpoint: Option<&Checkpoint>,
+) -> IoResult<MosaicResult> {
+    let started = Instant::now();
+    let image = render_with(&plan, options, cancel, checkpoint)?;
+    let rendering = started.elapsed();
+    let cell_size = cell_size(options, plan.target.dimensions());
+    Ok(MosaicResult::new(
+        image,
+        plan,
+        cell_size,
+        (planning, rendering),
+    ))
+}
+
+/// Draw the planned tiles into the mosaic image, a band at a time with the
+/// bands kept in the checkpoint if given.
+fn render_with(
+    plan: &Plan,
+    options: &MosaicOptions,
+    cancel: &CancelToken,
+    checkpoint: Option<&Checkpoint>,
+) -> IoResult<RgbaImage> {
+    refuse_random(options)?;
+    let luminance = |region| LuminanceStats::of(&plan.target_cell(region).to_image());
+    let drawn = |region: &PixelRegion| region.inset(options.tile_inset);
+    let mipmaps = options.mipmaps.as_ref();
+    let mut image = if let Some(frame) = &options.frame {
+        let framer = Framer::new(frame)?;
+        let tiles = plan
+            .tiles
+            .iter()
+            .map(|(path, region)| FramedTile {
+                tile: path,
+                region: drawn(region),
+                target: options.match_luminance.then(|| luminance(region)),
+                framer: &framer,
+                mipmaps,
+            })
+            .collect();
+        draw_tiles(plan.size, tiles, options, cancel, checkpoint)?
+    } else if options.match_luminance {
+        let tiles = plan
+            .tiles
+            .iter()
+            .map(|(path, region)| LuminanceMatchedTile {
+                tile: path,
+                region: drawn(region),
+                target: luminance(region),
+                mipmaps,
+            })
+            .collect();
+        draw_tiles(plan.size, tiles, options, cancel, checkpoint)?
+    } else if options.jitter.is_some()
+        || options.tile_inset != 0
+        || options.feather > 0
+        || mipmaps.is_some()
+        || checkpoint.is_some()
+    {
+        let tiles = plan
+            .tiles
+            .iter()
+            .map(|(path, region)| ResizedTile {
+                tile: path,
+                region: drawn(region),
+                mipmaps,
+            })
+            .collect();
+        draw_tiles(plan.size, tiles, options, cancel, checkpoint)?
+    } else {
+        build_tiled_image(plan.size, plan.tiles.clone(), resize(options), cancel)?
+    };
+    cancel.check()?;
+    if let Some(blend) = &options.blend {
+        blend::composite(&mut image, &plan.target, blend);
+    }
+    if let Some(background) = options.mask.as_ref().and_then(|m| m.background) {
+        mask::fill_background(&mut image, background);
+    }
+    if let Some(background) = options.background {
+        let cell_size = cell_size(options, plan.target.dimensions());
+        background::fill(&mut image, background, &plan.target, cell_size);
+    }
+    postprocess::post_process(&mut image, &options.post_process);
+    if let Some(watermark) = &options.watermark {
+        watermark::stamp(&mut image, watermark)?;
+    }
+    Ok(image)
+}
+
 // Path handling
 
-fn find_paths(path: &str) -> IoResult<Vec<PathBuf>> {
+fn find_paths(path: &Path) -> IoResult<Vec<PathBuf>> {
     let path_reader = read_dir(path)?;
-    let paths = path_reader.filter_map(Result::ok).map(|f| f.path());
-    Ok(paths.collect())
+    let mut paths: Vec<PathBuf> = path_reader
+        .filter_map(Result::ok)
+        .map(|f| f.path())
+        .collect();
+    // Directories are read in whatever order the file system keeps them.
+    paths.sort();
+    Ok(paths)
 }
 
 // Image handling
-fn analyse_available_images<'a>(
-    lib_paths: &'a [PathBuf],
-    options: &'a AnalysisOptions,
-) -> HashMap<&'a PathBuf, ImageInfo> {
-    lib_paths
-        .iter()
-        .filter_map(|p| load_image(p).map(|i| (p, analyse(&i, options))).ok())
-        .collect()
+
+/// Find and analyse the images in the given libraries whose names pass the
+/// filter, reusing the stored analysis of any prepared libraries.
+fn load_library<P: AsRef<Path>>(
+    lib_dirs: &[P],
+    filter: &LibraryFilter,
+    options: &AnalysisOptions,
+    cancel: &CancelToken,
+) -> IoResult<LoadedLibrary> {
+    let mut library = LoadedLibrary::default();
+    for lib_dir in lib_dirs {
+        library.extend(library::open(lib_dir.as_ref(), filter)?.load(options, cancel)?);
+    }
+    Ok(library)
 }
 
-/// Load an image from a file
+/// Load an image from a file as sRGB, converting from any embedded color
+/// profile and tone mapping any high dynamic range image, and failing with
+/// an error naming the file if it is too large to decode safely or in a
+/// format, such as HEIC, this build can't decode. Camera RAW files are
+/// drawn from their embedded previews with the `raw` feature, and HEIF
+/// images are decoded with libheif with the `heif` feature.
 fn load_image(path: &Path) -> ImageResult<RgbaImage> {
-    image::open(path).map(DynamicImage::into_rgba8)
+    #[cfg(feature = "raw")]
+    if raw::is_raw(path) {
+        return raw::load_preview(path).and_then(|img| refuse_empty(path, img));
+    }
+    #[cfg(feature = "heif")]
+    if let Some(img) = heif::load(path) {
+        return img.and_then(|img| refuse_empty(path, img));
+    }
+    color::decode(image::io::Reader::open(path)?)
+        .map_err(|e| match heif::unsupported(path) {
+            Some(error) => ImageError::IoError(error),
+            None => color::name_refused(path, e),
+        })
+        .and_then(|img| refuse_empty(path, img))
+}
+
+/// The image, or an error naming the file if it has no pixels to sample or
+/// draw, so empty library images are skipped like unreadable ones.
+fn refuse_empty(path: &Path, img: RgbaImage) -> ImageResult<RgbaImage> {
+    if img.width() == 0 || img.height() == 0 {
+        let message = format!("{} is empty", path.display());
+        return Err(ImageError::IoError(IoError::new(
+            ErrorKind::InvalidData,
+            message,
+        )));
+    }
+    Ok(img)
 }
 
 // Thumbnails
 
 /// Build a tile for the given image
-fn build_tile(img: &RgbaImage, size: Dimensions) -> RgbaImage {
+fn build_tile(img: &RgbaImage, size: Dimensions, resize: Resize) -> RgbaImage {
     let (width, height) = size;
     let tile = extract_tile(img).to_image();
-    at_size(tile, width, height)
+    at_size(tile, width, height, resize)
 }
 
 /// Extract a square tile from the given image.
@@ -99,37 +938,593 @@ where
 // Image constructions
 
 /// Resize an image if necessary
-fn at_size(img: RgbaImage, w: u32, h: u32) -> RgbaImage {
-    let size = img.dimensions();
-    if size == (w, h) {
+fn at_size(img: RgbaImage, w: u32, h: u32, resize: Resize) -> RgbaImage {
+    if img.dimensions() == (w, h) {
         img
     } else {
-        imageops::thumbnail(&img, w, h)
+        resize.apply(&img, w, h)
+    }
+}
+
+/// How tiles are resized to be drawn.
+fn resize(options: &MosaicOptions) -> Resize {
+    Resize::new(options.resize_filter, options.linear_light)
+}
+
+/// Build an image of the tiles, on the GPU if there is one to use
+fn build_tiled_image(
+    size: Dimensions,
+    tiles: Vec<(PathBuf, PixelRegion)>,
+    resize: Resize,
+    cancel: &CancelToken,
+) -> IoResult<RgbaImage> {
+    #[cfg(feature = "gpu")]
+    if let Some(image) = gpu::build_image(size, &tiles, resize, cancel)? {
+        return Ok(image);
+    }
+    build_image(size, tiles, resize, cancel)
+}
+
+/// Build an image of the drawables, feathering their edges and turning each
+/// a little if asked
+fn draw_tiles<T: Drawable>(
+    size: Dimensions,
+    tiles: Vec<T>,
+    options: &MosaicOptions,
+    cancel: &CancelToken,
+    checkpoint: Option<&Checkpoint>,
+) -> IoResult<RgbaImage> {
+    if options.feather > 0 {
+        let feathered = feather::feather(tiles, options.feather);
+        return draw_turned(size, feathered, options, cancel, checkpoint);
+    }
+    draw_turned(size, tiles, options, cancel, checkpoint)
+}
+
+/// Build an image of the drawables, turning each a little if asked
+fn draw_turned<T: Drawable>(
+    size: Dimensions,
+    tiles: Vec<T>,
+    options: &MosaicOptions,
+    cancel: &CancelToken,
+    checkpoint: Option<&Checkpoint>,
+) -> IoResult<RgbaImage> {
+    #[cfg(feature = "rand")]
+    if let Some(jitter) = &options.jitter {
+        let turned = jitter::turn(tiles, jitter, options.seed);
+        return draw_checkpointed(size, turned, resize(options), cancel, checkpoint);
+    }
+    draw_checkpointed(size, tiles, resize(options), cancel, checkpoint)
+}
+
+/// The areas of the target to protect: those given, and those around any
+/// patches of skin tone found in it if asked to look for them.
+#[cfg_attr(not(feature = "skin-tones"), allow(unused_variables))]
+fn protected_areas(target: &RgbaImage, options: &MosaicOptions) -> IoResult<Vec<ProtectedArea>> {
+    if !options.protect_skin_tones {
+        return Ok(options.protected.clone());
     }
+    #[cfg(feature = "skin-tones")]
+    return Ok([options.protected.clone(), skin::find_skin_tones(target)].concat());
+    // Without the `skin-tones` feature there is nothing to find skin with.
+    #[cfg(not(feature = "skin-tones"))]
+    Err(IoError::new(
+        ErrorKind::Unsupported,
+        "Protecting skin tones needs the skin-tones feature",
+    ))
+}
+
+/// Refuse the build if it makes random choices without the `rand` feature,
+/// which has nothing to make them with.
+fn refuse_random(options: &MosaicOptions) -> IoResult<()> {
+    let random =
+        options.temperature > 0.0 || options.refinement.iterations > 0 || options.jitter.is_some();
+    if cfg!(feature = "rand") || !random {
+        return Ok(());
+    }
+    Err(IoError::new(
+        ErrorKind::Unsupported,
+        "Temperature, refinement, and jitter need the rand feature",
+    ))
+}
+
+/// Build an image of the drawables, keeping each band of rows in the
+/// checkpoint once drawn if given
+fn draw_checkpointed<T: Drawable>(
+    size: Dimensions,
+    tiles: Vec<T>,
+    resize: Resize,
+    cancel: &CancelToken,
+    checkpoint: Option<&Checkpoint>,
+) -> IoResult<RgbaImage> {
+    let Some(checkpoint) = checkpoint else {
+        return build_image(size, tiles, resize, cancel);
+    };
+    let (width, height) = size;
+    let count = height.div_ceil(CHECKPOINT_ROWS) as usize;
+    let rows = bands((0, height), &tiles, count);
+    checkpoint.draw(size, rows, cancel, |rows| {
+        build_rows(width, rows, &tiles, resize, cancel)
+    })
+}
+
+/// Build an image of the given size from the drawables, drawn in turn so
+/// later ones cover earlier ones, leaving transparent any part none covers.
+///
+/// Horizontal bands of the image are drawn on separate threads. Drawing stops
+/// between tiles once cancelled, and fails with the first error any drawable
+/// gives.
+pub fn build_image<T>(
+    size: Dimensions,
+    tiles: Vec<T>,
+    resize: Resize,
+    cancel: &CancelToken,
+) -> IoResult<RgbaImage>
+where
+    T: Drawable,
+{
+    let (width, height) = size;
+    build_rows(width, (0, height), &tiles, resize, cancel)
 }
 
-/// Build an image
-fn build_image<T>((width, height): Dimensions, tiles: Vec<T>) -> RgbaImage
+/// Build the given rows of an image as `build_image` does
+fn build_rows<T>(
+    width: u32,
+    (top, bottom): (u32, u32),
+    tiles: &[T],
+    resize: Resize,
+    cancel: &CancelToken,
+) -> IoResult<RgbaImage>
 where
     T: Drawable,
 {
-    let mut output = RgbaImage::new(width, height);
-    for t in tiles {
-        t.draw_onto(&mut output);
+    let mut output = RgbaImage::new(width, bottom - top);
+    let workers = thread::available_parallelism().map_or(1, |n| n.get());
+    thread::scope(|scope| {
+        let mut rest: &mut [u8] = &mut output;
+        let mut threads = Vec::new();
+        for (band_top, band_bottom) in bands((top, bottom), tiles, workers) {
+            let len = (band_bottom - band_top) as usize * width as usize * 4;
+            let (band, remaining) = std::mem::take(&mut rest).split_at_mut(len);
+            rest = remaining;
+            threads.push(scope.spawn(move || -> IoResult<()> {
+                let mut band =
+                    ImageBuffer::<Rgba<u8>, _>::from_raw(width, band_bottom - band_top, band)
+                        .unwrap();
+                for t in tiles.iter().take_while(|_| !cancel.is_cancelled()) {
+                    let region = t.region();
+                    if region.y < band_bottom.into() && region.bottom() > band_top.into() {
+                        let y = region.y - i64::from(band_top);
+                        imageops::overlay(&mut band, &t.render(resize)?, region.x, y);
+                    }
+                }
+                Ok(())
+            }));
+        }
+        threads
+            .into_iter()
+            .try_for_each(|thread| thread.join().expect("Drawing thread panicked"))
+    })?;
+    Ok(output)
+}
+
+/// Split the given rows of an image into about the given number of bands,
+/// each starting where a row of tiles starts so that grid cells are drawn
+/// once.
+fn bands<T: Drawable>((top, bottom): (u32, u32), tiles: &[T], count: usize) -> Vec<(u32, u32)> {
+    let mut tops: Vec<u32> = tiles
+        .iter()
+        .map(|t| t.region().y.clamp(top.into(), bottom.into()) as u32)
+        .chain([bottom])
+        .collect();
+    tops.sort_unstable();
+    tops.dedup();
+
+    let band_height = (bottom - top).div_ceil(count.max(1) as u32);
+    let mut bands = Vec::new();
+    let mut top = top;
+    for y in tops {
+        if y > top && (y - top >= band_height || y == bottom) {
+            bands.push((top, y));
+            top = y;
+        }
     }
-    output
+    bands
 }
 
-trait Drawable {
-    /// Draw this drawable onto the given target image.
-    fn draw_onto(&self, target: &mut RgbaImage);
+/// Something drawn into a mosaic by `build_image`, such as a library image
+/// resized to fit its cell, or one with an effect applied.
+pub trait Drawable: Sync {
+    /// Where (in output pixels) to draw this drawable.
+    fn region(&self) -> &PixelRegion;
+
+    /// Render this drawable at the size of its region, resizing as asked, or
+    /// fail if it can't be, such as when its image can't be read.
+    fn render(&self, resize: Resize) -> IoResult<RgbaImage>;
 }
 
-impl Drawable for TileLocation<'_, PathBuf, PixelRegion> {
-    fn draw_onto(&self, target: &mut RgbaImage) {
+impl Drawable for (PathBuf, PixelRegion) {
+    fn region(&self) -> &PixelRegion {
+        &self.1
+    }
+
+    fn render(&self, resize: Resize) -> IoResult<RgbaImage> {
         let (tile, region) = self;
-        let img = load_image(tile).unwrap();
-        let thumb = at_size(img, region.width, region.height);
-        imageops::overlay(target, &thumb, region.x, region.y);
+        let img = load_image(tile).map_err(IoError::other)?;
+        Ok(at_size(img, region.width, region.height, resize))
+    }
+}
+
+/// Load a library image to draw in the region, from its closest mipmap if
+/// they are kept.
+fn load_tile(
+    tile: &Path,
+    region: &PixelRegion,
+    mipmaps: Option<&Mipmaps>,
+    resize: Resize,
+) -> IoResult<RgbaImage> {
+    let size = (region.width, region.height);
+    match mipmaps {
+        Some(mipmaps) => mipmaps.load(tile, size, resize),
+        None => load_image(tile),
+    }
+    .map_err(IoError::other)
+}
+
+/// A tile drawn resized to fit its region, from its mipmaps if kept.
+struct ResizedTile<'a> {
+    tile: &'a PathBuf,
+    region: PixelRegion,
+    mipmaps: Option<&'a Mipmaps>,
+}
+
+impl Drawable for ResizedTile<'_> {
+    fn region(&self) -> &PixelRegion {
+        &self.region
+    }
+
+    fn render(&self, resize: Resize) -> IoResult<RgbaImage> {
+        let img = load_tile(self.tile, &self.region, self.mipmaps, resize)?;
+        Ok(at_size(img, self.region.width, self.region.height, resize))
+    }
+}
+
+/// A tile drawn with its brightness and contrast matched to its target cell.
+struct LuminanceMatchedTile<'a> {
+    tile: &'a PathBuf,
+    region: PixelRegion,
+    target: LuminanceStats,
+    mipmaps: Option<&'a Mipmaps>,
+}
+
+impl Drawable for LuminanceMatchedTile<'_> {
+    fn region(&self) -> &PixelRegion {
+        &self.region
+    }
+
+    fn render(&self, resize: Resize) -> IoResult<RgbaImage> {
+        let img = load_tile(self.tile, &self.region, self.mipmaps, resize)?;
+        let mut thumb = at_size(img, self.region.width, self.region.height, resize);
+        match_luminance(&mut thumb, &self.target);
+        Ok(thumb)
+    }
+}
+
+/// A tile drawn as a photo framed in a card, with its brightness and
+/// contrast matched to its target cell if asked.
+struct FramedTile<'a> {
+    tile: &'a PathBuf,
+    region: PixelRegion,
+    target: Option<LuminanceStats>,
+    framer: &'a Framer<'a>,
+    mipmaps: Option<&'a Mipmaps>,
+}
+
+impl Drawable for FramedTile<'_> {
+    fn region(&self) -> &PixelRegion {
+        &self.region
+    }
+
+    fn render(&self, resize: Resize) -> IoResult<RgbaImage> {
+        let img = load_tile(self.tile, &self.region, self.mipmaps, resize)?;
+        let caption = self.tile.file_stem().unwrap_or_default().to_string_lossy();
+        let size = (self.region.width, self.region.height);
+        Ok(self.framer.draw(img, &caption, size, resize, |photo| {
+            if let Some(target) = &self.target {
+                match_luminance(photo, target);
+            }
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_support::{fixture, scratch_dir};
+
+    /// A solid block of color.
+    struct Block(PixelRegion, u8);
+
+    impl Drawable for Block {
+        fn region(&self) -> &PixelRegion {
+            &self.0
+        }
+
+        fn render(&self, _resize: Resize) -> IoResult<RgbaImage> {
+            Ok(RgbaImage::from_pixel(
+                self.0.width,
+                self.0.height,
+                Rgba([self.1, 0, 0, 255]),
+            ))
+        }
+    }
+
+    #[test]
+    fn test_sizes_cells_from_budget() {
+        let budget = |cell_budget| MosaicOptions {
+            cell_budget,
+            ..MosaicOptions::default()
+        };
+        let cells =
+            |(width, height): Dimensions, size: u32| width.div_ceil(size) * height.div_ceil(size);
+
+        assert_eq!(cell_size(&budget(None), (4000, 3000)), 20);
+        assert_eq!(cell_size(&budget(Some(1200)), (4000, 3000)), 100);
+        // A panorama gets a long strip of cells, about as many as budgeted.
+        let panorama = (20_000, 500);
+        let size = cell_size(&budget(Some(2000)), panorama);
+        assert_eq!(size, 71);
+        assert_eq!(
+            (panorama.0.div_ceil(size), panorama.1.div_ceil(size)),
+            (282, 8)
+        );
+        assert!(cells(panorama, size).abs_diff(2000) < 300);
+        assert_eq!(cell_size(&budget(Some(0)), (10, 10)), 10);
+        assert_eq!(cell_size(&budget(Some(1000)), (10, 10)), 1);
+    }
+
+    #[test]
+    fn test_sizes_planned_cells_in_target_pixels() {
+        let target = fixture("target.png");
+        let planned = |options: MosaicOptions| planned_cell_size(&target, &options).unwrap();
+
+        assert_eq!(planned(MosaicOptions::default()), 20);
+        // 24 cells of 10 pixels cover the 60 by 40 target.
+        let budgeted = MosaicOptions {
+            cell_budget: Some(24),
+            ..MosaicOptions::default()
+        };
+        assert_eq!(planned(budgeted), 10);
+        // Cells of 2 pixels at a tenth of the target's size.
+        let working = MosaicOptions {
+            cell_size: 2,
+            working_cells: Some(6),
+            ..MosaicOptions::default()
+        };
+        assert_eq!(planned(working), 20);
+    }
+
+    #[test]
+    fn test_splits_rows_into_bands_at_tile_rows() {
+        let rows: Vec<Block> = (0..10)
+            .map(|i| Block(PixelRegion::new(0, i * 10, 10, 10), 0))
+            .collect();
+
+        assert_eq!(
+            bands((0, 100), &rows, 3),
+            vec![(0, 40), (40, 80), (80, 100)]
+        );
+        assert_eq!(bands((0, 100), &rows, 1), vec![(0, 100)]);
+        assert_eq!(
+            bands((0, 100), &rows, 50),
+            (0..10).map(|i| (i * 10, i * 10 + 10)).collect::<Vec<_>>()
+        );
+        assert_eq!(bands((0, 0), &rows[..0], 4), vec![]);
+        assert_eq!(bands((30, 70), &rows, 2), vec![(30, 50), (50, 70)]);
+    }
+
+    #[test]
+    fn test_draws_tiles_across_bands() {
+        // Staggered tiles cross the bands started by the other column.
+        let tiles: Vec<Block> = (0..8)
+            .map(|i| {
+                let (x, y) = (i % 2 * 10, i / 2 * 10 + i % 2 * 5);
+                Block(PixelRegion::new(x, y - 5, 10, 10), i as u8 + 1)
+            })
+            .collect();
+
+        let output = build_image(
+            (20, 40),
+            tiles,
+            resize(&MosaicOptions::default()),
+            &CancelToken::new(),
+        )
+        .unwrap();
+
+        for (x, y, i) in [
+            (0, 0, 1),
+            (0, 34, 7),
+            (10, 0, 2),
+            (10, 9, 2),
+            (10, 10, 4),
+            (19, 39, 8),
+        ] {
+            assert_eq!(output.get_pixel(x, y), &Rgba([i, 0, 0, 255]), "{x},{y}");
+        }
+    }
+
+    #[test]
+    fn test_stops_cancelled_builds() {
+        let (target, library) = (fixture("target.png"), [fixture("library")]);
+        let analysis = AnalysisOptions::new(Some(2));
+        let cancel = CancelToken::new();
+        cancel.cancel();
+        let interrupted = |result: IoResult<MosaicResult>| result.unwrap_err().kind();
+
+        let error =
+            load_library(&library, &LibraryFilter::default(), &analysis, &cancel).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Interrupted);
+        for strategy in strategy_names() {
+            let options = MosaicOptions {
+                strategy: strategy.to_string(),
+                ..MosaicOptions::default()
+            };
+            let result = mosaic_cancellable(&target, &library, &options, &cancel);
+            assert_eq!(interrupted(result), ErrorKind::Interrupted, "{strategy}");
+        }
+        let tiles = vec![Block(PixelRegion::new(0, 0, 10, 10), 1)];
+        let output = build_image((10, 10), tiles, resize(&MosaicOptions::default()), &cancel);
+        assert_eq!(output.unwrap().get_pixel(0, 0).0[3], 0);
+    }
+
+    #[test]
+    fn test_fails_to_draw_missing_tiles() {
+        let tiles = vec![(PathBuf::from("missing.png"), PixelRegion::new(0, 0, 10, 10))];
+
+        let output = build_image(
+            (10, 10),
+            tiles,
+            resize(&MosaicOptions::default()),
+            &CancelToken::new(),
+        );
+
+        assert!(output.is_err());
+    }
+
+    #[test]
+    fn test_builds_mosaics_of_tiny_targets() {
+        let library = [fixture("library")];
+        let dir = scratch_dir("tiny");
+
+        for (width, height) in [(1, 1), (5, 3)] {
+            let target = dir.join(format!("{width}x{height}.png"));
+            RgbaImage::from_pixel(width, height, Rgba([200, 100, 50, 255]))
+                .save(&target)
+                .unwrap();
+            for strategy in strategy_names() {
+                for cell_size in [0, 2, 20] {
+                    let options = MosaicOptions {
+                        strategy: strategy.to_string(),
+                        cell_size,
+                        ..MosaicOptions::default()
+                    };
+                    let result = mosaic(&target, &library, &options).unwrap();
+                    let ratio = output_ratio(options.tile_size, cell_size);
+                    let case = format!("{width}x{height} {strategy} {cell_size}");
+                    assert_eq!(
+                        result.image.dimensions(),
+                        {
+                            let output = PixelRegion::new(0, 0, width, height).scale_f32(ratio);
+                            (output.width, output.height)
+                        },
+                        "{case}"
+                    );
+                    assert!(!result.placements.is_empty(), "{case}");
+                }
+            }
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_draws_cells_at_fractional_ratios_without_gaps() {
+        let library = [fixture("library")];
+        let dir = scratch_dir("fraction");
+        let target = dir.join("target.png");
+        RgbaImage::from_pixel(60, 30, Rgba([50, 100, 200, 255]))
+            .save(&target)
+            .unwrap();
+        let options = MosaicOptions {
+            cell_size: 20,
+            tile_size: 150,
+            ..MosaicOptions::default()
+        };
+
+        let result = mosaic(&target, &library, &options).unwrap();
+
+        assert_eq!(output_ratio(150, 20), 7.5);
+        assert_eq!(result.image.dimensions(), (450, 225));
+        let widths: Vec<u32> = result
+            .placements
+            .iter()
+            .filter(|(_, region)| region.y == 0)
+            .map(|(_, region)| region.width)
+            .collect();
+        assert_eq!(widths, vec![150, 150, 150]);
+        let area: u64 = result
+            .placements
+            .iter()
+            .map(|(_, region)| u64::from(region.width) * u64::from(region.height))
+            .sum();
+        assert_eq!(area, 450 * 300);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_skips_empty_images() {
+        let dir = scratch_dir("empty");
+        let empty = dir.join("empty.ppm");
+        std::fs::write(&empty, b"P6\n0 0\n255\n").unwrap();
+        RgbaImage::from_pixel(1, 1, Rgba([0, 0, 0, 255]))
+            .save(dir.join("pixel.png"))
+            .unwrap();
+
+        let error = load_image(&empty).unwrap_err().to_string();
+        assert!(error.contains("empty.ppm"), "{error}");
+        let analysis = AnalysisOptions::new(Some(2));
+        let library = load_library(
+            &[&dir],
+            &LibraryFilter::default(),
+            &analysis,
+            &CancelToken::new(),
+        )
+        .unwrap();
+        assert_eq!(library.images.len(), 1);
+        let result = mosaic(&empty, &[&dir], &MosaicOptions::default());
+        assert_eq!(Failure::of(&result.unwrap_err()), Failure::Decode);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_skips_images_too_large_to_decode() {
+        // Only the header of a 30000 by 30000 bitmap, which would take up
+        // gigabytes once decoded.
+        let mut bomb = Vec::new();
+        bomb.extend(b"BM");
+        for value in [54u32, 0, 54, 40, 30_000, 30_000] {
+            bomb.extend(value.to_le_bytes());
+        }
+        for value in [1u16, 24] {
+            bomb.extend(value.to_le_bytes());
+        }
+        bomb.extend([0; 24]);
+        let dir = scratch_dir("bomb");
+        let path = dir.join("bomb.bmp");
+        std::fs::write(&path, bomb).unwrap();
+        RgbaImage::from_pixel(1, 1, Rgba([0, 0, 0, 255]))
+            .save(dir.join("pixel.png"))
+            .unwrap();
+
+        let Err(ImageError::IoError(error)) = load_image(&path) else {
+            panic!("Decoded the bomb")
+        };
+        assert_eq!(error.kind(), ErrorKind::OutOfMemory);
+        assert!(error.to_string().contains("bomb.bmp"));
+
+        // The rest of the library is still used, without the bomb.
+        let analysis = AnalysisOptions::new(Some(2));
+        let library = load_library(
+            &[&dir],
+            &LibraryFilter::default(),
+            &analysis,
+            &CancelToken::new(),
+        )
+        .unwrap();
+        let paths: Vec<&PathBuf> = library.images.iter().map(|(p, _)| p).collect();
+        assert_eq!(paths, vec![&dir.join("pixel.png")]);
+        // And the bomb is reported for the caller to warn about.
+        assert_eq!(library.refused.len(), 1);
+        assert_eq!(library.refused[0].0, dir.join("bomb.bmp"));
+        std::fs::remove_dir_all(dir).unwrap();
     }
 }
@@ -0,0 +1,630 @@
+use std::fs::{create_dir_all, read_to_string, rename, write};
+use std::io::{stdin, Error as IoError, ErrorKind, Read, Result as IoResult};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+
+use image::ImageError;
+
+use crate::analysis::{analyse_tile, AnalysisOptions, ImageInfo};
+use crate::cancel::CancelToken;
+use crate::ignore::{Ignore, IGNORE_FILE};
+#[cfg(feature = "index")]
+use crate::index::IndexedLibrary;
+use crate::metadata::LibraryFilter;
+#[cfg(feature = "index")]
+use crate::prepare;
+use crate::{find_paths, load_image};
+
+/// Number of images downloaded at once from a remote library.
+const DOWNLOAD_WORKERS: usize = 8;
+
+/// A source of library images to use as tiles.
+pub trait TileLibrary {
+    /// Paths to the images of the library.
+    fn images(&self) -> IoResult<Vec<PathBuf>>;
+
+    /// The images of the library and their analysis, stopping between
+    /// images once cancelled.
+    fn load(&self, options: &AnalysisOptions, cancel: &CancelToken) -> IoResult<LoadedLibrary> {
+        analyse_sources(self.images()?, options, cancel)
+    }
+
+    /// Whether loading the library reuses analysis stored with it, rather
+    /// than analysing each of its images.
+    #[cfg(feature = "async")]
+    fn stores_analysis(&self) -> bool {
+        false
+    }
+}
+
+/// The analysed images of libraries, along with the images left out for being
+/// too large to decode safely.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LoadedLibrary {
+    /// Each image and its analysis, in the order of the sources.
+    pub images: Vec<(PathBuf, ImageInfo)>,
+    /// Each image left out and why.
+    pub refused: Vec<(PathBuf, String)>,
+}
+
+impl LoadedLibrary {
+    /// Add the images of another library after these.
+    pub(crate) fn extend(&mut self, other: LoadedLibrary) {
+        self.images.extend(other.images);
+        self.refused.extend(other.refused);
+    }
+}
+
+impl From<Vec<(PathBuf, ImageInfo)>> for LoadedLibrary {
+    fn from(images: Vec<(PathBuf, ImageInfo)>) -> Self {
+        LoadedLibrary {
+            images,
+            refused: Vec::new(),
+        }
+    }
+}
+
+/// Library path that reads a list of image paths from standard input.
+const STDIN_LIBRARY: &str = "-";
+
+/// Extension of library index files.
+const INDEX_EXTENSION: &str = "idx";
+
+/// Open the library at the given path, either a directory of images, an
+/// index file (ending `.idx`) of analysed images, a manifest file listing the
+/// URLs or paths of images, `-` for a list of image paths on standard input,
+/// or (with the `s3` feature) an `s3://bucket/prefix` location.
+///
+/// Images whose names don't pass the filter's include and exclude patterns
+/// are left out as the library lists them, so they are never read.
+pub fn open(path: &Path, filter: &LibraryFilter) -> IoResult<Box<dyn TileLibrary>> {
+    let names = filter.name_patterns();
+    if path == Path::new(STDIN_LIBRARY) {
+        return Ok(Box::new(StdinLibrary { names }));
+    }
+
+    #[cfg(feature = "s3")]
+    if let Some(location) = path.to_str().and_then(|p| p.strip_prefix("s3://")) {
+        return Ok(Box::new(crate::s3::S3Library::new(location, names)?));
+    }
+
+    if path.extension().is_some_and(|e| e == INDEX_EXTENSION) {
+        #[cfg(feature = "index")]
+        return Ok(Box::new(IndexedLibrary::new(path)));
+        // Without the `index` feature there is nothing to read indexes with.
+        #[cfg(not(feature = "index"))]
+        return Err(IoError::new(
+            ErrorKind::Unsupported,
+            format!("Reading {} needs the index feature", path.display()),
+        ));
+    }
+
+    if path.is_file() {
+        Ok(Box::new(RemoteLibrary::new(path, names)))
+    } else {
+        Ok(Box::new(DirectoryLibrary {
+            dir: path.to_owned(),
+            names,
+        }))
+    }
+}
+
+/// A directory of images, possibly prepared for repeated builds (with the
+/// `index` feature), leaving out anything its `.tilerignore` file lists.
+pub struct DirectoryLibrary {
+    dir: PathBuf,
+    /// Patterns the names of the images must pass.
+    names: LibraryFilter,
+}
+
+impl TileLibrary for DirectoryLibrary {
+    fn images(&self) -> IoResult<Vec<PathBuf>> {
+        let ignore = Ignore::read(&self.dir)?;
+        let mut images = find_paths(&self.dir)?;
+        images.retain(|path| {
+            let relative = path.strip_prefix(&self.dir).unwrap_or(path);
+            relative != Path::new(IGNORE_FILE)
+                && !ignore.ignores(relative, path.is_dir())
+                && self.names.names(path)
+        });
+        Ok(images)
+    }
+
+    fn load(&self, options: &AnalysisOptions, cancel: &CancelToken) -> IoResult<LoadedLibrary> {
+        #[cfg(feature = "index")]
+        if let Some(prepared) = prepare::read_prepared_library(&self.dir, options, cancel)? {
+            return Ok(prepared.into());
+        }
+        analyse_sources(self.images()?, options, cancel)
+    }
+
+    #[cfg(all(feature = "async", feature = "index"))]
+    fn stores_analysis(&self) -> bool {
+        prepare::is_prepared(&self.dir)
+    }
+}
+
+/// Images listed in a manifest file, one per line, either by URL or by path
+/// relative to the manifest. Images listed by URL are downloaded into a cache
+/// directory beside the manifest.
+///
+/// Blank lines and lines starting with `#` are ignored. Images already in
+/// the cache are not downloaded again, and images that can't be downloaded
+/// or don't exist are skipped.
+pub struct RemoteLibrary {
+    manifest: PathBuf,
+    cache_dir: PathBuf,
+    /// Patterns the names of the images must pass.
+    names: LibraryFilter,
+}
+
+impl RemoteLibrary {
+    pub fn new(manifest: &Path, names: LibraryFilter) -> RemoteLibrary {
+        RemoteLibrary {
+            manifest: manifest.to_owned(),
+            cache_dir: manifest.with_extension("cache"),
+            names,
+        }
+    }
+}
+
+impl TileLibrary for RemoteLibrary {
+    fn images(&self) -> IoResult<Vec<PathBuf>> {
+        let text = read_to_string(&self.manifest)?;
+        let mut entries = parse_manifest(&text);
+        // Entries are left out by name before they are downloaded.
+        entries.retain(|entry| self.names.names(Path::new(entry)));
+        let base = self.manifest.parent().unwrap_or(Path::new(""));
+        if entries.iter().any(|e| is_url(e)) {
+            create_dir_all(&self.cache_dir)?;
+        }
+        Ok(fetch_all(&entries, |entry| {
+            if !is_url(entry) {
+                return local_image(base.join(entry));
+            }
+            let stem = format!("{:016x}", fnv1a(entry.as_bytes()));
+            cached_fetch(&self.cache_dir, &stem, || download(entry))
+        }))
+    }
+}
+
+/// Images listed by path on standard input, one per line, read once however
+/// often the library is loaded.
+///
+/// Blank lines and lines starting with `#` are ignored, as are paths that
+/// don't exist.
+pub struct StdinLibrary {
+    /// Patterns the names of the images must pass.
+    names: LibraryFilter,
+}
+
+impl TileLibrary for StdinLibrary {
+    fn images(&self) -> IoResult<Vec<PathBuf>> {
+        static LIST: OnceLock<String> = OnceLock::new();
+        if LIST.get().is_none() {
+            let mut text = String::new();
+            stdin().read_to_string(&mut text)?;
+            let _ = LIST.set(text);
+        }
+        let text = LIST.get().map_or("", String::as_str);
+        Ok(parse_manifest(text)
+            .into_iter()
+            .map(PathBuf::from)
+            .filter(|path| self.names.names(path))
+            .filter_map(|path| local_image(path).ok())
+            .collect())
+    }
+}
+
+fn is_url(entry: &str) -> bool {
+    entry.starts_with("http://") || entry.starts_with("https://")
+}
+
+/// The path, if there is a file there.
+fn local_image(path: PathBuf) -> IoResult<PathBuf> {
+    if path.is_file() {
+        Ok(path)
+    } else {
+        let message = format!("Not a file: {}", path.display());
+        Err(IoError::new(ErrorKind::NotFound, message))
+    }
+}
+
+/// The URLs or paths listed in a manifest.
+fn parse_manifest(text: &str) -> Vec<&str> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .collect()
+}
+
+/// Fetch the items into local files using a pool of workers, returning the
+/// paths of the files that could be fetched, in the order of the items.
+pub(crate) fn fetch_all<T, F>(items: &[T], fetch: F) -> Vec<PathBuf>
+where
+    T: Sync,
+    F: Fn(&T) -> IoResult<PathBuf> + Sync,
+{
+    let next = AtomicUsize::new(0);
+    let mut fetched: Vec<(usize, PathBuf)> = thread::scope(|scope| {
+        let workers: Vec<_> = (0..DOWNLOAD_WORKERS.min(items.len()))
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut paths = Vec::new();
+                    loop {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(item) = items.get(i) else {
+                            return paths;
+                        };
+                        if let Ok(path) = fetch(item) {
+                            paths.push((i, path));
+                        }
+                    }
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|w| w.join().unwrap_or_default())
+            .collect()
+    });
+
+    // Keep the order of the items, whichever worker finished first.
+    fetched.sort_by_key(|(i, _)| *i);
+    fetched.into_iter().map(|(_, path)| path).collect()
+}
+
+/// The cached image with the given file stem, fetching it if needed.
+pub(crate) fn cached_fetch<F>(cache_dir: &Path, stem: &str, fetch: F) -> IoResult<PathBuf>
+where
+    F: FnOnce() -> IoResult<Vec<u8>>,
+{
+    let existing = find_paths(cache_dir)?
+        .into_iter()
+        .find(|p| p.file_stem().is_some_and(|s| *s == *stem) && !is_partial(p));
+    if let Some(path) = existing {
+        return Ok(path);
+    }
+
+    let bytes = fetch()?;
+
+    // Name the file for its format, which is how images are opened later.
+    let format = image::guess_format(&bytes).map_err(IoError::other)?;
+    let extension = format.extensions_str().first().unwrap_or(&"img");
+    let path = cache_dir.join(format!("{stem}.{extension}"));
+    let partial = cache_dir.join(format!("{stem}.partial"));
+    write(&partial, &bytes)?;
+    rename(&partial, &path)?;
+    Ok(path)
+}
+
+/// Download the body of the URL.
+#[cfg(feature = "remote")]
+fn download(url: &str) -> IoResult<Vec<u8>> {
+    let response = ureq::get(url).call().map_err(IoError::other)?;
+    let mut bytes = Vec::new();
+    response.into_body().into_reader().read_to_end(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// Without the `remote` feature there is no HTTP client to download with.
+#[cfg(not(feature = "remote"))]
+fn download(url: &str) -> IoResult<Vec<u8>> {
+    Err(IoError::new(
+        ErrorKind::Unsupported,
+        format!("Downloading {url} needs the remote feature"),
+    ))
+}
+
+/// Whether the path is a download that didn't finish.
+fn is_partial(path: &Path) -> bool {
+    path.extension().is_some_and(|e| e == "partial")
+}
+
+/// A library image to analyse, by its path.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LibrarySource(PathBuf);
+
+impl From<PathBuf> for LibrarySource {
+    fn from(path: PathBuf) -> Self {
+        LibrarySource(path)
+    }
+}
+
+impl From<&Path> for LibrarySource {
+    fn from(path: &Path) -> Self {
+        LibrarySource(path.to_owned())
+    }
+}
+
+impl From<String> for LibrarySource {
+    fn from(path: String) -> Self {
+        LibrarySource(path.into())
+    }
+}
+
+impl From<&str> for LibrarySource {
+    fn from(path: &str) -> Self {
+        LibrarySource(path.into())
+    }
+}
+
+/// The paths of the images in the given libraries, as each would load them
+/// with the filter's include and exclude patterns.
+pub fn library_images<P: AsRef<Path>>(
+    lib_dirs: &[P],
+    filter: &LibraryFilter,
+) -> IoResult<Vec<PathBuf>> {
+    let mut images = Vec::new();
+    for lib_dir in lib_dirs {
+        images.extend(open(lib_dir.as_ref(), filter)?.images()?);
+    }
+    Ok(images)
+}
+
+/// Analyse the library images as the sources produce them, using a pool of
+/// workers, returning the analysis of the images that could be read in the
+/// order of the sources.
+///
+/// Images that can't be read, including those too large to decode safely,
+/// are left out.
+///
+/// Sources are only taken as workers are ready for them, so only a few
+/// images are decoded at once and the sources can be read lazily, such as
+/// from a manifest or pipe, however many images there are.
+pub fn analyse_library<I>(sources: I, options: &AnalysisOptions) -> Vec<(PathBuf, ImageInfo)>
+where
+    I: IntoIterator,
+    I::IntoIter: Send,
+    I::Item: Into<LibrarySource> + Send,
+{
+    analyse_each(sources, options, &CancelToken::new()).images
+}
+
+/// Analyse the library images as `analyse_library` does, stopping between
+/// images once cancelled.
+pub(crate) fn analyse_sources<I>(
+    sources: I,
+    options: &AnalysisOptions,
+    cancel: &CancelToken,
+) -> IoResult<LoadedLibrary>
+where
+    I: IntoIterator,
+    I::IntoIter: Send,
+    I::Item: Into<LibrarySource> + Send,
+{
+    let analysed = analyse_each(sources, options, cancel);
+    cancel.check()?;
+    Ok(analysed)
+}
+
+/// Analyse the library images on separate workers, returning the analysis
+/// of the images that could be read in the order of the sources. Images too
+/// large to decode safely are left out with the reason why.
+fn analyse_each<I>(sources: I, options: &AnalysisOptions, cancel: &CancelToken) -> LoadedLibrary
+where
+    I: IntoIterator,
+    I::IntoIter: Send,
+    I::Item: Into<LibrarySource> + Send,
+{
+    let sources = Mutex::new(sources.into_iter().enumerate());
+    let workers = thread::available_parallelism().map_or(1, |n| n.get());
+    let mut analysed: Vec<(usize, Analysed)> = thread::scope(|scope| {
+        let workers: Vec<_> = (0..workers)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut analysed = Vec::new();
+                    while !cancel.is_cancelled() {
+                        let next = sources.lock().ok().and_then(|mut s| s.next());
+                        let Some((i, source)) = next else {
+                            return analysed;
+                        };
+                        let LibrarySource(path) = source.into();
+                        match load_image(&path) {
+                            Ok(img) => {
+                                let info = analyse_tile(&img, options);
+                                analysed.push((i, Ok((path, info))));
+                            }
+                            Err(ImageError::IoError(e)) if e.kind() == ErrorKind::OutOfMemory => {
+                                analysed.push((i, Err((path, e.to_string()))));
+                            }
+                            Err(_) => {}
+                        }
+                    }
+                    analysed
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|w| w.join().unwrap_or_default())
+            .collect()
+    });
+
+    // Keep the order of the sources, whichever worker finished first.
+    analysed.sort_by_key(|(i, _)| *i);
+    let mut library = LoadedLibrary::default();
+    for (_, image) in analysed {
+        match image {
+            Ok(image) => library.images.push(image),
+            Err(refused) => library.refused.push(refused),
+        }
+    }
+    library
+}
+
+/// An image and its analysis, or the image and why it was refused.
+type Analysed = Result<(PathBuf, ImageInfo), (PathBuf, String)>;
+
+/// FNV-1a hash, stable across builds unlike the standard library's hasher.
+pub(crate) fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, b| {
+        (hash ^ u64::from(*b)).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_support::scratch_dir;
+    use image::{Rgba, RgbaImage};
+    use std::fs::remove_dir_all;
+
+    /// Serve the PNG to the given number of requests, returning its URL.
+    #[cfg(feature = "remote")]
+    fn serve_png(requests: usize) -> String {
+        use image::ImageOutputFormat;
+        use std::io::{Cursor, Write};
+        use std::net::TcpListener;
+
+        let mut png = Cursor::new(Vec::new());
+        RgbaImage::from_pixel(4, 4, Rgba([255, 0, 0, 255]))
+            .write_to(&mut png, ImageOutputFormat::Png)
+            .unwrap();
+        let png = png.into_inner();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/red", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            for stream in listener.incoming().take(requests) {
+                let mut stream = stream.unwrap();
+                let mut request = [0; 1024];
+                let _ = stream.read(&mut request).unwrap();
+                let header = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    png.len()
+                );
+                stream.write_all(header.as_bytes()).unwrap();
+                stream.write_all(&png).unwrap();
+            }
+        });
+        url
+    }
+
+    #[test]
+    fn test_parses_manifest_urls() {
+        let text = "# Holiday\nhttp://a/1.jpg\n\n  http://a/2.jpg  \n";
+
+        assert_eq!(
+            parse_manifest(text),
+            vec!["http://a/1.jpg", "http://a/2.jpg"]
+        );
+    }
+
+    #[test]
+    fn test_analyses_streamed_sources_in_order() {
+        let dir = scratch_dir("library-stream");
+        let colors = [[255, 0, 0], [0, 255, 0], [0, 0, 255]];
+        for (i, [r, g, b]) in colors.into_iter().enumerate() {
+            RgbaImage::from_pixel(4, 4, Rgba([r, g, b, 255]))
+                .save(dir.join(format!("{i}.png")))
+                .unwrap();
+        }
+        let options = AnalysisOptions::new(Some(1));
+        // A lazy stream of names, including one that isn't an image.
+        let names = (0..4).map(|i| dir.join(format!("{i}.png")).display().to_string());
+
+        let library = analyse_library(names, &options);
+
+        let paths: Vec<PathBuf> = library.iter().map(|(p, _)| p.clone()).collect();
+        assert_eq!(
+            paths,
+            (0..3)
+                .map(|i| dir.join(format!("{i}.png")))
+                .collect::<Vec<_>>()
+        );
+        let blue = RgbaImage::from_pixel(4, 4, Rgba([0, 0, 255, 255]));
+        assert_eq!(library[2].1, crate::analyse(&blue, &options));
+        remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_leaves_out_ignored_images() {
+        let dir = scratch_dir("library-ignored");
+        for name in ["beach.jpg", "beach.tmp"] {
+            write(dir.join(name), "").unwrap();
+        }
+        create_dir_all(dir.join("trash")).unwrap();
+        write(dir.join(IGNORE_FILE), "trash/\n*.tmp\n").unwrap();
+
+        let images = open(&dir, &LibraryFilter::default())
+            .unwrap()
+            .images()
+            .unwrap();
+
+        assert_eq!(images, vec![dir.join("beach.jpg")]);
+        remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_never_loads_images_excluded_by_name() {
+        let dir = scratch_dir("library-excluded");
+        let photos = dir.join("photos");
+        create_dir_all(&photos).unwrap();
+        RgbaImage::from_pixel(4, 4, Rgba([255, 0, 0, 255]))
+            .save(photos.join("red.png"))
+            .unwrap();
+        // Not an image at all, so loading it would fail.
+        write(photos.join("red.thumb.png"), "").unwrap();
+        let manifest = dir.join("list.txt");
+        let listed = "photos/red.png\nphotos/red.thumb.png\nhttp://a/b.thumb.png\n";
+        write(&manifest, listed).unwrap();
+        let filter = LibraryFilter {
+            exclude: vec!["*.thumb.png".parse().unwrap()],
+            ..LibraryFilter::default()
+        };
+        let options = AnalysisOptions::new(Some(1));
+
+        for path in [&photos, &manifest] {
+            let library = open(path, &filter).unwrap();
+            assert_eq!(library.images().unwrap(), vec![photos.join("red.png")]);
+            let loaded = library.load(&options, &CancelToken::new()).unwrap();
+            let paths: Vec<&PathBuf> = loaded.images.iter().map(|(p, _)| p).collect();
+            assert_eq!(paths, vec![&photos.join("red.png")]);
+        }
+        // The excluded URL was never downloaded.
+        assert!(!dir.join("list.cache").exists());
+        remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_lists_manifest_paths_relative_to_manifest() {
+        let dir = scratch_dir("library-list");
+        create_dir_all(dir.join("photos")).unwrap();
+        RgbaImage::from_pixel(4, 4, Rgba([255, 0, 0, 255]))
+            .save(dir.join("photos/red.png"))
+            .unwrap();
+        let manifest = dir.join("list.txt");
+        write(&manifest, "photos/red.png\nphotos/missing.png\n").unwrap();
+
+        let images = open(&manifest, &LibraryFilter::default())
+            .unwrap()
+            .images()
+            .unwrap();
+
+        assert_eq!(images, vec![dir.join("photos/red.png")]);
+        assert!(!dir.join("list.cache").exists());
+        remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "remote")]
+    fn test_downloads_manifest_images_once() {
+        let dir = scratch_dir("library-remote");
+        let url = serve_png(1);
+        let manifest = dir.join("urls.txt");
+        write(&manifest, format!("{url}\nhttp://127.0.0.1:1/missing\n")).unwrap();
+
+        let library = open(&manifest, &LibraryFilter::default()).unwrap();
+        let first = library.images().unwrap();
+        let second = library.images().unwrap();
+
+        assert_eq!(first.len(), 1);
+        assert_eq!(first[0].extension().unwrap(), "png");
+        assert!(first[0].starts_with(dir.join("urls.cache")));
+        assert_eq!(first, second);
+        remove_dir_all(dir).unwrap();
+    }
+}
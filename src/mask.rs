@@ -0,0 +1,177 @@
+use std::io::{Error as IoError, Result as IoResult};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use image::imageops::{self, FilterType};
+use image::{GrayImage, Luma, Pixel, Rgba, RgbaImage};
+#[cfg(feature = "serde")]
+use serde::Deserialize;
+
+use crate::core::{Dimensions, PixelRegion};
+use crate::load_image;
+use crate::text::{render_text, TextShape};
+
+/// A mask shaping the mosaic, such as a logo, a heart, or some text, so that
+/// tiles are only drawn in the cells it covers.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(deny_unknown_fields))]
+pub struct Mask {
+    /// The shape to draw tiles in.
+    pub shape: MaskShape,
+    /// Share of a cell, from 0 to 1, the mask must cover for the cell to get
+    /// a tile.
+    #[cfg_attr(feature = "serde", serde(default = "default_threshold"))]
+    pub threshold: f64,
+    /// Color to fill the cells without tiles, left transparent if not given.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub background: Option<Color>,
+}
+
+#[cfg(feature = "serde")]
+fn default_threshold() -> f64 {
+    0.5
+}
+
+/// What gives a mask its shape.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
+pub enum MaskShape {
+    /// Image marking where to draw tiles by its alpha, or by its brightness
+    /// (white for tiles) if it is opaque. Stretched to fit the target.
+    Image(PathBuf),
+    /// Text drawn over the target, with tiles drawn inside its letters.
+    Text(TextShape),
+}
+
+/// An opaque color, written as `#rrggbb`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "String"))]
+pub struct Color(pub [u8; 3]);
+
+impl FromStr for Color {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let hex = s.strip_prefix('#').unwrap_or(s);
+        let channel = |i: usize| {
+            hex.get(i..i + 2)
+                .and_then(|c| u8::from_str_radix(c, 16).ok())
+        };
+        match (hex.len(), channel(0), channel(2), channel(4)) {
+            (6, Some(r), Some(g), Some(b)) => Ok(Color([r, g, b])),
+            _ => Err(format!("Invalid color '{s}': expected #rrggbb")),
+        }
+    }
+}
+
+impl TryFrom<String> for Color {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// How much of each pixel of a target of the given size the mask covers,
+/// from 0 to 255.
+pub(crate) fn load_coverage(mask: &Mask, size: Dimensions) -> IoResult<GrayImage> {
+    match &mask.shape {
+        MaskShape::Image(path) => image_coverage(path, size),
+        MaskShape::Text(text) => render_text(text, size),
+    }
+}
+
+fn image_coverage(path: &Path, (width, height): Dimensions) -> IoResult<GrayImage> {
+    let img = load_image(path).map_err(IoError::other)?;
+    let opaque = img.pixels().all(|p| p[3] == u8::MAX);
+    let coverage = GrayImage::from_fn(img.width(), img.height(), |x, y| {
+        let pixel = img.get_pixel(x, y);
+        if opaque {
+            pixel.to_luma()
+        } else {
+            Luma([pixel[3]])
+        }
+    });
+
+    if coverage.dimensions() == (width, height) {
+        Ok(coverage)
+    } else {
+        Ok(imageops::resize(
+            &coverage,
+            width,
+            height,
+            FilterType::Triangle,
+        ))
+    }
+}
+
+/// Whether enough of the cell is covered to draw a tile in it.
+pub(crate) fn covers(coverage: &GrayImage, cell: &PixelRegion, threshold: f64) -> bool {
+    let (width, height) = coverage.dimensions();
+    let Some(inside) = cell.intersect(&PixelRegion::new(0, 0, width, height)) else {
+        return false;
+    };
+    let pixels = inside.area() as usize;
+    let xs = inside.x as u32..inside.right() as u32;
+    let ys = inside.y as u32..inside.bottom() as u32;
+
+    let total: u64 = ys
+        .flat_map(|y| xs.clone().map(move |x| (x, y)))
+        .map(|(x, y)| u64::from(coverage.get_pixel(x, y)[0]))
+        .sum();
+    total as f64 / (pixels as f64 * 255.0) >= threshold
+}
+
+/// Fill in the transparent parts of the image with the color.
+pub(crate) fn fill_background(img: &mut RgbaImage, Color([r, g, b]): Color) {
+    for pixel in img.pixels_mut() {
+        let mut filled = Rgba([r, g, b, u8::MAX]);
+        filled.blend(pixel);
+        *pixel = filled;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parses_colors() {
+        assert_eq!("#ff8000".parse(), Ok(Color([255, 128, 0])));
+        assert_eq!("0a0B0c".parse(), Ok(Color([10, 11, 12])));
+        assert!("#fff".parse::<Color>().is_err());
+        assert!("#gg0000".parse::<Color>().is_err());
+    }
+
+    #[test]
+    fn test_covers_cells_by_share_of_mask() {
+        // The left quarter of the mask is covered.
+        let coverage = GrayImage::from_fn(40, 10, |x, _| Luma([if x < 10 { 255 } else { 0 }]));
+        let cell = |x| PixelRegion::new(x, 0, 20, 10);
+
+        assert!(covers(&coverage, &cell(0), 0.5));
+        assert!(!covers(&coverage, &cell(0), 0.6));
+        assert!(!covers(&coverage, &cell(20), 0.1));
+        assert!(covers(&coverage, &cell(20), 0.0));
+        assert!(!covers(&coverage, &cell(40), 0.0));
+    }
+
+    #[test]
+    fn test_fills_transparent_pixels_with_background() {
+        let mut img = RgbaImage::from_fn(2, 1, |x, _| {
+            if x == 0 {
+                Rgba([0, 0, 255, 255])
+            } else {
+                Rgba([0, 0, 0, 0])
+            }
+        });
+
+        fill_background(&mut img, Color([255, 255, 255]));
+
+        assert_eq!(img.get_pixel(0, 0), &Rgba([0, 0, 255, 255]));
+        assert_eq!(img.get_pixel(1, 0), &Rgba([255, 255, 255, 255]));
+    }
+}
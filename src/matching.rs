@@ -1,91 +1,427 @@
 use std::collections::HashMap;
+use std::hash::Hash;
 
 use image::{imageops, GenericImageView, RgbaImage};
 
 use crate::analysis::{analyse, AnalysisOptions, ImageInfo};
-use crate::core::{Dimensions, PixelRegion, Rectangle, TileLocation};
+use crate::core::{product, CellCoords, Dimensions, PixelRegion, Rectangle, TileLocation};
+use crate::pyramid::PyramidLibrary;
+use crate::strategy::{StrategyOptions, TilingStrategy};
 
 pub struct MatchingTileStrategy<'a, T> {
-    options: &'a AnalysisOptions,
+    options: &'a StrategyOptions,
     analysis: &'a HashMap<&'a T, ImageInfo>,
+    /// The library with coarser levels of each image, if tiles are ruled
+    /// out by them.
+    pyramids: Option<PyramidLibrary<'a, T>>,
 }
 
-impl<T> MatchingTileStrategy<'_, T> {
+impl<T: Ord + Hash> MatchingTileStrategy<'_, T> {
     pub fn new<'a>(
         analysis: &'a HashMap<&T, ImageInfo>,
-        options: &'a AnalysisOptions,
+        options: &'a StrategyOptions,
     ) -> MatchingTileStrategy<'a, T> {
-        MatchingTileStrategy { options, analysis }
+        let pyramids = options.pyramid.then(|| PyramidLibrary::new(analysis));
+        MatchingTileStrategy {
+            options,
+            analysis,
+            pyramids,
+        }
     }
 
-    // Independent tile selection
+    fn select_tile(
+        &self,
+        analyser: &CellAnalyser,
+        r: &Rectangle,
+    ) -> TileLocation<'_, T, PixelRegion> {
+        let target_info = analyser.analyse(r);
+        let (best_tile, _) = match &self.pyramids {
+            Some(pyramids) => pyramids.best(&target_info, r, self.options),
+            None => {
+                let tiles = self.analysis.iter().map(|(tile, info)| (*tile, info));
+                self.options.best_match(tiles, &target_info, r)
+            }
+        }
+        .unwrap();
+        (best_tile, PixelRegion::from(r))
+    }
+}
 
-    pub fn choose(
+// Independent tile selection
+
+impl<T: Ord + Hash> TilingStrategy<T> for MatchingTileStrategy<'_, T> {
+    fn choose(
         &self,
         target: &RgbaImage,
         cell_size: &Dimensions,
-    ) -> Vec<TileLocation<T, PixelRegion>> {
+    ) -> Vec<TileLocation<'_, T, PixelRegion>> {
         // This implementation assumes we can select the correct tile for
         // each cell independently.
+        let analyser = CellAnalyser::new(target, *cell_size, &self.options.analysis);
         grid(target, cell_size)
             .iter()
-            .map(|t| self.select_tile(target, t))
+            .take_while(|_| !self.options.cancel.is_cancelled())
+            .map(|t| self.select_tile(&analyser, t))
             .collect()
     }
+}
+
+/// Split the target into cells of the given size, at least a pixel across,
+/// the last row and column running past its edges if it isn't a whole number
+/// of cells, so even a target smaller than a cell gets one.
+pub(crate) fn grid<I>(target: &I, cell_size: &Dimensions) -> Vec<Rectangle>
+where
+    I: GenericImageView,
+{
+    let (tw, th) = target.dimensions();
+    let (cw, ch) = (cell_size.0.max(1), cell_size.1.max(1));
+
+    let xs = (0..tw).step_by(cw as usize);
+    let ys = (0..th).step_by(ch as usize);
+
+    product(xs, ys)
+        .map(|(x, y)| Rectangle::new(x, y, cw, ch))
+        .collect()
+}
+
+/// The cells of a grid in the order they are visited, along with which cell
+/// is at each column and row so that the cells around one can be found
+/// directly.
+pub(crate) struct CellGrid {
+    pub(crate) cells: Vec<Rectangle>,
+    pub(crate) cell_size: Dimensions,
+    columns: u32,
+    rows: u32,
+    /// The position in `cells` of the cell at each column of each row.
+    at: Vec<usize>,
+}
 
-    fn select_tile(&self, img: &RgbaImage, r: &Rectangle) -> TileLocation<T, PixelRegion> {
-        let target_info = analyse_cell(img, r, &self.options);
-        let best_tile = *self
-            .analysis
+impl CellGrid {
+    pub(crate) fn new(cells: Vec<Rectangle>, cell_size: Dimensions) -> CellGrid {
+        let coords = |r| CellCoords::of(r, cell_size);
+        let columns = cells
             .iter()
-            .min_by_key(|(_, info)| info.diff(&target_info).iter().sum::<i32>())
-            .unwrap()
-            .0;
-        (best_tile, PixelRegion::from(r))
+            .map(|r| coords(r).column + 1)
+            .max()
+            .unwrap_or(0);
+        let rows = cells.iter().map(|r| coords(r).row + 1).max().unwrap_or(0);
+        let mut at = vec![0; (columns * rows) as usize];
+        for (i, r) in cells.iter().enumerate() {
+            let CellCoords { column, row } = coords(r);
+            at[(row * columns + column) as usize] = i;
+        }
+        CellGrid {
+            cells,
+            cell_size,
+            columns,
+            rows,
+            at,
+        }
+    }
+
+    /// The coordinates of the cell.
+    pub(crate) fn coords(&self, cell: usize) -> CellCoords {
+        CellCoords::of(&self.cells[cell], self.cell_size)
     }
 
-    // Holistic tile selection
+    /// The position of the cell at the coordinates, if they are in the grid.
+    pub(crate) fn get(&self, CellCoords { column, row }: CellCoords) -> Option<usize> {
+        (column < self.columns && row < self.rows)
+            .then(|| self.at[(row * self.columns + column) as usize])
+    }
 
-    #[allow(dead_code)]
-    pub fn choose2(
+    /// The cells up to the given number of cells across and down from the
+    /// cell, including itself.
+    pub(crate) fn around(
         &self,
-        target: &RgbaImage,
-        cell_size: &Dimensions,
-    ) -> Vec<TileLocation<T, PixelRegion>> {
-        let _cells_info: Vec<(&Rectangle, ImageInfo)> = grid(target, cell_size)
-            .iter()
-            .map(|t| (t, analyse_cell(target, t, &self.options)))
-            .collect();
-        todo!();
+        cell: usize,
+        (across, down): (u32, u32),
+    ) -> impl Iterator<Item = usize> + '_ {
+        let CellCoords { column, row } = self.coords(cell);
+        let columns = column.saturating_sub(across)..=column.saturating_add(across);
+        let rows = row.saturating_sub(down)..=row.saturating_add(down);
+        product(rows, columns).filter_map(|(row, column)| self.get(CellCoords { column, row }))
     }
 }
 
-fn grid<I>(target: &I, cell_size: &Dimensions) -> Vec<Rectangle>
-where
-    I: GenericImageView,
-{
-    let (tw, th) = target.dimensions();
-    let (cw, ch) = cell_size;
+/// Analyses the cells of a target from a copy of its whole cells scaled once
+/// to their samples, rather than cropping and scaling each cell in turn.
+/// Areas off the grid, such as cells split over protected areas or cells
+/// running past the edge of the target, are analysed on their own.
+pub(crate) struct CellAnalyser<'a> {
+    target: &'a RgbaImage,
+    cell_size: Dimensions,
+    options: AnalysisOptions,
+    /// The whole cells of the target, each scaled to its samples.
+    scaled: RgbaImage,
+}
 
-    let xs = (0..tw).step_by(*cw as usize);
-    let ys = (0..th).step_by(*ch as usize);
+impl<'a> CellAnalyser<'a> {
+    pub(crate) fn new(
+        target: &'a RgbaImage,
+        cell_size: Dimensions,
+        options: &AnalysisOptions,
+    ) -> CellAnalyser<'a> {
+        let (cw, ch) = (cell_size.0.max(1), cell_size.1.max(1));
+        let (columns, rows) = (target.width() / cw, target.height() / ch);
+        let size = u32::from(options.sample_size);
+        let scaled = if columns == 0 || rows == 0 {
+            RgbaImage::new(0, 0)
+        } else if columns * cw == target.width() && rows * ch == target.height() {
+            options.resize().apply(target, columns * size, rows * size)
+        } else {
+            let whole = imageops::crop_imm(target, 0, 0, columns * cw, rows * ch).to_image();
+            options.resize().apply(&whole, columns * size, rows * size)
+        };
+        CellAnalyser {
+            target,
+            cell_size: (cw, ch),
+            options: *options,
+            scaled,
+        }
+    }
 
-    itertools::iproduct!(xs, ys)
-        .map(|(x, y)| Rectangle::new(x, y, *cw, *ch))
-        .collect()
+    /// Analyse the area of the target, usually a cell.
+    pub(crate) fn analyse(&self, r: &Rectangle) -> ImageInfo {
+        let (cw, ch) = self.cell_size;
+        let size = u32::from(self.options.sample_size);
+        let (x, y) = (r.x / cw * size, r.y / ch * size);
+        let on_grid =
+            (r.width, r.height) == (cw, ch) && r.x.is_multiple_of(cw) && r.y.is_multiple_of(ch);
+        if !on_grid || x + size > self.scaled.width() || y + size > self.scaled.height() {
+            return analyse_cell(self.target, r, &self.options);
+        }
+        let samples = imageops::crop_imm(&self.scaled, x, y, size, size);
+        ImageInfo::sampled(r.width, r.height, &*samples, &self.options)
+    }
 }
 
-fn analyse_cell(img: &RgbaImage, r: &Rectangle, options: &AnalysisOptions) -> ImageInfo {
-    let target = imageops::crop_imm(img, r.x, r.y, r.width, r.height);
-    analyse(&target.to_image(), options)
+/// Analyse the cell of the target, repeating the target's edge pixels over
+/// any part of the cell past the edge if sampling layouts are matched, since
+/// the tile drawn there covers the whole cell.
+pub(crate) fn analyse_cell(img: &RgbaImage, r: &Rectangle, options: &AnalysisOptions) -> ImageInfo {
+    let (width, height) = img.dimensions();
+    let inside = r.intersect(&Rectangle::new(0, 0, width, height)) == Some(*r);
+    if !options.matched_layout || inside {
+        let target = imageops::crop_imm(img, r.x, r.y, r.width, r.height);
+        return analyse(&target.to_image(), options);
+    }
+    let cell = RgbaImage::from_fn(r.width, r.height, |x, y| {
+        let x = (r.x + x).min(width.saturating_sub(1));
+        let y = (r.y + y).min(height.saturating_sub(1));
+        *img.get_pixel(x, y)
+    });
+    analyse(&cell, options)
+}
+
+/// How far a tile's colors are from a cell's, lower is a better match.
+///
+/// Summed as `i64` as large sample grids can exceed `i32::MAX`.
+pub(crate) fn tile_difference_weight(
+    tile: &ImageInfo,
+    cell: &ImageInfo,
+    options: &AnalysisOptions,
+) -> i64 {
+    tile.diff_sum(cell, &options.channel_weights)
 }
 
 #[cfg(test)]
 mod test {
-    // use super::*;
+    use super::*;
+    use image::Rgba;
+    use proptest::prelude::*;
+
+    proptest! {
+        #[test]
+        fn test_grid_covers_each_pixel_once(
+            width in 1u32..120,
+            height in 1u32..120,
+            cw in 1u32..40,
+            ch in 1u32..40,
+        ) {
+            let cells = grid(&RgbaImage::new(width, height), &(cw, ch));
+
+            let mut covered = vec![0; (width * height) as usize];
+            for r in &cells {
+                // Cells start inside the image, and only the last row and
+                // column run past its edges, by less than a cell.
+                prop_assert!(r.x < width && r.y < height);
+                prop_assert_eq!((r.width, r.height), (cw, ch));
+                for y in r.y..(r.y + r.height).min(height) {
+                    for x in r.x..(r.x + r.width).min(width) {
+                        covered[(y * width + x) as usize] += 1;
+                    }
+                }
+            }
+            prop_assert!(covered.iter().all(|c| *c == 1));
+            prop_assert_eq!(cells.len() as u32, width.div_ceil(cw) * height.div_ceil(ch));
+        }
+    }
+
+    #[test]
+    fn test_grids_tiny_targets() {
+        let cells = |width, height, size| grid(&RgbaImage::new(width, height), &(size, size));
+
+        assert_eq!(cells(1, 1, 20), vec![Rectangle::new(0, 0, 20, 20)]);
+        assert_eq!(cells(5, 3, 20), vec![Rectangle::new(0, 0, 20, 20)]);
+        assert_eq!(cells(5, 3, 0).len(), 15);
+        assert_eq!(cells(0, 0, 20), vec![]);
+    }
+
+    #[test]
+    fn test_weights_large_sample_grids_without_overflow() {
+        let options = AnalysisOptions::new(Some(u8::MAX));
+        let solid = |v| {
+            analyse(
+                &RgbaImage::from_pixel(255, 255, Rgba([v, v, v, 255])),
+                &options,
+            )
+        };
+        let (black, white) = (solid(0), solid(255));
+
+        let weight = tile_difference_weight(&black, &white, &options);
+
+        assert_eq!(weight, 255 * 255 * 3 * 255 * 255);
+        assert!(weight > i32::MAX.into());
+    }
+
+    #[test]
+    fn test_selects_best_tile_with_large_sample_grids() {
+        let (black, grey) = ("black".to_string(), "grey".to_string());
+        let options = StrategyOptions {
+            analysis: AnalysisOptions::new(Some(u8::MAX)),
+            ..StrategyOptions::default()
+        };
+        let solid = |v| {
+            analyse(
+                &RgbaImage::from_pixel(255, 255, Rgba([v, v, v, 255])),
+                &options.analysis,
+            )
+        };
+        let analysis = HashMap::from([(&black, solid(0)), (&grey, solid(200))]);
+        let target = RgbaImage::from_pixel(255, 255, Rgba([255, 255, 255, 255]));
 
-    // #[test]
-    // fn test_foo() {
-    //     todo!();
-    // }
+        let strategy = MatchingTileStrategy::new(&analysis, &options);
+        let tiles = strategy.choose(&target, &(255, 255));
+
+        assert_eq!(tiles.len(), 1);
+        assert_eq!(tiles[0].0, &grey);
+    }
+
+    #[test]
+    fn test_repeats_target_edge_over_cells_past_it_with_matched_layout() {
+        // The last column of the target is white, the rest black.
+        let target = RgbaImage::from_fn(30, 10, |x, _| {
+            if x == 29 {
+                Rgba([255, 255, 255, 255])
+            } else {
+                Rgba([0, 0, 0, 255])
+            }
+        });
+        let cell = Rectangle::new(20, 0, 20, 10);
+        let clipped = AnalysisOptions {
+            linear_light: false,
+            ..AnalysisOptions::new(Some(2))
+        };
+        let matched = AnalysisOptions {
+            matched_layout: true,
+            ..clipped
+        };
+        // The whole cell, with the white column repeated past the edge.
+        let padded = RgbaImage::from_fn(20, 10, |x, _| {
+            if x >= 9 {
+                Rgba([255, 255, 255, 255])
+            } else {
+                Rgba([0, 0, 0, 255])
+            }
+        });
+
+        assert_ne!(
+            analyse_cell(&target, &cell, &clipped),
+            analyse(&padded, &clipped)
+        );
+        assert_eq!(
+            analyse_cell(&target, &cell, &matched),
+            analyse(&padded, &matched)
+        );
+    }
+
+    #[test]
+    fn test_slices_cells_from_the_scaled_target() {
+        let options = AnalysisOptions::new(Some(2));
+        // Black and white cells, with a partial column of cells on the right.
+        let target = RgbaImage::from_fn(45, 20, |x, y| {
+            let v = if (x / 10 + y / 10) % 2 == 0 { 0 } else { 255 };
+            Rgba([v, v, v, 255])
+        });
+        let analyser = CellAnalyser::new(&target, (10, 10), &options);
+
+        let black = analyser.analyse(&Rectangle::new(0, 0, 10, 10));
+        let white = analyser.analyse(&Rectangle::new(10, 0, 10, 10));
+        let mean = |info: &ImageInfo| info.mean_color()[0];
+        assert!(mean(&black) < 64 && mean(&white) > 192);
+
+        // Areas off the grid are analysed on their own.
+        for r in [Rectangle::new(40, 0, 10, 10), Rectangle::new(5, 5, 5, 5)] {
+            assert_eq!(analyser.analyse(&r), analyse_cell(&target, &r, &options));
+        }
+
+        let flat = RgbaImage::from_pixel(30, 30, Rgba([40, 80, 120, 255]));
+        let cell = Rectangle::new(10, 20, 10, 10);
+        assert_eq!(
+            CellAnalyser::new(&flat, (10, 10), &options).analyse(&cell),
+            analyse_cell(&flat, &cell, &options)
+        );
+    }
+
+    #[test]
+    fn test_finds_cells_around_a_cell() {
+        // A 4x3 grid, visited column by column.
+        let mut cells = grid(&RgbaImage::new(40, 30), &(10, 10));
+        cells.sort_by_key(|r| (r.x, r.y));
+        let grid = CellGrid::new(cells, (10, 10));
+        let coords = |cells: Vec<usize>| -> Vec<(u32, u32)> {
+            let mut coords: Vec<_> = cells
+                .into_iter()
+                .map(|i| grid.coords(i))
+                .map(|c| (c.column, c.row))
+                .collect();
+            coords.sort();
+            coords
+        };
+
+        let corner = grid.get(CellCoords { column: 0, row: 0 }).unwrap();
+        assert_eq!(
+            coords(grid.around(corner, (1, 1)).collect()),
+            vec![(0, 0), (0, 1), (1, 0), (1, 1)]
+        );
+        let middle = grid.get(CellCoords { column: 2, row: 1 }).unwrap();
+        assert_eq!(
+            coords(grid.around(middle, (1, 0)).collect()),
+            vec![(1, 1), (2, 1), (3, 1)]
+        );
+        assert_eq!(grid.around(middle, (9, 9)).count(), 12);
+        assert_eq!(grid.get(CellCoords { column: 4, row: 0 }), None);
+        assert_eq!(grid.coords(corner).offset(-1, 0), None);
+    }
+
+    #[test]
+    fn test_breaks_ties_by_name() {
+        let options = StrategyOptions {
+            analysis: AnalysisOptions::new(Some(1)),
+            ..StrategyOptions::default()
+        };
+        let names: Vec<String> = (0..10).map(|i| format!("copy {i}")).collect();
+        let red = analyse(
+            &RgbaImage::from_pixel(20, 20, Rgba([255, 0, 0, 255])),
+            &options.analysis,
+        );
+        let analysis: HashMap<&String, ImageInfo> =
+            names.iter().map(|name| (name, red.clone())).collect();
+        let target = RgbaImage::from_pixel(20, 20, Rgba([255, 0, 0, 255]));
+
+        let strategy = MatchingTileStrategy::new(&analysis, &options);
+
+        assert_eq!(strategy.choose(&target, &(20, 20))[0].0, &names[0]);
+    }
 }
@@ -0,0 +1,385 @@
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::fs::File;
+#[cfg(feature = "exif")]
+use std::io::Cursor;
+use std::io::Read;
+use std::path::Path;
+use std::str::FromStr;
+
+#[cfg(feature = "exif")]
+use exif::{In, Reader, Tag, Value};
+#[cfg(feature = "serde")]
+use serde::Deserialize;
+
+use crate::glob::Glob;
+
+/// Number of bytes from the start of an image file searched for metadata,
+/// which image formats keep ahead of the pixels.
+const METADATA_BYTES: u64 = 1 << 20;
+
+/// A calendar day, such as the day a photo was taken.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "String"))]
+pub struct Date {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+}
+
+impl FromStr for Date {
+    type Err = String;
+
+    /// Parse a `YYYY-MM-DD` date, ignoring any time after it. EXIF's
+    /// `YYYY:MM:DD` is accepted too.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid date '{s}': expected YYYY-MM-DD");
+        let day = s.get(..10).ok_or_else(invalid)?;
+        let time = s.get(10..).ok_or_else(invalid)?;
+        let parts: Vec<&str> = day.split(['-', ':']).collect();
+        let [year, month, day] = parts[..] else {
+            return Err(invalid());
+        };
+        let date = Date {
+            year: year.parse().map_err(|_| invalid())?,
+            month: month.parse().map_err(|_| invalid())?,
+            day: day.parse().map_err(|_| invalid())?,
+        };
+        let separated = time.is_empty() || time.starts_with(['T', ' ']);
+        if !separated || !(1..=12).contains(&date.month) || !(1..=31).contains(&date.day) {
+            return Err(invalid());
+        }
+        Ok(date)
+    }
+}
+
+impl TryFrom<String> for Date {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl Display for Date {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "{:04}-{:02}-{:02}", self.year, self.month, self.day)
+    }
+}
+
+/// Which library images to build with, chosen by their EXIF and XMP
+/// metadata. Images without the metadata a condition needs are left out.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
+pub struct LibraryFilter {
+    /// Earliest day the images were taken on.
+    pub taken_from: Option<Date>,
+    /// Latest day the images were taken on.
+    pub taken_until: Option<Date>,
+    /// Keywords the images must have at least one of, ignoring case.
+    pub keywords: Vec<String>,
+    /// Lowest star rating of the images.
+    pub min_rating: Option<u8>,
+    /// Patterns the names of the images must match at least one of.
+    pub include: Vec<Glob>,
+    /// Patterns the names of the images must match none of.
+    pub exclude: Vec<Glob>,
+}
+
+impl LibraryFilter {
+    /// Whether the filter keeps every image, without reading any metadata.
+    pub fn is_empty(&self) -> bool {
+        *self == LibraryFilter::default()
+    }
+
+    /// Whether the image at the given path passes the filter, reading its
+    /// metadata only if its name does and there are conditions on it.
+    pub(crate) fn accepts(&self, path: &Path) -> bool {
+        self.names(path) && (!self.reads_metadata() || self.matches(&read_metadata(path)))
+    }
+
+    /// Just the include and exclude patterns of the filter, which libraries
+    /// list their images by.
+    pub(crate) fn name_patterns(&self) -> LibraryFilter {
+        LibraryFilter {
+            include: self.include.clone(),
+            exclude: self.exclude.clone(),
+            ..LibraryFilter::default()
+        }
+    }
+
+    /// Whether the name of the image passes the include and exclude
+    /// patterns.
+    pub(crate) fn names(&self, path: &Path) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|glob| glob.matches(path)))
+            && !self.exclude.iter().any(|glob| glob.matches(path))
+    }
+
+    fn reads_metadata(&self) -> bool {
+        self.taken_from.is_some()
+            || self.taken_until.is_some()
+            || !self.keywords.is_empty()
+            || self.min_rating.is_some()
+    }
+
+    fn matches(&self, metadata: &ImageMetadata) -> bool {
+        let taken = |check: fn(&Date, &Date) -> bool, bound: &Option<Date>| {
+            bound.is_none_or(|bound| metadata.taken.is_some_and(|taken| check(&taken, &bound)))
+        };
+        let keyword = self.keywords.is_empty()
+            || self.keywords.iter().any(|wanted| {
+                metadata
+                    .keywords
+                    .iter()
+                    .any(|k| k.to_lowercase() == wanted.to_lowercase())
+            });
+        let rating = self
+            .min_rating
+            .is_none_or(|min| metadata.rating.is_some_and(|r| r >= min));
+
+        taken(Date::ge, &self.taken_from) && taken(Date::le, &self.taken_until) && keyword && rating
+    }
+}
+
+/// What an image's metadata says about it.
+#[derive(Debug, Default, PartialEq)]
+struct ImageMetadata {
+    taken: Option<Date>,
+    keywords: Vec<String>,
+    rating: Option<u8>,
+}
+
+/// Read the metadata of the image at the given path, preferring EXIF to XMP
+/// for the date taken. Unreadable metadata is treated as missing.
+fn read_metadata(path: &Path) -> ImageMetadata {
+    let mut bytes = Vec::new();
+    let read = File::open(path).and_then(|f| f.take(METADATA_BYTES).read_to_end(&mut bytes));
+    if read.is_err() {
+        return ImageMetadata::default();
+    }
+
+    let mut metadata = parse_xmp(&xmp_packet(&bytes).unwrap_or_default());
+    if let Some(taken) = exif_date(&bytes) {
+        metadata.taken = Some(taken);
+    }
+    metadata
+}
+
+/// The date in the EXIF data of an image file, if any.
+#[cfg(feature = "exif")]
+fn exif_date(bytes: &[u8]) -> Option<Date> {
+    let exif = Reader::new()
+        .read_from_container(&mut Cursor::new(bytes))
+        .ok()?;
+    [Tag::DateTimeOriginal, Tag::DateTime]
+        .iter()
+        .find_map(|tag| match &exif.get_field(*tag, In::PRIMARY)?.value {
+            Value::Ascii(values) => std::str::from_utf8(values.first()?).ok()?.parse().ok(),
+            _ => None,
+        })
+}
+
+/// Without the `exif` feature there is nothing to read EXIF data with, so
+/// dates are read from XMP alone.
+#[cfg(not(feature = "exif"))]
+fn exif_date(_bytes: &[u8]) -> Option<Date> {
+    None
+}
+
+/// The XMP packet embedded in an image file, if any.
+fn xmp_packet(bytes: &[u8]) -> Option<String> {
+    let find = |needle: &[u8], from: usize| {
+        bytes[from..]
+            .windows(needle.len())
+            .position(|w| w == needle)
+            .map(|i| i + from)
+    };
+    let start = find(b"<x:xmpmeta", 0)?;
+    let end = find(b"</x:xmpmeta>", start)?;
+    Some(String::from_utf8_lossy(&bytes[start..end]).into_owned())
+}
+
+fn parse_xmp(xmp: &str) -> ImageMetadata {
+    let keywords = xmp
+        .split_once("<dc:subject>")
+        .and_then(|(_, rest)| rest.split_once("</dc:subject>"))
+        .map(|(subject, _)| {
+            subject
+                .split("<rdf:li")
+                .skip(1)
+                .filter_map(|item| item.split_once('>')?.1.split_once("</rdf:li>"))
+                .map(|(keyword, _)| unescape(keyword.trim()))
+                .filter(|keyword| !keyword.is_empty())
+                .collect()
+        })
+        .unwrap_or_default();
+
+    ImageMetadata {
+        taken: [
+            "exif:DateTimeOriginal",
+            "xmp:CreateDate",
+            "photoshop:DateCreated",
+        ]
+        .iter()
+        .find_map(|name| property(xmp, name)?.parse().ok()),
+        keywords,
+        rating: property(xmp, "xmp:Rating").and_then(|r| r.parse().ok()),
+    }
+}
+
+/// The value of a simple XMP property, written as either an attribute or an
+/// element.
+fn property<'a>(xmp: &'a str, name: &str) -> Option<&'a str> {
+    if let Some((_, rest)) = xmp.split_once(&format!("{name}=")) {
+        let quote = rest.chars().next().filter(|c| ['"', '\''].contains(c))?;
+        return rest[1..].split(quote).next();
+    }
+    let (_, rest) = xmp.split_once(&format!("<{name}>"))?;
+    rest.split_once('<').map(|(value, _)| value.trim())
+}
+
+fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[cfg(feature = "exif")]
+    use {
+        exif::experimental::Writer,
+        exif::Field,
+        image::{ImageOutputFormat, RgbImage},
+        std::env::temp_dir,
+        std::fs::{remove_file, write},
+    };
+
+    const XMP: &str = r#"<x:xmpmeta xmlns:x="adobe:ns:meta/">
+      <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+        <rdf:Description xmp:Rating="4" xmp:CreateDate="2023-06-01T10:00:00">
+          <dc:subject><rdf:Bag>
+            <rdf:li>Beach</rdf:li>
+            <rdf:li>Fish &amp; chips</rdf:li>
+          </rdf:Bag></dc:subject>
+        </rdf:Description>
+      </rdf:RDF>
+    </x:xmpmeta>"#;
+
+    fn date(year: u16, month: u8, day: u8) -> Date {
+        Date { year, month, day }
+    }
+
+    #[test]
+    fn test_parses_dates() {
+        assert_eq!("2023-01-31".parse(), Ok(date(2023, 1, 31)));
+        assert_eq!("2023:01:31 12:00:00".parse(), Ok(date(2023, 1, 31)));
+        assert_eq!("2023-01-31T12:00".parse(), Ok(date(2023, 1, 31)));
+        assert!("2023-13-01".parse::<Date>().is_err());
+        assert!("2023-01-310".parse::<Date>().is_err());
+        assert!("2023".parse::<Date>().is_err());
+    }
+
+    #[test]
+    fn test_parses_xmp_properties() {
+        assert_eq!(
+            parse_xmp(XMP),
+            ImageMetadata {
+                taken: Some(date(2023, 6, 1)),
+                keywords: vec!["Beach".to_string(), "Fish & chips".to_string()],
+                rating: Some(4),
+            }
+        );
+        let elements =
+            "<xmp:Rating>2</xmp:Rating><photoshop:DateCreated>2020-02-02</photoshop:DateCreated>";
+        assert_eq!(parse_xmp(elements).rating, Some(2));
+        assert_eq!(parse_xmp(elements).taken, Some(date(2020, 2, 2)));
+    }
+
+    #[test]
+    fn test_filters_by_metadata() {
+        let metadata = parse_xmp(XMP);
+        let filter = |f: LibraryFilter| f.matches(&metadata);
+
+        assert!(filter(LibraryFilter::default()));
+        assert!(filter(LibraryFilter {
+            taken_from: Some(date(2023, 1, 1)),
+            taken_until: Some(date(2023, 6, 1)),
+            keywords: vec!["beach".to_string(), "snow".to_string()],
+            min_rating: Some(4),
+            ..LibraryFilter::default()
+        }));
+        assert!(!filter(LibraryFilter {
+            taken_until: Some(date(2023, 5, 31)),
+            ..LibraryFilter::default()
+        }));
+        assert!(!filter(LibraryFilter {
+            keywords: vec!["snow".to_string()],
+            ..LibraryFilter::default()
+        }));
+        assert!(!filter(LibraryFilter {
+            min_rating: Some(5),
+            ..LibraryFilter::default()
+        }));
+        // Images without the metadata are left out.
+        assert!(!LibraryFilter {
+            min_rating: Some(1),
+            ..LibraryFilter::default()
+        }
+        .matches(&ImageMetadata::default()));
+    }
+
+    #[test]
+    fn test_filters_by_name() {
+        let globs = |patterns: &[&str]| patterns.iter().map(|p| p.parse().unwrap()).collect();
+        let filter = LibraryFilter {
+            include: globs(&["2023-*", "*.png"]),
+            exclude: globs(&["*.thumb.jpg"]),
+            ..LibraryFilter::default()
+        };
+
+        assert!(filter.accepts(Path::new("library/2023-05-01.jpg")));
+        assert!(filter.accepts(Path::new("library/beach.png")));
+        assert!(!filter.accepts(Path::new("library/2023-05-01.thumb.jpg")));
+        assert!(!filter.accepts(Path::new("library/2022-05-01.jpg")));
+    }
+
+    #[cfg(feature = "exif")]
+    #[test]
+    fn test_reads_exif_and_xmp_from_jpeg() {
+        let mut jpeg = Vec::new();
+        RgbImage::new(8, 8)
+            .write_to(&mut Cursor::new(&mut jpeg), ImageOutputFormat::Jpeg(90))
+            .unwrap();
+        let taken = Field {
+            tag: Tag::DateTimeOriginal,
+            ifd_num: In::PRIMARY,
+            value: Value::Ascii(vec![b"2022:12:25 09:30:00".to_vec()]),
+        };
+        let mut exif = Cursor::new(Vec::new());
+        let mut writer = Writer::new();
+        writer.push_field(&taken);
+        writer.write(&mut exif, false).unwrap();
+        let exif = [b"Exif\0\0".as_slice(), exif.get_ref()].concat();
+        let xmp = [b"http://ns.adobe.com/xap/1.0/\0".as_slice(), XMP.as_bytes()].concat();
+
+        // APP1 segments go straight after the start of image marker.
+        let segment = |data: &[u8]| {
+            let length = (data.len() + 2) as u16;
+            [[0xff, 0xe1].as_slice(), &length.to_be_bytes(), data].concat()
+        };
+        let file = [&jpeg[..2], &segment(&exif), &segment(&xmp), &jpeg[2..]].concat();
+        let path = temp_dir().join(format!("tiler-metadata-{}.jpg", std::process::id()));
+        write(&path, file).unwrap();
+
+        let metadata = read_metadata(&path);
+        remove_file(&path).unwrap();
+        assert_eq!(metadata.taken, Some(date(2022, 12, 25)));
+        assert_eq!(metadata.rating, Some(4));
+        assert_eq!(metadata.keywords.len(), 2);
+    }
+}
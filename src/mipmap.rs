@@ -0,0 +1,166 @@
+use std::fs::{create_dir_all, metadata, rename, write};
+use std::io::{Cursor, Error as IoError, Result as IoResult};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use image::{ImageOutputFormat, ImageResult, RgbaImage};
+#[cfg(feature = "serde")]
+use serde::Deserialize;
+
+use crate::core::Dimensions;
+use crate::library::fnv1a;
+use crate::load_image;
+use crate::resize::Resize;
+
+/// Smaller copies of library images kept to draw tiles from, so each tile is
+/// resized from a copy near its size instead of its full-sized original.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(deny_unknown_fields))]
+pub struct Mipmaps {
+    /// Directory the copies are kept in.
+    pub dir: PathBuf,
+    /// Length (in pixels) of the shorter side of each size of copy kept.
+    #[cfg_attr(feature = "serde", serde(default = "default_sizes"))]
+    pub sizes: Vec<u32>,
+}
+
+fn default_sizes() -> Vec<u32> {
+    vec![64, 256]
+}
+
+impl Mipmaps {
+    /// Keep copies of the default sizes in the directory.
+    pub fn new(dir: PathBuf) -> Mipmaps {
+        Mipmaps {
+            dir,
+            sizes: default_sizes(),
+        }
+    }
+
+    /// The image to draw a tile of the given size from: the smallest copy at
+    /// least that size, or the original if no copy is large enough.
+    ///
+    /// Copies are made the first time an image is drawn, all sizes at once
+    /// since the original is read anyway, and remade if it has changed since.
+    pub(crate) fn load(
+        &self,
+        path: &Path,
+        (width, height): Dimensions,
+        resize: Resize,
+    ) -> ImageResult<RgbaImage> {
+        let needed = width.max(height);
+        let Some(size) = self.sizes.iter().copied().filter(|s| *s >= needed).min() else {
+            return load_image(path);
+        };
+        let copy = self.copy_path(path, size);
+        if is_newer(&copy, path) {
+            if let Ok(img) = load_image(&copy) {
+                return Ok(img);
+            }
+        }
+
+        let original = load_image(path)?;
+        let mut chosen = None;
+        for &level in &self.sizes {
+            let shrunk = shrink(&original, level, resize);
+            // Failing to keep a copy only means it is made again next time.
+            let _ = save_copy(&shrunk, &self.copy_path(path, level));
+            if level == size {
+                chosen = Some(shrunk);
+            }
+        }
+        Ok(chosen.unwrap_or(original))
+    }
+
+    fn copy_path(&self, path: &Path, size: u32) -> PathBuf {
+        let hash = fnv1a(path.to_string_lossy().as_bytes());
+        self.dir.join(format!("{hash:016x}-{size}.png"))
+    }
+}
+
+/// The image scaled down so its shorter side is the given size, keeping its
+/// shape, or as it is if already no larger.
+fn shrink(img: &RgbaImage, size: u32, resize: Resize) -> RgbaImage {
+    let (width, height) = img.dimensions();
+    let shorter = width.min(height);
+    if shorter <= size {
+        return img.clone();
+    }
+    let scale = |side: u32| (u64::from(side) * u64::from(size) / u64::from(shorter)) as u32;
+    let (w, h) = (scale(width).max(size), scale(height).max(size));
+    resize.apply(img, w, h)
+}
+
+/// Whether the copy exists and was written after the original last changed.
+fn is_newer(copy: &Path, original: &Path) -> bool {
+    let modified = |path| metadata(path).and_then(|m| m.modified());
+    match (modified(copy), modified(original)) {
+        (Ok(copied), Ok(changed)) => copied >= changed,
+        _ => false,
+    }
+}
+
+/// Write the copy, so it only appears once complete, even if the same image
+/// is being drawn for several tiles at once.
+pub(crate) fn save_copy(img: &RgbaImage, path: &Path) -> IoResult<()> {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    let mut bytes = Cursor::new(Vec::new());
+    img.write_to(&mut bytes, ImageOutputFormat::Png)
+        .map_err(IoError::other)?;
+    if let Some(dir) = path.parent() {
+        create_dir_all(dir)?;
+    }
+    let partial = path.with_extension(format!("{}.partial", NEXT.fetch_add(1, Ordering::Relaxed)));
+    write(&partial, bytes.into_inner())?;
+    rename(&partial, path)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_support::scratch_dir;
+    use crate::ResizeFilter;
+    use image::Rgba;
+    use std::fs::remove_dir_all;
+
+    #[test]
+    fn test_shrinks_to_the_shorter_side() {
+        let img = RgbaImage::from_pixel(400, 200, Rgba([10, 20, 30, 255]));
+
+        assert_eq!(
+            shrink(&img, 64, Resize::new(ResizeFilter::Fast, false)).dimensions(),
+            (128, 64)
+        );
+        assert_eq!(
+            shrink(&img, 64, Resize::new(ResizeFilter::Lanczos3, true)).dimensions(),
+            (128, 64)
+        );
+        assert_eq!(
+            shrink(&img, 256, Resize::new(ResizeFilter::Fast, false)).dimensions(),
+            (400, 200)
+        );
+    }
+
+    #[test]
+    fn test_draws_from_the_closest_copy() {
+        let dir = scratch_dir("mipmap-closest");
+        let original = dir.join("photo.png");
+        RgbaImage::from_pixel(600, 300, Rgba([200, 100, 50, 255]))
+            .save(&original)
+            .unwrap();
+        let mipmaps = Mipmaps::new(dir.join("mipmaps"));
+        let resize = Resize::new(ResizeFilter::Fast, false);
+
+        let small = mipmaps.load(&original, (40, 30), resize).unwrap();
+        assert_eq!(small.dimensions(), (128, 64));
+        assert!(mipmaps.copy_path(&original, 64).exists());
+        assert!(mipmaps.copy_path(&original, 256).exists());
+
+        let large = mipmaps.load(&original, (100, 200), resize).unwrap();
+        assert_eq!(large.dimensions(), (512, 256));
+        let full = mipmaps.load(&original, (300, 300), resize).unwrap();
+        assert_eq!(full.dimensions(), (600, 300));
+        remove_dir_all(dir).unwrap();
+    }
+}
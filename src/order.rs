@@ -0,0 +1,138 @@
+use std::cmp::Ordering;
+
+#[cfg(feature = "cli")]
+use clap::ValueEnum;
+#[cfg(feature = "rand")]
+use rand::seq::SliceRandom;
+#[cfg(feature = "serde")]
+use serde::Deserialize;
+
+use crate::core::Rectangle;
+use crate::seed::Seed;
+
+/// The order cells are visited in when choosing tiles, which decides which
+/// cells get first pick of the best matching tiles.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "cli", derive(ValueEnum))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
+pub enum ProcessingOrder {
+    /// Column by column, top to bottom.
+    #[default]
+    ColumnMajor,
+    /// Row by row, left to right.
+    RowMajor,
+    /// Row by row, alternating left to right and right to left.
+    Serpentine,
+    /// Ring by ring, out from the centre.
+    Spiral,
+    /// Shuffled using the seed.
+    #[cfg(feature = "rand")]
+    Random,
+}
+
+impl ProcessingOrder {
+    /// Sort the cells into this order.
+    #[cfg_attr(not(feature = "rand"), allow(unused_variables))]
+    pub fn arrange(&self, cells: &mut [Rectangle], seed: Seed) {
+        match self {
+            ProcessingOrder::ColumnMajor => cells.sort_by_key(|r| (r.x, r.y)),
+            ProcessingOrder::RowMajor => cells.sort_by_key(|r| (r.y, r.x)),
+            ProcessingOrder::Serpentine => {
+                cells.sort_by_key(|r| (r.y, r.x));
+                for (i, row) in cells.chunk_by_mut(|a, b| a.y == b.y).enumerate() {
+                    if i % 2 == 1 {
+                        row.reverse();
+                    }
+                }
+            }
+            ProcessingOrder::Spiral => {
+                let centre = centre(cells);
+                cells
+                    .sort_by(|a, b| compare_spiral(&spiral_key(a, centre), &spiral_key(b, centre)));
+            }
+            #[cfg(feature = "rand")]
+            ProcessingOrder::Random => cells.shuffle(&mut seed.rng("order")),
+        }
+    }
+}
+
+/// The centre of the area covered by the cells.
+fn centre(cells: &[Rectangle]) -> (f64, f64) {
+    let left = cells.iter().map(|r| r.x).min().unwrap_or(0);
+    let top = cells.iter().map(|r| r.y).min().unwrap_or(0);
+    let right = cells.iter().map(|r| r.x + r.width).max().unwrap_or(0);
+    let bottom = cells.iter().map(|r| r.y + r.height).max().unwrap_or(0);
+    ((left + right) as f64 / 2.0, (top + bottom) as f64 / 2.0)
+}
+
+/// The ring (in cells) around the centre a cell is in, and its angle around
+/// the centre.
+fn spiral_key(r: &Rectangle, (cx, cy): (f64, f64)) -> (i64, f64) {
+    let dx = (r.x as f64 + r.width as f64 / 2.0 - cx) / r.width as f64;
+    let dy = (r.y as f64 + r.height as f64 / 2.0 - cy) / r.height as f64;
+    let ring = dx.abs().max(dy.abs()).round() as i64;
+    (ring, dy.atan2(dx))
+}
+
+fn compare_spiral((ring_a, angle_a): &(i64, f64), (ring_b, angle_b): &(i64, f64)) -> Ordering {
+    ring_a.cmp(ring_b).then(angle_a.total_cmp(angle_b))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::product;
+
+    /// A 3×3 grid of unit cells, in column major order.
+    fn cells() -> Vec<Rectangle> {
+        product(0..3, 0..3)
+            .map(|(x, y)| Rectangle::new(x, y, 1, 1))
+            .collect()
+    }
+
+    fn arranged(order: ProcessingOrder, seed: u64) -> Vec<(u32, u32)> {
+        let mut cells = cells();
+        order.arrange(&mut cells, Seed(seed));
+        cells.iter().map(|r| (r.x, r.y)).collect()
+    }
+
+    /// When each cell of the grid is visited, row by row.
+    fn visits(order: ProcessingOrder) -> [[usize; 3]; 3] {
+        let mut visits = [[0; 3]; 3];
+        for (i, (x, y)) in arranged(order, 0).into_iter().enumerate() {
+            visits[y as usize][x as usize] = i;
+        }
+        visits
+    }
+
+    #[test]
+    fn test_arranges_cells_by_rows_and_columns() {
+        use ProcessingOrder::*;
+        assert_eq!(visits(ColumnMajor), [[0, 3, 6], [1, 4, 7], [2, 5, 8]]);
+        assert_eq!(visits(RowMajor), [[0, 1, 2], [3, 4, 5], [6, 7, 8]]);
+        assert_eq!(visits(Serpentine), [[0, 1, 2], [5, 4, 3], [6, 7, 8]]);
+    }
+
+    #[test]
+    fn test_arranges_cells_out_from_the_centre() {
+        let visits = visits(ProcessingOrder::Spiral);
+
+        assert_eq!(visits[1][1], 0);
+        let mut order: Vec<usize> = visits.iter().flatten().copied().collect();
+        order.sort();
+        assert_eq!(order, (0..9).collect::<Vec<_>>());
+    }
+
+    #[cfg(feature = "rand")]
+    #[test]
+    fn test_shuffles_cells_by_seed() {
+        let first = arranged(ProcessingOrder::Random, 1);
+
+        assert_eq!(first, arranged(ProcessingOrder::Random, 1));
+        assert_ne!(first, arranged(ProcessingOrder::Random, 2));
+        let mut sorted = first.clone();
+        sorted.sort();
+        assert_eq!(sorted, arranged(ProcessingOrder::ColumnMajor, 0));
+    }
+}
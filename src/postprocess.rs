@@ -0,0 +1,168 @@
+use std::str::FromStr;
+
+use image::{imageops, RgbaImage};
+#[cfg(feature = "serde")]
+use serde::Deserialize;
+
+use crate::adjust::luminance;
+
+/// A finishing touch to the whole mosaic once it is drawn, such as for
+/// printing.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(rename_all = "kebab-case", deny_unknown_fields)
+)]
+pub enum PostProcess {
+    /// Sharpen edges by adding back the difference from a blurred copy,
+    /// where it is more than the threshold.
+    UnsharpMask {
+        /// Standard deviation (in pixels) of the blur.
+        sigma: f32,
+        /// Smallest difference (out of 255) that is sharpened.
+        #[cfg_attr(feature = "serde", serde(default))]
+        threshold: i32,
+    },
+    /// Change the contrast by the percentage, reducing it if negative.
+    Contrast(f32),
+    /// Scale how far colors are from gray, 0 giving grayscale.
+    Saturation(f32),
+    /// Darken towards the corners, by up to the share (0 to 1) at them.
+    Vignette(f32),
+}
+
+impl PostProcess {
+    /// Apply this step to the image.
+    pub(crate) fn apply(&self, img: &mut RgbaImage) {
+        match *self {
+            PostProcess::UnsharpMask { sigma, threshold } => {
+                *img = imageops::unsharpen(img, sigma, threshold);
+            }
+            PostProcess::Contrast(percent) => *img = imageops::contrast(img, percent),
+            PostProcess::Saturation(scale) => saturate(img, scale),
+            PostProcess::Vignette(strength) => vignette(img, strength),
+        }
+    }
+}
+
+impl FromStr for PostProcess {
+    type Err = String;
+
+    /// Parse a step written as `unsharp-mask:sigma[:threshold]`,
+    /// `contrast:percent`, `saturation:scale`, or `vignette:strength`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "Invalid step '{s}': expected unsharp-mask:sigma[:threshold], contrast:percent, saturation:scale, or vignette:strength"
+            )
+        };
+        let mut parts = s.split(':').map(str::trim);
+        let name = parts.next().unwrap_or_default();
+        let values = parts
+            .map(|v| v.parse().map_err(|_| invalid()))
+            .collect::<Result<Vec<f32>, _>>()?;
+        Ok(match (name, &values[..]) {
+            ("unsharp-mask", [sigma]) => PostProcess::UnsharpMask {
+                sigma: *sigma,
+                threshold: 0,
+            },
+            ("unsharp-mask", [sigma, threshold]) => PostProcess::UnsharpMask {
+                sigma: *sigma,
+                threshold: threshold.round() as i32,
+            },
+            ("contrast", [percent]) => PostProcess::Contrast(*percent),
+            ("saturation", [scale]) => PostProcess::Saturation(*scale),
+            ("vignette", [strength]) => PostProcess::Vignette(*strength),
+            _ => return Err(invalid()),
+        })
+    }
+}
+
+/// Apply each step to the image in turn.
+pub(crate) fn post_process(img: &mut RgbaImage, steps: &[PostProcess]) {
+    for step in steps {
+        step.apply(img);
+    }
+}
+
+/// Scale how far each pixel is from the gray of the same luminance.
+fn saturate(img: &mut RgbaImage, scale: f32) {
+    for p in img.pixels_mut() {
+        let gray = luminance(p);
+        for c in &mut p.0[..3] {
+            *c = (gray + (f32::from(*c) - gray) * scale)
+                .round()
+                .clamp(0.0, 255.0) as u8;
+        }
+    }
+}
+
+/// Darken each pixel by the strength times the square of its distance from
+/// the centre, as a share of the distance to the corners.
+fn vignette(img: &mut RgbaImage, strength: f32) {
+    let (cx, cy) = (img.width() as f32 / 2.0, img.height() as f32 / 2.0);
+    let corner = (cx * cx + cy * cy).max(f32::EPSILON);
+    for (x, y, p) in img.enumerate_pixels_mut() {
+        let (dx, dy) = (x as f32 + 0.5 - cx, y as f32 + 0.5 - cy);
+        let scale = 1.0 - strength.clamp(0.0, 1.0) * (dx * dx + dy * dy) / corner;
+        for c in &mut p.0[..3] {
+            *c = (f32::from(*c) * scale).round() as u8;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use image::Rgba;
+
+    #[test]
+    fn test_parses_steps() {
+        assert_eq!(
+            "unsharp-mask:1.5:2".parse(),
+            Ok(PostProcess::UnsharpMask {
+                sigma: 1.5,
+                threshold: 2
+            })
+        );
+        assert_eq!("contrast:-10".parse(), Ok(PostProcess::Contrast(-10.0)));
+        assert_eq!("vignette:0.3".parse(), Ok(PostProcess::Vignette(0.3)));
+        assert!("saturation".parse::<PostProcess>().is_err());
+        assert!("blur:2".parse::<PostProcess>().is_err());
+    }
+
+    #[test]
+    fn test_adjusts_colors() {
+        let mut img = RgbaImage::from_pixel(3, 3, Rgba([200, 100, 50, 255]));
+
+        post_process(&mut img, &[PostProcess::Saturation(0.0)]);
+        let [r, g, b, a] = img.get_pixel(1, 1).0;
+        assert_eq!((r, r, a), (g, b, 255));
+
+        let mut img = RgbaImage::from_pixel(10, 10, Rgba([200, 200, 200, 255]));
+        post_process(&mut img, &[PostProcess::Vignette(0.5)]);
+        assert!(img.get_pixel(0, 0).0[0] < 130);
+        assert!(img.get_pixel(5, 5).0[0] > 195);
+    }
+
+    #[test]
+    fn test_sharpens_edges() {
+        let mut img = RgbaImage::from_fn(20, 4, |x, _| {
+            let v = if x < 10 { 100 } else { 150 };
+            Rgba([v, v, v, 255])
+        });
+
+        post_process(
+            &mut img,
+            &[PostProcess::UnsharpMask {
+                sigma: 1.0,
+                threshold: 0,
+            }],
+        );
+
+        assert!(img.get_pixel(9, 2).0[0] < 100);
+        assert!(img.get_pixel(10, 2).0[0] > 150);
+        assert_eq!(img.get_pixel(0, 2).0[0], 100);
+    }
+}
@@ -0,0 +1,278 @@
+use std::fs::{create_dir_all, File};
+use std::io::{BufReader, BufWriter, Error as IoError, ErrorKind, Result as IoResult};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::thread;
+
+use image::ImageFormat::{self, Png};
+use serde::{Deserialize, Serialize};
+
+use crate::analysis::{analyse, AnalysisOptions, ImageInfo, ANALYSIS_VERSION};
+use crate::cancel::CancelToken;
+use crate::resize::ResizeFilter;
+use crate::{build_tile, find_paths, load_image};
+
+/// Name of the index file describing a prepared library.
+const INDEX_FILE: &str = "index.json";
+
+/// Most library images decoded at once while preparing, so that libraries of
+/// large photos don't run out of memory.
+const MAX_DECODING: usize = 8;
+
+/// The contents of a prepared library's index file.
+#[derive(Serialize, Deserialize)]
+struct PreparedIndex {
+    tile_size: u32,
+    analysis_size: u8,
+    /// Missing from indexes prepared before colors were averaged in linear light.
+    #[serde(default)]
+    linear_light: bool,
+    /// Missing from indexes prepared before the filter could be chosen.
+    #[serde(default)]
+    filter: ResizeFilter,
+    /// Version of the way tiles were analysed, missing from indexes prepared
+    /// before it was stored, which were all analysed in version 1.
+    #[serde(default = "first_analysis_version")]
+    analysis_version: u32,
+    tiles: Vec<PreparedTile>,
+}
+
+fn first_analysis_version() -> u32 {
+    1
+}
+
+/// A prepared tile, its original image, and the analysis of the tile.
+#[derive(Serialize, Deserialize)]
+struct PreparedTile {
+    file: PathBuf,
+    source: PathBuf,
+    info: ImageInfo,
+}
+
+/// Crop and scale each library image to a square tile of the given size,
+/// writing the tiles and an index of their analysis to the output directory.
+///
+/// Images are prepared on separate workers, each decoding one image at a
+/// time and dropping it once its tile is written, so that at most a few
+/// originals are held in memory however large the library.
+///
+/// Images that can't be loaded are skipped. Returns the number of tiles.
+///
+/// Fails without writing anything if the output directory holds images but
+/// isn't a prepared library, such as a library given as the output by
+/// mistake.
+pub fn write_prepared_library(
+    lib_paths: &[PathBuf],
+    out_dir: &Path,
+    tile_size: u32,
+    options: &AnalysisOptions,
+) -> IoResult<usize> {
+    refuse_unprepared(out_dir)?;
+    create_dir_all(out_dir)?;
+
+    let sources = Mutex::new(lib_paths.iter().enumerate());
+    let workers = thread::available_parallelism()
+        .map_or(1, |n| n.get())
+        .min(MAX_DECODING);
+    let prepared: IoResult<Vec<Vec<(usize, PreparedTile)>>> = thread::scope(|scope| {
+        let workers: Vec<_> = (0..workers)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut tiles = Vec::new();
+                    loop {
+                        let next = sources.lock().ok().and_then(|mut s| s.next());
+                        let Some((i, source)) = next else {
+                            return Ok(tiles);
+                        };
+                        if let Some(tile) = prepare_tile(i, source, out_dir, tile_size, options)? {
+                            tiles.push((i, tile));
+                        }
+                    }
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .map(|w| {
+                w.join()
+                    .unwrap_or_else(|_| Err(IoError::other("Worker panicked")))
+            })
+            .collect()
+    });
+    let mut tiles: Vec<(usize, PreparedTile)> = prepared?.into_iter().flatten().collect();
+    tiles.sort_unstable_by_key(|(i, _)| *i);
+    let tiles: Vec<PreparedTile> = tiles.into_iter().map(|(_, tile)| tile).collect();
+
+    let count = tiles.len();
+    let index = PreparedIndex {
+        tile_size,
+        analysis_size: options.sample_size,
+        linear_light: options.linear_light,
+        filter: options.filter,
+        analysis_version: ANALYSIS_VERSION,
+        tiles,
+    };
+    let writer = BufWriter::new(File::create(out_dir.join(INDEX_FILE))?);
+    serde_json::to_writer(writer, &index).map_err(IoError::other)?;
+
+    Ok(count)
+}
+
+/// Fail if the directory holds images but no index, so that a library isn't
+/// mistaken for where to write a prepared one and filled with its tiles.
+fn refuse_unprepared(out_dir: &Path) -> IoResult<()> {
+    if !out_dir.is_dir() || out_dir.join(INDEX_FILE).exists() {
+        return Ok(());
+    }
+    let holds_images = find_paths(out_dir)?
+        .iter()
+        .any(|path| path.is_file() && ImageFormat::from_path(path).is_ok());
+    if holds_images {
+        let message = format!(
+            "{} holds images but isn't a prepared library",
+            out_dir.display()
+        );
+        return Err(IoError::new(ErrorKind::InvalidInput, message));
+    }
+    Ok(())
+}
+
+/// Crop and scale the library image to a tile, named by its place in the
+/// library, and write it to the output directory, or nothing if it can't be
+/// loaded.
+fn prepare_tile(
+    i: usize,
+    source: &Path,
+    out_dir: &Path,
+    tile_size: u32,
+    options: &AnalysisOptions,
+) -> IoResult<Option<PreparedTile>> {
+    let Ok(img) = load_image(source) else {
+        return Ok(None);
+    };
+    let tile = build_tile(&img, (tile_size, tile_size), options.resize());
+    drop(img);
+
+    let file = PathBuf::from(format!("{i}.png"));
+    tile.save_with_format(out_dir.join(&file), Png)
+        .map_err(IoError::other)?;
+
+    Ok(Some(PreparedTile {
+        file,
+        source: source.to_owned(),
+        info: analyse(&tile, options),
+    }))
+}
+
+/// Whether the directory holds a prepared library.
+pub(crate) fn is_prepared(dir: &Path) -> bool {
+    dir.join(INDEX_FILE).is_file()
+}
+
+/// Read the tiles of a prepared library and their analysis, if the given
+/// directory holds one.
+///
+/// Tiles are re-analysed if they were prepared with a different analysis size
+/// or way of resizing and averaging colors, or by a build analysing images differently.
+pub fn read_prepared_library(
+    dir: &Path,
+    options: &AnalysisOptions,
+    cancel: &CancelToken,
+) -> IoResult<Option<Vec<(PathBuf, ImageInfo)>>> {
+    if !is_prepared(dir) {
+        return Ok(None);
+    }
+    let index_path = dir.join(INDEX_FILE);
+
+    let reader = BufReader::new(File::open(index_path)?);
+    let index: PreparedIndex =
+        serde_json::from_reader(reader).map_err(|e| IoError::new(ErrorKind::InvalidData, e))?;
+
+    let reanalyse = index.analysis_size != options.sample_size
+        || index.linear_light != options.linear_light
+        || index.filter != options.filter
+        || index.analysis_version != ANALYSIS_VERSION;
+    let mut tiles = Vec::with_capacity(index.tiles.len());
+    for tile in index.tiles {
+        cancel.check()?;
+        let path = dir.join(tile.file);
+        let info = if reanalyse {
+            let img = load_image(&path).map_err(IoError::other)?;
+            analyse(&img, options)
+        } else {
+            tile.info
+        };
+        tiles.push((path, info));
+    }
+
+    Ok(Some(tiles))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_support::scratch_dir;
+    use image::{Rgba, RgbaImage};
+
+    #[test]
+    fn test_prepared_library_round_trips() {
+        let lib_dir = scratch_dir("prepare-lib");
+        let out_dir = scratch_dir("prepare-out");
+        let red = lib_dir.join("red.png");
+        let blue = lib_dir.join("blue.png");
+        RgbaImage::from_pixel(30, 20, Rgba([255, 0, 0, 255]))
+            .save(&red)
+            .unwrap();
+        RgbaImage::from_pixel(20, 30, Rgba([0, 0, 255, 255]))
+            .save(&blue)
+            .unwrap();
+        let missing = lib_dir.join("missing.png");
+        let options = AnalysisOptions::new(Some(2));
+
+        let count = write_prepared_library(&[red, missing, blue], &out_dir, 10, &options).unwrap();
+        let tiles = read_prepared_library(&out_dir, &options, &CancelToken::new())
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(count, 2);
+        assert_eq!(tiles.len(), 2);
+        // Tiles keep the order of the library, whichever worker made them.
+        assert_eq!(tiles[0].0, out_dir.join("0.png"));
+        assert_eq!(tiles[1].0, out_dir.join("2.png"));
+        for (path, info) in tiles {
+            let tile = load_image(&path).unwrap();
+            assert_eq!(tile.dimensions(), (10, 10));
+            assert_eq!(info, analyse(&tile, &options));
+        }
+    }
+
+    #[test]
+    fn test_refuses_to_prepare_into_a_library() {
+        let lib_dir = scratch_dir("prepare-swapped");
+        let red = [lib_dir.join("red.png")];
+        RgbaImage::from_pixel(20, 20, Rgba([255, 0, 0, 255]))
+            .save(&red[0])
+            .unwrap();
+        let out_dir = lib_dir.join("prepared");
+        let options = AnalysisOptions::new(Some(2));
+
+        let swapped = write_prepared_library(&red, &lib_dir, 10, &options);
+        write_prepared_library(&red, &out_dir, 10, &options).unwrap();
+        let again = write_prepared_library(&red, &out_dir, 10, &options);
+
+        assert_eq!(swapped.unwrap_err().kind(), ErrorKind::InvalidInput);
+        assert!(!lib_dir.join(INDEX_FILE).exists());
+        // A prepared library can be prepared again.
+        assert_eq!(again.unwrap(), 1);
+    }
+
+    #[test]
+    fn test_ignores_unprepared_directories() {
+        let dir = scratch_dir("prepare-plain");
+        let options = AnalysisOptions::new(Some(2));
+
+        assert!(read_prepared_library(&dir, &options, &CancelToken::new())
+            .unwrap()
+            .is_none());
+    }
+}
@@ -0,0 +1,409 @@
+use std::fs::{create_dir_all, write};
+use std::io::{Cursor, Error as IoError, ErrorKind, Result as IoResult};
+use std::path::Path;
+use std::str::FromStr;
+
+use image::{imageops, DynamicImage, GenericImageView, ImageOutputFormat, Rgba, RgbaImage};
+
+/// Millimetres in an inch.
+const MM_PER_INCH: f64 = 25.4;
+
+/// PDF points in an inch.
+const POINTS_PER_INCH: f64 = 72.0;
+
+/// Length of each crop mark.
+const CROP_MARK_MM: f64 = 5.0;
+
+/// Gap left between each crop mark and the printed area.
+const CROP_MARK_GAP_MM: f64 = 1.0;
+
+/// Quality of the page images embedded in PDFs.
+const JPEG_QUALITY: u8 = 90;
+
+/// Size of a sheet of paper, in portrait orientation.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PageSize {
+    pub width_mm: f64,
+    pub height_mm: f64,
+}
+
+impl PageSize {
+    pub const A4: PageSize = PageSize::new(210.0, 297.0);
+    pub const A3: PageSize = PageSize::new(297.0, 420.0);
+
+    pub const fn new(width_mm: f64, height_mm: f64) -> PageSize {
+        PageSize {
+            width_mm,
+            height_mm,
+        }
+    }
+}
+
+impl FromStr for PageSize {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "a4" => return Ok(PageSize::A4),
+            "a3" => return Ok(PageSize::A3),
+            _ => {}
+        }
+
+        let sizes: Vec<f64> = s
+            .split('x')
+            .map(|w| w.trim().parse::<f64>())
+            .collect::<Result<_, _>>()
+            .map_err(|e| format!("Invalid page size '{s}': {e}"))?;
+        match sizes[..] {
+            [width, height] if sizes.iter().all(|s| s.is_finite() && *s > 0.0) => {
+                Ok(PageSize::new(width, height))
+            }
+            _ => Err(format!(
+                "Invalid page size '{s}': expected a4, a3, or WIDTHxHEIGHT in millimetres"
+            )),
+        }
+    }
+}
+
+/// How a mosaic is split into pages for printing.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PrintLayout {
+    /// Size of each sheet of paper.
+    pub page: PageSize,
+    /// Whether to turn the pages on their side.
+    pub landscape: bool,
+    /// Mosaic pixels printed per inch.
+    pub dpi: u32,
+    /// Blank border (in millimetres) around the printed area of each page.
+    pub margin_mm: f64,
+    /// How much (in millimetres) neighbouring pages repeat of each other, to
+    /// give room for trimming and gluing.
+    pub overlap_mm: f64,
+    /// Whether to mark the corners of the printed area in the margins.
+    pub crop_marks: bool,
+}
+
+impl Default for PrintLayout {
+    fn default() -> Self {
+        Self {
+            page: PageSize::A4,
+            landscape: false,
+            dpi: 150,
+            margin_mm: 10.0,
+            overlap_mm: 5.0,
+            crop_marks: false,
+        }
+    }
+}
+
+impl PrintLayout {
+    /// The size of the page, turned if landscape.
+    fn page_mm(&self) -> (f64, f64) {
+        let PageSize {
+            width_mm,
+            height_mm,
+        } = self.page;
+        if self.landscape {
+            (height_mm, width_mm)
+        } else {
+            (width_mm, height_mm)
+        }
+    }
+
+    /// The number of pixels printed in the length.
+    fn pixels(&self, mm: f64) -> u32 {
+        (mm / MM_PER_INCH * self.dpi as f64).round().max(0.0) as u32
+    }
+}
+
+/// A page of a printed mosaic, and its place in the grid of pages.
+struct Page {
+    row: u32,
+    column: u32,
+    image: RgbaImage,
+}
+
+/// Split the mosaic into images of whole pages, left to right then top to
+/// bottom, failing if the pages are too small to print a pixel on.
+fn paginate(mosaic: &RgbaImage, layout: &PrintLayout) -> IoResult<Vec<Page>> {
+    let (page_width, page_height) = layout.page_mm();
+    let (width, height) = (layout.pixels(page_width), layout.pixels(page_height));
+    if width == 0 || height == 0 {
+        return Err(IoError::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "Pages of {page_width}x{page_height}mm at {} dpi have no pixels to print",
+                layout.dpi
+            ),
+        ));
+    }
+    let margin = layout
+        .pixels(layout.margin_mm)
+        .min(width.min(height).saturating_sub(1) / 2);
+    let area = (width - 2 * margin, height - 2 * margin);
+    let overlap = layout.pixels(layout.overlap_mm);
+    let step = (
+        area.0 - overlap.min(area.0 - 1),
+        area.1 - overlap.min(area.1 - 1),
+    );
+
+    let columns = pages_needed(mosaic.width(), area.0, step.0);
+    let rows = pages_needed(mosaic.height(), area.1, step.1);
+    let mut pages = Vec::new();
+    for row in 0..rows {
+        for column in 0..columns {
+            let (x, y) = (column * step.0, row * step.1);
+            let part = imageops::crop_imm(
+                mosaic,
+                x,
+                y,
+                area.0.min(mosaic.width() - x),
+                area.1.min(mosaic.height() - y),
+            );
+
+            let mut image = RgbaImage::from_pixel(width, height, Rgba([255, 255, 255, 255]));
+            imageops::overlay(&mut image, &*part, margin as i64, margin as i64);
+            if layout.crop_marks {
+                let (part_width, part_height) = part.dimensions();
+                let corners = (margin, margin, margin + part_width, margin + part_height);
+                draw_crop_marks(&mut image, corners, layout);
+            }
+            pages.push(Page { row, column, image });
+        }
+    }
+    Ok(pages)
+}
+
+/// The number of pages needed to cover the length.
+fn pages_needed(length: u32, area: u32, step: u32) -> u32 {
+    1 + length.saturating_sub(area).div_ceil(step)
+}
+
+/// Draw lines in the margins lining up with the edges of the printed area.
+fn draw_crop_marks(
+    image: &mut RgbaImage,
+    (left, top, right, bottom): (u32, u32, u32, u32),
+    layout: &PrintLayout,
+) {
+    let gap = layout.pixels(CROP_MARK_GAP_MM);
+    let length = layout
+        .pixels(CROP_MARK_MM)
+        .min(left.min(top).saturating_sub(gap));
+    let thickness = layout.pixels(0.25).max(1);
+    if length == 0 {
+        return;
+    }
+
+    for x in [left, right - thickness] {
+        fill(image, x, top - gap - length, thickness, length);
+        fill(image, x, bottom + gap, thickness, length);
+    }
+    for y in [top, bottom - thickness] {
+        fill(image, left - gap - length, y, length, thickness);
+        fill(image, right + gap, y, length, thickness);
+    }
+}
+
+/// Fill the rectangle with black, clipped to the image.
+fn fill(image: &mut RgbaImage, x: u32, y: u32, width: u32, height: u32) {
+    for py in y..(y + height).min(image.height()) {
+        for px in x..(x + width).min(image.width()) {
+            image.put_pixel(px, py, Rgba([0, 0, 0, 255]));
+        }
+    }
+}
+
+/// Write the pages of the mosaic as a PDF if the path ends in `.pdf`, or
+/// otherwise as PNGs named by row and column in the directory at the path.
+///
+/// Returns the number of pages.
+pub fn export_pages(mosaic: &RgbaImage, layout: &PrintLayout, path: &Path) -> IoResult<usize> {
+    let pages = paginate(mosaic, layout)?;
+    if path
+        .extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("pdf"))
+    {
+        write(path, build_pdf(&pages, layout)?)?;
+    } else {
+        create_dir_all(path)?;
+        for page in &pages {
+            let name = format!("page-{}-{}.png", page.row + 1, page.column + 1);
+            page.image.save(path.join(name)).map_err(IoError::other)?;
+        }
+    }
+    Ok(pages.len())
+}
+
+/// Build a PDF document with a page showing each page image.
+fn build_pdf(pages: &[Page], layout: &PrintLayout) -> IoResult<Vec<u8>> {
+    let (page_width, page_height) = layout.page_mm();
+    let points = |mm: f64| mm / MM_PER_INCH * POINTS_PER_INCH;
+    let (width, height) = (points(page_width), points(page_height));
+
+    // Objects are the catalog, the page tree, then a page, its contents,
+    // and its image for each page.
+    let kids: Vec<String> = (0..pages.len())
+        .map(|i| format!("{} 0 R", 3 + 3 * i))
+        .collect();
+    let mut objects: Vec<Vec<u8>> = vec![
+        b"<< /Type /Catalog /Pages 2 0 R >>".to_vec(),
+        format!(
+            "<< /Type /Pages /Kids [{}] /Count {} >>",
+            kids.join(" "),
+            pages.len()
+        )
+        .into_bytes(),
+    ];
+    for (i, page) in pages.iter().enumerate() {
+        let (contents, image) = (4 + 3 * i, 5 + 3 * i);
+        objects.push(
+            format!(
+                "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {width:.2} {height:.2}] \
+                 /Resources << /XObject << /Page {image} 0 R >> >> /Contents {contents} 0 R >>"
+            )
+            .into_bytes(),
+        );
+        objects.push(stream(
+            "",
+            format!("q {width:.2} 0 0 {height:.2} 0 0 cm /Page Do Q").as_bytes(),
+        ));
+
+        let mut jpeg = Cursor::new(Vec::new());
+        DynamicImage::ImageRgba8(page.image.clone())
+            .to_rgb8()
+            .write_to(&mut jpeg, ImageOutputFormat::Jpeg(JPEG_QUALITY))
+            .map_err(IoError::other)?;
+        let dictionary = format!(
+            "/Type /XObject /Subtype /Image /Width {} /Height {} /ColorSpace /DeviceRGB \
+             /BitsPerComponent 8 /Filter /DCTDecode ",
+            page.image.width(),
+            page.image.height()
+        );
+        objects.push(stream(&dictionary, &jpeg.into_inner()));
+    }
+
+    let mut pdf = b"%PDF-1.4\n".to_vec();
+    let mut offsets = Vec::new();
+    for (i, object) in objects.iter().enumerate() {
+        offsets.push(pdf.len());
+        pdf.extend(format!("{} 0 obj\n", i + 1).as_bytes());
+        pdf.extend(object);
+        pdf.extend(b"\nendobj\n");
+    }
+    let xref = pdf.len();
+    pdf.extend(format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1).as_bytes());
+    for offset in offsets {
+        pdf.extend(format!("{offset:010} 00000 n \n").as_bytes());
+    }
+    pdf.extend(
+        format!(
+            "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{xref}\n%%EOF\n",
+            objects.len() + 1
+        )
+        .as_bytes(),
+    );
+    Ok(pdf)
+}
+
+/// A PDF stream object with the data and any other dictionary entries.
+fn stream(dictionary: &str, data: &[u8]) -> Vec<u8> {
+    let mut object = format!("<< {dictionary}/Length {} >>\nstream\n", data.len()).into_bytes();
+    object.extend(data);
+    object.extend(b"\nendstream");
+    object
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::env::temp_dir;
+
+    /// A layout printing ten pixels per millimetre on tiny pages.
+    fn layout(crop_marks: bool) -> PrintLayout {
+        PrintLayout {
+            page: PageSize::new(4.0, 3.0),
+            dpi: 254,
+            margin_mm: 0.5,
+            overlap_mm: 1.0,
+            crop_marks,
+            ..PrintLayout::default()
+        }
+    }
+
+    #[test]
+    fn test_parses_page_sizes() {
+        assert_eq!("A3".parse(), Ok(PageSize::A3));
+        assert_eq!("100x150".parse(), Ok(PageSize::new(100.0, 150.0)));
+        assert!("100".parse::<PageSize>().is_err());
+    }
+
+    #[test]
+    fn test_splits_mosaic_into_overlapping_pages() {
+        let mosaic = RgbaImage::from_fn(100, 50, |x, y| Rgba([x as u8, y as u8, 0, 255]));
+
+        let pages = paginate(&mosaic, &layout(false)).unwrap();
+
+        assert_eq!(pages.len(), 5 * 4);
+        assert_eq!((pages[6].row, pages[6].column), (1, 1));
+        assert_eq!(pages[6].image.dimensions(), (40, 30));
+        assert_eq!(pages[6].image.get_pixel(5, 5), &Rgba([20, 10, 0, 255]));
+        assert_eq!(pages[6].image.get_pixel(0, 0), &Rgba([255, 255, 255, 255]));
+    }
+
+    #[test]
+    fn test_writes_pdf_with_a_page_each() {
+        let mosaic = RgbaImage::from_pixel(100, 20, Rgba([200, 0, 0, 255]));
+        let layout = PrintLayout {
+            page: PageSize::new(10.0, 8.0),
+            margin_mm: 2.0,
+            ..layout(true)
+        };
+        let pages = paginate(&mosaic, &layout).unwrap();
+
+        let pdf = build_pdf(&pages, &layout).unwrap();
+        let text = String::from_utf8_lossy(&pdf);
+
+        assert_eq!(pages.len(), 2);
+        assert_eq!(pages[0].image.get_pixel(20, 5), &Rgba([0, 0, 0, 255]));
+        assert!(text.starts_with("%PDF-1.4"));
+        assert!(text.contains("/Count 2"));
+        let xref: usize = text
+            .rsplit("startxref\n")
+            .next()
+            .unwrap()
+            .lines()
+            .next()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!(pdf[xref..].starts_with(b"xref"));
+    }
+
+    /// Export the mosaic with the layout, expecting it to be refused.
+    fn assert_refused(layout: PrintLayout) {
+        let mosaic = RgbaImage::from_pixel(10, 10, Rgba([200, 0, 0, 255]));
+        let unprinted = temp_dir().join(format!("tiler-print-{}.pdf", std::process::id()));
+
+        let error = export_pages(&mosaic, &layout, &unprinted).unwrap_err();
+
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+        assert!(!unprinted.exists());
+    }
+
+    #[test]
+    fn test_refuses_zero_dpi() {
+        assert_refused(PrintLayout {
+            dpi: 0,
+            ..PrintLayout::default()
+        });
+    }
+
+    #[test]
+    fn test_refuses_pages_smaller_than_a_pixel() {
+        assert_refused(PrintLayout {
+            page: PageSize::new(1.0, 1.0),
+            dpi: 1,
+            ..PrintLayout::default()
+        });
+    }
+}
@@ -0,0 +1,426 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::str::FromStr;
+
+use image::RgbaImage;
+#[cfg(feature = "serde")]
+use serde::Deserialize;
+
+use crate::analysis::ImageInfo;
+use crate::core::{Dimensions, PixelRegion, Rectangle, TileLocation};
+use crate::cost::CostWeights;
+use crate::matching::analyse_cell;
+use crate::strategy::{Penalty, StrategyOptions, TilingStrategy};
+
+/// An area of the target, such as a face, drawn with smaller cells because
+/// poor matches there are most noticeable, and optionally with its own cost
+/// weights and a stronger duplicate penalty than elsewhere.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(deny_unknown_fields))]
+pub struct ProtectedArea {
+    /// Left edge (in target pixels) of the area.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub x: u32,
+    /// Top edge (in target pixels) of the area.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub y: u32,
+    /// Width (in target pixels) of the area.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub width: u32,
+    /// Height (in target pixels) of the area.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub height: u32,
+    /// Corners (in target pixels) of a polygon the area covers instead of
+    /// the rectangle, such as the outline of a figure.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub outline: Vec<[u32; 2]>,
+    /// Number of smaller cells along each side that cells over the area are
+    /// split into.
+    #[cfg_attr(feature = "serde", serde(default = "default_divisions"))]
+    pub divisions: u32,
+    /// How much each cost term counts in the area, or as elsewhere if not
+    /// given.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub cost: Option<CostWeights>,
+    /// Penalty for drawing a tile in the area that is drawn anywhere else in
+    /// the mosaic, or only tiles drawn twice within a cell are avoided if not
+    /// given.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub penalty: Option<Penalty>,
+}
+
+fn default_divisions() -> u32 {
+    2
+}
+
+impl FromStr for ProtectedArea {
+    type Err = String;
+
+    /// Parse an area written as `x,y,width,height`, or as the corners of a
+    /// polygon written as `x,y;x,y;x,y`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid area '{s}': expected x,y,width,height or x,y;x,y;x,y");
+        let numbers = |part: &str| {
+            part.split(',')
+                .map(|v| v.trim().parse().map_err(|_| invalid()))
+                .collect::<Result<Vec<u32>, _>>()
+        };
+        if s.contains(';') {
+            let outline = s
+                .split(';')
+                .map(|point| match numbers(point)?[..] {
+                    [x, y] => Ok([x, y]),
+                    _ => Err(invalid()),
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            if outline.len() < 3 {
+                return Err(invalid());
+            }
+            return Ok(ProtectedArea {
+                outline,
+                ..ProtectedArea::new(0, 0, 0, 0)
+            });
+        }
+        let [x, y, width, height] = numbers(s)?[..] else {
+            return Err(invalid());
+        };
+        Ok(ProtectedArea::new(x, y, width, height))
+    }
+}
+
+impl ProtectedArea {
+    /// The rectangle of the target, split into the default number of
+    /// divisions.
+    pub(crate) fn new(x: u32, y: u32, width: u32, height: u32) -> ProtectedArea {
+        ProtectedArea {
+            x,
+            y,
+            width,
+            height,
+            outline: Vec::new(),
+            divisions: default_divisions(),
+            cost: None,
+            penalty: None,
+        }
+    }
+
+    fn overlaps(&self, region: &PixelRegion) -> bool {
+        if !self.outline.is_empty() {
+            return self.outline_overlaps(region);
+        }
+        let area = Rectangle::new(self.x, self.y, self.width, self.height);
+        region.intersect(&area.into()).is_some()
+    }
+
+    /// Whether the outline covers the middle of the region, or has a corner
+    /// inside it.
+    fn outline_overlaps(&self, region: &PixelRegion) -> bool {
+        let inside = |[x, y]: &[u32; 2]| region.contains((i64::from(*x), i64::from(*y)));
+        if self.outline.iter().any(inside) {
+            return true;
+        }
+        // Count the edges crossed by a ray to the right of the middle.
+        let middle_x = region.x as f64 + f64::from(region.width) / 2.0;
+        let middle_y = region.y as f64 + f64::from(region.height) / 2.0;
+        let corners = self
+            .outline
+            .iter()
+            .map(|[x, y]| (f64::from(*x), f64::from(*y)));
+        let edges = corners.clone().zip(corners.cycle().skip(1));
+        edges
+            .filter(|((x1, y1), (x2, y2))| {
+                (*y1 > middle_y) != (*y2 > middle_y)
+                    && middle_x < x1 + (middle_y - y1) * (x2 - x1) / (y2 - y1)
+            })
+            .count()
+            % 2
+            == 1
+    }
+
+    /// Whether cells over the area are chosen again rather than kept.
+    fn rechooses(&self) -> bool {
+        self.divisions > 1 || self.cost.is_some() || self.penalty.is_some()
+    }
+
+    /// The options to choose tiles in the area with.
+    fn options(&self, options: &StrategyOptions) -> StrategyOptions {
+        StrategyOptions {
+            cost: self.cost.unwrap_or(options.cost),
+            penalty: self.penalty.unwrap_or(options.penalty),
+            ..options.clone()
+        }
+    }
+}
+
+/// Split the cells chosen by another strategy over protected areas into
+/// smaller cells, each given its best matching tile, for finer detail where
+/// it matters most.
+pub struct ProtectedTileStrategy<'a, T> {
+    inner: Box<dyn TilingStrategy<T> + 'a>,
+    analysis: &'a HashMap<&'a T, ImageInfo>,
+    areas: &'a [ProtectedArea],
+    /// The options to choose tiles in each area with.
+    options: Vec<StrategyOptions>,
+}
+
+impl<'a, T: Ord + Hash> ProtectedTileStrategy<'a, T> {
+    pub(crate) fn new(
+        inner: Box<dyn TilingStrategy<T> + 'a>,
+        analysis: &'a HashMap<&'a T, ImageInfo>,
+        options: &'a StrategyOptions,
+        areas: &'a [ProtectedArea],
+    ) -> ProtectedTileStrategy<'a, T> {
+        ProtectedTileStrategy {
+            inner,
+            analysis,
+            areas,
+            options: areas.iter().map(|area| area.options(options)).collect(),
+        }
+    }
+
+    /// The smaller cells and their tiles making up the region, avoiding
+    /// using a tile twice in the region while there are others to use, and
+    /// penalising tiles drawn elsewhere if the area has a penalty.
+    fn split(
+        &self,
+        target: &RgbaImage,
+        region: PixelRegion,
+        area: usize,
+        placed: &[TileLocation<'a, T, PixelRegion>],
+    ) -> Vec<TileLocation<'a, T, PixelRegion>> {
+        let options = &self.options[area];
+        let penalised = self.areas[area].penalty.is_some();
+        let divisions = self.areas[area].divisions;
+        let mut used: Vec<&T> = Vec::new();
+        let mut tiles = Vec::new();
+        for (y, height) in spans(region.y, region.height, divisions) {
+            for (x, width) in spans(region.x, region.width, divisions) {
+                // Cells start inside the target, though may run past it.
+                let rectangle = Rectangle::new(x as u32, y as u32, width, height);
+                let cell = analyse_cell(target, &rectangle, &options.analysis);
+                let weigh = |(tile, info): (&&'a T, &ImageInfo)| {
+                    let duplicates = placed
+                        .iter()
+                        .chain(&tiles)
+                        .filter(|(t, _)| penalised && t == tile)
+                        .map(|(_, other)| {
+                            let offset = [x.abs_diff(other.x), y.abs_diff(other.y)]
+                                .map(|d| d.try_into().unwrap_or(u32::MAX));
+                            options.duplicate_cost(offset.into(), (width, height))
+                        })
+                        .fold(0, i64::saturating_add);
+                    let weight = options
+                        .weight(info, &cell, &rectangle)
+                        .saturating_add(duplicates);
+                    (*tile, weight)
+                };
+                let best = self
+                    .analysis
+                    .iter()
+                    .filter(|(tile, _)| !used.contains(tile))
+                    .map(weigh)
+                    .min_by(|a, b| options.compare_weights(a, b))
+                    .or_else(|| {
+                        self.analysis
+                            .iter()
+                            .map(weigh)
+                            .min_by(|a, b| options.compare_weights(a, b))
+                    });
+                if let Some((tile, _)) = best {
+                    used.push(tile);
+                    tiles.push((
+                        tile,
+                        PixelRegion {
+                            x,
+                            y,
+                            width,
+                            height,
+                        },
+                    ));
+                }
+            }
+        }
+        tiles
+    }
+
+    /// The area, if any, the cells over the region are chosen again for: the
+    /// one splitting it most finely.
+    fn area_over(&self, region: &PixelRegion) -> Option<usize> {
+        self.areas
+            .iter()
+            .enumerate()
+            .filter(|(_, area)| area.rechooses() && area.overlaps(region))
+            .max_by_key(|(i, area)| (area.divisions, std::cmp::Reverse(*i)))
+            .map(|(i, _)| i)
+    }
+}
+
+/// The starts and lengths of up to `divisions` spans covering a length, all
+/// the same but the last, which may be shorter.
+fn spans(start: i64, length: u32, divisions: u32) -> Vec<(i64, u32)> {
+    let size = length.div_ceil(divisions.max(1)).max(1);
+    (0..length)
+        .step_by(size as usize)
+        .map(|offset| (start + i64::from(offset), size.min(length - offset)))
+        .collect()
+}
+
+impl<T: Ord + Hash> TilingStrategy<T> for ProtectedTileStrategy<'_, T> {
+    fn choose(
+        &self,
+        target: &RgbaImage,
+        cell_size: &Dimensions,
+    ) -> Vec<TileLocation<'_, T, PixelRegion>> {
+        let chosen: Vec<_> = self
+            .inner
+            .choose(target, cell_size)
+            .into_iter()
+            .map(|(tile, region)| (tile, region, self.area_over(&region)))
+            .collect();
+        // Tiles over areas are penalised for duplicates of those kept.
+        let mut placed: Vec<_> = chosen
+            .iter()
+            .filter(|(_, _, area)| area.is_none())
+            .map(|(tile, region, _)| (*tile, *region))
+            .collect();
+        let mut tiles = Vec::with_capacity(chosen.len());
+        for (tile, region, area) in chosen {
+            match area {
+                Some(area) => {
+                    let split = self.split(target, region, area, &placed);
+                    placed.extend(split.iter().copied());
+                    tiles.extend(split);
+                }
+                None => tiles.push((tile, region)),
+            }
+        }
+        tiles
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::analysis::{analyse, AnalysisOptions};
+    use crate::strategy::build_strategy;
+    use image::Rgba;
+
+    #[test]
+    fn test_splits_spans_evenly() {
+        assert_eq!(spans(10, 20, 2), vec![(10, 10), (20, 10)]);
+        assert_eq!(spans(0, 20, 3), vec![(0, 7), (7, 7), (14, 6)]);
+        assert_eq!(spans(0, 2, 4), vec![(0, 1), (1, 1)]);
+        assert_eq!(spans(0, 5, 0), vec![(0, 5)]);
+    }
+
+    #[test]
+    fn test_parses_areas() {
+        let area: ProtectedArea = "10, 20,30,40".parse().unwrap();
+        assert_eq!((area.x, area.y, area.width, area.height), (10, 20, 30, 40));
+        assert_eq!(area.divisions, 2);
+        assert!("10,20,30".parse::<ProtectedArea>().is_err());
+
+        let outline: ProtectedArea = "0,0; 40,0;0,40".parse().unwrap();
+        assert_eq!(outline.outline, vec![[0, 0], [40, 0], [0, 40]]);
+        assert!("0,0;40,0".parse::<ProtectedArea>().is_err());
+    }
+
+    #[test]
+    fn test_overlaps_outlines() {
+        let area: ProtectedArea = "0,0;40,0;0,40".parse().unwrap();
+        let cell = |x, y| PixelRegion::new(x, y, 10, 10);
+
+        assert!(area.overlaps(&cell(0, 0)));
+        assert!(area.overlaps(&cell(10, 10)));
+        assert!(!area.overlaps(&cell(30, 30)));
+        assert!(!area.overlaps(&cell(50, 0)));
+    }
+
+    #[test]
+    fn test_penalises_tiles_drawn_outside_areas() {
+        let (red, dark_red) = ("red".to_string(), "dark red".to_string());
+        let options = StrategyOptions {
+            analysis: AnalysisOptions::new(Some(1)),
+            ..StrategyOptions::default()
+        };
+        let solid = |c| analyse(&RgbaImage::from_pixel(20, 20, Rgba(c)), &options.analysis);
+        let analysis = HashMap::from([
+            (&red, solid([255, 0, 0, 255])),
+            (&dark_red, solid([200, 0, 0, 255])),
+        ]);
+        let target = RgbaImage::from_pixel(60, 20, Rgba([255, 0, 0, 255]));
+        let tiles = |penalty| -> Vec<String> {
+            let areas = [ProtectedArea {
+                divisions: 1,
+                penalty,
+                ..ProtectedArea::from_str("40,0,20,20").unwrap()
+            }];
+            let inner = build_strategy("independent", &analysis, &options).unwrap();
+            let strategy = ProtectedTileStrategy::new(inner, &analysis, &options, &areas);
+            let tiles = strategy.choose(&target, &(20, 20));
+            tiles.into_iter().map(|(t, _)| t.clone()).collect()
+        };
+
+        assert_eq!(tiles(None), ["red", "red", "red"]);
+        assert_eq!(tiles(Some(Penalty::default())), ["red", "red", "dark red"]);
+    }
+
+    #[test]
+    fn test_splits_cells_over_protected_areas() {
+        let (red, dark_red, blue) = (
+            "red".to_string(),
+            "dark red".to_string(),
+            "blue".to_string(),
+        );
+        let options = StrategyOptions {
+            analysis: AnalysisOptions::new(Some(1)),
+            ..StrategyOptions::default()
+        };
+        let solid = |c| analyse(&RgbaImage::from_pixel(20, 20, Rgba(c)), &options.analysis);
+        let analysis = HashMap::from([
+            (&red, solid([255, 0, 0, 255])),
+            (&dark_red, solid([200, 0, 0, 255])),
+            (&blue, solid([0, 0, 255, 255])),
+        ]);
+        // The second cell is red on the left and blue on the right.
+        let target = RgbaImage::from_fn(40, 20, |x, _| {
+            Rgba(if x < 30 {
+                [255, 0, 0, 255]
+            } else {
+                [0, 0, 255, 255]
+            })
+        });
+        let areas = [ProtectedArea {
+            x: 25,
+            y: 5,
+            width: 10,
+            height: 10,
+            outline: Vec::new(),
+            divisions: 2,
+            cost: None,
+            penalty: None,
+        }];
+
+        let inner = build_strategy("independent", &analysis, &options).unwrap();
+        let strategy = ProtectedTileStrategy::new(inner, &analysis, &options, &areas);
+        let tiles: Vec<(&String, (i64, i64, u32))> = strategy
+            .choose(&target, &(20, 20))
+            .into_iter()
+            .map(|(t, r)| (t, (r.x, r.y, r.width)))
+            .collect();
+
+        assert_eq!(
+            tiles,
+            vec![
+                (&red, (0, 0, 20)),
+                (&red, (20, 0, 10)),
+                (&blue, (30, 0, 10)),
+                // Red is used, so dark red is the best left.
+                (&dark_red, (20, 10, 10)),
+                // All are used, so the best of all is chosen again.
+                (&blue, (30, 10, 10)),
+            ]
+        );
+    }
+}
@@ -0,0 +1,122 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::hash::Hash;
+
+use image::RgbaImage;
+
+use crate::analysis::ImageInfo;
+use crate::core::{Dimensions, PixelRegion, TileLocation};
+use crate::matching::{grid, CellAnalyser};
+use crate::strategy::{fixed_weight, StrategyOptions, TilingStrategy};
+use crate::summary::Summary;
+
+/// Choose the best tile for each cell independently, but only compare in
+/// full the tiles whose color summaries are closest to the cell's.
+///
+/// Trades a little accuracy for speed on large libraries.
+pub struct PrunedTileStrategy<'a, T> {
+    options: &'a StrategyOptions,
+    analysis: &'a HashMap<&'a T, ImageInfo>,
+    summaries: Vec<(&'a T, Cow<'a, Summary>)>,
+}
+
+impl<'a, T> PrunedTileStrategy<'a, T> {
+    pub fn new(
+        analysis: &'a HashMap<&'a T, ImageInfo>,
+        options: &'a StrategyOptions,
+    ) -> PrunedTileStrategy<'a, T> {
+        let summaries = analysis
+            .iter()
+            .map(|(tile, info)| (*tile, info.summary()))
+            .collect();
+        PrunedTileStrategy {
+            options,
+            analysis,
+            summaries,
+        }
+    }
+}
+
+impl<T: Ord + Hash> TilingStrategy<T> for PrunedTileStrategy<'_, T> {
+    fn choose(
+        &self,
+        target: &RgbaImage,
+        cell_size: &Dimensions,
+    ) -> Vec<TileLocation<'_, T, PixelRegion>> {
+        let analyser = CellAnalyser::new(target, *cell_size, &self.options.analysis);
+        grid(target, cell_size)
+            .iter()
+            .take_while(|_| !self.options.cancel.is_cancelled())
+            .map(|r| {
+                let cell = analyser.analyse(r);
+                let summary = cell.summary();
+
+                // Pinned tiles are always kept, and excluded ones kept last.
+                let mut candidates: Vec<(&T, i64)> = self
+                    .summaries
+                    .iter()
+                    .map(|(tile, s)| {
+                        let fixed = fixed_weight(&self.analysis[tile], r);
+                        (*tile, fixed.unwrap_or_else(|| s.distance(&summary)))
+                    })
+                    .collect();
+                let keep = self.options.candidates.clamp(1, candidates.len().max(1));
+                if keep < candidates.len() {
+                    candidates.select_nth_unstable_by(keep - 1, |a, b| {
+                        self.options.compare_weights(a, b)
+                    });
+                    candidates.truncate(keep);
+                }
+
+                let weighted = candidates.into_iter().map(|(tile, _)| {
+                    let info = &self.analysis[tile];
+                    (tile, self.options.weight(info, &cell, r))
+                });
+                let (best_tile, _) = self.options.pick(weighted, r).unwrap();
+                (best_tile, PixelRegion::from(r))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::analysis::{analyse, AnalysisOptions};
+    use image::Rgba;
+
+    #[test]
+    fn test_chooses_best_of_the_closest_summaries() {
+        let names = ["red", "dark red", "blue", "green"].map(String::from);
+        let options = StrategyOptions {
+            analysis: AnalysisOptions::new(Some(2)),
+            candidates: 2,
+            ..StrategyOptions::default()
+        };
+        let colors = [[255, 0, 0], [200, 0, 0], [0, 0, 255], [0, 255, 0]];
+        let analysis: HashMap<&String, ImageInfo> = names
+            .iter()
+            .zip(colors)
+            .map(|(name, [r, g, b])| {
+                let img = RgbaImage::from_pixel(20, 20, Rgba([r, g, b, 255]));
+                (name, analyse(&img, &options.analysis))
+            })
+            .collect();
+        let target = RgbaImage::from_fn(40, 20, |x, _| {
+            if x < 20 {
+                Rgba([240, 0, 0, 255])
+            } else {
+                Rgba([0, 0, 230, 255])
+            }
+        });
+
+        let strategy = PrunedTileStrategy::new(&analysis, &options);
+        let tiles: Vec<&String> = strategy
+            .choose(&target, &(20, 20))
+            .iter()
+            .map(|(t, _)| *t)
+            .collect();
+
+        assert_eq!(tiles, vec![&names[0], &names[2]]);
+    }
+}
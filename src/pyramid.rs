@@ -0,0 +1,230 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+
+use crate::analysis::{ChannelWeights, ImageInfo};
+use crate::core::Rectangle;
+use crate::strategy::{fixed_weight, StrategyOptions};
+
+/// Number of blocks across each coarser level of a pyramid, coarsest first,
+/// of those that divide the samples evenly.
+const LEVELS: [usize; 2] = [1, 4];
+
+/// Cost a tile's bound may be above the best so far and still be compared
+/// in full, allowing for the rounding of each term of the cost.
+const SLACK: i64 = 2;
+
+/// The samples of an image summed over blocks of a few sizes, coarsest
+/// first, so that tiles which can't match a cell are ruled out before all
+/// of their samples are compared.
+pub(crate) struct Pyramid {
+    levels: Vec<Level>,
+}
+
+struct Level {
+    /// Sums of the red, green, and blue channels of each block.
+    blocks: Vec<[u32; 3]>,
+    /// Number of samples in each block.
+    samples: u32,
+}
+
+impl Pyramid {
+    /// The coarser levels of the image's samples.
+    pub(crate) fn of(info: &ImageInfo) -> Pyramid {
+        let samples = info.samples();
+        let side = (samples.len() / 3).isqrt();
+        let levels = LEVELS
+            .iter()
+            .filter(|blocks| **blocks < side && side.is_multiple_of(**blocks))
+            .map(|blocks| {
+                let span = side / blocks;
+                let mut sums = vec![[0u32; 3]; blocks * blocks];
+                for (i, color) in samples.chunks_exact(3).enumerate() {
+                    let (x, y) = (i % side / span, i / side / span);
+                    for (sum, v) in sums[y * blocks + x].iter_mut().zip(color) {
+                        *sum += u32::from(*v);
+                    }
+                }
+                Level {
+                    blocks: sums,
+                    samples: (span * span) as u32,
+                }
+            })
+            .collect();
+        Pyramid { levels }
+    }
+
+    /// The most the difference between the images, as given by `diff_sum`,
+    /// can be said to be at least from each level, coarsest first.
+    ///
+    /// The squared differences of the samples in a block add up to at least
+    /// the squared difference of their sums over the number of samples, so
+    /// each bound is never more than the difference in full.
+    fn bounds<'p>(
+        &'p self,
+        other: &'p Pyramid,
+        weights: &'p ChannelWeights,
+    ) -> impl Iterator<Item = i64> + 'p {
+        self.levels.iter().zip(&other.levels).map(move |(a, b)| {
+            let mut channels = [0.0; 3];
+            for (x, y) in a.blocks.iter().zip(&b.blocks) {
+                for c in 0..3 {
+                    channels[c] += f64::from(x[c].abs_diff(y[c])).powi(2);
+                }
+            }
+            let n = f64::from(a.samples);
+            let bound = (weights.red * channels[0]
+                + weights.green * channels[1]
+                + weights.blue * channels[2])
+                / n;
+            // Round down past any float error, to stay a bound.
+            (bound - 1.0).max(0.0) as i64
+        })
+    }
+}
+
+/// The library with the pyramid of each image, for finding the best tile for
+/// each cell without comparing every tile in full.
+pub(crate) struct PyramidLibrary<'a, T> {
+    tiles: Vec<(&'a T, &'a ImageInfo, Pyramid)>,
+}
+
+impl<'a, T: Ord + Hash> PyramidLibrary<'a, T> {
+    pub(crate) fn new(analysis: &'a HashMap<&'a T, ImageInfo>) -> PyramidLibrary<'a, T> {
+        let tiles = analysis
+            .iter()
+            .map(|(tile, info)| (*tile, info, Pyramid::of(info)))
+            .collect();
+        PyramidLibrary { tiles }
+    }
+
+    /// The tile with the lowest weight for the cell, the same as comparing
+    /// every tile in full, but comparing tiles in the order of their coarsest
+    /// bounds and stopping once the bounds pass the best weight so far.
+    ///
+    /// Bounds only rule tiles out if the cost rises with the difference and
+    /// no next best tiles are picked, otherwise every tile is compared.
+    pub(crate) fn best(
+        &self,
+        cell: &ImageInfo,
+        r: &Rectangle,
+        options: &StrategyOptions,
+    ) -> Option<(&'a T, i64)> {
+        let weighted =
+            |(tile, info, _): &(&'a T, &ImageInfo, Pyramid)| (*tile, options.weight(info, cell, r));
+        let cost = options.cost;
+        if !options.rules_out_tiles() {
+            return options.pick(self.tiles.iter().map(weighted), r);
+        }
+
+        let weights = &options.analysis.channel_weights;
+        let pyramid = Pyramid::of(cell);
+        let bound = |info: &ImageInfo, difference: i64| cost.of_match(info, difference);
+        let mut ordered: Vec<(i64, usize)> = self
+            .tiles
+            .iter()
+            .enumerate()
+            .map(|(i, (_, info, tiles))| match fixed_weight(info, r) {
+                // Pinned tiles are compared first, and excluded ones last.
+                Some(weight) => (weight, i),
+                None => {
+                    let coarsest = tiles.bounds(&pyramid, weights).next().unwrap_or(0);
+                    (bound(info, coarsest), i)
+                }
+            })
+            .collect();
+        ordered.sort_unstable();
+
+        let mut best: Option<(&'a T, i64)> = None;
+        for (coarsest, i) in ordered {
+            let beaten = |b: i64| best.is_some_and(|(_, w)| b > w.saturating_add(SLACK));
+            if beaten(coarsest) {
+                break;
+            }
+            let (tile, info, tiles) = &self.tiles[i];
+            if fixed_weight(info, r).is_none()
+                && tiles
+                    .bounds(&pyramid, weights)
+                    .skip(1)
+                    .any(|difference| beaten(bound(info, difference)))
+            {
+                continue;
+            }
+            if let Some(weight) = options.weight_within(info, cell, r, best.map(|(_, w)| w)) {
+                options.keep_best(&mut best, (tile, weight));
+            }
+        }
+        best
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::analysis::{analyse, AnalysisOptions};
+    use image::{Rgba, RgbaImage};
+    use proptest::prelude::*;
+
+    fn info(seed: u32, options: &AnalysisOptions) -> ImageInfo {
+        let img = RgbaImage::from_fn(16, 16, |x, y| {
+            let v = |k: u32| ((x * k + y * (k + 3) + seed * 37) % 256) as u8;
+            Rgba([v(7), v(11), v(13), 255])
+        });
+        analyse(&img, options)
+    }
+
+    proptest! {
+        #[test]
+        fn test_pyramid_bounds_never_exceed_the_difference(
+            a in 0u32..1000,
+            b in 0u32..1000,
+            size in prop::sample::select(vec![4u8, 8, 16, 20]),
+        ) {
+            let options = AnalysisOptions::new(Some(size));
+            let (a, b) = (info(a, &options), info(b, &options));
+            let weights = ChannelWeights::LUMINANCE;
+
+            let difference = a.diff_sum(&b, &weights);
+            let (pa, pb) = (Pyramid::of(&a), Pyramid::of(&b));
+            for bound in pa.bounds(&pb, &weights) {
+                prop_assert!(bound <= difference);
+            }
+        }
+    }
+
+    #[test]
+    fn test_builds_levels_that_divide_the_samples() {
+        let levels = |size: u8| Pyramid::of(&info(0, &AnalysisOptions::new(Some(size)))).levels;
+
+        let sizes: Vec<(usize, u32)> = levels(8)
+            .iter()
+            .map(|l| (l.blocks.len(), l.samples))
+            .collect();
+        assert_eq!(sizes, vec![(1, 64), (16, 4)]);
+        assert_eq!(levels(6).len(), 1);
+        assert!(levels(1).is_empty());
+    }
+
+    #[test]
+    fn test_picks_the_same_tile_as_comparing_in_full() {
+        let options = StrategyOptions::default();
+        let paths: Vec<String> = (0..60).map(|i| format!("{i}.jpg")).collect();
+        let analysis: HashMap<&String, ImageInfo> = paths
+            .iter()
+            .enumerate()
+            .map(|(i, path)| (path, info(i as u32 * 7, &options.analysis)))
+            .collect();
+        let library = PyramidLibrary::new(&analysis);
+        let r = Rectangle::new(0, 0, 10, 10);
+
+        for seed in [3, 500, 901] {
+            let cell = info(seed, &options.analysis);
+            let everything = analysis
+                .iter()
+                .map(|(tile, info)| (*tile, options.match_cost(info, &cell)));
+            assert_eq!(
+                library.best(&cell, &r, &options),
+                options.pick(everything, &r)
+            );
+        }
+    }
+}
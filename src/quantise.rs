@@ -0,0 +1,130 @@
+use std::collections::HashMap;
+
+use image::RgbaImage;
+
+/// Most pixels sampled to choose the palette from.
+const MAX_SAMPLES: usize = 1 << 16;
+
+/// The image with each pixel's color replaced by the closest of a palette of
+/// at most the given number of colors, chosen by median cut, keeping alpha.
+pub(crate) fn quantise(img: &RgbaImage, colors: u16) -> RgbaImage {
+    let palette = palette(img, usize::from(colors.max(1)));
+    let mut closest: HashMap<[u8; 3], [u8; 3]> = HashMap::new();
+    let mut quantised = img.clone();
+    for p in quantised.pixels_mut() {
+        let color = [p[0], p[1], p[2]];
+        let q = *closest
+            .entry(color)
+            .or_insert_with(|| nearest(&palette, color));
+        p.0[..3].copy_from_slice(&q);
+    }
+    quantised
+}
+
+/// Choose a palette for the image by median cut: repeatedly split the box
+/// of sampled colors with the widest range of a channel at the median of
+/// that channel, then take the mean color of each box.
+fn palette(img: &RgbaImage, colors: usize) -> Vec<[u8; 3]> {
+    let step = (img.pixels().len() / MAX_SAMPLES).max(1);
+    let samples: Vec<[u8; 3]> = img
+        .pixels()
+        .step_by(step)
+        .map(|p| [p[0], p[1], p[2]])
+        .collect();
+    let mut boxes = vec![samples];
+    while boxes.len() < colors {
+        let widest = boxes
+            .iter()
+            .enumerate()
+            .map(|(i, colors)| (i, widest_channel(colors)))
+            .max_by_key(|(_, (_, range))| *range);
+        let Some((i, (channel, range))) = widest else {
+            break;
+        };
+        if range == 0 {
+            break;
+        }
+        let mut lower = boxes.swap_remove(i);
+        lower.sort_unstable_by_key(|c| c[channel]);
+        // Split between values, so that equal colors stay together.
+        let median = lower[lower.len() / 2][channel];
+        let mut at = lower.partition_point(|c| c[channel] < median);
+        if at == 0 {
+            at = lower.partition_point(|c| c[channel] <= median);
+        }
+        let upper = lower.split_off(at);
+        boxes.extend([lower, upper]);
+    }
+    boxes
+        .iter()
+        .filter(|colors| !colors.is_empty())
+        .map(|colors| mean(colors))
+        .collect()
+}
+
+/// The channel whose values range most widely over the colors, and that
+/// range.
+fn widest_channel(colors: &[[u8; 3]]) -> (usize, u8) {
+    (0..3)
+        .map(|c| {
+            let (low, high) = colors.iter().fold((u8::MAX, u8::MIN), |(l, h), color| {
+                (l.min(color[c]), h.max(color[c]))
+            });
+            (c, high.saturating_sub(low))
+        })
+        .max_by_key(|(_, range)| *range)
+        .unwrap_or_default()
+}
+
+fn mean(colors: &[[u8; 3]]) -> [u8; 3] {
+    let count = colors.len().max(1) as u64;
+    [0, 1, 2].map(|c| {
+        let sum: u64 = colors.iter().map(|color| u64::from(color[c])).sum();
+        ((sum + count / 2) / count) as u8
+    })
+}
+
+fn nearest(palette: &[[u8; 3]], color: [u8; 3]) -> [u8; 3] {
+    let distance = |p: &[u8; 3]| -> u32 {
+        p.iter()
+            .zip(color)
+            .map(|(a, b)| u32::from(a.abs_diff(b)).pow(2))
+            .sum()
+    };
+    palette
+        .iter()
+        .copied()
+        .min_by_key(distance)
+        .unwrap_or(color)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use image::Rgba;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_reduces_colors() {
+        let gradient =
+            RgbaImage::from_fn(64, 64, |x, y| Rgba([x as u8 * 4, y as u8 * 4, 100, 200]));
+
+        let quantised = quantise(&gradient, 4);
+
+        let colors: HashSet<[u8; 4]> = quantised.pixels().map(|p| p.0).collect();
+        assert_eq!(colors.len(), 4);
+        assert!(colors.iter().all(|c| c[2] == 100 && c[3] == 200));
+    }
+
+    #[test]
+    fn test_keeps_images_with_few_colors() {
+        let colors = [
+            Rgba([255, 0, 0, 255]),
+            Rgba([0, 0, 255, 255]),
+            Rgba([20, 20, 20, 255]),
+        ];
+        let img = RgbaImage::from_fn(9, 9, |x, _| colors[x as usize % 3]);
+
+        assert_eq!(quantise(&img, 8), img);
+    }
+}
@@ -0,0 +1,288 @@
+use std::collections::HashSet;
+use std::fs::read;
+use std::io::{Cursor, Error as IoError, ErrorKind};
+use std::path::Path;
+
+use image::io::Reader;
+use image::{ImageError, ImageResult, RgbaImage};
+
+use crate::color;
+
+/// Extensions of the camera RAW files whose embedded previews are drawn.
+const RAW_EXTENSIONS: [&str; 3] = ["cr2", "nef", "arw"];
+
+/// Most image file directories read from a RAW file, in case they loop.
+const MAX_DIRECTORIES: usize = 32;
+
+/// TIFF tags locating embedded images.
+const STRIP_OFFSETS: u16 = 0x0111;
+const STRIP_BYTE_COUNTS: u16 = 0x0117;
+const SUB_IFDS: u16 = 0x014a;
+const JPEG_OFFSET: u16 = 0x0201;
+const JPEG_LENGTH: u16 = 0x0202;
+
+/// Whether the file is a camera RAW file, by its extension.
+pub(crate) fn is_raw(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| RAW_EXTENSIONS.contains(&e.to_ascii_lowercase().as_str()))
+}
+
+/// Load the largest JPEG preview embedded in a camera RAW file, as sRGB.
+pub(crate) fn load_preview(path: &Path) -> ImageResult<RgbaImage> {
+    let bytes = read(path)?;
+    let Some(jpeg) = preview(&bytes) else {
+        let message = format!("{} has no embedded preview", path.display());
+        return Err(ImageError::IoError(IoError::new(
+            ErrorKind::InvalidData,
+            message,
+        )));
+    };
+    color::decode(Reader::new(Cursor::new(jpeg))).map_err(|e| color::name_refused(path, e))
+}
+
+/// The largest baseline or progressive JPEG embedded in the TIFF structure
+/// RAW files share, leaving out the lossless JPEG some hold sensor data in.
+pub(crate) fn preview(bytes: &[u8]) -> Option<&[u8]> {
+    let tiff = Tiff::new(bytes)?;
+    let mut pending = vec![tiff.u32(4)?];
+    let mut visited = HashSet::new();
+    let mut previews = Vec::new();
+    while let Some(offset) = pending.pop() {
+        if offset == 0 || visited.len() >= MAX_DIRECTORIES || !visited.insert(offset) {
+            continue;
+        }
+        let Some(entries) = tiff.directory(offset) else {
+            continue;
+        };
+        let value = |tag| {
+            entries
+                .iter()
+                .find(|e| e.tag == tag)
+                .map(|e| e.values(&tiff))
+        };
+        let single = |tag| match value(tag)?.as_slice() {
+            [value] => Some(*value),
+            _ => None,
+        };
+        for (start, length) in [
+            (JPEG_OFFSET, JPEG_LENGTH),
+            (STRIP_OFFSETS, STRIP_BYTE_COUNTS),
+        ] {
+            if let (Some(start), Some(length)) = (single(start), single(length)) {
+                let (start, length) = (start as usize, length as usize);
+                previews.extend(bytes.get(start..start.saturating_add(length)));
+            }
+        }
+        pending.extend(value(SUB_IFDS).unwrap_or_default());
+        pending.extend(tiff.next_directory(offset));
+    }
+    previews
+        .into_iter()
+        .filter(|jpeg| is_drawable_jpeg(jpeg))
+        .max_by_key(|jpeg| jpeg.len())
+}
+
+/// Whether the data is a JPEG coded in a way common decoders can draw, by
+/// the frame marker of its first frame.
+fn is_drawable_jpeg(data: &[u8]) -> bool {
+    if !data.starts_with(&[0xff, 0xd8]) {
+        return false;
+    }
+    let mut i = 2;
+    while let (Some(0xff), Some(&marker)) = (data.get(i), data.get(i + 1)) {
+        match marker {
+            // Baseline, extended, and progressive frames.
+            0xc0..=0xc2 => return true,
+            // Any other frame, such as lossless.
+            0xc3 | 0xc5..=0xc7 | 0xc9..=0xcb | 0xcd..=0xcf => return false,
+            _ => {}
+        }
+        let Some(length) = data.get(i + 2..i + 4) else {
+            return false;
+        };
+        i += 2 + usize::from(u16::from_be_bytes([length[0], length[1]]));
+    }
+    false
+}
+
+/// The bytes of a TIFF structure and their byte order.
+struct Tiff<'a> {
+    bytes: &'a [u8],
+    little_endian: bool,
+}
+
+/// An entry of an image file directory.
+struct Entry {
+    tag: u16,
+    kind: u16,
+    count: u32,
+    /// Offset of the value, or of the offset of the values if they don't fit.
+    at: usize,
+}
+
+impl Entry {
+    /// The entry's values, if they are unsigned integers.
+    fn values(&self, tiff: &Tiff) -> Vec<u32> {
+        let size = match self.kind {
+            3 => 2,
+            4 | 13 => 4,
+            _ => return Vec::new(),
+        };
+        let count = self.count as usize;
+        let start = if size * count > 4 {
+            tiff.u32(self.at).map_or(usize::MAX, |o| o as usize)
+        } else {
+            self.at
+        };
+        (0..count.min(tiff.bytes.len() / size))
+            .map_while(|i| {
+                let at = start.checked_add(i * size)?;
+                match size {
+                    2 => tiff.u16(at).map(u32::from),
+                    _ => tiff.u32(at),
+                }
+            })
+            .collect()
+    }
+}
+
+impl<'a> Tiff<'a> {
+    fn new(bytes: &'a [u8]) -> Option<Tiff<'a>> {
+        let little_endian = match bytes.get(0..2)? {
+            b"II" => true,
+            b"MM" => false,
+            _ => return None,
+        };
+        let tiff = Tiff {
+            bytes,
+            little_endian,
+        };
+        (tiff.u16(2)? == 42).then_some(tiff)
+    }
+
+    fn u16(&self, at: usize) -> Option<u16> {
+        let b: [u8; 2] = self.bytes.get(at..at.checked_add(2)?)?.try_into().ok()?;
+        Some(if self.little_endian {
+            u16::from_le_bytes(b)
+        } else {
+            u16::from_be_bytes(b)
+        })
+    }
+
+    fn u32(&self, at: usize) -> Option<u32> {
+        let b: [u8; 4] = self.bytes.get(at..at.checked_add(4)?)?.try_into().ok()?;
+        Some(if self.little_endian {
+            u32::from_le_bytes(b)
+        } else {
+            u32::from_be_bytes(b)
+        })
+    }
+
+    /// The entries of the directory at the offset.
+    fn directory(&self, offset: u32) -> Option<Vec<Entry>> {
+        let offset = offset as usize;
+        let count = self.u16(offset)?;
+        (0..usize::from(count))
+            .map(|i| {
+                let at = offset + 2 + i * 12;
+                Some(Entry {
+                    tag: self.u16(at)?,
+                    kind: self.u16(at + 2)?,
+                    count: self.u32(at + 4)?,
+                    at: at + 8,
+                })
+            })
+            .collect()
+    }
+
+    /// The offset of the directory after the one at the offset.
+    fn next_directory(&self, offset: u32) -> Option<u32> {
+        let count = usize::from(self.u16(offset as usize)?);
+        self.u32(offset as usize + 2 + count * 12)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use image::{ImageOutputFormat, Rgb, RgbImage};
+
+    fn jpeg(size: u32) -> Vec<u8> {
+        let mut bytes = Cursor::new(Vec::new());
+        RgbImage::from_pixel(size, size, Rgb([200, 40, 40]))
+            .write_to(&mut bytes, ImageOutputFormat::Jpeg(90))
+            .unwrap();
+        bytes.into_inner()
+    }
+
+    /// A little endian TIFF structure with a thumbnail in its first
+    /// directory and a preview in a sub directory, like a NEF file.
+    fn raw(thumbnail: &[u8], preview: &[u8]) -> Vec<u8> {
+        let entry = |tag: u16, kind: u16, value: u32| {
+            let mut e = tag.to_le_bytes().to_vec();
+            e.extend(kind.to_le_bytes());
+            e.extend(1u32.to_le_bytes());
+            e.extend(value.to_le_bytes());
+            e
+        };
+        let directory = |entries: Vec<Vec<u8>>| {
+            let mut d = (entries.len() as u16).to_le_bytes().to_vec();
+            entries.into_iter().for_each(|e| d.extend(e));
+            d.extend(0u32.to_le_bytes());
+            d
+        };
+        // Header, then two directories of three and two entries.
+        let sub = 8 + 2 + 3 * 12 + 4;
+        let data = sub + 2 + 2 * 12 + 4;
+        let mut bytes = b"II*\0".to_vec();
+        bytes.extend(8u32.to_le_bytes());
+        bytes.extend(directory(vec![
+            entry(SUB_IFDS, 4, sub as u32),
+            entry(JPEG_OFFSET, 4, data as u32),
+            entry(JPEG_LENGTH, 4, thumbnail.len() as u32),
+        ]));
+        assert_eq!(bytes.len(), sub);
+        bytes.extend(directory(vec![
+            entry(JPEG_OFFSET, 4, (data + thumbnail.len()) as u32),
+            entry(JPEG_LENGTH, 4, preview.len() as u32),
+        ]));
+        assert_eq!(bytes.len(), data);
+        bytes.extend(thumbnail);
+        bytes.extend(preview);
+        bytes
+    }
+
+    #[test]
+    fn test_finds_the_largest_preview() {
+        let (thumbnail, large) = (jpeg(8), jpeg(64));
+        let bytes = raw(&thumbnail, &large);
+
+        assert_eq!(preview(&bytes), Some(&large[..]));
+        assert_eq!(preview(b"II*\0\0\0\0\0"), None);
+        assert_eq!(preview(&large), None);
+    }
+
+    #[test]
+    fn test_skips_lossless_jpegs() {
+        let mut lossless = jpeg(64);
+        // Mark the frame as lossless.
+        let frame = lossless.windows(2).position(|w| w == [0xff, 0xc0]).unwrap();
+        lossless[frame + 1] = 0xc3;
+        let thumbnail = jpeg(8);
+        let bytes = raw(&thumbnail, &lossless);
+
+        assert_eq!(preview(&bytes), Some(&thumbnail[..]));
+    }
+
+    #[test]
+    fn test_loads_previews_of_raw_files() {
+        let path = std::env::temp_dir().join(format!("tiler-raw-{}.NEF", std::process::id()));
+        std::fs::write(&path, raw(&jpeg(8), &jpeg(64))).unwrap();
+
+        assert!(is_raw(&path));
+        let img = crate::load_image(&path).unwrap();
+        assert_eq!(img.dimensions(), (64, 64));
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,296 @@
+#[cfg(feature = "rand")]
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    time::{Duration, Instant},
+};
+
+#[cfg(feature = "rand")]
+use image::RgbaImage;
+#[cfg(feature = "rand")]
+use rand::Rng;
+#[cfg(feature = "serde")]
+use serde::Deserialize;
+
+#[cfg(feature = "rand")]
+use crate::{
+    analysis::ImageInfo,
+    core::{Dimensions, PixelRegion, Rectangle, TileLocation},
+    matching::CellAnalyser,
+    strategy::{StrategyOptions, TilingStrategy},
+};
+
+/// Budget and settings for polishing the tiles chosen by a strategy.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
+pub struct Refinement {
+    /// Number of changes to try, none disables refinement.
+    pub iterations: u32,
+    /// Time to stop trying changes after, in milliseconds (0 for no limit).
+    ///
+    /// How many changes are tried in time depends on the machine and its
+    /// load, so a limit that is reached can change the mosaic from one build
+    /// to the next even with the same seed. Leave it at 0 for builds that
+    /// must be reproducible.
+    pub time_limit_ms: u64,
+    /// Number of best matching tiles for a cell to try replacing its tile with.
+    pub shortlist: usize,
+    /// Starting temperature, above zero to sometimes accept worse changes
+    /// early on (simulated annealing) rather than only improvements.
+    pub temperature: f64,
+}
+
+impl Default for Refinement {
+    fn default() -> Self {
+        Self {
+            iterations: 0,
+            time_limit_ms: 0,
+            shortlist: 5,
+            temperature: 0.0,
+        }
+    }
+}
+
+/// Improve the tiles chosen by another strategy by repeatedly swapping tiles
+/// between cells, or replacing a cell's tile with one from its shortlist,
+/// keeping changes that lower the total cost of the mosaic.
+#[cfg(feature = "rand")]
+pub struct RefinedTileStrategy<'a, T> {
+    inner: Box<dyn TilingStrategy<T> + 'a>,
+    options: &'a StrategyOptions,
+    analysis: &'a HashMap<&'a T, ImageInfo>,
+}
+
+#[cfg(feature = "rand")]
+impl<'a, T> RefinedTileStrategy<'a, T> {
+    pub fn new(
+        inner: Box<dyn TilingStrategy<T> + 'a>,
+        analysis: &'a HashMap<&'a T, ImageInfo>,
+        options: &'a StrategyOptions,
+    ) -> RefinedTileStrategy<'a, T> {
+        RefinedTileStrategy {
+            inner,
+            options,
+            analysis,
+        }
+    }
+}
+
+#[cfg(feature = "rand")]
+impl<T: Ord + Hash> TilingStrategy<T> for RefinedTileStrategy<'_, T> {
+    fn choose(
+        &self,
+        target: &RgbaImage,
+        cell_size: &Dimensions,
+    ) -> Vec<TileLocation<'_, T, PixelRegion>> {
+        let chosen = self.inner.choose(target, cell_size);
+        if chosen.is_empty() {
+            return chosen;
+        }
+
+        let analyser = CellAnalyser::new(target, *cell_size, &self.options.analysis);
+        let cells: Vec<Cell> = chosen
+            .iter()
+            .map(|(_, region)| Cell::new(&analyser, region))
+            .collect();
+        let mut mosaic = RefiningMosaic {
+            cells: &cells,
+            tiles: chosen.iter().map(|(tile, _)| *tile).collect(),
+            analysis: self.analysis,
+            options: self.options,
+        };
+        let shortlists: Vec<Vec<&T>> = cells.iter().map(|c| mosaic.shortlist(c)).collect();
+
+        let refinement = &self.options.refinement;
+        let deadline = (refinement.time_limit_ms > 0)
+            .then(|| Instant::now() + Duration::from_millis(refinement.time_limit_ms));
+        let mut rng = self.options.seed.rng("refine");
+
+        for iteration in 0..refinement.iterations {
+            if deadline.is_some_and(|d| Instant::now() > d) || self.options.cancel.is_cancelled() {
+                break;
+            }
+            let progress = iteration as f64 / refinement.iterations as f64;
+            let temperature = refinement.temperature * (1.0 - progress);
+
+            let i = rng.gen_range(0..cells.len());
+            let changes = if rng.gen_bool(0.5) {
+                let j = rng.gen_range(0..cells.len());
+                vec![(i, mosaic.tiles[j]), (j, mosaic.tiles[i])]
+            } else {
+                let shortlist = &shortlists[i];
+                vec![(i, shortlist[rng.gen_range(0..shortlist.len())])]
+            };
+
+            let previous: Vec<(usize, &T)> = changes
+                .iter()
+                .map(|(c, _)| (*c, mosaic.tiles[*c]))
+                .collect();
+            let delta = changes
+                .iter()
+                .map(|(c, tile)| mosaic.replace(*c, tile))
+                .fold(0, i64::saturating_add);
+
+            let accept = delta < 0
+                || (temperature > 0.0 && rng.gen::<f64>() < (-(delta as f64) / temperature).exp());
+            if !accept {
+                for (c, tile) in previous.into_iter().rev() {
+                    mosaic.replace(c, tile);
+                }
+            }
+        }
+
+        mosaic
+            .tiles
+            .into_iter()
+            .zip(chosen)
+            .map(|(tile, (_, region))| (tile, region))
+            .collect()
+    }
+}
+
+/// A cell of the target being refined.
+#[cfg(feature = "rand")]
+struct Cell {
+    rectangle: Rectangle,
+    info: ImageInfo,
+}
+
+#[cfg(feature = "rand")]
+impl Cell {
+    fn new(analyser: &CellAnalyser, region: &PixelRegion) -> Self {
+        let rectangle = Rectangle::new(
+            region.x as u32,
+            region.y as u32,
+            region.width,
+            region.height,
+        );
+        let info = analyser.analyse(&rectangle);
+        Self { rectangle, info }
+    }
+}
+
+/// The current tile for each cell, and how to cost changes to them.
+#[cfg(feature = "rand")]
+struct RefiningMosaic<'a, 't, T> {
+    cells: &'a [Cell],
+    tiles: Vec<&'t T>,
+    analysis: &'t HashMap<&'t T, ImageInfo>,
+    options: &'a StrategyOptions,
+}
+
+#[cfg(feature = "rand")]
+impl<'t, T: Ord + Hash> RefiningMosaic<'_, 't, T> {
+    /// The tiles best matching the cell.
+    fn shortlist(&self, cell: &Cell) -> Vec<&'t T> {
+        let mut weights: Vec<(&'t T, i64)> = self
+            .analysis
+            .iter()
+            .map(|(tile, info)| {
+                let weight = self.options.weight(info, &cell.info, &cell.rectangle);
+                (*tile, weight)
+            })
+            .collect();
+        weights.sort_by(|a, b| self.options.compare_weights(a, b));
+        weights
+            .into_iter()
+            .take(self.options.refinement.shortlist.max(1))
+            .map(|(tile, _)| tile)
+            .collect()
+    }
+
+    /// The cost of drawing the tile in the cell, including the penalty for
+    /// duplicates of it in the other cells.
+    fn cost(&self, c: usize, tile: &T) -> i64 {
+        let cell = &self.cells[c];
+        let weight = self
+            .options
+            .weight(&self.analysis[tile], &cell.info, &cell.rectangle);
+        let penalty: i64 = self
+            .cells
+            .iter()
+            .zip(&self.tiles)
+            .enumerate()
+            .filter(|(other, (_, t))| *other != c && **t == tile)
+            .map(|(_, (other, _))| {
+                let (r, o) = (&cell.rectangle, &other.rectangle);
+                let offset = (r.x.abs_diff(o.x), r.y.abs_diff(o.y));
+                self.options.duplicate_cost(offset, (r.width, r.height))
+            })
+            .fold(0, i64::saturating_add);
+        weight.saturating_add(penalty)
+    }
+
+    /// Draw the tile in the cell, returning the change in total cost.
+    fn replace(&mut self, c: usize, tile: &'t T) -> i64 {
+        let before = self.cost(c, self.tiles[c]);
+        let after = self.cost(c, tile);
+        self.tiles[c] = tile;
+        after.saturating_sub(before)
+    }
+}
+
+#[cfg(all(test, feature = "rand"))]
+mod test {
+    use super::*;
+    use crate::analysis::{analyse, AnalysisOptions};
+    use image::Rgba;
+
+    /// A strategy that draws the given tiles left to right along a row.
+    struct FixedTileStrategy<'a, T>(Vec<&'a T>);
+
+    impl<T> TilingStrategy<T> for FixedTileStrategy<'_, T> {
+        fn choose(
+            &self,
+            _target: &RgbaImage,
+            (width, height): &Dimensions,
+        ) -> Vec<TileLocation<'_, T, PixelRegion>> {
+            self.0
+                .iter()
+                .enumerate()
+                .map(|(i, t)| {
+                    (
+                        *t,
+                        PixelRegion::new(i as i64 * *width as i64, 0, *width, *height),
+                    )
+                })
+                .collect()
+        }
+    }
+
+    #[test]
+    fn test_improves_chosen_tiles() {
+        let (red, blue) = ("red".to_string(), "blue".to_string());
+        let options = StrategyOptions {
+            analysis: AnalysisOptions::new(Some(1)),
+            refinement: Refinement {
+                iterations: 50,
+                ..Refinement::default()
+            },
+            ..StrategyOptions::default()
+        };
+        let solid = |c| analyse(&RgbaImage::from_pixel(20, 20, Rgba(c)), &options.analysis);
+        let analysis = HashMap::from([
+            (&red, solid([255, 0, 0, 255])),
+            (&blue, solid([0, 0, 255, 255])),
+        ]);
+        let target = RgbaImage::from_fn(40, 20, |x, _| {
+            if x < 20 {
+                Rgba([255, 0, 0, 255])
+            } else {
+                Rgba([0, 0, 255, 255])
+            }
+        });
+
+        let inner = Box::new(FixedTileStrategy(vec![&blue, &red]));
+        let strategy = RefinedTileStrategy::new(inner, &analysis, &options);
+        let tiles: Vec<&String> = strategy
+            .choose(&target, &(20, 20))
+            .iter()
+            .map(|(t, _)| *t)
+            .collect();
+
+        assert_eq!(tiles, vec![&red, &blue]);
+    }
+}
@@ -0,0 +1,107 @@
+#[cfg(feature = "cli")]
+use clap::ValueEnum;
+use image::imageops::{self, FilterType};
+use image::RgbaImage;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::color;
+
+/// The filter images are resized with, trading speed for sharpness.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "cli", derive(ValueEnum))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
+pub enum ResizeFilter {
+    /// Averages the pixels under each pixel: quick, but soft.
+    #[default]
+    Fast,
+    /// Takes the nearest pixel: quickest, but blocky.
+    Nearest,
+    /// Interpolates linearly.
+    Triangle,
+    /// Interpolates with a cubic spline, for sharper images.
+    CatmullRom,
+    /// Windowed sinc, the sharpest and slowest, for print resolution.
+    Lanczos3,
+}
+
+impl ResizeFilter {
+    fn filter_type(&self) -> Option<FilterType> {
+        match self {
+            ResizeFilter::Fast => None,
+            ResizeFilter::Nearest => Some(FilterType::Nearest),
+            ResizeFilter::Triangle => Some(FilterType::Triangle),
+            ResizeFilter::CatmullRom => Some(FilterType::CatmullRom),
+            ResizeFilter::Lanczos3 => Some(FilterType::Lanczos3),
+        }
+    }
+}
+
+/// How images are resized: with which filter, and whether colors are
+/// blended in linear light rather than as encoded.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Resize {
+    pub filter: ResizeFilter,
+    pub linear_light: bool,
+}
+
+impl Resize {
+    pub fn new(filter: ResizeFilter, linear_light: bool) -> Resize {
+        Resize {
+            filter,
+            linear_light,
+        }
+    }
+
+    /// The image resized to the given size.
+    pub fn apply(&self, img: &RgbaImage, width: u32, height: u32) -> RgbaImage {
+        match (self.filter.filter_type(), self.linear_light) {
+            (None, true) => color::resize(img, width, height),
+            (None, false) => imageops::thumbnail(img, width, height),
+            (Some(filter), true) => color::resize_filtered(img, width, height, filter),
+            (Some(filter), false) => imageops::resize(img, width, height, filter),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use image::Rgba;
+
+    /// A sharp black to white edge down the middle.
+    fn edge() -> RgbaImage {
+        RgbaImage::from_fn(64, 8, |x, _| {
+            let v = if x < 32 { 0 } else { 255 };
+            Rgba([v, v, v, 255])
+        })
+    }
+
+    #[test]
+    #[cfg(feature = "cli")]
+    fn test_resizes_with_each_filter() {
+        for filter in ResizeFilter::value_variants() {
+            for linear_light in [false, true] {
+                let resized = Resize::new(*filter, linear_light).apply(&edge(), 16, 2);
+
+                assert_eq!(resized.dimensions(), (16, 2));
+                assert_eq!(resized.get_pixel(0, 0), &Rgba([0, 0, 0, 255]));
+                assert_eq!(resized.get_pixel(15, 1), &Rgba([255, 255, 255, 255]));
+            }
+        }
+    }
+
+    #[test]
+    fn test_sharper_filters_keep_edges_sharper() {
+        // How far the pixels beside the edge are from black and white.
+        let blur = |filter| {
+            let resized = Resize::new(filter, false).apply(&edge(), 16, 2);
+            let [dark, light] = [7, 8].map(|x| i32::from(resized.get_pixel(x, 0).0[0]));
+            dark + (255 - light)
+        };
+
+        assert_eq!(blur(ResizeFilter::Nearest), 0);
+        assert!(blur(ResizeFilter::Lanczos3) <= blur(ResizeFilter::Triangle));
+    }
+}
@@ -0,0 +1,172 @@
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use image::RgbaImage;
+#[cfg(feature = "serde")]
+use serde::Serialize;
+
+use crate::core::{Dimensions, PixelRegion};
+use crate::evaluate::{self, EvaluationOptions, Quality};
+use crate::stats::PlanStats;
+use crate::undersized::SmallTiles;
+use crate::Plan;
+
+/// A finished mosaic, the library images drawn in it and where, and how it
+/// was built.
+#[derive(Clone, Debug)]
+pub struct MosaicResult {
+    /// The mosaic image.
+    pub image: RgbaImage,
+    /// Each library image drawn and the region (in output pixels) it is
+    /// drawn in, in the order drawn.
+    pub placements: Vec<(PathBuf, PixelRegion)>,
+    /// How long the build took and how well the mosaic turned out.
+    pub stats: MosaicStats,
+}
+
+/// How long a mosaic took to build and how well it turned out.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct MosaicStats {
+    /// Time taken to analyse the target and library and choose the tiles.
+    pub planning: Duration,
+    /// Time taken to draw the chosen tiles.
+    pub rendering: Duration,
+    /// Number of library images read.
+    pub library_size: usize,
+    /// Number of library images left out by the filter.
+    pub filtered_out: usize,
+    /// Library images left out for being too large to decode safely, and
+    /// why.
+    pub refused: Vec<(PathBuf, String)>,
+    /// Number of library images smaller than the tiles, either drawn scaled
+    /// up or left out, as the small tile policy says.
+    pub undersized: usize,
+    /// What was done with the library images smaller than the tiles.
+    pub small_tiles: SmallTiles,
+    /// Number of columns and rows of cells the target was split into.
+    pub grid: Dimensions,
+    /// Number of different library images drawn.
+    pub distinct_tiles: usize,
+    /// Most times any one library image is drawn.
+    pub most_reused: usize,
+    /// How the tiles were used and how well they match their cells.
+    pub plan: PlanStats,
+    /// How closely the mosaic recreates its target, compared over cells of
+    /// the size the target was split into.
+    pub quality: Quality,
+}
+
+impl MosaicResult {
+    /// Describe the mosaic drawn from the plan, measuring it against its
+    /// target.
+    pub(crate) fn new(
+        image: RgbaImage,
+        plan: Plan,
+        cell_size: u32,
+        (planning, rendering): (Duration, Duration),
+    ) -> MosaicResult {
+        let stats = PlanStats::new(&plan.tiles, &plan.costs);
+        let options = EvaluationOptions {
+            cell_size,
+            ..EvaluationOptions::default()
+        };
+        let (width, height) = plan.target.dimensions();
+        let cell_size = cell_size.max(1);
+        let stats = MosaicStats {
+            planning,
+            rendering,
+            library_size: plan.library_size,
+            filtered_out: plan.filtered_out,
+            refused: plan.refused,
+            undersized: plan.undersized,
+            small_tiles: plan.small_tiles,
+            grid: (width.div_ceil(cell_size), height.div_ceil(cell_size)),
+            distinct_tiles: stats.distinct_tiles,
+            most_reused: stats.most_reused,
+            plan: stats,
+            quality: evaluate::measure(&plan.target, &image, &options),
+        };
+        MosaicResult {
+            image,
+            placements: plan.tiles,
+            stats,
+        }
+    }
+}
+
+impl Display for MosaicStats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "Library: {} images", self.library_size)?;
+        if self.filtered_out > 0 {
+            write!(f, ", {} left out by the filter", self.filtered_out)?;
+        }
+        if self.undersized > 0 {
+            match self.small_tiles {
+                SmallTiles::Exclude => {
+                    write!(f, ", {} smaller than the tiles left out", self.undersized)?
+                }
+                _ => write!(f, ", {} smaller than the tiles and blurry", self.undersized)?,
+            }
+        }
+        let (columns, rows) = self.grid;
+        writeln!(f)?;
+        writeln!(f, "Grid: {columns} x {rows} cells")?;
+        writeln!(
+            f,
+            "Tiles: {} drawn, {} distinct, the most used {} times",
+            self.plan.cells, self.distinct_tiles, self.most_reused
+        )?;
+        write!(
+            f,
+            "Time: {:.2?} planning, {:.2?} rendering",
+            self.planning, self.rendering
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use image::Rgba;
+
+    #[test]
+    fn test_counts_tiles_used() {
+        let img = RgbaImage::from_pixel(30, 10, Rgba([10, 20, 30, 255]));
+        let placements = vec![
+            (PathBuf::from("a.jpg"), PixelRegion::new(0, 0, 10, 10)),
+            (PathBuf::from("b.jpg"), PixelRegion::new(10, 0, 10, 10)),
+            (PathBuf::from("a.jpg"), PixelRegion::new(20, 0, 10, 10)),
+        ];
+        let plan = Plan {
+            target: img.clone(),
+            ratio: 1.0,
+            size: (30, 10),
+            tiles: placements.clone(),
+            costs: vec![5, 9, 7],
+            library_size: 4,
+            filtered_out: 1,
+            refused: vec![(PathBuf::from("c.jpg"), "c.jpg is too large".to_owned())],
+            undersized: 2,
+            small_tiles: SmallTiles::Warn,
+        };
+        let times = (Duration::from_millis(5), Duration::from_millis(7));
+
+        let result = MosaicResult::new(img, plan, 10, times);
+
+        assert_eq!(result.placements, placements);
+        assert_eq!(result.stats.distinct_tiles, 2);
+        assert_eq!(result.stats.most_reused, 2);
+        assert_eq!(result.stats.refused[0].0, PathBuf::from("c.jpg"));
+        assert_eq!(result.stats.plan.cost.total, 21);
+        assert_eq!(result.stats.grid, (3, 1));
+        assert_eq!(result.stats.rendering, Duration::from_millis(7));
+        assert_eq!(result.stats.quality.mean_cell_error, 0.0);
+        let summary = result.stats.to_string();
+        assert!(
+            summary.starts_with("Library: 4 images, 1 left out by the filter, 2 smaller than the tiles and blurry\nGrid: 3 x 1 cells\n")
+        );
+        assert!(summary.contains("Tiles: 3 drawn, 2 distinct, the most used 2 times"));
+    }
+}
@@ -0,0 +1,68 @@
+use std::fmt;
+use std::num::ParseIntError;
+use std::str::FromStr;
+
+#[cfg(feature = "rand")]
+use rand::rngs::StdRng;
+#[cfg(feature = "rand")]
+use rand::SeedableRng;
+#[cfg(feature = "serde")]
+use serde::Deserialize;
+
+#[cfg(feature = "rand")]
+use crate::library::fnv1a;
+
+/// Seed for every random choice made while building a mosaic, so that the
+/// same inputs and seed always build the same mosaic, unless refinement is
+/// stopped by its time limit.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
+pub struct Seed(pub u64);
+
+impl Seed {
+    /// A random number generator for the named component.
+    ///
+    /// Each component gets its own stream, so a change to how many numbers
+    /// one component draws doesn't change the choices of the others.
+    #[cfg(feature = "rand")]
+    pub(crate) fn rng(self, component: &str) -> StdRng {
+        StdRng::seed_from_u64(self.0 ^ fnv1a(component.as_bytes()))
+    }
+}
+
+impl From<u64> for Seed {
+    fn from(value: u64) -> Self {
+        Seed(value)
+    }
+}
+
+impl FromStr for Seed {
+    type Err = ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse().map(Seed)
+    }
+}
+
+impl fmt::Display for Seed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+#[cfg(all(test, feature = "rand"))]
+mod test {
+    use super::*;
+    use rand::Rng;
+
+    #[test]
+    fn test_gives_each_component_its_own_stream() {
+        let draw = |seed: Seed, component| seed.rng(component).gen::<u64>();
+
+        assert_eq!(draw(Seed(1), "order"), draw(Seed(1), "order"));
+        assert_ne!(draw(Seed(1), "order"), draw(Seed(1), "refine"));
+        assert_ne!(draw(Seed(1), "order"), draw(Seed(2), "order"));
+        assert_eq!("42".parse(), Ok(Seed(42)));
+    }
+}
@@ -0,0 +1,433 @@
+use std::collections::{HashMap, VecDeque};
+use std::io::{Cursor, Error as IoError, Read, Result as IoResult};
+use std::path::{Path, PathBuf};
+use std::sync::{Condvar, Mutex};
+use std::thread;
+
+use image::io::Reader;
+use image::{ImageOutputFormat, RgbaImage};
+use serde_json::{json, Value};
+use tiny_http::{Header, Response, Server};
+
+use crate::analysis::ImageInfo;
+use crate::{
+    color, load_library, plan_with_library, render, strategy_names, strategy_options, CancelToken,
+    LoadedLibrary, MosaicOptions,
+};
+
+/// Largest target image accepted, in bytes.
+const MAX_UPLOAD: u64 = 64 * 1024 * 1024;
+
+/// Most finished jobs kept for their mosaics or errors to be fetched, before
+/// the oldest are forgotten.
+const MAX_FINISHED: usize = 64;
+
+/// Quality of the JPEG mosaics returned.
+const JPEG_QUALITY: u8 = 85;
+
+/// Builds mosaics of uploaded targets in the background, from libraries
+/// analysed once when the service starts.
+///
+/// Over HTTP:
+/// - `GET /libraries` lists the libraries and their number of tiles.
+/// - `POST /jobs?library=NAME[&strategy=NAME]` with a target image as the
+///   body queues a mosaic, returning its job `id`.
+/// - `GET /jobs/ID` gives the job's `status`: queued, running, done, or
+///   failed with an `error`.
+/// - `GET /jobs/ID/mosaic` returns the finished mosaic as a JPEG.
+/// - `DELETE /jobs/ID` forgets the job and its mosaic, stopping its build
+///   if it is running.
+///
+/// Only the most recently finished jobs are kept, so mosaics should be
+/// fetched soon after they are done.
+pub struct MosaicService {
+    libraries: HashMap<String, LoadedLibrary>,
+    options: MosaicOptions,
+    jobs: Mutex<Jobs>,
+    queued: Condvar,
+}
+
+#[derive(Default)]
+struct Jobs {
+    next_id: u64,
+    by_id: HashMap<u64, Job>,
+    queue: VecDeque<u64>,
+    /// Finished jobs, oldest first.
+    finished: VecDeque<u64>,
+}
+
+struct Job {
+    library: String,
+    options: MosaicOptions,
+    /// The uploaded target image, until it is decoded to be built.
+    target: Option<Vec<u8>>,
+    status: Status,
+    cancel: CancelToken,
+}
+
+enum Status {
+    Queued,
+    Running,
+    Done(Vec<u8>),
+    Failed(String),
+}
+
+/// A response to a request.
+struct Reply {
+    status: u16,
+    content_type: &'static str,
+    body: Vec<u8>,
+}
+
+impl Reply {
+    fn json(status: u16, value: Value) -> Reply {
+        Reply {
+            status,
+            content_type: "application/json",
+            body: value.to_string().into_bytes(),
+        }
+    }
+
+    fn error(status: u16, message: &str) -> Reply {
+        Reply::json(status, json!({ "error": message }))
+    }
+}
+
+impl MosaicService {
+    /// Analyse the named libraries for building mosaics with the options.
+    pub fn new(libraries: &[(String, PathBuf)], options: MosaicOptions) -> IoResult<Self> {
+        let analysis = strategy_options(&options).analysis;
+        let libraries = libraries
+            .iter()
+            .map(|(name, dir)| {
+                let library =
+                    load_library(&[dir], &options.filter, &analysis, &CancelToken::new())?;
+                Ok((name.clone(), library))
+            })
+            .collect::<IoResult<_>>()?;
+        Ok(MosaicService {
+            libraries,
+            options,
+            jobs: Mutex::default(),
+            queued: Condvar::new(),
+        })
+    }
+
+    /// The library images left out for being too large to decode safely,
+    /// and why.
+    pub fn refused(&self) -> impl Iterator<Item = &(PathBuf, String)> {
+        self.libraries.values().flat_map(|library| &library.refused)
+    }
+
+    /// Serve requests at the address, building mosaics on a worker per CPU.
+    ///
+    /// Only returns if the address can't be listened on.
+    pub fn serve(&self, address: &str) -> IoResult<()> {
+        let server = Server::http(address).map_err(IoError::other)?;
+        let workers = thread::available_parallelism().map_or(1, |n| n.get());
+        thread::scope(|scope| {
+            for _ in 0..workers {
+                scope.spawn(|| loop {
+                    let id = self.next_job();
+                    self.run(id);
+                });
+            }
+
+            for mut request in server.incoming_requests() {
+                let mut body = Vec::new();
+                // Reading a byte more than allowed shows if the body is too big.
+                let reply = match request
+                    .as_reader()
+                    .take(MAX_UPLOAD + 1)
+                    .read_to_end(&mut body)
+                {
+                    Ok(n) if n as u64 > MAX_UPLOAD => Reply::error(413, "Target image too large"),
+                    Ok(_) => self.handle(request.method().as_str(), request.url(), &body),
+                    Err(_) => Reply::error(400, "Unreadable body"),
+                };
+                let content_type = Header::from_bytes("Content-Type", reply.content_type).unwrap();
+                let response = Response::from_data(reply.body)
+                    .with_status_code(reply.status)
+                    .with_header(content_type);
+                let _ = request.respond(response);
+            }
+            Ok(())
+        })
+    }
+
+    /// Respond to a request with the given method, URL, and body.
+    fn handle(&self, method: &str, url: &str, body: &[u8]) -> Reply {
+        let (path, query) = url.split_once('?').unwrap_or((url, ""));
+        let query: HashMap<String, String> = query
+            .split('&')
+            .filter_map(|pair| pair.split_once('='))
+            .map(|(key, value)| (percent_decode(key), percent_decode(value)))
+            .collect();
+        let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+
+        match (method, segments.as_slice()) {
+            ("GET", ["libraries"]) => {
+                let libraries: Vec<Value> = self
+                    .libraries
+                    .iter()
+                    .map(|(name, library)| json!({ "name": name, "tiles": library.images.len() }))
+                    .collect();
+                Reply::json(200, json!({ "libraries": libraries }))
+            }
+            ("POST", ["jobs"]) => self.submit(&query, body),
+            (method, ["jobs", id, rest @ ..]) => {
+                let Ok(id) = id.parse() else {
+                    return Reply::error(404, "No such job");
+                };
+                let mut jobs = self.jobs.lock().unwrap();
+                let Some(job) = jobs.by_id.get(&id) else {
+                    return Reply::error(404, "No such job");
+                };
+                match (method, rest, &job.status) {
+                    ("GET", [], status) => Reply::json(200, describe(id, status)),
+                    ("GET", ["mosaic"], Status::Done(jpeg)) => Reply {
+                        status: 200,
+                        content_type: "image/jpeg",
+                        body: jpeg.clone(),
+                    },
+                    ("GET", ["mosaic"], _) => Reply::error(409, "Mosaic not built"),
+                    ("DELETE", [], _) => {
+                        job.cancel.cancel();
+                        jobs.by_id.remove(&id);
+                        Reply::json(200, json!({ "id": id }))
+                    }
+                    _ => Reply::error(404, "Not found"),
+                }
+            }
+            _ => Reply::error(404, "Not found"),
+        }
+    }
+
+    /// Queue a mosaic of the uploaded target.
+    fn submit(&self, query: &HashMap<String, String>, body: &[u8]) -> Reply {
+        let Some(library) = query
+            .get("library")
+            .filter(|l| self.libraries.contains_key(*l))
+        else {
+            return Reply::error(400, "Unknown library");
+        };
+        let mut options = self.options.clone();
+        if let Some(strategy) = query.get("strategy") {
+            if !strategy_names().contains(&strategy.as_str()) {
+                return Reply::error(400, "Unknown strategy");
+            }
+            options.strategy = strategy.clone();
+        }
+
+        let mut jobs = self.jobs.lock().unwrap();
+        let id = jobs.next_id;
+        jobs.next_id += 1;
+        jobs.by_id.insert(
+            id,
+            Job {
+                library: library.clone(),
+                options,
+                target: Some(body.to_vec()),
+                status: Status::Queued,
+                cancel: CancelToken::new(),
+            },
+        );
+        jobs.queue.push_back(id);
+        self.queued.notify_one();
+        Reply::json(202, describe(id, &Status::Queued))
+    }
+
+    /// Wait for a job to be queued, and take it from the queue.
+    fn next_job(&self) -> u64 {
+        let mut jobs = self.jobs.lock().unwrap();
+        loop {
+            if let Some(id) = jobs.queue.pop_front() {
+                return id;
+            }
+            jobs = self.queued.wait(jobs).unwrap();
+        }
+    }
+
+    /// Build the mosaic for the job, unless it has been deleted.
+    fn run(&self, id: u64) {
+        let (target, library, options, cancel) = {
+            let mut jobs = self.jobs.lock().unwrap();
+            let Some(job) = jobs.by_id.get_mut(&id) else {
+                return;
+            };
+            job.status = Status::Running;
+            let target = job.target.take().unwrap();
+            let library = &self.libraries[&job.library].images;
+            (target, library, job.options.clone(), job.cancel.clone())
+        };
+
+        let status = match color::decode(Reader::new(Cursor::new(target))) {
+            Ok(target) => match build(target, library, &options, &cancel) {
+                Ok(jpeg) => Status::Done(jpeg),
+                Err(e) => Status::Failed(e.to_string()),
+            },
+            Err(e) => Status::Failed(format!("Unreadable target image: {e}")),
+        };
+        let mut jobs = self.jobs.lock().unwrap();
+        let Some(job) = jobs.by_id.get_mut(&id) else {
+            return;
+        };
+        job.status = status;
+        jobs.finished.push_back(id);
+        while jobs.finished.len() > MAX_FINISHED {
+            let oldest = jobs.finished.pop_front().unwrap();
+            jobs.by_id.remove(&oldest);
+        }
+    }
+}
+
+/// Build the mosaic as a JPEG.
+fn build(
+    target: RgbaImage,
+    library: &[(PathBuf, ImageInfo)],
+    options: &MosaicOptions,
+    cancel: &CancelToken,
+) -> IoResult<Vec<u8>> {
+    let plan = plan_with_library(target, library, options, cancel)?;
+    let mosaic = render(plan, options, cancel)?;
+    let mut jpeg = Cursor::new(Vec::new());
+    image::DynamicImage::ImageRgba8(mosaic)
+        .to_rgb8()
+        .write_to(&mut jpeg, ImageOutputFormat::Jpeg(JPEG_QUALITY))
+        .map_err(IoError::other)?;
+    Ok(jpeg.into_inner())
+}
+
+fn describe(id: u64, status: &Status) -> Value {
+    match status {
+        Status::Queued => json!({ "id": id, "status": "queued" }),
+        Status::Running => json!({ "id": id, "status": "running" }),
+        Status::Done(_) => json!({ "id": id, "status": "done" }),
+        Status::Failed(error) => json!({ "id": id, "status": "failed", "error": error }),
+    }
+}
+
+/// Decode the `%XX` escapes, and `+` for a space, in a query string part.
+fn percent_decode(s: &str) -> String {
+    let s = s.replace('+', " ");
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut rest = s.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        let escaped = tail
+            .get(..2)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (byte, escaped) {
+            (b'%', Some(escaped)) => {
+                bytes.push(escaped);
+                rest = &tail[2..];
+            }
+            _ => {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// Parse a `NAME=DIR` library argument.
+pub fn parse_library(arg: &str) -> Result<(String, PathBuf), String> {
+    match arg.split_once('=') {
+        Some((name, dir)) if !name.is_empty() => Ok((name.to_owned(), Path::new(dir).to_owned())),
+        _ => Err(format!("expected NAME=DIR, got {arg}")),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_support::scratch_dir;
+    use image::Rgba;
+    use std::fs::remove_dir_all;
+
+    fn png(color: [u8; 4]) -> Vec<u8> {
+        let mut png = Cursor::new(Vec::new());
+        RgbaImage::from_pixel(40, 20, Rgba(color))
+            .write_to(&mut png, ImageOutputFormat::Png)
+            .unwrap();
+        png.into_inner()
+    }
+
+    fn status(service: &MosaicService, id: u64) -> Value {
+        let reply = service.handle("GET", &format!("/jobs/{id}"), &[]);
+        serde_json::from_slice(&reply.body).unwrap()
+    }
+
+    #[test]
+    fn test_builds_uploaded_targets_in_jobs() {
+        let dir = scratch_dir("serve");
+        RgbaImage::from_pixel(8, 8, Rgba([255, 0, 0, 255]))
+            .save(dir.join("red.png"))
+            .unwrap();
+        let libraries = [("holiday".to_owned(), dir.clone())];
+        let service = MosaicService::new(&libraries, MosaicOptions::default()).unwrap();
+
+        let unknown = service.handle("POST", "/jobs?library=work", &png([0; 4]));
+        let submitted = service.handle("POST", "/jobs?library=holiday", &png([250, 0, 0, 255]));
+        let id = serde_json::from_slice::<Value>(&submitted.body).unwrap()["id"]
+            .as_u64()
+            .unwrap();
+        let early = service.handle("GET", &format!("/jobs/{id}/mosaic"), &[]);
+        let queued = status(&service, id);
+        service.run(service.next_job());
+        let mosaic = service.handle("GET", &format!("/jobs/{id}/mosaic"), &[]);
+
+        assert_eq!(unknown.status, 400);
+        assert_eq!(submitted.status, 202);
+        assert_eq!(early.status, 409);
+        assert_eq!(queued["status"], "queued");
+        assert_eq!(status(&service, id)["status"], "done");
+        assert_eq!(mosaic.content_type, "image/jpeg");
+        let mosaic = image::load_from_memory(&mosaic.body).unwrap();
+        assert_eq!((mosaic.width(), mosaic.height()), (200, 100));
+        remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_fails_unreadable_targets_and_forgets_old_jobs() {
+        let dir = scratch_dir("serve-old");
+        let libraries = [("my photos".to_owned(), dir.clone())];
+        let service = MosaicService::new(&libraries, MosaicOptions::default()).unwrap();
+
+        let submit = || {
+            let reply = service.handle("POST", "/jobs?library=my%20photos", b"not an image");
+            assert_eq!(reply.status, 202);
+            serde_json::from_slice::<Value>(&reply.body).unwrap()["id"]
+                .as_u64()
+                .unwrap()
+        };
+        let first = submit();
+        service.run(service.next_job());
+        let failed = status(&service, first);
+        for _ in 0..MAX_FINISHED {
+            submit();
+            service.run(service.next_job());
+        }
+
+        assert_eq!(failed["status"], "failed");
+        assert!(failed["error"]
+            .as_str()
+            .unwrap()
+            .starts_with("Unreadable target image"));
+        assert_eq!(
+            service.handle("GET", &format!("/jobs/{first}"), &[]).status,
+            404
+        );
+        assert_eq!(status(&service, first + 1)["status"], "failed");
+        remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_percent_decodes_query_values() {
+        assert_eq!(percent_decode("my%20photos"), "my photos");
+        assert_eq!(percent_decode("caf%C3%A9+au+lait"), "café au lait");
+        assert_eq!(percent_decode("100%"), "100%");
+        assert_eq!(percent_decode("%zz"), "%zz");
+    }
+}
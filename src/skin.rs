@@ -0,0 +1,205 @@
+use std::ops::RangeInclusive;
+
+use image::{imageops, Rgba, RgbaImage};
+
+use crate::protect::ProtectedArea;
+
+/// Longest side (in pixels) the target is scaled down to before looking for
+/// skin in it, which is plenty to find faces large enough to matter.
+const SEARCH_SIZE: u32 = 256;
+
+/// Smallest share of the target a patch of skin tone must cover to be
+/// protected, so that specks of skin color are passed over.
+const MIN_SHARE: f64 = 0.002;
+
+/// Heights, as multiples of their widths, of the patches protected, which
+/// are those shaped roughly like a face.
+const PATCH_RATIOS: RangeInclusive<f64> = 0.8..=2.2;
+
+/// Smallest share of its bounding box a patch must fill to be protected, as
+/// the oval of a face does but a diagonal arm doesn't.
+const MIN_FILL: f64 = 0.4;
+
+/// Share of a patch's size its area is widened by on each side, to take in
+/// the hairline and chin of a face.
+const MARGIN: f64 = 0.15;
+
+/// Find the patches of skin tone in the target shaped roughly like faces,
+/// returning the areas around them to protect.
+///
+/// This looks at color and shape alone, and is not a face detector. It finds
+/// most faces in ordinary light, but misses faces in colored light, and also
+/// protects hands, bare shoulders, wood, or sand of the right color and shape.
+pub(crate) fn find_skin_tones(target: &RgbaImage) -> Vec<ProtectedArea> {
+    let (width, height) = target.dimensions();
+    if width == 0 || height == 0 {
+        return Vec::new();
+    }
+    let scale = (f64::from(SEARCH_SIZE) / f64::from(width.max(height))).min(1.0);
+    let side = |length: u32| ((f64::from(length) * scale).round() as u32).max(1);
+    let small = imageops::thumbnail(target, side(width), side(height));
+
+    let min_size = (MIN_SHARE * f64::from(small.width()) * f64::from(small.height())) as usize;
+    patches(&small)
+        .into_iter()
+        .filter(|patch| patch.size >= min_size.max(1) && patch.is_face_shaped())
+        .map(|patch| {
+            // Back to target pixels, widened by the margin and kept on the target.
+            let margin_x = f64::from(patch.width()) * MARGIN;
+            let margin_y = f64::from(patch.height()) * MARGIN;
+            let left = ((f64::from(patch.left) - margin_x) / scale).max(0.0) as u32;
+            let top = ((f64::from(patch.top) - margin_y) / scale).max(0.0) as u32;
+            let right = ((f64::from(patch.right + 1) + margin_x) / scale).ceil() as u32;
+            let bottom = ((f64::from(patch.bottom + 1) + margin_y) / scale).ceil() as u32;
+            ProtectedArea::new(left, top, right.min(width) - left, bottom.min(height) - top)
+        })
+        .collect()
+}
+
+/// Whether the pixel is the color of skin, from the ranges of blue and red
+/// difference skin takes in the YCbCr color space, whatever its brightness.
+fn is_skin(&Rgba([r, g, b, a]): &Rgba<u8>) -> bool {
+    let (r, g, b) = (f64::from(r), f64::from(g), f64::from(b));
+    let luma = 0.299 * r + 0.587 * g + 0.114 * b;
+    let cb = 128.0 - 0.168_736 * r - 0.331_264 * g + 0.5 * b;
+    let cr = 128.0 + 0.5 * r - 0.418_688 * g - 0.081_312 * b;
+    a > 0 && luma > 40.0 && (77.0..=127.0).contains(&cb) && (133.0..=173.0).contains(&cr)
+}
+
+/// A patch of touching skin-colored pixels.
+struct Patch {
+    left: u32,
+    top: u32,
+    right: u32,
+    bottom: u32,
+    /// Number of pixels in the patch.
+    size: usize,
+}
+
+impl Patch {
+    fn width(&self) -> u32 {
+        self.right - self.left + 1
+    }
+
+    fn height(&self) -> u32 {
+        self.bottom - self.top + 1
+    }
+
+    fn is_face_shaped(&self) -> bool {
+        let (width, height) = (f64::from(self.width()), f64::from(self.height()));
+        PATCH_RATIOS.contains(&(height / width)) && self.size as f64 / (width * height) >= MIN_FILL
+    }
+}
+
+/// The patches of skin-colored pixels in the image, each pixel joined to
+/// those above, below, and either side of it.
+fn patches(img: &RgbaImage) -> Vec<Patch> {
+    let (width, height) = img.dimensions();
+    let mut skin: Vec<bool> = img.pixels().map(is_skin).collect();
+    let mut patches = Vec::new();
+    let mut stack = Vec::new();
+    for start in 0..skin.len() {
+        if !skin[start] {
+            continue;
+        }
+        skin[start] = false;
+        stack.push(start);
+        let (x, y) = (
+            (start % width as usize) as u32,
+            (start / width as usize) as u32,
+        );
+        let mut patch = Patch {
+            left: x,
+            top: y,
+            right: x,
+            bottom: y,
+            size: 0,
+        };
+        while let Some(i) = stack.pop() {
+            let (x, y) = ((i % width as usize) as u32, (i / width as usize) as u32);
+            patch.left = patch.left.min(x);
+            patch.right = patch.right.max(x);
+            patch.top = patch.top.min(y);
+            patch.bottom = patch.bottom.max(y);
+            patch.size += 1;
+            let neighbours = [
+                (x > 0).then(|| i - 1),
+                (x + 1 < width).then(|| i + 1),
+                (y > 0).then(|| i - width as usize),
+                (y + 1 < height).then(|| i + width as usize),
+            ];
+            for n in neighbours.into_iter().flatten() {
+                if skin[n] {
+                    skin[n] = false;
+                    stack.push(n);
+                }
+            }
+        }
+        patches.push(patch);
+    }
+    patches
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const SKIN: Rgba<u8> = Rgba([224, 172, 140, 255]);
+    const SKY: Rgba<u8> = Rgba([40, 80, 200, 255]);
+
+    /// A sky with a face-shaped oval of skin centred at (100, 80), 40 pixels
+    /// wide and 56 high.
+    fn portrait() -> RgbaImage {
+        RgbaImage::from_fn(300, 200, |x, y| {
+            let (dx, dy) = ((f64::from(x) - 100.0) / 20.0, (f64::from(y) - 80.0) / 28.0);
+            if dx * dx + dy * dy <= 1.0 {
+                SKIN
+            } else {
+                SKY
+            }
+        })
+    }
+
+    #[test]
+    fn test_recognises_skin() {
+        assert!(is_skin(&SKIN));
+        assert!(is_skin(&Rgba([141, 85, 36, 255])));
+        assert!(!is_skin(&SKY));
+        assert!(!is_skin(&Rgba([120, 200, 80, 255])));
+        assert!(!is_skin(&Rgba([224, 172, 140, 0])));
+    }
+
+    #[test]
+    fn test_protects_face_shaped_patches() {
+        let patches = find_skin_tones(&portrait());
+
+        assert_eq!(patches.len(), 1);
+        let face = &patches[0];
+        // The oval, with a margin around it.
+        assert!(face.x < 80 && face.x > 70, "{face:?}");
+        assert!(face.y < 52 && face.y > 40, "{face:?}");
+        assert!(
+            face.x + face.width > 120 && face.x + face.width < 130,
+            "{face:?}"
+        );
+        assert!(
+            face.y + face.height > 108 && face.y + face.height < 120,
+            "{face:?}"
+        );
+    }
+
+    #[test]
+    fn test_passes_over_specks_and_strips_of_skin() {
+        let mut img = RgbaImage::from_pixel(300, 200, SKY);
+        // A speck, and a long strip like an arm.
+        img.put_pixel(10, 10, SKIN);
+        for x in 50..250 {
+            for y in 150..170 {
+                img.put_pixel(x, y, SKIN);
+            }
+        }
+
+        assert!(find_skin_tones(&img).is_empty());
+        assert!(find_skin_tones(&RgbaImage::new(0, 0)).is_empty());
+    }
+}
@@ -0,0 +1,141 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::path::PathBuf;
+
+#[cfg(feature = "serde")]
+use serde::Serialize;
+
+use crate::core::PixelRegion;
+
+/// How the tiles of a planned mosaic are used and how well they match, to
+/// judge a build before or after drawing it.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct PlanStats {
+    /// Number of cells given a tile.
+    pub cells: usize,
+    /// Number of different library images used.
+    pub distinct_tiles: usize,
+    /// Most times any one library image is used.
+    pub most_reused: usize,
+    /// Number of library images used each number of times, keyed by the
+    /// number of times.
+    pub reuse: BTreeMap<usize, usize>,
+    /// The costs of the chosen tiles in their cells, lower is a better
+    /// match.
+    pub cost: CostStats,
+}
+
+/// A summary of the costs of the chosen tiles in their cells.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct CostStats {
+    /// Sum of the costs.
+    pub total: i64,
+    /// Mean cost per cell.
+    pub mean: f64,
+    /// The cost half the cells are at or under.
+    pub median: i64,
+    /// The cost 90% of the cells are at or under.
+    pub p90: i64,
+    /// The cost 99% of the cells are at or under.
+    pub p99: i64,
+    /// Highest cost of any cell.
+    pub max: i64,
+}
+
+impl PlanStats {
+    /// Describe the tiles planned and the cost of each in its cell, in the
+    /// same order.
+    pub(crate) fn new(tiles: &[(PathBuf, PixelRegion)], costs: &[i64]) -> PlanStats {
+        let mut uses: HashMap<&PathBuf, usize> = HashMap::new();
+        for (path, _) in tiles {
+            *uses.entry(path).or_default() += 1;
+        }
+        let mut reuse = BTreeMap::new();
+        for count in uses.values() {
+            *reuse.entry(*count).or_default() += 1;
+        }
+        PlanStats {
+            cells: tiles.len(),
+            distinct_tiles: uses.len(),
+            most_reused: uses.values().copied().max().unwrap_or_default(),
+            reuse,
+            cost: CostStats::of(costs),
+        }
+    }
+}
+
+impl CostStats {
+    fn of(costs: &[i64]) -> CostStats {
+        if costs.is_empty() {
+            return CostStats::default();
+        }
+        let mut sorted = costs.to_vec();
+        sorted.sort_unstable();
+        let total = sorted.iter().fold(0i64, |sum, c| sum.saturating_add(*c));
+        // The nearest rank: the smallest cost at least the share of cells
+        // are at or under.
+        let percentile = |p: usize| sorted[(sorted.len() * p).div_ceil(100).max(1) - 1];
+        CostStats {
+            total,
+            mean: total as f64 / sorted.len() as f64,
+            median: percentile(50),
+            p90: percentile(90),
+            p99: percentile(99),
+            max: sorted[sorted.len() - 1],
+        }
+    }
+}
+
+impl Display for PlanStats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        writeln!(f, "Cells: {}", self.cells)?;
+        writeln!(f, "Distinct tiles: {}", self.distinct_tiles)?;
+        writeln!(f, "Most reused: {}", self.most_reused)?;
+        writeln!(f, "Uses  Tiles")?;
+        for (uses, tiles) in &self.reuse {
+            writeln!(f, "{uses:>4}  {tiles:>5}")?;
+        }
+        let c = &self.cost;
+        write!(
+            f,
+            "Cost: total {}, mean {:.1}, median {}, 90% {}, 99% {}, max {}",
+            c.total, c.mean, c.median, c.p90, c.p99, c.max
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_counts_reuse_and_costs() {
+        let (a, b) = (PathBuf::from("a.png"), PathBuf::from("b.png"));
+        let region = PixelRegion::new(0, 0, 10, 10);
+        let tiles = vec![
+            (a.clone(), region),
+            (b.clone(), region),
+            (a.clone(), region),
+            (a, region),
+        ];
+
+        let stats = PlanStats::new(&tiles, &[40, 10, 30, 20]);
+
+        assert_eq!(stats.cells, 4);
+        assert_eq!(stats.distinct_tiles, 2);
+        assert_eq!(stats.most_reused, 3);
+        assert_eq!(stats.reuse, BTreeMap::from([(1, 1), (3, 1)]));
+        let cost = CostStats {
+            total: 100,
+            mean: 25.0,
+            median: 20,
+            p90: 40,
+            p99: 40,
+            max: 40,
+        };
+        assert_eq!(stats.cost, cost);
+        assert_eq!(PlanStats::new(&[], &[]).cost, CostStats::default());
+    }
+}
@@ -0,0 +1,969 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::hash::Hash;
+
+use image::RgbaImage;
+#[cfg(feature = "rand")]
+use rand::Rng;
+#[cfg(feature = "serde")]
+use serde::Deserialize;
+
+use crate::analysis::{AnalysisOptions, ImageInfo};
+use crate::cancel::CancelToken;
+use crate::collage::CollageTileStrategy;
+use crate::core::{Dimensions, PixelRegion, Rectangle, TileLocation};
+use crate::cost::CostWeights;
+use crate::diffusion::DiffusionTileStrategy;
+use crate::matching::{grid, tile_difference_weight, CellAnalyser, CellGrid, MatchingTileStrategy};
+use crate::order::ProcessingOrder;
+use crate::pruned::PrunedTileStrategy;
+#[cfg(feature = "rand")]
+use crate::refine::RefinedTileStrategy;
+use crate::refine::Refinement;
+use crate::seed::Seed;
+use crate::ties::TieBreak;
+
+/// Number of best tiles for a cell a temperature picks between.
+#[cfg(feature = "rand")]
+const PICKED_FROM: usize = 3;
+
+/// The weight of a tile in a cell it is pinned to, below any other so that
+/// it is always chosen there.
+pub(crate) const PINNED: i64 = i64::MIN;
+
+/// The weight of a tile in a cell it is excluded from, above any other so
+/// that it is only chosen there if every tile is excluded.
+pub(crate) const EXCLUDED: i64 = i64::MAX;
+
+/// A way of placing library tiles over a target.
+///
+/// This is the one abstraction every placement mode implements: grid-based
+/// strategies return a region per cell, while others, like the collage, may
+/// return regions spanning several cells. The regions are in target pixels
+/// and are scaled and drawn the same way whatever chose them.
+pub trait TilingStrategy<T> {
+    /// Choose the tiles to draw and the regions of the target to draw them in,
+    /// given the size of the cells the target is split into.
+    fn choose(
+        &self,
+        target: &RgbaImage,
+        cell_size: &Dimensions,
+    ) -> Vec<TileLocation<'_, T, PixelRegion>>;
+}
+
+/// Settings shared by all the strategies.
+#[derive(Clone)]
+pub struct StrategyOptions {
+    pub analysis: AnalysisOptions,
+    pub penalty: Penalty,
+    /// Order cells are visited in by strategies where order matters.
+    pub order: ProcessingOrder,
+    /// Seed for strategies that make random choices.
+    pub seed: Seed,
+    /// How to choose between tiles that match a cell equally well.
+    pub tie_break: TieBreak,
+    /// Budget for polishing the chosen tiles.
+    #[cfg_attr(not(feature = "rand"), allow(dead_code))]
+    pub refinement: Refinement,
+    /// Number of tiles with the closest color summaries compared in full by
+    /// the pruned strategy.
+    pub candidates: usize,
+    /// Whether the independent strategy rules out tiles by comparing coarser
+    /// versions of their samples with each cell's before comparing them all.
+    pub pyramid: bool,
+    /// How much each term counts towards the cost of drawing a tile in a cell.
+    pub cost: CostWeights,
+    /// Whether the holistic strategy also chooses tiles in the reverse
+    /// order, keeping whichever choices cost less in total.
+    pub reverse_pass: bool,
+    /// How likely a cell is to get one of its next best tiles instead of the
+    /// best, from 0 (never) to 1.
+    pub temperature: f64,
+    /// Stops choosing tiles between cells once cancelled, leaving the
+    /// choices unfinished.
+    pub cancel: CancelToken,
+}
+
+impl Default for StrategyOptions {
+    fn default() -> Self {
+        Self {
+            analysis: AnalysisOptions::new(None),
+            penalty: Penalty::default(),
+            order: ProcessingOrder::default(),
+            seed: Seed::default(),
+            tie_break: TieBreak::default(),
+            refinement: Refinement::default(),
+            candidates: 20,
+            pyramid: false,
+            cost: CostWeights::default(),
+            reverse_pass: false,
+            temperature: 0.0,
+            cancel: CancelToken::default(),
+        }
+    }
+}
+
+impl StrategyOptions {
+    /// Order weighted tiles, lowest weight first, breaking ties between
+    /// equal weights the same way in every run.
+    ///
+    /// Strategies iterate the analysis of the library in an order that
+    /// changes from run to run, so every choice between tiles goes through
+    /// this ordering for identical inputs to give identical mosaics.
+    pub(crate) fn compare_weights<T: Ord + Hash>(
+        &self,
+        (a, weight_a): &(&T, i64),
+        (b, weight_b): &(&T, i64),
+    ) -> Ordering {
+        weight_a
+            .cmp(weight_b)
+            .then_with(|| self.tie_break.compare(self.seed, *a, *b))
+    }
+
+    /// The tile with the lowest weight for the cell or, with a temperature
+    /// (and the `rand` feature), sometimes one of the next best, picked the
+    /// same way for the same seed.
+    #[cfg_attr(not(feature = "rand"), allow(unused_variables))]
+    pub(crate) fn pick<'t, T, I>(&self, weighted: I, cell: &Rectangle) -> Option<(&'t T, i64)>
+    where
+        T: Ord + Hash,
+        I: Iterator<Item = (&'t T, i64)>,
+    {
+        #[cfg(feature = "rand")]
+        if self.temperature > 0.0 {
+            return self.pick_warm(weighted, cell);
+        }
+        weighted.min_by(|a, b| self.compare_weights(a, b))
+    }
+
+    /// One of the tiles with the lowest weights for the cell, each the
+    /// temperature times as likely to be picked as the one before.
+    #[cfg(feature = "rand")]
+    fn pick_warm<'t, T, I>(&self, weighted: I, cell: &Rectangle) -> Option<(&'t T, i64)>
+    where
+        T: Ord + Hash,
+        I: Iterator<Item = (&'t T, i64)>,
+    {
+        let mut best: Vec<(&T, i64)> = Vec::with_capacity(PICKED_FROM + 1);
+        for w in weighted {
+            let at = best.partition_point(|b| self.compare_weights(b, &w) == Ordering::Less);
+            if at < PICKED_FROM {
+                best.insert(at, w);
+                best.truncate(PICKED_FROM);
+            }
+        }
+        // Pinned tiles are always chosen, and excluded ones only if nothing
+        // else is left.
+        if best.first().is_some_and(|(_, w)| *w == PINNED) {
+            return best.first().copied();
+        }
+        let allowed = best.iter().take_while(|(_, w)| *w != EXCLUDED).count();
+        best.truncate(allowed.max(1));
+        // Each tile is the temperature times as likely as the one before.
+        let temperature = self.temperature.min(1.0);
+        let shares: Vec<f64> = (0..best.len())
+            .map(|rank| temperature.powi(rank as i32))
+            .collect();
+        let position = (u64::from(cell.x) << 32) | u64::from(cell.y);
+        let mut rng = Seed(self.seed.0 ^ position).rng("temperature");
+        let mut draw = rng.gen::<f64>() * shares.iter().sum::<f64>();
+        for (w, share) in best.iter().zip(shares) {
+            if draw < share {
+                return Some(*w);
+            }
+            draw -= share;
+        }
+        best.last().copied()
+    }
+
+    /// The weight of drawing the tile in the cell, lower is a better match,
+    /// before any duplicates are counted.
+    pub(crate) fn match_cost(&self, tile: &ImageInfo, cell: &ImageInfo) -> i64 {
+        let difference = tile_difference_weight(tile, cell, &self.analysis);
+        self.cost.of_match(tile, difference)
+    }
+
+    /// The weight of drawing the tile in the cell at the rectangle: lowest
+    /// if the tile is pinned to the cell, highest if it is excluded from any
+    /// part of it, and otherwise its match cost.
+    pub(crate) fn weight(&self, tile: &ImageInfo, cell: &ImageInfo, r: &Rectangle) -> i64 {
+        fixed_weight(tile, r).unwrap_or_else(|| self.match_cost(tile, cell))
+    }
+
+    /// The weight of drawing the tile in the cell, as `weight` gives, or
+    /// `None` once it is sure to be more than the given weight, giving up
+    /// before comparing all of the tile's samples.
+    pub(crate) fn weight_within(
+        &self,
+        tile: &ImageInfo,
+        cell: &ImageInfo,
+        r: &Rectangle,
+        most: Option<i64>,
+    ) -> Option<i64> {
+        if let Some(weight) = fixed_weight(tile, r) {
+            return Some(weight).filter(|w| most.is_none_or(|most| *w <= most));
+        }
+        let limit = most.and_then(|most| self.cost.difference_within(tile, most));
+        let Some(limit) = limit else {
+            return Some(self.match_cost(tile, cell));
+        };
+        let difference = tile.diff_less_than(cell, &self.analysis.channel_weights, limit)?;
+        Some(self.cost.of_match(tile, difference))
+    }
+
+    /// Whether tiles can be ruled out by how far they are from a cell, which
+    /// needs their weights to rise with the difference and only the best tile
+    /// to be picked.
+    pub(crate) fn rules_out_tiles(&self) -> bool {
+        self.temperature <= 0.0
+            && self.cost.preference >= 0.0
+            && self.cost.color >= self.cost.preference
+    }
+
+    /// The tile with the lowest weight for the cell, as `pick` gives, giving
+    /// up on each tile once it can't beat the best so far.
+    pub(crate) fn best_match<'t, T, I>(
+        &self,
+        tiles: I,
+        cell: &ImageInfo,
+        r: &Rectangle,
+    ) -> Option<(&'t T, i64)>
+    where
+        T: Ord + Hash,
+        I: Iterator<Item = (&'t T, &'t ImageInfo)>,
+    {
+        if !self.rules_out_tiles() {
+            let weighted = tiles.map(|(tile, info)| (tile, self.weight(info, cell, r)));
+            return self.pick(weighted, r);
+        }
+        let mut best = None;
+        for (tile, info) in tiles {
+            if let Some(weight) = self.weight_within(info, cell, r, best.map(|(_, w)| w)) {
+                self.keep_best(&mut best, (tile, weight));
+            }
+        }
+        best
+    }
+
+    /// Keep the candidate as the best if it weighs less, or breaks the tie.
+    pub(crate) fn keep_best<'t, T: Ord + Hash>(
+        &self,
+        best: &mut Option<(&'t T, i64)>,
+        candidate: (&'t T, i64),
+    ) {
+        if best.is_none_or(|b| self.compare_weights(&candidate, &b).is_lt()) {
+            *best = Some(candidate);
+        }
+    }
+
+    /// The weight to add for a duplicate the given offset (in pixels across
+    /// and down) away, from a cell of the given size.
+    pub(crate) fn duplicate_cost(&self, offset: (u32, u32), cell: Dimensions) -> i64 {
+        self.cost.of_duplicate(&self.penalty, offset, cell)
+    }
+
+    /// How many cells of the given size across and down from a cell its
+    /// duplicates cost anything, or `None` if they cost something at any
+    /// distance because reuse is weighted.
+    pub(crate) fn duplicate_window(&self, cell: Dimensions) -> Option<(u32, u32)> {
+        (self.cost.reuse == 0.0).then(|| self.penalty.window(cell))
+    }
+}
+
+/// The weight the tile has in the cell at the rectangle whatever it looks
+/// like, if it is pinned to the cell or excluded from it. A pin counts over
+/// an exclusion.
+pub(crate) fn fixed_weight(tile: &ImageInfo, r: &Rectangle) -> Option<i64> {
+    if tile.is_pinned_to(r) {
+        Some(PINNED)
+    } else if tile.is_excluded_from(r) {
+        Some(EXCLUDED)
+    } else {
+        None
+    }
+}
+
+/// How strongly to discourage placing the same tile near itself.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
+pub struct Penalty {
+    /// Weight added to a duplicate tile placed right next to itself.
+    pub amount: i64,
+    /// Distance (in pixels) at which the penalty has halved, beyond which
+    /// duplicates aren't penalised.
+    pub radius: u32,
+    /// Difference (in the same units as matching weights) within which two
+    /// distinct tiles look alike enough to be penalised as duplicates of each
+    /// other, or only the same tile is penalised if not given.
+    pub similar_within: Option<i64>,
+    /// Distances (in cells) along a row and down a column at which the
+    /// penalty has halved, and beyond which duplicates aren't penalised, in
+    /// place of `radius`, so that duplicates can be kept further apart in one
+    /// direction than the other.
+    pub cell_radii: Option<CellRadii>,
+}
+
+/// Distances (in cells) along a row and down a column at which a penalty has
+/// halved, with those in between falling on an ellipse.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(deny_unknown_fields))]
+pub struct CellRadii {
+    /// Distance (in cells) along a row.
+    pub horizontal: f64,
+    /// Distance (in cells) down a column.
+    pub vertical: f64,
+}
+
+impl Default for Penalty {
+    fn default() -> Self {
+        Self {
+            amount: 1_000_000,
+            radius: 100,
+            similar_within: None,
+            cell_radii: None,
+        }
+    }
+}
+
+impl Penalty {
+    /// The weight to add to a duplicate the given offset (in pixels across
+    /// and down) away, from a cell of the given size, or nothing once it is
+    /// past the radius.
+    pub(crate) fn at(&self, (dx, dy): (u32, u32), (width, height): Dimensions) -> i64 {
+        let Some(radii) = self.cell_radii else {
+            let radius = i64::from(self.radius);
+            let distance = i64::from(dx) + i64::from(dy);
+            if distance > radius {
+                return 0;
+            }
+            return self.amount.saturating_mul(radius) / (radius + distance).max(1);
+        };
+        // How many radii away the duplicate is in each direction.
+        let radii_away = |d: u32, size: u32, radius: f64| {
+            if d == 0 {
+                0.0
+            } else {
+                f64::from(d) / (f64::from(size.max(1)) * radius)
+            }
+        };
+        let x = radii_away(dx, width, radii.horizontal);
+        let y = radii_away(dy, height, radii.vertical);
+        let away = x.hypot(y);
+        if away > 1.0 {
+            return 0;
+        }
+        (self.amount as f64 / (1.0 + away)) as i64
+    }
+
+    /// How many cells of the given size across and down from a cell its
+    /// duplicates can be and still be penalised.
+    pub(crate) fn window(&self, (width, height): Dimensions) -> (u32, u32) {
+        match self.cell_radii {
+            None => (self.radius / width.max(1), self.radius / height.max(1)),
+            Some(radii) => (radii.horizontal as u32, radii.vertical as u32),
+        }
+    }
+}
+
+// Registry
+
+type Constructor<'a, T> =
+    fn(&'a HashMap<&'a T, ImageInfo>, &'a StrategyOptions) -> Box<dyn TilingStrategy<T> + 'a>;
+
+/// The strategies that can be selected by name at runtime.
+fn registry<'a, T: Ord + Hash + 'a>() -> [(&'static str, Constructor<'a, T>); 5] {
+    [
+        ("independent", |analysis, options| {
+            Box::new(MatchingTileStrategy::new(analysis, options))
+        }),
+        ("holistic", |analysis, options| {
+            Box::new(HolisticTileStrategy::new(analysis, options))
+        }),
+        ("diffusion", |analysis, options| {
+            Box::new(DiffusionTileStrategy::new(analysis, options))
+        }),
+        ("pruned", |analysis, options| {
+            Box::new(PrunedTileStrategy::new(analysis, options))
+        }),
+        ("collage", |analysis, options| {
+            Box::new(CollageTileStrategy::new(analysis, options))
+        }),
+    ]
+}
+
+/// The names of the strategies that can be built with `build_strategy`.
+pub fn strategy_names() -> Vec<&'static str> {
+    registry::<()>().iter().map(|(name, _)| *name).collect()
+}
+
+/// Build the strategy with the given name, if there is one, refining its
+/// choices if the options give a budget for it.
+pub fn build_strategy<'a, T: Ord + Hash + 'a>(
+    name: &str,
+    analysis: &'a HashMap<&'a T, ImageInfo>,
+    options: &'a StrategyOptions,
+) -> Option<Box<dyn TilingStrategy<T> + 'a>> {
+    let (_, constructor) = registry().into_iter().find(|(n, _)| *n == name)?;
+    let strategy = constructor(analysis, options);
+    #[cfg(feature = "rand")]
+    if options.refinement.iterations > 0 {
+        return Some(Box::new(RefinedTileStrategy::new(
+            strategy, analysis, options,
+        )));
+    }
+    Some(strategy)
+}
+
+// Holistic tile selection
+
+/// Choose tiles cell by cell, penalising the chosen tile in the cells still
+/// to be chosen so that duplicates are spread out.
+pub struct HolisticTileStrategy<'a, T> {
+    options: &'a StrategyOptions,
+    analysis: &'a HashMap<&'a T, ImageInfo>,
+}
+
+/// The weight of every tile in every cell, stored as one row of tiles per
+/// cell so that penalising a cell touches a single run of memory.
+struct CellCosts<'a, T> {
+    /// The tiles, in order, that each row holds a weight for.
+    tiles: Vec<&'a T>,
+    weights: Vec<i64>,
+}
+
+// Derived, this would needlessly require the tiles themselves to be Clone.
+impl<T> Clone for CellCosts<'_, T> {
+    fn clone(&self) -> Self {
+        CellCosts {
+            tiles: self.tiles.clone(),
+            weights: self.weights.clone(),
+        }
+    }
+}
+
+impl<'a, T: Ord> CellCosts<'a, T> {
+    /// The position of the tile in each row.
+    fn index(&self, tile: &T) -> usize {
+        self.tiles.binary_search_by(|t| (*t).cmp(tile)).unwrap()
+    }
+
+    fn row(&self, cell: usize) -> &[i64] {
+        let width = self.tiles.len();
+        &self.weights[cell * width..(cell + 1) * width]
+    }
+
+    fn row_mut(&mut self, cell: usize) -> &mut [i64] {
+        let width = self.tiles.len();
+        &mut self.weights[cell * width..(cell + 1) * width]
+    }
+}
+
+impl<'a, T: Ord + Hash> HolisticTileStrategy<'a, T> {
+    pub fn new(
+        analysis: &'a HashMap<&'a T, ImageInfo>,
+        options: &'a StrategyOptions,
+    ) -> HolisticTileStrategy<'a, T> {
+        HolisticTileStrategy { options, analysis }
+    }
+
+    /// The weight of every tile in every cell, or `None` if cancelled before
+    /// all the cells were analysed.
+    fn cell_costs(&self, analyser: &CellAnalyser, cells: &[Rectangle]) -> Option<CellCosts<'a, T>> {
+        let mut tiles: Vec<&'a T> = self.analysis.keys().copied().collect();
+        tiles.sort();
+        let mut weights = Vec::with_capacity(cells.len() * tiles.len());
+        for r in cells {
+            if self.options.cancel.is_cancelled() {
+                return None;
+            }
+            let target_info = analyser.analyse(r);
+            weights.extend(
+                tiles
+                    .iter()
+                    .map(|tile| self.options.weight(&self.analysis[tile], &target_info, r)),
+            );
+        }
+        Some(CellCosts { tiles, weights })
+    }
+
+    /// The tiles penalised as duplicates of the tile, by their position in
+    /// each row: itself, and any that look alike within the penalty's
+    /// threshold.
+    fn lookalikes(&self, costs: &CellCosts<'a, T>, tile: usize) -> Vec<usize> {
+        let Some(threshold) = self.options.penalty.similar_within else {
+            return vec![tile];
+        };
+        let info = &self.analysis[costs.tiles[tile]];
+        costs
+            .tiles
+            .iter()
+            .enumerate()
+            .filter(|(other, other_tile)| {
+                *other == tile
+                    || self.analysis[**other_tile]
+                        .diff_sum(info, &self.options.analysis.channel_weights)
+                        <= threshold
+            })
+            .map(|(other, _)| other)
+            .collect()
+    }
+}
+
+impl<T: Ord + Hash> TilingStrategy<T> for HolisticTileStrategy<'_, T> {
+    fn choose(
+        &self,
+        target: &RgbaImage,
+        cell_size: &Dimensions,
+    ) -> Vec<TileLocation<'_, T, PixelRegion>> {
+        let analyser = CellAnalyser::new(target, *cell_size, &self.options.analysis);
+        let mut cells = grid(target, cell_size);
+        self.options.order.arrange(&mut cells, self.options.seed);
+        // Pinned cells go first, so that every other cell sees their tiles.
+        let is_pinned = |r: &Rectangle| self.analysis.values().any(|info| info.is_pinned_to(r));
+        cells.sort_by_key(|r| !is_pinned(r));
+        let pinned = cells.iter().take_while(|r| is_pinned(r)).count();
+        let Some(costs) = self.cell_costs(&analyser, &cells) else {
+            return vec![];
+        };
+        let grid = CellGrid::new(cells, *cell_size);
+
+        let mut lookalikes = HashMap::new();
+        let forward: Vec<usize> = (0..grid.cells.len()).collect();
+        let mut tiles = self.pass(&grid, &forward, costs.clone(), &mut lookalikes);
+        if self.options.reverse_pass {
+            // Later cells get the leftovers of earlier ones, so choosing in
+            // the other order as well gives them a turn at going first.
+            let backward: Vec<usize> = (0..pinned)
+                .chain((pinned..grid.cells.len()).rev())
+                .collect();
+            let mut reversed = self.pass(&grid, &backward, costs.clone(), &mut lookalikes);
+            reversed.reverse();
+            if reversed.len() == tiles.len()
+                && self.total_cost(&grid, &reversed, &costs, &mut lookalikes)
+                    < self.total_cost(&grid, &tiles, &costs, &mut lookalikes)
+            {
+                tiles = reversed;
+            }
+        }
+        tiles
+            .into_iter()
+            .map(|(tile, cell)| (costs.tiles[tile], PixelRegion::from(&grid.cells[cell])))
+            .collect()
+    }
+}
+
+impl<'a, T: Ord + Hash> HolisticTileStrategy<'a, T> {
+    /// Choose the tile for each cell in turn, penalising each choice in the
+    /// cells after it. Cells and tiles are given by their positions in the
+    /// grid's cells and the rows of `costs`.
+    fn pass(
+        &self,
+        grid: &CellGrid,
+        order: &[usize],
+        mut costs: CellCosts<'a, T>,
+        lookalikes: &mut HashMap<usize, Vec<usize>>,
+    ) -> Vec<(usize, usize)> {
+        // When each cell is chosen, to tell which cells are still to come.
+        let mut turns = vec![0; grid.cells.len()];
+        for (turn, &cell) in order.iter().enumerate() {
+            turns[cell] = turn;
+        }
+        let mut tiles = Vec::with_capacity(order.len());
+        for (turn, &cell) in order.iter().enumerate() {
+            if self.options.cancel.is_cancelled() {
+                break;
+            }
+            let best_tile = best_tile(&costs, cell, &grid.cells[cell], self.options);
+            let duplicates = lookalikes
+                .entry(best_tile)
+                .or_insert_with(|| self.lookalikes(&costs, best_tile));
+            let later = |other: usize| turns[other] > turn;
+            adjust_weights(
+                &mut costs,
+                grid,
+                cell,
+                &order[turn + 1..],
+                later,
+                duplicates,
+                self.options,
+            );
+            tiles.push((best_tile, cell));
+        }
+        tiles
+    }
+
+    /// The total cost of the tiles in their cells: how well each matches, and
+    /// the penalty for each pair of duplicates.
+    fn total_cost(
+        &self,
+        grid: &CellGrid,
+        tiles: &[(usize, usize)],
+        costs: &CellCosts<'a, T>,
+        lookalikes: &mut HashMap<usize, Vec<usize>>,
+    ) -> i64 {
+        let mut placed: Vec<Vec<&Rectangle>> = vec![Vec::new(); costs.tiles.len()];
+        let mut total = 0i64;
+        for &(tile, cell) in tiles {
+            let r = &grid.cells[cell];
+            // Pinned and excluded tiles are the same whichever pass chose
+            // them, so only the rest are weighed.
+            let weight = costs.row(cell)[tile];
+            if weight != PINNED && weight != EXCLUDED {
+                total = total.saturating_add(weight);
+            }
+            let duplicates = lookalikes
+                .entry(tile)
+                .or_insert_with(|| self.lookalikes(costs, tile));
+            for other in duplicates.iter().flat_map(|d| &placed[*d]) {
+                let offset = (r.x.abs_diff(other.x), r.y.abs_diff(other.y));
+                let cost = self
+                    .options
+                    .duplicate_cost(offset, (other.width, other.height));
+                total = total.saturating_add(cost);
+            }
+            placed[tile].push(r);
+        }
+        total
+    }
+}
+
+/// The position of the tile with the lowest weight for the cell, or with a
+/// temperature sometimes one of the next best.
+fn best_tile<T: Ord + Hash>(
+    costs: &CellCosts<'_, T>,
+    cell: usize,
+    r: &Rectangle,
+    options: &StrategyOptions,
+) -> usize {
+    let weighted = costs
+        .tiles
+        .iter()
+        .copied()
+        .zip(costs.row(cell).iter().copied());
+    let (tile, _) = options.pick(weighted, r).unwrap();
+    costs.index(tile)
+}
+
+/// Penalise the tile chosen for a cell, and its lookalikes, in the cells
+/// still to be chosen near enough for duplicates to cost anything, or in all
+/// of them if duplicates cost something at any distance.
+fn adjust_weights<T: Ord, F: Fn(usize) -> bool>(
+    costs: &mut CellCosts<'_, T>,
+    grid: &CellGrid,
+    chosen: usize,
+    remaining: &[usize],
+    later: F,
+    duplicates: &[usize],
+    options: &StrategyOptions,
+) {
+    // Cells are visited in processing order, so later cells only ever see
+    // the penalties of the cells before them. Each cell's row is updated on
+    // its own, so the rows could be penalised in parallel.
+    let mut penalise = |cell: usize| {
+        let (a, b) = (&grid.cells[chosen], &grid.cells[cell]);
+        let offset = (a.x.abs_diff(b.x), a.y.abs_diff(b.y));
+        let cost = options.duplicate_cost(offset, (a.width, a.height));
+        let row = costs.row_mut(cell);
+        for &tile in duplicates {
+            row[tile] = row[tile].saturating_add(cost);
+        }
+    };
+    match options.duplicate_window(grid.cell_size) {
+        Some(window) => grid
+            .around(chosen, window)
+            .filter(|cell| later(*cell))
+            .for_each(&mut penalise),
+        None => remaining.iter().for_each(|cell| penalise(*cell)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::analysis::analyse;
+    use image::Rgba;
+
+    fn solid(color: [u8; 4]) -> RgbaImage {
+        RgbaImage::from_pixel(20, 20, Rgba(color))
+    }
+
+    #[test]
+    fn test_builds_registered_strategies_by_name() {
+        let analysis: HashMap<&String, ImageInfo> = HashMap::new();
+        let options = StrategyOptions {
+            analysis: AnalysisOptions::new(Some(1)),
+            ..StrategyOptions::default()
+        };
+
+        assert_eq!(
+            strategy_names(),
+            vec!["independent", "holistic", "diffusion", "pruned", "collage"]
+        );
+        for name in strategy_names() {
+            assert!(build_strategy(name, &analysis, &options).is_some());
+        }
+        assert!(build_strategy("unknown", &analysis, &options).is_none());
+    }
+
+    #[test]
+    fn test_strategies_stop_once_cancelled() {
+        let red = "red".to_string();
+        let options = StrategyOptions {
+            analysis: AnalysisOptions::new(Some(1)),
+            ..StrategyOptions::default()
+        };
+        let analysis =
+            HashMap::from([(&red, analyse(&solid([255, 0, 0, 255]), &options.analysis))]);
+        let target = RgbaImage::from_pixel(40, 20, Rgba([255, 0, 0, 255]));
+        options.cancel.cancel();
+
+        for name in strategy_names() {
+            let strategy = build_strategy(name, &analysis, &options).unwrap();
+            assert!(strategy.choose(&target, &(10, 10)).is_empty(), "{name}");
+        }
+    }
+
+    #[cfg(feature = "rand")]
+    #[test]
+    fn test_sometimes_picks_the_next_best_tiles() {
+        let tiles = ["a", "b", "c", "d"];
+        let weighted = || tiles.iter().zip([10, 20, 30, 40]);
+        let picks = |temperature, seed| -> Vec<&str> {
+            let options = StrategyOptions {
+                temperature,
+                seed: Seed(seed),
+                ..StrategyOptions::default()
+            };
+            (0..100)
+                .map(|x| Rectangle::new(x, 0, 1, 1))
+                .map(|cell| *options.pick(weighted(), &cell).unwrap().0)
+                .collect()
+        };
+
+        assert!(picks(0.0, 1).iter().all(|t| *t == "a"));
+        let warm = picks(0.5, 1);
+        assert_eq!(warm, picks(0.5, 1));
+        assert_ne!(warm, picks(0.5, 2));
+        let count = |tile| warm.iter().filter(|t| **t == tile).count();
+        assert!(count("a") > count("b") && count("b") > count("c") && count("c") > 0);
+        assert_eq!(count("d"), 0);
+    }
+
+    #[test]
+    fn test_penalises_by_cells_along_each_axis() {
+        let pixels = Penalty {
+            amount: 1000,
+            radius: 10,
+            ..Penalty::default()
+        };
+        assert_eq!(pixels.at((4, 6), (10, 10)), 500);
+
+        let cells = Penalty {
+            amount: 1000,
+            cell_radii: Some(CellRadii {
+                horizontal: 4.0,
+                vertical: 1.0,
+            }),
+            ..Penalty::default()
+        };
+        let cell = (10, 20);
+        assert_eq!(cells.at((0, 0), cell), 1000);
+        // Four cells along the row is as far as one cell down the column.
+        assert_eq!(cells.at((40, 0), cell), 500);
+        assert_eq!(cells.at((0, 20), cell), 500);
+        // A diagonal neighbour is penalised less than a duplicate two cells
+        // along the same row.
+        assert!(cells.at((10, 20), cell) < cells.at((20, 0), cell));
+    }
+
+    #[test]
+    fn test_holistic_strategy_spreads_duplicates() {
+        let (red, dark_red) = ("red".to_string(), "dark red".to_string());
+        let options = StrategyOptions {
+            analysis: AnalysisOptions::new(Some(1)),
+            penalty: Penalty {
+                amount: i64::MAX,
+                radius: 20,
+                similar_within: None,
+                cell_radii: None,
+            },
+            ..StrategyOptions::default()
+        };
+        let analysis = HashMap::from([
+            (&red, analyse(&solid([255, 0, 0, 255]), &options.analysis)),
+            (
+                &dark_red,
+                analyse(&solid([200, 0, 0, 255]), &options.analysis),
+            ),
+        ]);
+        let target = RgbaImage::from_pixel(40, 20, Rgba([255, 0, 0, 255]));
+
+        let independent = build_strategy("independent", &analysis, &options).unwrap();
+        let holistic = build_strategy("holistic", &analysis, &options).unwrap();
+
+        let tiles = |s: &dyn TilingStrategy<String>| -> Vec<String> {
+            s.choose(&target, &(20, 20))
+                .iter()
+                .map(|(t, _)| (*t).clone())
+                .collect()
+        };
+        assert_eq!(tiles(independent.as_ref()), vec!["red", "red"]);
+        assert_eq!(tiles(holistic.as_ref()), vec!["red", "dark red"]);
+    }
+
+    #[test]
+    fn test_penalises_only_within_the_radius() {
+        let pixels = Penalty {
+            amount: 1000,
+            radius: 20,
+            ..Penalty::default()
+        };
+        assert_eq!(pixels.at((20, 0), (10, 10)), 500);
+        assert_eq!(pixels.at((20, 10), (10, 10)), 0);
+        assert_eq!(pixels.window((10, 5)), (2, 4));
+
+        let cells = Penalty {
+            cell_radii: Some(CellRadii {
+                horizontal: 2.5,
+                vertical: 1.0,
+            }),
+            ..pixels
+        };
+        assert_eq!(cells.at((20, 0), (10, 10)), 555);
+        assert_eq!(cells.at((30, 0), (10, 10)), 0);
+        assert_eq!(cells.window((10, 10)), (2, 1));
+    }
+
+    #[test]
+    fn test_holistic_strategy_reuses_tiles_beyond_the_radius() {
+        let (red, dark_red, darker_red) = (
+            "red".to_string(),
+            "dark red".to_string(),
+            "darker red".to_string(),
+        );
+        let analysis_options = AnalysisOptions::new(Some(1));
+        let analysis = HashMap::from([
+            (&red, analyse(&solid([255, 0, 0, 255]), &analysis_options)),
+            (
+                &dark_red,
+                analyse(&solid([200, 0, 0, 255]), &analysis_options),
+            ),
+            (
+                &darker_red,
+                analyse(&solid([150, 0, 0, 255]), &analysis_options),
+            ),
+        ]);
+        let target = RgbaImage::from_pixel(60, 20, Rgba([255, 0, 0, 255]));
+
+        let tiles = |reuse| -> Vec<String> {
+            let options = StrategyOptions {
+                analysis: AnalysisOptions::new(Some(1)),
+                penalty: Penalty {
+                    amount: 1_000_000_000,
+                    radius: 20,
+                    ..Penalty::default()
+                },
+                cost: CostWeights {
+                    reuse,
+                    ..CostWeights::default()
+                },
+                ..StrategyOptions::default()
+            };
+            HolisticTileStrategy::new(&analysis, &options)
+                .choose(&target, &(20, 20))
+                .iter()
+                .map(|(t, _)| (*t).clone())
+                .collect()
+        };
+        assert_eq!(tiles(0.0), vec!["red", "dark red", "red"]);
+        // Reuse costs the same at any distance, so is still counted.
+        assert_eq!(tiles(1e12), vec!["red", "dark red", "darker red"]);
+    }
+
+    #[test]
+    fn test_holistic_strategy_keeps_the_cheaper_pass() {
+        let (red, dim_red) = ("red".to_string(), "dim red".to_string());
+        let analysis_options = AnalysisOptions::new(Some(1));
+        let analysis = HashMap::from([
+            (&red, analyse(&solid([255, 0, 0, 255]), &analysis_options)),
+            (
+                &dim_red,
+                analyse(&solid([210, 0, 0, 255]), &analysis_options),
+            ),
+        ]);
+        // The left cell is a little closer to red than dim red, but the
+        // right cell is red and far from dim red.
+        let target = RgbaImage::from_fn(40, 20, |x, _| {
+            Rgba(if x < 20 {
+                [235, 0, 0, 255]
+            } else {
+                [255, 0, 0, 255]
+            })
+        });
+
+        let tiles = |reverse_pass| -> Vec<String> {
+            let options = StrategyOptions {
+                analysis: AnalysisOptions::new(Some(1)),
+                penalty: Penalty {
+                    amount: i64::MAX,
+                    ..Penalty::default()
+                },
+                reverse_pass,
+                ..StrategyOptions::default()
+            };
+            HolisticTileStrategy::new(&analysis, &options)
+                .choose(&target, &(20, 20))
+                .iter()
+                .map(|(t, _)| (*t).clone())
+                .collect()
+        };
+        assert_eq!(tiles(false), vec!["red", "dim red"]);
+        assert_eq!(tiles(true), vec!["dim red", "red"]);
+    }
+
+    #[test]
+    fn test_holistic_strategy_spreads_lookalikes() {
+        let (red, near_red, dark_red) = (
+            "red".to_string(),
+            "near red".to_string(),
+            "dark red".to_string(),
+        );
+        let analysis_options = AnalysisOptions::new(Some(1));
+        let analysis = HashMap::from([
+            (&red, analyse(&solid([255, 0, 0, 255]), &analysis_options)),
+            (
+                &near_red,
+                analyse(&solid([250, 0, 0, 255]), &analysis_options),
+            ),
+            (
+                &dark_red,
+                analyse(&solid([200, 0, 0, 255]), &analysis_options),
+            ),
+        ]);
+        let lookalike =
+            tile_difference_weight(&analysis[&red], &analysis[&near_red], &analysis_options);
+        let target = RgbaImage::from_pixel(40, 20, Rgba([255, 0, 0, 255]));
+
+        let tiles = |similar_within| -> Vec<String> {
+            let options = StrategyOptions {
+                analysis: AnalysisOptions::new(Some(1)),
+                penalty: Penalty {
+                    amount: i64::MAX,
+                    radius: 20,
+                    similar_within,
+                    cell_radii: None,
+                },
+                ..StrategyOptions::default()
+            };
+            HolisticTileStrategy::new(&analysis, &options)
+                .choose(&target, &(20, 20))
+                .iter()
+                .map(|(t, _)| (*t).clone())
+                .collect()
+        };
+        assert_eq!(tiles(None), vec!["red", "near red"]);
+        assert_eq!(tiles(Some(lookalike)), vec!["red", "dark red"]);
+    }
+}
@@ -0,0 +1,63 @@
+use crate::core::Dimensions;
+use crate::MosaicOptions;
+
+/// Number of cells aimed for per distinct library image, so each image is
+/// used a few times without any one being repeated everywhere.
+const CELLS_PER_IMAGE: usize = 4;
+
+/// Fewest cells worth splitting a target into, however small the library.
+const MIN_CELLS: usize = 100;
+
+/// Most cells to split a target into, however large the library.
+const MAX_CELLS: usize = 20_000;
+
+/// Longest side (in pixels) of the mosaic that tiles are shrunk to keep
+/// within.
+const MAX_OUTPUT: u32 = 12_000;
+
+/// Smallest size (in pixels) tiles are drawn at.
+const MIN_TILE_SIZE: u32 = 16;
+
+/// Suggest settings for a target of the given size and a library of the
+/// given number of images, with about four cells per image and tiles no
+/// larger than keeps the mosaic printable.
+pub fn suggest_parameters(target: Dimensions, library_size: usize) -> MosaicOptions {
+    let (width, height) = (target.0.max(1), target.1.max(1));
+    let cells = (library_size * CELLS_PER_IMAGE).clamp(MIN_CELLS, MAX_CELLS);
+    let area = f64::from(width) * f64::from(height);
+    let cell_size = ((area / cells as f64).sqrt().round() as u32).clamp(1, width.max(height));
+
+    let defaults = MosaicOptions::default();
+    let columns = width.max(height).div_ceil(cell_size);
+    let tile_size = (MAX_OUTPUT / columns).clamp(MIN_TILE_SIZE, defaults.tile_size);
+    MosaicOptions {
+        cell_size,
+        cell_budget: None,
+        tile_size,
+        ..defaults
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_suggests_a_few_cells_per_image() {
+        let options = suggest_parameters((4000, 3000), 300);
+        // 1200 cells of 100 pixels.
+        assert_eq!(options.cell_size, 100);
+        assert_eq!(options.tile_size, 100);
+
+        let small = suggest_parameters((4000, 3000), 3);
+        assert_eq!(small.cell_size, 346);
+    }
+
+    #[test]
+    fn test_shrinks_tiles_for_large_libraries() {
+        let options = suggest_parameters((4000, 3000), 100_000);
+        // 20,000 cells of 24 pixels, 167 across.
+        assert_eq!(options.cell_size, 24);
+        assert_eq!(options.tile_size, 71);
+    }
+}
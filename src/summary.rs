@@ -0,0 +1,212 @@
+use std::cmp::Reverse;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// Number of dominant colors found for each image.
+const DOMINANT_COLORS: usize = 3;
+
+/// Number of equal ranges of hue that samples are counted in.
+const HUE_BINS: usize = 12;
+
+/// Number of rounds of k-means used to find the dominant colors.
+const ITERATIONS: usize = 8;
+
+/// Scale applied to the hue histogram distance (at most 2000) to weigh it
+/// about the same as a difference in one dominant color.
+const HUE_SCALE: i64 = 100;
+
+/// A coarse description of the colors of an image, for cheaply ruling out
+/// library images before comparing them in full.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Summary {
+    /// The centres of the main clusters of colors, largest cluster first.
+    dominant: Vec<[u8; 3]>,
+    /// Share (per mille) of the samples in each range of hue, ignoring
+    /// samples too dark or grey to have a clear hue.
+    hues: [u16; HUE_BINS],
+}
+
+impl Summary {
+    /// Summarise the given red, green, and blue samples.
+    pub fn of(samples: &[u8]) -> Summary {
+        let colors: Vec<[u8; 3]> = samples
+            .chunks_exact(3)
+            .map(|c| [c[0], c[1], c[2]])
+            .collect();
+
+        let mut hues = [0; HUE_BINS];
+        let count = colors.len().max(1);
+        let mut counts = [0usize; HUE_BINS];
+        for bin in colors.iter().filter_map(hue_bin) {
+            counts[bin] += 1;
+        }
+        for (share, n) in hues.iter_mut().zip(counts) {
+            *share = (n * 1000 / count) as u16;
+        }
+
+        Summary {
+            dominant: dominant_colors(&colors),
+            hues,
+        }
+    }
+
+    /// How different this summary is from another, lower is more alike.
+    pub fn distance(&self, other: &Summary) -> i64 {
+        let colors: i64 = self
+            .dominant
+            .iter()
+            .zip(&other.dominant)
+            .map(|(a, b)| sqr_distance(a, b))
+            .sum();
+        let hues: i64 = self
+            .hues
+            .iter()
+            .zip(&other.hues)
+            .map(|(a, b)| i64::from(a.abs_diff(*b)))
+            .sum();
+        colors + hues * HUE_SCALE
+    }
+}
+
+/// The centres of the largest clusters of the colors, found with k-means
+/// started from colors spread across the range of brightness.
+fn dominant_colors(colors: &[[u8; 3]]) -> Vec<[u8; 3]> {
+    if colors.is_empty() {
+        return vec![[0; 3]; DOMINANT_COLORS];
+    }
+    clusters(colors, DOMINANT_COLORS)
+        .into_iter()
+        .map(|(centre, _)| centre)
+        .collect()
+}
+
+/// The centres of the given number of clusters of the colors, with how many
+/// colors are in each, found with k-means started from colors spread across
+/// the range of brightness. Largest cluster first.
+pub(crate) fn clusters(colors: &[[u8; 3]], count: usize) -> Vec<([u8; 3], usize)> {
+    if colors.is_empty() {
+        return Vec::new();
+    }
+
+    let mut by_brightness = colors.to_vec();
+    by_brightness.sort_by_key(|[r, g, b]| u16::from(*r) + u16::from(*g) + u16::from(*b));
+    let mut centres: Vec<[f32; 3]> = (0..count)
+        .map(|i| by_brightness[(2 * i + 1) * colors.len() / (2 * count)].map(f32::from))
+        .collect();
+
+    let mut sizes = vec![0usize; count];
+    for _ in 0..ITERATIONS {
+        let mut sums = vec![[0f32; 3]; count];
+        sizes = vec![0; count];
+        for color in colors {
+            let nearest = nearest(&centres, color);
+            sizes[nearest] += 1;
+            for (sum, v) in sums[nearest].iter_mut().zip(color) {
+                *sum += f32::from(*v);
+            }
+        }
+        for ((centre, sum), size) in centres.iter_mut().zip(sums).zip(&sizes) {
+            if *size > 0 {
+                *centre = sum.map(|v| v / *size as f32);
+            }
+        }
+    }
+
+    let mut order: Vec<usize> = (0..count).collect();
+    order.sort_by_key(|&i| Reverse(sizes[i]));
+    order
+        .into_iter()
+        .map(|i| (centres[i].map(|v| v.round() as u8), sizes[i]))
+        .collect()
+}
+
+/// The index of the centre closest to the color.
+fn nearest(centres: &[[f32; 3]], color: &[u8; 3]) -> usize {
+    let distance = |centre: &[f32; 3]| -> f32 {
+        centre
+            .iter()
+            .zip(color)
+            .map(|(c, v)| (c - f32::from(*v)).powi(2))
+            .sum()
+    };
+    (0..centres.len())
+        .min_by(|&a, &b| distance(&centres[a]).total_cmp(&distance(&centres[b])))
+        .unwrap()
+}
+
+/// The range of hue the color is in, if it is bright and saturated enough
+/// to have a clear hue.
+fn hue_bin(&[r, g, b]: &[u8; 3]) -> Option<usize> {
+    let (r, g, b) = (f32::from(r), f32::from(g), f32::from(b));
+    let max = r.max(g).max(b);
+    let delta = max - r.min(g).min(b);
+    if max < 40.0 || delta < max * 0.2 {
+        return None;
+    }
+
+    let sector = if max == r {
+        (g - b) / delta
+    } else if max == g {
+        (b - r) / delta + 2.0
+    } else {
+        (r - g) / delta + 4.0
+    };
+    let hue = (sector * 60.0).rem_euclid(360.0);
+    Some((hue / (360.0 / HUE_BINS as f32)) as usize % HUE_BINS)
+}
+
+fn sqr_distance(a: &[u8; 3], b: &[u8; 3]) -> i64 {
+    a.iter()
+        .zip(b)
+        .map(|(x, y)| i64::from(x.abs_diff(*y)).pow(2))
+        .sum()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn samples(colors: &[[u8; 3]]) -> Vec<u8> {
+        colors.iter().flatten().copied().collect()
+    }
+
+    #[test]
+    fn test_finds_dominant_colors_largest_first() {
+        let mut colors = vec![[250, 0, 0]; 6];
+        colors.extend([[0, 0, 250]; 3]);
+        colors.push([10, 10, 10]);
+
+        let summary = Summary::of(&samples(&colors));
+
+        assert_eq!(
+            summary.dominant,
+            vec![[250, 0, 0], [0, 0, 250], [10, 10, 10]]
+        );
+    }
+
+    #[test]
+    fn test_counts_only_clear_hues() {
+        let summary = Summary::of(&samples(&[
+            [255, 0, 0],
+            [0, 255, 0],
+            [128, 128, 128],
+            [5, 0, 0],
+        ]));
+
+        assert_eq!(summary.hues[0], 250);
+        assert_eq!(summary.hues[4], 250);
+        assert_eq!(summary.hues.iter().map(|h| *h as u32).sum::<u32>(), 500);
+    }
+
+    #[test]
+    fn test_similar_summaries_are_closer() {
+        let red = Summary::of(&samples(&[[250, 10, 10]; 4]));
+        let dark_red = Summary::of(&samples(&[[200, 10, 10]; 4]));
+        let blue = Summary::of(&samples(&[[10, 10, 250]; 4]));
+
+        assert_eq!(red.distance(&red), 0);
+        assert!(red.distance(&dark_red) < red.distance(&blue));
+    }
+}
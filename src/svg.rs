@@ -0,0 +1,104 @@
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use image::{ImageOutputFormat, ImageResult};
+
+use crate::core::{Dimensions, PixelRegion};
+use crate::resize::Resize;
+use crate::{at_size, load_image};
+
+/// How each tile's image is referenced from the SVG document.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SvgImages {
+    /// Link to the original library file.
+    Linked,
+    /// Embed a base64 JPEG thumbnail at the size the tile is drawn.
+    Embedded,
+}
+
+/// Build an SVG document with an `<image>` element for each tile.
+pub fn build_svg(
+    (width, height): Dimensions,
+    tiles: &[(PathBuf, PixelRegion)],
+    images: SvgImages,
+    resize: Resize,
+) -> ImageResult<String> {
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{width}\" height=\"{height}\" viewBox=\"0 0 {width} {height}\">\n"
+    );
+    for (path, region) in tiles {
+        let href = match images {
+            SvgImages::Linked => escape(&path.to_string_lossy()),
+            SvgImages::Embedded => embed(path, region, resize)?,
+        };
+        svg.push_str(&image_element(&href, region));
+    }
+    svg.push_str("</svg>\n");
+    Ok(svg)
+}
+
+/// Build the `<image>` element drawing the given href into the region.
+fn image_element(href: &str, region: &PixelRegion) -> String {
+    // Tiles are stretched to fill their region, matching the raster output.
+    format!(
+        "  <image x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" preserveAspectRatio=\"none\" href=\"{}\"/>\n",
+        region.x, region.y, region.width, region.height, href
+    )
+}
+
+/// Build a data URI containing a JPEG thumbnail of the tile.
+fn embed(path: &Path, region: &PixelRegion, resize: Resize) -> ImageResult<String> {
+    let img = load_image(path)?;
+    let thumb = at_size(img, region.width, region.height, resize);
+
+    let mut bytes = Vec::new();
+    thumb.write_to(&mut Cursor::new(&mut bytes), ImageOutputFormat::Jpeg(90))?;
+
+    Ok(format!("data:image/jpeg;base64,{}", STANDARD.encode(bytes)))
+}
+
+/// Escape a string for use inside a double quoted XML attribute.
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ResizeFilter;
+
+    #[test]
+    fn test_links_each_tile_at_its_region() {
+        let tiles = vec![
+            (PathBuf::from("a.jpg"), PixelRegion::new(0, 0, 10, 10)),
+            (PathBuf::from("b.jpg"), PixelRegion::new(10, 0, 10, 10)),
+        ];
+
+        let svg = build_svg(
+            (20, 10),
+            &tiles,
+            SvgImages::Linked,
+            Resize::new(ResizeFilter::Fast, true),
+        )
+        .unwrap();
+
+        assert!(svg.starts_with("<svg "));
+        assert!(svg.contains("width=\"20\" height=\"10\" viewBox=\"0 0 20 10\""));
+        assert!(svg.contains("<image x=\"0\" y=\"0\" width=\"10\" height=\"10\" preserveAspectRatio=\"none\" href=\"a.jpg\"/>"));
+        assert!(svg.contains("<image x=\"10\" y=\"0\" width=\"10\" height=\"10\" preserveAspectRatio=\"none\" href=\"b.jpg\"/>"));
+        assert!(svg.ends_with("</svg>\n"));
+    }
+
+    #[test]
+    fn test_escapes_linked_paths() {
+        assert_eq!(
+            escape("a&b <\"c\">.jpg"),
+            "a&amp;b &lt;&quot;c&quot;&gt;.jpg"
+        );
+    }
+}
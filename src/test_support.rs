@@ -0,0 +1,19 @@
+use std::env::temp_dir;
+use std::fs::{create_dir_all, remove_dir_all};
+use std::path::{Path, PathBuf};
+
+/// The path of a file or directory checked in under `tests/fixtures`.
+pub(crate) fn fixture(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(name)
+}
+
+/// An empty directory for a test to write in, emptied of anything left by an
+/// earlier run. Names must differ between tests, which may run at once.
+pub(crate) fn scratch_dir(name: &str) -> PathBuf {
+    let dir = temp_dir().join(format!("tiler-{name}-{}", std::process::id()));
+    let _ = remove_dir_all(&dir);
+    create_dir_all(&dir).unwrap();
+    dir
+}
@@ -0,0 +1,190 @@
+#[cfg(feature = "text")]
+use std::fs::read;
+use std::io::{Error as IoError, ErrorKind, Result as IoResult};
+use std::path::{Path, PathBuf};
+
+#[cfg(feature = "text")]
+pub(crate) use ab_glyph::FontVec;
+#[cfg(feature = "text")]
+use ab_glyph::{point, Font, Glyph, Point, ScaleFont};
+use image::GrayImage;
+#[cfg(feature = "serde")]
+use serde::Deserialize;
+
+use crate::core::Dimensions;
+
+/// Size (in pixels) text is laid out at before it is scaled to fit.
+#[cfg(feature = "text")]
+const NOMINAL_SIZE: f32 = 100.0;
+
+/// Share of the target's width and height text is scaled to fill when no
+/// size is given.
+#[cfg(feature = "text")]
+const FIT: f32 = 0.9;
+
+/// Text whose letters shape a mosaic, drawn centred over the target.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(deny_unknown_fields))]
+pub struct TextShape {
+    /// The text, drawn a line for each line break.
+    pub content: String,
+    /// TrueType or OpenType font file to draw the text in.
+    pub font: PathBuf,
+    /// Height (in target pixels) of each line, as large as fits the target if
+    /// not given.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub size: Option<f32>,
+}
+
+/// How much of each pixel of a target of the given size the text covers,
+/// from 0 to 255.
+pub(crate) fn render_text(shape: &TextShape, dimensions: Dimensions) -> IoResult<GrayImage> {
+    let font = load_font(&shape.font)?;
+    Ok(draw_text(&font, &shape.content, shape.size, dimensions))
+}
+
+/// Without the `text` feature there is nothing to read fonts with, so no
+/// font is ever loaded to draw text in.
+#[cfg(not(feature = "text"))]
+pub(crate) enum FontVec {}
+
+/// Load a TrueType or OpenType font file.
+#[cfg(feature = "text")]
+pub(crate) fn load_font(path: &Path) -> IoResult<FontVec> {
+    FontVec::try_from_vec(read(path)?).map_err(|e| IoError::new(ErrorKind::InvalidData, e))
+}
+
+#[cfg(not(feature = "text"))]
+pub(crate) fn load_font(path: &Path) -> IoResult<FontVec> {
+    Err(IoError::new(
+        ErrorKind::Unsupported,
+        format!("Drawing text in {} needs the text feature", path.display()),
+    ))
+}
+
+/// How much of each pixel of an area of the given size the text covers, from
+/// 0 to 255, drawn centred with lines of the given height or as large as
+/// fits.
+#[cfg(feature = "text")]
+pub(crate) fn draw_text(
+    font: &FontVec,
+    content: &str,
+    size: Option<f32>,
+    (width, height): Dimensions,
+) -> GrayImage {
+    let lines: Vec<&str> = content.lines().collect();
+
+    // Measure the text at a nominal size to find the size that fits.
+    let nominal = font.as_scaled(NOMINAL_SIZE);
+    let line_height = nominal.height() + nominal.line_gap();
+    let (widths, block) = measure(font, &lines);
+    let size = size.unwrap_or_else(|| NOMINAL_SIZE * fit(block, (width, height)));
+    let ratio = size / NOMINAL_SIZE;
+
+    let scaled = font.as_scaled(size);
+    let mut coverage = GrayImage::new(width, height);
+    let top = (height as f32 - block.1 * ratio) / 2.0;
+    for (i, line) in lines.iter().enumerate() {
+        let left = (width as f32 - widths[i] * ratio) / 2.0;
+        let baseline = top + i as f32 * line_height * ratio + scaled.ascent();
+        for glyph in layout(&scaled, line, point(left, baseline)).0 {
+            let Some(outlined) = scaled.outline_glyph(glyph) else {
+                continue;
+            };
+            let bounds = outlined.px_bounds();
+            outlined.draw(|x, y, c| {
+                let x = bounds.min.x as i64 + i64::from(x);
+                let y = bounds.min.y as i64 + i64::from(y);
+                if (0..width.into()).contains(&x) && (0..height.into()).contains(&y) {
+                    let pixel = coverage.get_pixel_mut(x as u32, y as u32);
+                    pixel[0] = pixel[0].max((c * 255.0).round() as u8);
+                }
+            });
+        }
+    }
+    coverage
+}
+
+#[cfg(not(feature = "text"))]
+pub(crate) fn draw_text(
+    font: &FontVec,
+    _content: &str,
+    _size: Option<f32>,
+    _dimensions: Dimensions,
+) -> GrayImage {
+    match *font {}
+}
+
+/// The size (in pixels) of the text drawn with lines of the given height.
+#[cfg(feature = "text")]
+pub(crate) fn text_size(font: &FontVec, content: &str, size: f32) -> Dimensions {
+    let lines: Vec<&str> = content.lines().collect();
+    let (_, (width, height)) = measure(font, &lines);
+    let ratio = size / NOMINAL_SIZE;
+    (
+        (width * ratio).ceil() as u32,
+        (height * ratio).ceil() as u32,
+    )
+}
+
+#[cfg(not(feature = "text"))]
+pub(crate) fn text_size(font: &FontVec, _content: &str, _size: f32) -> Dimensions {
+    match *font {}
+}
+
+/// The width of each line of the text, and the width and height of the
+/// whole block, at the nominal size.
+#[cfg(feature = "text")]
+fn measure(font: &FontVec, lines: &[&str]) -> (Vec<f32>, (f32, f32)) {
+    let nominal = font.as_scaled(NOMINAL_SIZE);
+    let line_height = nominal.height() + nominal.line_gap();
+    let widths: Vec<f32> = lines
+        .iter()
+        .map(|line| layout(&nominal, line, point(0.0, 0.0)).1)
+        .collect();
+    let block = (
+        widths.iter().copied().fold(0.0, f32::max),
+        line_height * lines.len() as f32 - nominal.line_gap(),
+    );
+    (widths, block)
+}
+
+/// Place the glyphs of a line of text from its baseline origin, returning
+/// them and the width of the line.
+#[cfg(feature = "text")]
+fn layout<F: Font, S: ScaleFont<F>>(font: &S, line: &str, origin: Point) -> (Vec<Glyph>, f32) {
+    let mut glyphs: Vec<Glyph> = Vec::new();
+    let mut caret = origin.x;
+    for c in line.chars() {
+        let id = font.glyph_id(c);
+        if let Some(previous) = glyphs.last() {
+            caret += font.kern(previous.id, id);
+        }
+        glyphs.push(id.with_scale_and_position(font.scale(), point(caret, origin.y)));
+        caret += font.h_advance(id);
+    }
+    (glyphs, caret - origin.x)
+}
+
+/// How much to scale a block of text by to fill most of the target.
+#[cfg(feature = "text")]
+fn fit((block_width, block_height): (f32, f32), (width, height): Dimensions) -> f32 {
+    if block_width <= 0.0 || block_height <= 0.0 {
+        return 0.0;
+    }
+    (width as f32 * FIT / block_width).min(height as f32 * FIT / block_height)
+}
+
+#[cfg(all(test, feature = "text"))]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_fits_text_within_target() {
+        // Wide text is limited by the width, tall text by the height.
+        assert_eq!(fit((100.0, 10.0), (200, 200)), 1.8);
+        assert_eq!(fit((10.0, 100.0), (200, 200)), 1.8);
+        assert_eq!(fit((0.0, 10.0), (200, 200)), 0.0);
+    }
+}
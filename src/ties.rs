@@ -0,0 +1,85 @@
+use std::cmp::Ordering;
+use std::hash::{Hash, Hasher};
+
+#[cfg(feature = "cli")]
+use clap::ValueEnum;
+#[cfg(feature = "serde")]
+use serde::Deserialize;
+
+use crate::library::fnv1a;
+use crate::seed::Seed;
+
+/// How to choose between tiles that match a cell equally well, so that
+/// repeated runs choose the same tiles.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "cli", derive(ValueEnum))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
+pub enum TieBreak {
+    /// The tile that sorts first, such as by file path.
+    #[default]
+    Name,
+    /// A tile picked using the seed.
+    Random,
+}
+
+impl TieBreak {
+    /// Order two tiles of equal weight, the same way in every run.
+    pub(crate) fn compare<T: Ord + Hash>(self, seed: Seed, a: &T, b: &T) -> Ordering {
+        match self {
+            TieBreak::Name => a.cmp(b),
+            TieBreak::Random => rank(seed, a).cmp(&rank(seed, b)).then_with(|| a.cmp(b)),
+        }
+    }
+}
+
+/// A position for the tile in a shuffle of all the tiles using the seed.
+fn rank<T: Hash>(seed: Seed, tile: &T) -> u64 {
+    let mut hasher = StableHasher(seed.0.to_le_bytes().to_vec());
+    tile.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Collects the bytes a value hashes, to hash them the same way in every
+/// run unlike the standard library's randomly keyed hasher.
+struct StableHasher(Vec<u8>);
+
+impl Hasher for StableHasher {
+    fn finish(&self) -> u64 {
+        fnv1a(&self.0)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        self.0.extend_from_slice(bytes);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn first(tie_break: TieBreak, seed: u64, tiles: &[&str]) -> String {
+        let mut tiles = tiles.to_vec();
+        tiles.sort_by(|a, b| tie_break.compare(Seed(seed), a, b));
+        tiles[0].to_string()
+    }
+
+    #[test]
+    fn test_breaks_ties_the_same_way_whatever_the_order() {
+        let tiles = ["a", "b", "c", "d", "e", "f"];
+        let mut reversed = tiles;
+        reversed.reverse();
+
+        assert_eq!(first(TieBreak::Name, 0, &reversed), "a");
+        for seed in 0..4 {
+            assert_eq!(
+                first(TieBreak::Random, seed, &tiles),
+                first(TieBreak::Random, seed, &reversed)
+            );
+        }
+        let picks: Vec<String> = (0..20)
+            .map(|seed| first(TieBreak::Random, seed, &tiles))
+            .collect();
+        assert!(picks.iter().any(|p| *p != picks[0]));
+    }
+}
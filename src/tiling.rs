@@ -14,6 +14,24 @@ pub fn choose_tile_area(width: u32, height: u32) -> Rectangle {
 #[cfg(test)]
 mod test {
     use super::*;
+    use proptest::prelude::*;
+
+    proptest! {
+        #[test]
+        fn test_chooses_largest_central_square_inside_image(
+            width in 1u32..10_000,
+            height in 1u32..10_000,
+        ) {
+            let r = choose_tile_area(width, height);
+
+            prop_assert_eq!(r.width, width.min(height));
+            prop_assert_eq!(r.height, r.width);
+            prop_assert!(r.x + r.width <= width && r.y + r.height <= height);
+            // Any odd pixel left over goes after the square, not before.
+            prop_assert!(matches!(width - r.width - 2 * r.x, 0 | 1));
+            prop_assert!(matches!(height - r.height - 2 * r.y, 0 | 1));
+        }
+    }
 
     #[test]
     fn test_chooses_central_square_for_portrait_tile() {
@@ -24,4 +42,12 @@ mod test {
     fn test_chooses_central_square_for_landscape_tile() {
         assert_eq!(choose_tile_area(20, 10), Rectangle::new(5, 0, 10, 10));
     }
+
+    #[test]
+    fn test_chooses_central_square_for_tiny_tiles() {
+        assert_eq!(choose_tile_area(1, 1), Rectangle::new(0, 0, 1, 1));
+        assert_eq!(choose_tile_area(5, 3), Rectangle::new(1, 0, 3, 3));
+        assert_eq!(choose_tile_area(3, 5), Rectangle::new(0, 1, 3, 3));
+        assert_eq!(choose_tile_area(0, 5).width, 0);
+    }
 }
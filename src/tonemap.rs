@@ -0,0 +1,97 @@
+use image::{DynamicImage, Rgba, Rgba32FImage, RgbaImage};
+
+/// Luminance the log average luminance of an image is mapped to.
+const MIDDLE_GREY: f32 = 0.18;
+
+/// Convert an image to 8 bits per channel, tone mapping high dynamic range
+/// images so their highlights are compressed rather than clipped.
+///
+/// Integer images (including 16-bit) are scaled down directly.
+pub fn into_rgba8(img: DynamicImage) -> RgbaImage {
+    match img {
+        DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_) => {
+            tone_map(&img.into_rgba32f())
+        }
+        _ => img.into_rgba8(),
+    }
+}
+
+/// Map linear HDR pixels to sRGB with the Reinhard operator, exposed so the
+/// log average luminance of the image lands at middle grey.
+fn tone_map(img: &Rgba32FImage) -> RgbaImage {
+    let exposure = MIDDLE_GREY / log_average_luminance(img);
+
+    RgbaImage::from_fn(img.width(), img.height(), |x, y| {
+        let [r, g, b, a] = img.get_pixel(x, y).0;
+        let scale = exposure / (1.0 + luminance(r, g, b) * exposure);
+        Rgba([
+            encode(r * scale),
+            encode(g * scale),
+            encode(b * scale),
+            quantize(a),
+        ])
+    })
+}
+
+fn log_average_luminance(img: &Rgba32FImage) -> f32 {
+    // Offset so black pixels don't take the log to negative infinity.
+    let delta = 1e-4;
+    let count = (img.width() * img.height()).max(1) as f32;
+    let sum: f32 = img
+        .pixels()
+        .map(|p| (delta + luminance(p[0], p[1], p[2]).max(0.0)).ln())
+        .sum();
+    (sum / count).exp()
+}
+
+/// Rec. 709 luminance of linear channel values.
+fn luminance(r: f32, g: f32, b: f32) -> f32 {
+    0.2126 * r + 0.7152 * g + 0.0722 * b
+}
+
+/// Gamma encode a linear channel value to sRGB.
+fn encode(v: f32) -> u8 {
+    let v = v.clamp(0.0, 1.0);
+    if v <= 0.003_130_8 {
+        quantize(12.92 * v)
+    } else {
+        quantize(1.055 * v.powf(1.0 / 2.4) - 0.055)
+    }
+}
+
+fn quantize(v: f32) -> u8 {
+    (v.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use image::{ImageBuffer, Rgb, Rgb32FImage};
+
+    #[test]
+    fn test_keeps_highlights_of_hdr_images() {
+        // Linear values well above 1.0 would all clip to white.
+        let img = Rgb32FImage::from_fn(3, 1, |x, _| {
+            let v = [0.5, 4.0, 16.0][x as usize];
+            Rgb([v, v, v])
+        });
+
+        let result = into_rgba8(DynamicImage::ImageRgb32F(img));
+
+        let values: Vec<u8> = result.pixels().map(|p| p[0]).collect();
+        assert!(values[0] < values[1] && values[1] < values[2]);
+        assert!(values[2] < 255);
+        assert!(result
+            .pixels()
+            .all(|p| p[0] == p[1] && p[1] == p[2] && p[3] == 255));
+    }
+
+    #[test]
+    fn test_scales_16_bit_images() {
+        let img = ImageBuffer::from_pixel(1, 1, Rgba([0, 32896, 65535, 65535]));
+
+        let result = into_rgba8(DynamicImage::ImageRgba16(img));
+
+        assert_eq!(result.get_pixel(0, 0), &Rgba([0, 128, 255, 255]));
+    }
+}
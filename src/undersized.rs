@@ -0,0 +1,79 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+
+#[cfg(feature = "cli")]
+use clap::ValueEnum;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::analysis::ImageInfo;
+
+/// What to do with library images smaller than the tiles drawn from them,
+/// which are scaled up to fill their cells and look blurry.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "cli", derive(ValueEnum))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
+pub enum SmallTiles {
+    /// Draw them without comment.
+    Allow,
+    /// Draw them, counting them in the build summary.
+    #[default]
+    Warn,
+    /// Leave them out of the library.
+    Exclude,
+}
+
+impl SmallTiles {
+    /// Apply the policy to the library for tiles drawn the given number of
+    /// pixels across, returning how many images are smaller than that, or
+    /// none if they are allowed.
+    pub(crate) fn apply<T: Eq + Hash>(
+        self,
+        library: &mut HashMap<&T, ImageInfo>,
+        tile_size: u32,
+    ) -> usize {
+        let small = |info: &ImageInfo| info.shortest_side() < tile_size;
+        match self {
+            SmallTiles::Allow => 0,
+            SmallTiles::Warn => library.values().filter(|info| small(info)).count(),
+            SmallTiles::Exclude => {
+                let before = library.len();
+                library.retain(|_, info| !small(info));
+                before - library.len()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::analysis::{analyse, AnalysisOptions};
+    use image::RgbaImage;
+
+    #[test]
+    fn test_flags_images_smaller_than_tiles() {
+        let options = AnalysisOptions::new(Some(2));
+        let (small, wide, large) = ("small", "wide", "large");
+        let library = HashMap::from([
+            (&small, analyse(&RgbaImage::new(40, 40), &options)),
+            (&wide, analyse(&RgbaImage::new(400, 50), &options)),
+            (&large, analyse(&RgbaImage::new(100, 120), &options)),
+        ]);
+        let apply = |policy: SmallTiles| {
+            let mut library = library.clone();
+            let flagged = policy.apply(&mut library, 100);
+            let mut kept: Vec<&str> = library.keys().map(|name| **name).collect();
+            kept.sort();
+            (flagged, kept)
+        };
+
+        assert_eq!(
+            apply(SmallTiles::Allow),
+            (0, vec!["large", "small", "wide"])
+        );
+        assert_eq!(apply(SmallTiles::Warn), (2, vec!["large", "small", "wide"]));
+        assert_eq!(apply(SmallTiles::Exclude), (2, vec!["large"]));
+    }
+}
@@ -0,0 +1,168 @@
+use std::collections::HashMap;
+use std::fs::metadata;
+use std::io::{Error as IoError, Result as IoResult};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::channel;
+use std::time::{Duration, SystemTime};
+
+use image::RgbaImage;
+use notify::{Event, RecursiveMode, Watcher};
+
+use crate::analysis::{analyse_tile, AnalysisOptions, ImageInfo};
+use crate::{
+    library, load_image, plan_with_library, render, strategy_options, CancelToken, LibraryFilter,
+    MosaicOptions,
+};
+
+/// How long to wait for further changes before rebuilding, so that copying
+/// in a batch of photos causes one rebuild rather than many.
+const SETTLE_TIME: Duration = Duration::from_millis(500);
+
+/// Build a mosaic, then build it again whenever the target or the images in
+/// the libraries change, passing each build to `on_build`.
+///
+/// Only returns if the files can no longer be watched. Library images are
+/// only analysed again when they have been modified.
+pub fn watch<P, F>(
+    target_path: &Path,
+    lib_dirs: &[P],
+    options: &MosaicOptions,
+    mut on_build: F,
+) -> IoResult<()>
+where
+    P: AsRef<Path>,
+    F: FnMut(IoResult<RgbaImage>),
+{
+    // Watch the target's directory since editors often replace files
+    // rather than write to them, but only react to changes to the target.
+    let target = target_path.canonicalize()?;
+    let target_dir = target.parent().unwrap_or(Path::new("/")).to_owned();
+    let dirs: Vec<PathBuf> = lib_dirs
+        .iter()
+        .filter_map(|d| d.as_ref().canonicalize().ok())
+        .filter(|d| d.is_dir())
+        .collect();
+
+    let (sender, changes) = channel();
+    let mut watcher = {
+        let dirs = dirs.clone();
+        notify::recommended_watcher(move |event: notify::Result<Event>| {
+            let Ok(event) = event else {
+                return;
+            };
+            let relevant = |p: &PathBuf| *p == target || dirs.iter().any(|d| p.starts_with(d));
+            if !event.kind.is_access() && event.paths.iter().any(relevant) {
+                let _ = sender.send(());
+            }
+        })
+        .map_err(IoError::other)?
+    };
+    for dir in dirs.iter().chain([&target_dir]) {
+        watcher
+            .watch(dir, RecursiveMode::NonRecursive)
+            .map_err(IoError::other)?;
+    }
+
+    let analysis_options = strategy_options(options).analysis;
+    let cancel = CancelToken::new();
+    let mut cache = AnalysisCache::default();
+    loop {
+        let build = cache
+            .load(lib_dirs, &options.filter, &analysis_options)
+            .and_then(|library| {
+                let target = load_image(target_path).map_err(IoError::other)?;
+                let plan = plan_with_library(target, &library, options, &cancel)?;
+                render(plan, options, &cancel)
+            });
+        on_build(build);
+
+        changes.recv().map_err(IoError::other)?;
+        while changes.recv_timeout(SETTLE_TIME).is_ok() {}
+    }
+}
+
+/// The analysis of library images, kept until they are modified.
+#[derive(Default)]
+struct AnalysisCache {
+    entries: HashMap<PathBuf, (SystemTime, ImageInfo)>,
+}
+
+impl AnalysisCache {
+    /// Find the images in the libraries, analysing only those that are new
+    /// or modified since the last load.
+    fn load<P: AsRef<Path>>(
+        &mut self,
+        lib_dirs: &[P],
+        filter: &LibraryFilter,
+        options: &AnalysisOptions,
+    ) -> IoResult<Vec<(PathBuf, ImageInfo)>> {
+        let mut entries = HashMap::new();
+        let mut library = Vec::new();
+        for lib_dir in lib_dirs {
+            for path in library::open(lib_dir.as_ref(), filter)?.images()? {
+                let Ok(modified) = metadata(&path).and_then(|m| m.modified()) else {
+                    continue;
+                };
+                let info = match self.entries.remove(&path) {
+                    Some((m, info)) if m == modified => info,
+                    _ => {
+                        let Ok(img) = load_image(&path) else {
+                            continue;
+                        };
+                        analyse_tile(&img, options)
+                    }
+                };
+                library.push((path.clone(), info.clone()));
+                entries.insert(path, (modified, info));
+            }
+        }
+        self.entries = entries;
+        Ok(library)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_support::scratch_dir;
+    use image::Rgba;
+    use std::fs::{remove_dir_all, File};
+
+    fn save_with_time(path: &Path, color: [u8; 3], modified: SystemTime) {
+        let [r, g, b] = color;
+        RgbaImage::from_pixel(4, 4, Rgba([r, g, b, 255]))
+            .save(path)
+            .unwrap();
+        let file = File::options().write(true).open(path).unwrap();
+        file.set_modified(modified).unwrap();
+    }
+
+    #[test]
+    fn test_reanalyses_only_modified_images() {
+        let dir = scratch_dir("watch");
+        let (kept, modified) = (dir.join("kept.png"), dir.join("modified.png"));
+        let then = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+        save_with_time(&kept, [255, 0, 0], then);
+        save_with_time(&modified, [255, 0, 0], then);
+        let options = AnalysisOptions::new(Some(2));
+        let mut cache = AnalysisCache::default();
+        let red: ImageInfo = cache
+            .load(&[&dir], &LibraryFilter::default(), &options)
+            .unwrap()
+            .remove(0)
+            .1;
+
+        // Only the modification time tells the cache an image has changed.
+        save_with_time(&kept, [0, 0, 255], then);
+        save_with_time(&modified, [0, 0, 255], then + Duration::from_secs(1));
+        let library: HashMap<PathBuf, ImageInfo> = cache
+            .load(&[&dir], &LibraryFilter::default(), &options)
+            .unwrap()
+            .into_iter()
+            .collect();
+
+        assert_eq!(library[&kept], red);
+        assert_ne!(library[&modified], red);
+        remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,205 @@
+use std::io::{Error as IoError, Result as IoResult};
+use std::path::PathBuf;
+
+#[cfg(feature = "cli")]
+use clap::ValueEnum;
+use image::{Rgba, RgbaImage};
+#[cfg(feature = "serde")]
+use serde::Deserialize;
+
+use crate::core::Dimensions;
+use crate::load_image;
+use crate::mask::Color;
+use crate::text::{draw_text, load_font, text_size};
+
+/// A text or image mark stamped in a corner of the mosaic, such as an
+/// attribution for sharing it.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(deny_unknown_fields))]
+pub struct Watermark {
+    /// What to stamp.
+    pub mark: Mark,
+    /// Corner to stamp the mark in.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub corner: Corner,
+    /// How opaque the mark is, from 0 to 1.
+    #[cfg_attr(feature = "serde", serde(default = "default_opacity"))]
+    pub opacity: f64,
+    /// Distance (in pixels) of the mark from the edges of the mosaic.
+    #[cfg_attr(feature = "serde", serde(default = "default_margin"))]
+    pub margin: u32,
+}
+
+fn default_opacity() -> f64 {
+    0.5
+}
+
+fn default_margin() -> u32 {
+    20
+}
+
+impl Watermark {
+    /// Stamp the mark at the default opacity and margin.
+    pub fn new(mark: Mark, corner: Corner) -> Watermark {
+        Watermark {
+            mark,
+            corner,
+            opacity: default_opacity(),
+            margin: default_margin(),
+        }
+    }
+}
+
+/// What a watermark stamps.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
+pub enum Mark {
+    /// Image drawn at its own size, keeping its transparency.
+    Image(PathBuf),
+    /// Text, a line for each line break.
+    Text(TextMark),
+}
+
+/// Text stamped as a watermark.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(deny_unknown_fields))]
+pub struct TextMark {
+    pub content: String,
+    /// TrueType or OpenType font file to draw the text in.
+    pub font: PathBuf,
+    /// Height (in pixels) of each line.
+    #[cfg_attr(feature = "serde", serde(default = "default_size"))]
+    pub size: f32,
+    #[cfg_attr(feature = "serde", serde(default = "default_color"))]
+    pub color: Color,
+}
+
+impl TextMark {
+    /// Draw the text in the font at the default size and color.
+    pub fn new(content: String, font: PathBuf) -> TextMark {
+        TextMark {
+            content,
+            font,
+            size: default_size(),
+            color: default_color(),
+        }
+    }
+}
+
+fn default_size() -> f32 {
+    24.0
+}
+
+fn default_color() -> Color {
+    Color([255, 255, 255])
+}
+
+/// A corner of the mosaic.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "cli", derive(ValueEnum))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
+pub enum Corner {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    #[default]
+    BottomRight,
+}
+
+impl Corner {
+    /// Where to put the top left of a mark of the given size in an image,
+    /// the margin in from the corner.
+    fn place(&self, (width, height): Dimensions, (w, h): Dimensions, margin: u32) -> (i64, i64) {
+        let margin = i64::from(margin);
+        let left = margin;
+        let right = i64::from(width) - i64::from(w) - margin;
+        let top = margin;
+        let bottom = i64::from(height) - i64::from(h) - margin;
+        match self {
+            Corner::TopLeft => (left, top),
+            Corner::TopRight => (right, top),
+            Corner::BottomLeft => (left, bottom),
+            Corner::BottomRight => (right, bottom),
+        }
+    }
+}
+
+impl Mark {
+    fn render(&self) -> IoResult<RgbaImage> {
+        match self {
+            Mark::Image(path) => load_image(path).map_err(IoError::other),
+            Mark::Text(text) => {
+                let font = load_font(&text.font)?;
+                let size = text_size(&font, &text.content, text.size);
+                let coverage = draw_text(&font, &text.content, Some(text.size), size);
+                let [r, g, b] = text.color.0;
+                Ok(RgbaImage::from_fn(size.0, size.1, |x, y| {
+                    Rgba([r, g, b, coverage.get_pixel(x, y)[0]])
+                }))
+            }
+        }
+    }
+}
+
+/// Stamp the watermark onto the image, blending it in at its opacity.
+pub(crate) fn stamp(img: &mut RgbaImage, watermark: &Watermark) -> IoResult<()> {
+    let mark = watermark.mark.render()?;
+    let (left, top) = watermark
+        .corner
+        .place(img.dimensions(), mark.dimensions(), watermark.margin);
+    let opacity = watermark.opacity.clamp(0.0, 1.0);
+    for (x, y, p) in mark.enumerate_pixels() {
+        let (px, py) = (left + i64::from(x), top + i64::from(y));
+        if px < 0 || py < 0 || px >= img.width().into() || py >= img.height().into() {
+            continue;
+        }
+        let share = f64::from(p[3]) / 255.0 * opacity;
+        let pixel = img.get_pixel_mut(px as u32, py as u32);
+        for c in 0..3 {
+            let blended = f64::from(pixel[c]) * (1.0 - share) + f64::from(p[c]) * share;
+            pixel[c] = blended.round() as u8;
+        }
+        pixel[3] = (f64::from(pixel[3]) + (255.0 - f64::from(pixel[3])) * share).round() as u8;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::env::temp_dir;
+
+    #[test]
+    fn test_places_marks_in_corners() {
+        let (image, mark) = ((100, 50), (30, 10));
+
+        assert_eq!(Corner::TopLeft.place(image, mark, 5), (5, 5));
+        assert_eq!(Corner::TopRight.place(image, mark, 5), (65, 5));
+        assert_eq!(Corner::BottomLeft.place(image, mark, 5), (5, 35));
+        assert_eq!(Corner::BottomRight.place(image, mark, 0), (70, 40));
+    }
+
+    #[test]
+    fn test_stamps_images_at_their_opacity() {
+        let path = temp_dir().join(format!("tiler-watermark-{}.png", std::process::id()));
+        RgbaImage::from_pixel(4, 4, Rgba([255, 255, 255, 255]))
+            .save(&path)
+            .unwrap();
+        let mut img = RgbaImage::from_pixel(20, 20, Rgba([0, 0, 0, 255]));
+
+        let watermark = Watermark {
+            margin: 2,
+            ..Watermark::new(Mark::Image(path.clone()), Corner::BottomRight)
+        };
+        stamp(&mut img, &watermark).unwrap();
+
+        assert_eq!(*img.get_pixel(15, 15), Rgba([128, 128, 128, 255]));
+        assert_eq!(*img.get_pixel(13, 15), Rgba([0, 0, 0, 255]));
+        assert_eq!(*img.get_pixel(18, 18), Rgba([0, 0, 0, 255]));
+        std::fs::remove_file(path).unwrap();
+    }
+}
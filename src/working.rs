@@ -0,0 +1,129 @@
+use std::borrow::Cow;
+
+use image::RgbaImage;
+
+use crate::core::Dimensions;
+use crate::{resize, MosaicOptions};
+
+/// Resize the target to its working resolution, if the options ask for one,
+/// so that about the asked for number of cells of the cell size cover it
+/// however large the target image is. The options are returned with their
+/// areas and points, given in target pixels, moved to match.
+pub(crate) fn to_working_resolution(
+    target: RgbaImage,
+    options: &MosaicOptions,
+) -> (RgbaImage, Cow<'_, MosaicOptions>) {
+    let (width, height) = target.dimensions();
+    let size = working_dimensions((width, height), options);
+    if size == (width, height) {
+        return (target, Cow::Borrowed(options));
+    }
+    let scale = (
+        f64::from(size.0) / f64::from(width),
+        f64::from(size.1) / f64::from(height),
+    );
+    let resized = resize(options).apply(&target, size.0, size.1);
+    (resized, Cow::Owned(scaled(options, scale)))
+}
+
+/// The size of a target of the given size at its working resolution, which
+/// is its own size unless the options ask for one.
+pub(crate) fn working_dimensions(
+    (width, height): Dimensions,
+    options: &MosaicOptions,
+) -> Dimensions {
+    match options.working_cells {
+        Some(cells) if width > 0 && height > 0 => {
+            working_size((width, height), cells, options.cell_size)
+        }
+        _ => (width, height),
+    }
+}
+
+/// The size to resize a target of the given size to, keeping its aspect
+/// ratio, for about the given number of cells of the given size to cover it.
+fn working_size((width, height): Dimensions, cells: u32, cell_size: u32) -> Dimensions {
+    let area = f64::from(width) * f64::from(height);
+    let wanted = f64::from(cells.max(1)) * f64::from(cell_size.max(1)).powi(2);
+    let scale = (wanted / area.max(1.0)).sqrt();
+    let side = |length: u32| ((f64::from(length) * scale).round() as u32).max(1);
+    (side(width), side(height))
+}
+
+/// The options with the areas and points they place in the target scaled
+/// across and down by the given amounts.
+fn scaled(options: &MosaicOptions, (across, down): (f64, f64)) -> MosaicOptions {
+    // Points are rounded down so that they stay inside the resized target.
+    let x = |v: u32| (f64::from(v) * across) as u32;
+    let y = |v: u32| (f64::from(v) * down) as u32;
+    let width = |v: u32| (f64::from(v) * across).round() as u32;
+    let height = |v: u32| (f64::from(v) * down).round() as u32;
+
+    let mut options = options.clone();
+    for pin in &mut options.pins {
+        pin.at = (x(pin.at.0), y(pin.at.1));
+    }
+    for exclusion in &mut options.exclusions {
+        exclusion.x = x(exclusion.x);
+        exclusion.y = y(exclusion.y);
+        exclusion.width = width(exclusion.width);
+        exclusion.height = height(exclusion.height);
+    }
+    for area in &mut options.protected {
+        area.x = x(area.x);
+        area.y = y(area.y);
+        area.width = width(area.width);
+        area.height = height(area.height);
+        for corner in &mut area.outline {
+            *corner = [x(corner[0]), y(corner[1])];
+        }
+    }
+    options
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Pin, ProtectedArea};
+    use image::Rgba;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_sizes_working_resolution_from_cells() {
+        assert_eq!(working_size((4000, 3000), 1200, 20), (800, 600));
+        assert_eq!(working_size((40, 30), 1200, 20), (800, 600));
+        assert_eq!(working_size((20_000, 500), 100, 10), (632, 16));
+        assert_eq!(working_size((10, 10), 0, 0), (1, 1));
+    }
+
+    #[test]
+    fn test_resizes_target_and_moves_areas_to_match() {
+        let target = RgbaImage::from_pixel(400, 200, Rgba([10, 20, 30, 255]));
+        let options = MosaicOptions {
+            cell_size: 10,
+            working_cells: Some(50),
+            pins: vec![Pin {
+                tile: PathBuf::from("us.jpg"),
+                at: (399, 101),
+            }],
+            protected: vec![ProtectedArea {
+                outline: vec![[100, 50], [300, 150]],
+                .."40,20,80,60".parse().unwrap()
+            }],
+            ..MosaicOptions::default()
+        };
+
+        let (resized, working) = to_working_resolution(target.clone(), &options);
+
+        assert_eq!(resized.dimensions(), (100, 50));
+        assert_eq!(working.pins[0].at, (99, 25));
+        let area = &working.protected[0];
+        assert_eq!((area.x, area.y, area.width, area.height), (10, 5, 20, 15));
+        assert_eq!(area.outline, vec![[25, 12], [75, 37]]);
+
+        let unchanged = MosaicOptions::default();
+        let (same, kept) = to_working_resolution(target, &unchanged);
+        assert_eq!(same.dimensions(), (400, 200));
+        assert!(matches!(kept, Cow::Borrowed(_)));
+    }
+}
@@ -0,0 +1,150 @@
+//! Compare the outputs of building from the checked-in fixtures against
+//! golden images, by how similar they look rather than byte for byte, so
+//! that changes to strategies can be checked end to end.
+//!
+//! Run with `UPDATE_GOLDEN=1` to replace the golden images with the current
+//! outputs, after checking the differences are intended.
+
+use std::env;
+use std::path::{Path, PathBuf};
+
+use image::{Pixel, RgbaImage};
+use tiler::{mosaic, strategy_names, tile, Color, Mask, MaskShape, MosaicOptions};
+
+/// Lowest structural similarity accepted between an output and its golden
+/// image, where 1 is identical.
+const MIN_SIMILARITY: f64 = 0.95;
+
+/// Size of the windows structural similarity is measured over.
+const WINDOW: u32 = 8;
+
+fn fixture(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(name)
+}
+
+fn options(strategy: &str) -> MosaicOptions {
+    MosaicOptions {
+        analysis_size: 2,
+        cell_size: 10,
+        tile_size: 16,
+        strategy: strategy.to_string(),
+        ..MosaicOptions::default()
+    }
+}
+
+/// Check the image looks like the golden image of the given name.
+fn assert_matches_golden(name: &str, actual: &RgbaImage) {
+    let path = fixture("golden").join(format!("{name}.png"));
+    if env::var_os("UPDATE_GOLDEN").is_some() {
+        actual.save(&path).unwrap();
+        return;
+    }
+
+    let expected = image::open(&path).unwrap().to_rgba8();
+    assert_eq!(actual.dimensions(), expected.dimensions(), "{name}");
+    let similarity = similarity(actual, &expected);
+    assert!(
+        similarity >= MIN_SIMILARITY,
+        "{name} has similarity {similarity:.3} to its golden image"
+    );
+}
+
+/// The mean structural similarity (SSIM) of the brightness of two images of
+/// the same size, over windows tiling the images.
+fn similarity(a: &RgbaImage, b: &RgbaImage) -> f64 {
+    const C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+    const C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+    let luma = |img: &RgbaImage, x, y| f64::from(img.get_pixel(x, y).to_luma()[0]);
+
+    let (width, height) = a.dimensions();
+    let mut total = 0.0;
+    let mut windows = 0;
+    for wy in (0..height).step_by(WINDOW as usize) {
+        for wx in (0..width).step_by(WINDOW as usize) {
+            let pixels: Vec<(f64, f64)> = (wy..(wy + WINDOW).min(height))
+                .flat_map(|y| (wx..(wx + WINDOW).min(width)).map(move |x| (x, y)))
+                .map(|(x, y)| (luma(a, x, y), luma(b, x, y)))
+                .collect();
+            let n = pixels.len() as f64;
+            let mean_a = pixels.iter().map(|p| p.0).sum::<f64>() / n;
+            let mean_b = pixels.iter().map(|p| p.1).sum::<f64>() / n;
+            let (mut var_a, mut var_b, mut covar) = (0.0, 0.0, 0.0);
+            for (pa, pb) in &pixels {
+                var_a += (pa - mean_a).powi(2) / n;
+                var_b += (pb - mean_b).powi(2) / n;
+                covar += (pa - mean_a) * (pb - mean_b) / n;
+            }
+            total += ((2.0 * mean_a * mean_b + C1) * (2.0 * covar + C2))
+                / ((mean_a.powi(2) + mean_b.powi(2) + C1) * (var_a + var_b + C2));
+            windows += 1;
+        }
+    }
+    total / f64::from(windows)
+}
+
+#[test]
+fn test_similarity_tells_images_apart() {
+    let target = image::open(fixture("target.png")).unwrap().to_rgba8();
+    let mut inverted = target.clone();
+    image::imageops::invert(&mut inverted);
+
+    assert!((similarity(&target, &target) - 1.0).abs() < 1e-9);
+    assert!(similarity(&target, &inverted) < 0.5);
+}
+
+#[test]
+fn test_mosaics_match_golden_images() {
+    let library = fixture("library");
+
+    for strategy in strategy_names() {
+        let output = mosaic(&fixture("target.png"), &[&library], &options(strategy))
+            .unwrap()
+            .image;
+
+        assert_matches_golden(&format!("mosaic-{strategy}"), &output);
+    }
+}
+
+#[test]
+fn test_luminance_matched_mosaic_matches_golden_image() {
+    let options = MosaicOptions {
+        match_luminance: true,
+        ..options("independent")
+    };
+
+    let output = mosaic(&fixture("target.png"), &[fixture("library")], &options)
+        .unwrap()
+        .image;
+
+    assert_matches_golden("mosaic-match-luminance", &output);
+}
+
+#[test]
+fn test_masked_mosaic_matches_golden_image() {
+    let options = MosaicOptions {
+        mask: Some(Mask {
+            shape: MaskShape::Image(fixture("mask.png")),
+            threshold: 0.5,
+            background: Some(Color([255, 255, 255])),
+        }),
+        ..options("independent")
+    };
+
+    let output = mosaic(&fixture("target.png"), &[fixture("library")], &options)
+        .unwrap()
+        .image;
+
+    assert_matches_golden("mosaic-masked", &output);
+}
+
+#[test]
+fn test_tile_matches_golden_image() {
+    // A landscape image, so the tile is cropped from its centre.
+    let path = fixture("library/0.png");
+
+    let output = tile(path.to_str().unwrap()).unwrap();
+
+    assert_matches_golden("tile", &output);
+}
@@ -0,0 +1,96 @@
+use std::env::temp_dir;
+use std::fs::{create_dir_all, remove_dir_all};
+use std::path::PathBuf;
+
+use image::{Rgba, RgbaImage};
+use tiler::{mosaic, strategy_names, MosaicOptions, ProcessingOrder, Refinement, Seed};
+
+/// A scratch directory holding a target and a library of distinct colors.
+fn fixtures() -> (PathBuf, PathBuf) {
+    let dir = temp_dir().join(format!("tiler-reproducibility-{}", std::process::id()));
+    let _ = remove_dir_all(&dir);
+    let library = dir.join("library");
+    create_dir_all(&library).unwrap();
+    for i in 0..8u8 {
+        RgbaImage::from_fn(12, 12, |x, y| {
+            Rgba([i * 30, (x * 20) as u8, (y * 20) as u8 + i * 5, 255])
+        })
+        .save(library.join(format!("{i}.png")))
+        .unwrap();
+    }
+    let target = dir.join("target.png");
+    RgbaImage::from_fn(60, 40, |x, y| {
+        Rgba([(x * 4) as u8, (y * 6) as u8, 128, 255])
+    })
+    .save(&target)
+    .unwrap();
+    (target, library)
+}
+
+#[test]
+fn test_builds_identical_mosaics_from_the_same_seed() {
+    let (target, library) = fixtures();
+
+    for strategy in strategy_names() {
+        let options = MosaicOptions {
+            analysis_size: 2,
+            cell_size: 10,
+            tile_size: 6,
+            strategy: strategy.to_string(),
+            order: ProcessingOrder::Random,
+            seed: Seed(7),
+            refinement: Refinement {
+                iterations: 200,
+                temperature: 1000.0,
+                ..Refinement::default()
+            },
+            ..MosaicOptions::default()
+        };
+
+        let first = mosaic(&target, &[&library], &options).unwrap();
+        let second = mosaic(&target, &[&library], &options).unwrap();
+
+        assert_eq!(first.image.as_raw(), second.image.as_raw(), "{strategy}");
+        assert_eq!(first.placements, second.placements, "{strategy}");
+    }
+    remove_dir_all(target.parent().unwrap()).unwrap();
+}
+
+#[test]
+fn test_breaks_ties_between_identical_tiles_the_same_way() {
+    let dir = temp_dir().join(format!("tiler-reproducibility-ties-{}", std::process::id()));
+    let _ = remove_dir_all(&dir);
+    let library = dir.join("library");
+    create_dir_all(&library).unwrap();
+    // Pairs of identical images, which match every cell equally well.
+    for i in 0..8u8 {
+        let shade = (i / 2) * 60;
+        RgbaImage::from_pixel(12, 12, Rgba([shade, shade, 255 - shade, 255]))
+            .save(library.join(format!("{i}.png")))
+            .unwrap();
+    }
+    let target = dir.join("target.png");
+    RgbaImage::from_fn(60, 40, |x, _| Rgba([(x * 4) as u8, 90, 160, 255]))
+        .save(&target)
+        .unwrap();
+
+    for strategy in strategy_names() {
+        for temperature in [0.0, 0.5] {
+            let options = MosaicOptions {
+                analysis_size: 2,
+                cell_size: 10,
+                tile_size: 6,
+                strategy: strategy.to_string(),
+                temperature,
+                ..MosaicOptions::default()
+            };
+
+            let first = mosaic(&target, &[&library], &options).unwrap();
+            for _ in 0..3 {
+                let again = mosaic(&target, &[&library], &options).unwrap();
+                assert_eq!(first.placements, again.placements, "{strategy}");
+            }
+        }
+    }
+    remove_dir_all(dir).unwrap();
+}